@@ -0,0 +1,172 @@
+/* Generated with cbindgen from bcsk-ffi, do not edit. */
+
+#ifndef BCSK_H
+#define BCSK_H
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * Result of the fallible functions.
+ */
+typedef enum BcskStatus {
+  BCSK_STATUS_OK = 0,
+  BCSK_STATUS_NULL_POINTER = -1,
+  /**
+   * The sketches have different parameters.
+   */
+  BCSK_STATUS_INCOMPATIBLE = -2,
+  /**
+   * Serialized bytes were malformed.
+   */
+  BCSK_STATUS_PARSE = -3,
+  /**
+   * The output buffer is too small, the required length was written.
+   */
+  BCSK_STATUS_BUFFER_TOO_SMALL = -4,
+  BCSK_STATUS_INVALID_ARGUMENT = -5,
+} BcskStatus;
+
+/**
+ * Opaque sketch handle.
+ */
+typedef struct BcskSketch BcskSketch;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Creates an empty sketch with the all-zero seed, or returns null if the parameters
+ * are invalid.
+ */
+BcskSketch *bcsk_new(uint64_t base_length, uint64_t level, uint64_t points);
+
+/**
+ * Same as `bcsk_new` with a 16 byte seed.
+ *
+ * # Safety
+ *
+ * `seed` must point to 16 readable bytes.
+ */
+BcskSketch *bcsk_new_seeded(uint64_t base_length, uint64_t level, uint64_t points, const uint8_t *seed);
+
+/**
+ * Releases a sketch. Null is ignored.
+ *
+ * # Safety
+ *
+ * `sketch` must be null or a pointer returned by this library that was not freed yet.
+ */
+void bcsk_free(BcskSketch *sketch);
+
+/**
+ * Returns a copy of `sketch`, or null if it is null.
+ *
+ * # Safety
+ *
+ * `sketch` must be null or a valid sketch.
+ */
+BcskSketch *bcsk_clone(const BcskSketch *sketch);
+
+/**
+ * Toggles the item of `len` bytes at `item` in the sketch.
+ *
+ * # Safety
+ *
+ * `sketch` must be a valid sketch and `item` must point to `len` readable bytes.
+ */
+BcskStatus bcsk_toggle(BcskSketch *sketch, const uint8_t *item, size_t len);
+
+/**
+ * Number of the item's points that are set, 0 if a pointer is null.
+ *
+ * # Safety
+ *
+ * `sketch` must be a valid sketch and `item` must point to `len` readable bytes.
+ */
+size_t bcsk_check(const BcskSketch *sketch, const uint8_t *item, size_t len);
+
+/**
+ * Diffs `other` into `sketch`, leaving the items in exactly one of them.
+ *
+ * # Safety
+ *
+ * `sketch` and `other` must be valid sketches, and may not be the same sketch.
+ */
+BcskStatus bcsk_diff(BcskSketch *sketch, const BcskSketch *other);
+
+/**
+ * Returns the diff of two sketches as a new sketch, or null if a pointer is null or
+ * their parameters differ.
+ *
+ * # Safety
+ *
+ * `sketch` and `other` must be null or valid sketches.
+ */
+BcskSketch *bcsk_diff_new(const BcskSketch *sketch, const BcskSketch *other);
+
+/**
+ * Writes the number of set points of each of `count` candidates to `scores`. Candidate
+ * `i` is the `lens[i]` bytes at `items[i]`.
+ *
+ * # Safety
+ *
+ * `sketch` must be a valid sketch, `items` and `lens` must point to `count` entries
+ * describing readable buffers, and `scores` must point to `count` writable entries.
+ */
+BcskStatus bcsk_decode(const BcskSketch *sketch,
+                       const uint8_t *const *items,
+                       const size_t *lens,
+                       size_t count,
+                       size_t *scores);
+
+/**
+ * Estimated number of items in the sketch, e.g. the size of a diff, or -1 if it is
+ * saturated or null.
+ *
+ * # Safety
+ *
+ * `sketch` must be null or a valid sketch.
+ */
+int64_t bcsk_estimate_difference(const BcskSketch *sketch);
+
+/**
+ * Lowest decode threshold for the default false positive rate, 0 if the sketch is null.
+ *
+ * # Safety
+ *
+ * `sketch` must be null or a valid sketch.
+ */
+size_t bcsk_suggest_threshold(const BcskSketch *sketch);
+
+/**
+ * Writes the `to_bytes` encoding of the sketch to the `out_len` bytes at `out` and its
+ * length to `written`. If `out_len` is too small, only the required length is written
+ * and `BCSK_STATUS_BUFFER_TOO_SMALL` returned, so callers can pass a null `out` first
+ * to size the buffer.
+ *
+ * # Safety
+ *
+ * `sketch` must be a valid sketch, `out` must point to `out_len` writable bytes and
+ * `written` must be writable.
+ */
+BcskStatus bcsk_serialize(const BcskSketch *sketch, uint8_t *out, size_t out_len, size_t *written);
+
+/**
+ * Reads a sketch written by `bcsk_serialize` or the Rust `to_bytes`, or returns null if
+ * the bytes are malformed.
+ *
+ * # Safety
+ *
+ * `data` must point to `len` readable bytes.
+ */
+BcskSketch *bcsk_deserialize(const uint8_t *data, size_t len);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* BCSK_H */
@@ -0,0 +1,310 @@
+//! C API of the sketch, see `include/bcsk.h`.
+//!
+//! Sketches are opaque `BcskSketch` pointers owned by the caller: every pointer returned
+//! by `bcsk_new`, `bcsk_new_seeded`, `bcsk_clone`, `bcsk_diff_new` and `bcsk_deserialize`
+//! must be released exactly once with `bcsk_free`, and not used afterwards. Functions
+//! taking a sketch only borrow it for the duration of the call, and byte buffers passed
+//! in are only read during the call. A sketch must not be used from several threads at
+//! once while one of them modifies it.
+//!
+//! Items are byte strings, hashed as by the Rust `toggle_bytes`, so C and Rust peers
+//! sketching the same bytes can diff their sketches.
+
+use std::ptr;
+use std::slice;
+
+use bcsk::{BinaryCountSketch, BinaryCountSketchError, ErrorKind, DEFAULT_FP_RATE};
+
+/// Opaque sketch handle.
+pub struct BcskSketch(BinaryCountSketch);
+
+/// Result of the fallible functions.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BcskStatus {
+    Ok = 0,
+    NullPointer = -1,
+    /// The sketches have different parameters.
+    Incompatible = -2,
+    /// Serialized bytes were malformed.
+    Parse = -3,
+    /// The output buffer is too small, the required length was written.
+    BufferTooSmall = -4,
+    InvalidArgument = -5,
+}
+
+impl From<BinaryCountSketchError> for BcskStatus {
+    fn from(e: BinaryCountSketchError) -> Self {
+        match e.kind() {
+            ErrorKind::Compatibility => BcskStatus::Incompatible,
+            ErrorKind::Parse => BcskStatus::Parse,
+            _ => BcskStatus::InvalidArgument,
+        }
+    }
+}
+
+/// Whether a sketch of `base_length << level` words can be allocated without overflow.
+fn valid_params(base_length: u64, level: u64) -> bool {
+    base_length > 0 && level < 64 && base_length.leading_zeros() >= level as u32 && usize::try_from(base_length << level).is_ok()
+}
+
+fn into_raw(sketch: BinaryCountSketch) -> *mut BcskSketch {
+    Box::into_raw(Box::new(BcskSketch(sketch)))
+}
+
+/// Bytes of a `(pointer, length)` pair, empty for a null pointer with length 0.
+unsafe fn bytes<'a>(data: *const u8, len: usize) -> Option<&'a [u8]> {
+    match (data.is_null(), len) {
+        (true, 0) => Some(&[]),
+        (true, _) => None,
+        (false, _) => Some(slice::from_raw_parts(data, len)),
+    }
+}
+
+/// Creates an empty sketch with the all-zero seed, or returns null if the parameters
+/// are invalid.
+#[no_mangle]
+pub extern "C" fn bcsk_new(base_length: u64, level: u64, points: u64) -> *mut BcskSketch {
+    if !valid_params(base_length, level) {
+        return ptr::null_mut();
+    }
+    into_raw(BinaryCountSketch::new(base_length, level, points))
+}
+
+/// Same as `bcsk_new` with a 16 byte seed.
+///
+/// # Safety
+///
+/// `seed` must point to 16 readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn bcsk_new_seeded(base_length: u64, level: u64, points: u64, seed: *const u8) -> *mut BcskSketch {
+    if seed.is_null() || !valid_params(base_length, level) {
+        return ptr::null_mut();
+    }
+    let seed: [u8; 16] = slice::from_raw_parts(seed, 16).try_into().unwrap();
+    into_raw(BinaryCountSketch::with_seed(base_length, level, points, seed))
+}
+
+/// Releases a sketch. Null is ignored.
+///
+/// # Safety
+///
+/// `sketch` must be null or a pointer returned by this library that was not freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn bcsk_free(sketch: *mut BcskSketch) {
+    if !sketch.is_null() {
+        drop(Box::from_raw(sketch));
+    }
+}
+
+/// Returns a copy of `sketch`, or null if it is null.
+///
+/// # Safety
+///
+/// `sketch` must be null or a valid sketch.
+#[no_mangle]
+pub unsafe extern "C" fn bcsk_clone(sketch: *const BcskSketch) -> *mut BcskSketch {
+    match sketch.as_ref() {
+        Some(s) => into_raw(s.0.clone()),
+        None => ptr::null_mut(),
+    }
+}
+
+/// Toggles the item of `len` bytes at `item` in the sketch.
+///
+/// # Safety
+///
+/// `sketch` must be a valid sketch and `item` must point to `len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn bcsk_toggle(sketch: *mut BcskSketch, item: *const u8, len: usize) -> BcskStatus {
+    match (sketch.as_mut(), bytes(item, len)) {
+        (Some(s), Some(item)) => {
+            s.0.toggle_bytes(item);
+            BcskStatus::Ok
+        }
+        _ => BcskStatus::NullPointer,
+    }
+}
+
+/// Number of the item's points that are set, 0 if a pointer is null.
+///
+/// # Safety
+///
+/// `sketch` must be a valid sketch and `item` must point to `len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn bcsk_check(sketch: *const BcskSketch, item: *const u8, len: usize) -> usize {
+    match (sketch.as_ref(), bytes(item, len)) {
+        (Some(s), Some(item)) => s.0.check_bytes(item),
+        _ => 0,
+    }
+}
+
+/// Diffs `other` into `sketch`, leaving the items in exactly one of them.
+///
+/// # Safety
+///
+/// `sketch` and `other` must be valid sketches, and may not be the same sketch.
+#[no_mangle]
+pub unsafe extern "C" fn bcsk_diff(sketch: *mut BcskSketch, other: *const BcskSketch) -> BcskStatus {
+    match (sketch.as_mut(), other.as_ref()) {
+        (Some(s), Some(o)) => s.0.diff_with(&o.0).map_or_else(BcskStatus::from, |_| BcskStatus::Ok),
+        _ => BcskStatus::NullPointer,
+    }
+}
+
+/// Returns the diff of two sketches as a new sketch, or null if a pointer is null or
+/// their parameters differ.
+///
+/// # Safety
+///
+/// `sketch` and `other` must be null or valid sketches.
+#[no_mangle]
+pub unsafe extern "C" fn bcsk_diff_new(sketch: *const BcskSketch, other: *const BcskSketch) -> *mut BcskSketch {
+    match (sketch.as_ref(), other.as_ref()) {
+        (Some(s), Some(o)) => s.0.diff(&o.0).map_or(ptr::null_mut(), into_raw),
+        _ => ptr::null_mut(),
+    }
+}
+
+/// Writes the number of set points of each of `count` candidates to `scores`. Candidate
+/// `i` is the `lens[i]` bytes at `items[i]`.
+///
+/// # Safety
+///
+/// `sketch` must be a valid sketch, `items` and `lens` must point to `count` entries
+/// describing readable buffers, and `scores` must point to `count` writable entries.
+#[no_mangle]
+pub unsafe extern "C" fn bcsk_decode(sketch: *const BcskSketch, items: *const *const u8, lens: *const usize, count: usize, scores: *mut usize) -> BcskStatus {
+    let s = match sketch.as_ref() {
+        Some(s) => s,
+        None => return BcskStatus::NullPointer,
+    };
+    if count > 0 && (items.is_null() || lens.is_null() || scores.is_null()) {
+        return BcskStatus::NullPointer;
+    }
+    for i in 0..count {
+        match bytes(*items.add(i), *lens.add(i)) {
+            Some(item) => *scores.add(i) = s.0.check_bytes(item),
+            None => return BcskStatus::NullPointer,
+        }
+    }
+    BcskStatus::Ok
+}
+
+/// Estimated number of items in the sketch, e.g. the size of a diff, or -1 if it is
+/// saturated or null.
+///
+/// # Safety
+///
+/// `sketch` must be null or a valid sketch.
+#[no_mangle]
+pub unsafe extern "C" fn bcsk_estimate_difference(sketch: *const BcskSketch) -> i64 {
+    sketch.as_ref().and_then(|s| s.0.estimate_difference()).map_or(-1, |d| d as i64)
+}
+
+/// Lowest decode threshold for the default false positive rate, 0 if the sketch is null.
+///
+/// # Safety
+///
+/// `sketch` must be null or a valid sketch.
+#[no_mangle]
+pub unsafe extern "C" fn bcsk_suggest_threshold(sketch: *const BcskSketch) -> usize {
+    sketch.as_ref().map_or(0, |s| s.0.suggest_threshold(DEFAULT_FP_RATE))
+}
+
+/// Writes the `to_bytes` encoding of the sketch to the `out_len` bytes at `out` and its
+/// length to `written`. If `out_len` is too small, only the required length is written
+/// and `BCSK_STATUS_BUFFER_TOO_SMALL` returned, so callers can pass a null `out` first
+/// to size the buffer.
+///
+/// # Safety
+///
+/// `sketch` must be a valid sketch, `out` must point to `out_len` writable bytes and
+/// `written` must be writable.
+#[no_mangle]
+pub unsafe extern "C" fn bcsk_serialize(sketch: *const BcskSketch, out: *mut u8, out_len: usize, written: *mut usize) -> BcskStatus {
+    let (s, written) = match (sketch.as_ref(), written.as_mut()) {
+        (Some(s), Some(written)) => (s, written),
+        _ => return BcskStatus::NullPointer,
+    };
+    let encoded = s.0.to_bytes();
+    *written = encoded.len();
+    if out.is_null() || out_len < encoded.len() {
+        return BcskStatus::BufferTooSmall;
+    }
+    ptr::copy_nonoverlapping(encoded.as_ptr(), out, encoded.len());
+    BcskStatus::Ok
+}
+
+/// Reads a sketch written by `bcsk_serialize` or the Rust `to_bytes`, or returns null if
+/// the bytes are malformed.
+///
+/// # Safety
+///
+/// `data` must point to `len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn bcsk_deserialize(data: *const u8, len: usize) -> *mut BcskSketch {
+    match bytes(data, len).map(BinaryCountSketch::from_bytes) {
+        Some(Ok(sketch)) => into_raw(sketch),
+        _ => ptr::null_mut(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ffi_diff_and_serialize() {
+        unsafe {
+            let a = bcsk_new(10, 2, 3);
+            let b = bcsk_new(10, 2, 3);
+            for i in 0..20u8 {
+                let item = [b'k', i];
+                assert_eq!(bcsk_toggle(a, item.as_ptr(), 2), BcskStatus::Ok);
+                if i >= 2 {
+                    bcsk_toggle(b, item.as_ptr(), 2);
+                }
+            }
+            assert_eq!(bcsk_diff(a, b), BcskStatus::Ok);
+
+            let items = [[b'k', 0], [b'k', 1], [b'k', 5]];
+            let ptrs: Vec<*const u8> = items.iter().map(|i| i.as_ptr()).collect();
+            let mut scores = [9; 3];
+            assert_eq!(bcsk_decode(a, ptrs.as_ptr(), [2; 3].as_ptr(), 3, scores.as_mut_ptr()), BcskStatus::Ok);
+            assert_eq!(scores[..2], [3, 3]);
+            assert_eq!(bcsk_estimate_difference(a), 2);
+
+            let mut len = 0;
+            assert_eq!(bcsk_serialize(a, ptr::null_mut(), 0, &mut len), BcskStatus::BufferTooSmall);
+            let mut buf = vec![0u8; len];
+            assert_eq!(bcsk_serialize(a, buf.as_mut_ptr(), len, &mut len), BcskStatus::Ok);
+            let c = bcsk_deserialize(buf.as_ptr(), len);
+            assert_eq!((*c).0, (*a).0);
+            assert!(bcsk_deserialize(buf.as_ptr(), 3).is_null());
+
+            for s in [a, b, c] {
+                bcsk_free(s);
+            }
+        }
+    }
+
+    #[test]
+    fn test_ffi_rejects_bad_arguments() {
+        unsafe {
+            assert!(bcsk_new(10, 64, 3).is_null());
+            assert!(bcsk_new(1 << 62, 4, 3).is_null());
+            assert!(bcsk_new_seeded(10, 2, 3, ptr::null()).is_null());
+
+            let a = bcsk_new_seeded(10, 2, 3, [1; 16].as_ptr());
+            let b = bcsk_new(10, 2, 3);
+            assert_eq!(bcsk_diff(a, b), BcskStatus::Incompatible);
+            assert!(bcsk_diff_new(a, b).is_null());
+            assert_eq!(bcsk_toggle(a, ptr::null(), 1), BcskStatus::NullPointer);
+            assert_eq!(bcsk_diff(ptr::null_mut(), b), BcskStatus::NullPointer);
+            bcsk_free(a);
+            bcsk_free(b);
+            bcsk_free(ptr::null_mut());
+        }
+    }
+}
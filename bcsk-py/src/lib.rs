@@ -0,0 +1,122 @@
+use bcsk::{BinaryCountSketchError, DEFAULT_FP_RATE};
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+
+fn value_error(e: BinaryCountSketchError) -> PyErr {
+    PyValueError::new_err(e.to_string())
+}
+
+/// `bcsk.BinaryCountSketch`: a sketch of byte string items, hashed as by the Rust
+/// `toggle_bytes` and `keyed_bytes`, so it interoperates with Rust services building
+/// sketches of the same bytes.
+#[pyclass(name = "BinaryCountSketch", eq)]
+#[derive(PartialEq)]
+struct Sketch {
+    inner: bcsk::BinaryCountSketch,
+}
+
+#[pymethods]
+impl Sketch {
+    #[new]
+    #[pyo3(signature = (base_length, level, points, seed = None))]
+    fn new(base_length: u64, level: u64, points: u64, seed: Option<&[u8]>) -> PyResult<Self> {
+        let seed = match seed {
+            Some(seed) => seed.try_into().map_err(|_| PyValueError::new_err("seed must be 16 bytes"))?,
+            None => [0; 16],
+        };
+        Ok(Sketch { inner: bcsk::BinaryCountSketch::with_seed(base_length, level, points, seed) })
+    }
+
+    #[getter]
+    fn base_length(&self) -> u64 {
+        self.inner.base_length()
+    }
+
+    #[getter]
+    fn level(&self) -> u64 {
+        self.inner.level()
+    }
+
+    #[getter]
+    fn points(&self) -> u64 {
+        self.inner.points()
+    }
+
+    #[getter]
+    fn bits(&self) -> usize {
+        self.inner.bits()
+    }
+
+    fn toggle(&mut self, item: &[u8]) {
+        self.inner.toggle_bytes(item);
+    }
+
+    fn check(&self, item: &[u8]) -> usize {
+        self.inner.check_bytes(item)
+    }
+
+    fn diff_with(&mut self, other: &Sketch) -> PyResult<()> {
+        self.inner.diff_with(&other.inner).map_err(value_error)
+    }
+
+    fn diff(&self, other: &Sketch) -> PyResult<Sketch> {
+        Ok(Sketch { inner: self.inner.diff(&other.inner).map_err(value_error)? })
+    }
+
+    fn level_down(&self, new_level: u64) -> PyResult<Sketch> {
+        Ok(Sketch { inner: self.inner.level_down(new_level).map_err(value_error)? })
+    }
+
+    /// Number of set points of each candidate, as `BinaryCountSketch::decode`.
+    fn decode(&self, candidates: Vec<Vec<u8>>) -> Vec<usize> {
+        let items: Vec<_> = candidates.iter().map(|c| self.inner.keyed_bytes(c)).collect();
+        self.inner.decode(&items)
+    }
+
+    /// Peels the candidates out of this diffed sketch and returns those found, at
+    /// `threshold` or the threshold `suggest_threshold` picks.
+    #[pyo3(signature = (candidates, threshold = None))]
+    fn reconcile(&mut self, candidates: Vec<Vec<u8>>, threshold: Option<usize>) -> PyResult<Vec<Vec<u8>>> {
+        let threshold = threshold.unwrap_or_else(|| self.inner.suggest_threshold(DEFAULT_FP_RATE));
+        let items: Vec<_> = candidates.iter().map(|c| self.inner.keyed_bytes(c)).collect();
+        let result = self.inner.reconcile_with_threshold(&items, threshold).map_err(value_error)?;
+        Ok(result.decoded.iter().map(|item| item.bytes().to_vec()).collect())
+    }
+
+    /// False positive and false negative counts among `samples` random items, drawn from
+    /// `seed` as `BinaryCountSketch::estimate_stats_seeded`.
+    #[pyo3(signature = (samples, threshold, seed = 0))]
+    fn estimate_stats(&self, samples: usize, threshold: usize, seed: u64) -> PyResult<(usize, usize)> {
+        self.inner.estimate_stats_seeded(seed, samples, threshold).map_err(value_error)
+    }
+
+    fn estimate_difference(&self) -> Option<usize> {
+        self.inner.estimate_difference()
+    }
+
+    #[pyo3(signature = (target_fp_rate = DEFAULT_FP_RATE))]
+    fn suggest_threshold(&self, target_fp_rate: f64) -> usize {
+        self.inner.suggest_threshold(target_fp_rate)
+    }
+
+    fn to_bytes<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        PyBytes::new(py, &self.inner.to_bytes())
+    }
+
+    #[staticmethod]
+    fn from_bytes(bytes: &[u8]) -> PyResult<Sketch> {
+        Ok(Sketch { inner: bcsk::BinaryCountSketch::from_bytes(bytes).map_err(value_error)? })
+    }
+
+    fn __repr__(&self) -> String {
+        format!("{:?}", self.inner)
+    }
+}
+
+#[pymodule]
+#[pyo3(name = "bcsk")]
+fn bcsk_module(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<Sketch>()?;
+    Ok(())
+}
@@ -0,0 +1,112 @@
+use bcsk::{BinaryCountSketchError, DEFAULT_FP_RATE};
+use js_sys::{Array, Uint8Array};
+use wasm_bindgen::prelude::*;
+
+fn js_error(e: BinaryCountSketchError) -> JsError {
+    JsError::new(&e.to_string())
+}
+
+/// Copies the `Uint8Array` items of a JS array.
+fn items_of(candidates: &Array) -> Vec<Vec<u8>> {
+    candidates.iter().map(|c| Uint8Array::new(&c).to_vec()).collect()
+}
+
+/// `BinaryCountSketch` of byte string items for JS, hashed as by the Rust `toggle_bytes`
+/// and `keyed_bytes`, so a browser can reconcile its local state with a Rust server
+/// sketching the same bytes. Build with `wasm-pack build bcsk-wasm`.
+#[wasm_bindgen(js_name = BinaryCountSketch)]
+pub struct Sketch {
+    inner: bcsk::BinaryCountSketch,
+}
+
+#[wasm_bindgen(js_class = BinaryCountSketch)]
+impl Sketch {
+    /// `seed` must be 16 bytes if given.
+    #[wasm_bindgen(constructor)]
+    pub fn new(base_length: u64, level: u64, points: u64, seed: Option<Vec<u8>>) -> Result<Sketch, JsError> {
+        let seed = match seed {
+            Some(seed) => seed.as_slice().try_into().map_err(|_| JsError::new("seed must be 16 bytes"))?,
+            None => [0; 16],
+        };
+        Ok(Sketch { inner: bcsk::BinaryCountSketch::with_seed(base_length, level, points, seed) })
+    }
+
+    #[wasm_bindgen(getter, js_name = baseLength)]
+    pub fn base_length(&self) -> u64 {
+        self.inner.base_length()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn level(&self) -> u64 {
+        self.inner.level()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn points(&self) -> u64 {
+        self.inner.points()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn bits(&self) -> usize {
+        self.inner.bits()
+    }
+
+    pub fn toggle(&mut self, item: &[u8]) {
+        self.inner.toggle_bytes(item);
+    }
+
+    pub fn check(&self, item: &[u8]) -> usize {
+        self.inner.check_bytes(item)
+    }
+
+    #[wasm_bindgen(js_name = diffWith)]
+    pub fn diff_with(&mut self, other: &Sketch) -> Result<(), JsError> {
+        self.inner.diff_with(&other.inner).map_err(js_error)
+    }
+
+    pub fn diff(&self, other: &Sketch) -> Result<Sketch, JsError> {
+        Ok(Sketch { inner: self.inner.diff(&other.inner).map_err(js_error)? })
+    }
+
+    /// Number of set points of each `Uint8Array` candidate, as `BinaryCountSketch::decode`.
+    pub fn decode(&self, candidates: &Array) -> Vec<usize> {
+        let items = items_of(candidates);
+        let keyed: Vec<_> = items.iter().map(|c| self.inner.keyed_bytes(c)).collect();
+        self.inner.decode(&keyed)
+    }
+
+    /// Peels the `Uint8Array` candidates out of this diffed sketch and returns those
+    /// found, at `threshold` or the threshold `suggest_threshold` picks.
+    pub fn reconcile(&mut self, candidates: &Array, threshold: Option<usize>) -> Result<Array, JsError> {
+        let threshold = threshold.unwrap_or_else(|| self.inner.suggest_threshold(DEFAULT_FP_RATE));
+        let items = items_of(candidates);
+        let keyed: Vec<_> = items.iter().map(|c| self.inner.keyed_bytes(c)).collect();
+        let result = self.inner.reconcile_with_threshold(&keyed, threshold).map_err(js_error)?;
+        Ok(result.decoded.iter().map(|item| Uint8Array::from(item.bytes())).collect())
+    }
+
+    /// Estimated number of items in this sketch, e.g. the size of a diff, or `undefined`
+    /// if it is saturated.
+    #[wasm_bindgen(js_name = estimateDifference)]
+    pub fn estimate_difference(&self) -> Option<usize> {
+        self.inner.estimate_difference()
+    }
+
+    /// False positive and false negative counts among `samples` random items drawn from
+    /// `seed`, as `BinaryCountSketch::estimate_stats_seeded`.
+    #[wasm_bindgen(js_name = estimateStats)]
+    pub fn estimate_stats(&self, samples: usize, threshold: usize, seed: u64) -> Result<Vec<usize>, JsError> {
+        let (fpos, fneg) = self.inner.estimate_stats_seeded(seed, samples, threshold).map_err(js_error)?;
+        Ok(vec![fpos, fneg])
+    }
+
+    #[wasm_bindgen(js_name = toBytes)]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.inner.to_bytes()
+    }
+
+    #[wasm_bindgen(js_name = fromBytes)]
+    pub fn from_bytes(bytes: &[u8]) -> Result<Sketch, JsError> {
+        Ok(Sketch { inner: bcsk::BinaryCountSketch::from_bytes(bytes).map_err(js_error)? })
+    }
+}
@@ -0,0 +1,22 @@
+use std::hint::black_box;
+
+use bcsk::{BinaryCountSketch, TestItem};
+use criterion::{criterion_group, criterion_main, Criterion};
+
+fn bench_toggle(c: &mut Criterion) {
+    let item = TestItem::new();
+    let mut sketch1 = BinaryCountSketch::new(100, 2, 5);
+
+    c.bench_function("toggle", |b| b.iter(|| sketch1.toggle(black_box(&item))));
+}
+
+fn bench_check(c: &mut Criterion) {
+    let item = TestItem::new();
+    let mut sketch1 = BinaryCountSketch::new(100, 2, 5);
+    sketch1.toggle(&item);
+
+    c.bench_function("check", |b| b.iter(|| sketch1.check(black_box(&item))));
+}
+
+criterion_group!(benches, bench_toggle, bench_check);
+criterion_main!(benches);
@@ -0,0 +1,123 @@
+use std::hint::black_box;
+
+use bcsk::{BinaryCountSketch, HashedItem, PeelingDecoder, SketchParams};
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+
+const ITEMS: u64 = 1_000_000;
+
+/// Differences decoded out of `ITEMS` candidates.
+const DIFFERENCE: u64 = 1000;
+
+/// Words of the sketches diffed by `bench_diff`, 100MB each.
+const DIFF_WORDS: u64 = 100 << 17;
+
+fn items(range: std::ops::Range<u64>) -> Vec<HashedItem<u64>> {
+    range.map(HashedItem::new).collect()
+}
+
+/// Sketch of the given size filled with pseudo-random words.
+fn random_sketch(base_length: u64, level: u64, seed: u64) -> BinaryCountSketch {
+    let mut x = seed | 1;
+    let words = (0..base_length << level)
+        .map(|_| {
+            // xorshift64
+            x ^= x << 13;
+            x ^= x >> 7;
+            x ^= x << 17;
+            x
+        })
+        .collect();
+    BinaryCountSketch::from_parts(SketchParams::new(base_length, level, 5), words).expect("Words match the parameters")
+}
+
+/// Diff of the sketches of two sets of `ITEMS` items sharing all but `DIFFERENCE` of them,
+/// and the items of both sets.
+fn diffed(params: SketchParams) -> (BinaryCountSketch, BinaryCountSketch, Vec<HashedItem<u64>>) {
+    let candidates = items(0..ITEMS + DIFFERENCE);
+    let mut local = BinaryCountSketch::from_params(params);
+    let mut remote = BinaryCountSketch::from_params(params);
+    local.toggle_all(&candidates[..ITEMS as usize]);
+    remote.toggle_all(&candidates[DIFFERENCE as usize..]);
+    (local, remote, candidates)
+}
+
+fn bench_insert(c: &mut Criterion) {
+    let items = items(0..ITEMS);
+    let mut sketch = BinaryCountSketch::new(1 << 20, 2, 5);
+
+    let mut group = c.benchmark_group("insert");
+    group.throughput(Throughput::Elements(ITEMS));
+    group.sample_size(10);
+    group.bench_function("toggle_all", |b| b.iter(|| sketch.toggle_all(black_box(&items))));
+    group.bench_function("toggle_each", |b| {
+        b.iter(|| {
+            for item in black_box(&items) {
+                sketch.toggle(item);
+            }
+        })
+    });
+    group.finish();
+}
+
+fn bench_diff(c: &mut Criterion) {
+    let mut local = random_sketch(DIFF_WORDS >> 2, 2, 1);
+    let remote = random_sketch(DIFF_WORDS >> 2, 2, 2);
+
+    let mut group = c.benchmark_group("diff");
+    group.throughput(Throughput::Bytes(DIFF_WORDS * 8));
+    group.sample_size(10);
+    group.bench_function("diff_with_100MB", |b| b.iter(|| local.diff_with(black_box(&remote)).expect("No errors")));
+    group.bench_function("level_down_100MB", |b| b.iter(|| black_box(&remote).level_down(0).expect("No errors")));
+    group.finish();
+}
+
+fn bench_decode(c: &mut Criterion) {
+    let params = SketchParams::for_expected_diff(DIFFERENCE as usize, 1e-4).expect("No errors");
+    let (mut diff, remote, candidates) = diffed(params);
+    diff.diff_with(&remote).expect("No errors");
+    let decoder = PeelingDecoder::new(params.points as usize - 1);
+
+    let mut group = c.benchmark_group("decode");
+    group.throughput(Throughput::Elements(candidates.len() as u64));
+    group.sample_size(10);
+    group.bench_function("check_1M", |b| b.iter(|| diff.decode(black_box(&candidates))));
+    group.bench_function("peel_1M", |b| {
+        b.iter_batched_ref(|| diff.clone(), |sketch| decoder.decode(sketch, black_box(&candidates)).expect("No errors"), BatchSize::LargeInput)
+    });
+    group.finish();
+}
+
+fn bench_decode_hierarchical(c: &mut Criterion) {
+    // Twice the words needed split into 7 levels, so the coarser levels fail first.
+    let needed = SketchParams::for_expected_diff(DIFFERENCE as usize, 1e-4).expect("No errors");
+    let params = SketchParams::new(((needed.base_length << needed.level) >> 5).max(1), 6, needed.points);
+    let (local, remote, candidates) = diffed(params);
+    let decoder = PeelingDecoder::new(params.points as usize - 1);
+
+    let mut group = c.benchmark_group("decode_hierarchical");
+    group.throughput(Throughput::Elements(candidates.len() as u64));
+    group.sample_size(10);
+    group.bench_function("from_level_0", |b| {
+        b.iter(|| decoder.decode_hierarchical(&local, black_box(&candidates), 0, |level| remote.at_level(level)).expect("No errors"))
+    });
+    group.finish();
+}
+
+fn bench_wire(c: &mut Criterion) {
+    let sketch = random_sketch(1 << 18, 2, 3);
+    let bytes = sketch.to_bytes();
+
+    let mut group = c.benchmark_group("wire");
+    group.throughput(Throughput::Bytes(bytes.len() as u64));
+    group.bench_function("to_bytes_8MB", |b| b.iter(|| black_box(&sketch).to_bytes()));
+    group.bench_function("from_bytes_8MB", |b| b.iter(|| BinaryCountSketch::from_bytes(black_box(&bytes)).expect("No errors")));
+    #[cfg(feature = "deflate")]
+    {
+        let compressed = sketch.to_bytes_compressed();
+        group.bench_function("from_bytes_compressed_8MB", |b| b.iter(|| BinaryCountSketch::from_bytes(black_box(&compressed)).expect("No errors")));
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_insert, bench_diff, bench_decode, bench_decode_hierarchical, bench_wire);
+criterion_main!(benches);
@@ -0,0 +1,133 @@
+use crate::{code_index, BinaryCountSketch, BinaryCountSketchError, ErrorKind, Item, SketchParams};
+
+/// Emitted by `AdaptiveSketch::toggle` when the sketch was re-encoded at a finer level.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ResizeEvent {
+    pub from: SketchParams,
+    pub to: SketchParams,
+    /// Density that triggered the resize.
+    pub density: f64,
+}
+
+/// Sketch that tracks the fraction of set bits as items are toggled, and re-encodes
+/// itself one level finer whenever it exceeds `max_density`, past which decoding a diff
+/// against it becomes unreliable.
+///
+/// Re-encoding needs every item currently in the set, so the sketch is given a `source`,
+/// e.g. a replay of the write-ahead log. The source must already include an item when
+/// it is toggled, and every re-encode is checked against the current sketch.
+pub struct AdaptiveSketch<F> {
+    sketch: BinaryCountSketch,
+    max_density: f64,
+    ones: usize,
+    source: F,
+}
+
+impl<F, I> AdaptiveSketch<F>
+where
+    F: FnMut() -> I,
+    I: IntoIterator,
+    I::Item: Item,
+{
+    pub fn new(params: SketchParams, max_density: f64, source: F) -> Result<Self, BinaryCountSketchError> {
+        if !(max_density > 0.0 && max_density < 0.5) { return Err(BinaryCountSketchError::with_kind(ErrorKind::InvalidArgument, "Incorrect density")); }
+
+        Ok(AdaptiveSketch {
+            sketch: BinaryCountSketch::from_params(params),
+            max_density,
+            ones: 0,
+            source,
+        })
+    }
+
+    pub fn density(&self) -> f64 {
+        self.ones as f64 / self.sketch.bits() as f64
+    }
+
+    pub fn toggle<V: Item>(&mut self, v: &V) -> Result<Option<ResizeEvent>, BinaryCountSketchError> {
+        let l = self.sketch.bits();
+        for i in 0..self.sketch.points_of(v) {
+            let b = code_index(v, i, l);
+            if self.sketch.words[b / 64] & (1 << (b % 64)) != 0 {
+                self.ones -= 1;
+            } else {
+                self.ones += 1;
+            }
+            self.sketch.words[b / 64] ^= 1 << (b % 64);
+        }
+
+        let density = self.density();
+        if density <= self.max_density {
+            return Ok(None);
+        }
+
+        let from = self.sketch.params();
+        while self.density() > self.max_density {
+            let params = self.sketch.params();
+            self.sketch = self.sketch.migrate((self.source)(), SketchParams::new(params.base_length, params.level + 1, params.points))?;
+            self.ones = self.sketch.words.iter().map(|w| w.count_ones() as usize).sum();
+        }
+
+        Ok(Some(ResizeEvent { from, to: self.sketch.params(), density }))
+    }
+
+    pub fn sketch(&self) -> &BinaryCountSketch {
+        &self.sketch
+    }
+
+    pub fn into_sketch(self) -> BinaryCountSketch {
+        self.sketch
+    }
+}
+
+#[cfg(all(test, feature = "rand"))]
+mod tests {
+    use std::cell::RefCell;
+
+    use super::*;
+    use crate::TestItem;
+
+    #[test]
+    fn test_adaptive_resize() {
+        let params = SketchParams::new(10, 0, 3);
+        assert!(AdaptiveSketch::new(params, 0.5, Vec::<TestItem>::new).is_err());
+
+        let log = RefCell::new(Vec::new());
+        let mut sketch = AdaptiveSketch::new(params, 0.1, || log.borrow().clone()).expect("No errors");
+
+        let mut events = vec![];
+        for _ in 0..300 {
+            let item = TestItem::new();
+            log.borrow_mut().push(item.clone());
+            if let Some(event) = sketch.toggle(&item).expect("No errors") {
+                assert!(event.density > 0.1);
+                assert_eq!(event.from.level + 1, event.to.level);
+                events.push(event);
+            }
+            assert!(sketch.density() <= 0.1);
+        }
+
+        // 900 points set about 15% of the 5120 bits of level 3, and 8% of level 4.
+        assert_eq!(events.len(), 4);
+        assert_eq!(sketch.sketch().params(), SketchParams::new(10, 4, 3));
+
+        let mut expected = BinaryCountSketch::from_params(sketch.sketch().params());
+        for item in log.borrow().iter() {
+            expected.toggle(item);
+        }
+        assert_eq!(sketch.into_sketch().words, expected.words);
+    }
+
+    #[test]
+    fn test_adaptive_stale_source() {
+        let mut sketch = AdaptiveSketch::new(SketchParams::new(1, 0, 3), 0.1, Vec::<TestItem>::new).expect("No errors");
+        let mut result = Ok(None);
+        for _ in 0..10 {
+            result = sketch.toggle(&TestItem::new());
+            if result.is_err() {
+                break;
+            }
+        }
+        assert!(result.is_err());
+    }
+}
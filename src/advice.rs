@@ -0,0 +1,244 @@
+use crate::{BinaryCountSketch, BinaryCountSketchError, ErrorKind};
+
+/// Largest number of levels above the local sketch that `advise` considers.
+const MAX_ESCALATION: u64 = 4;
+
+/// Peeling is expected to succeed while the diff has at most one point per this many bits.
+const BITS_PER_POINT: f64 = 8.0;
+
+/// Load factor above which a sketch is saturated: with one point per bit, a bit is set
+/// with probability about 0.43 and a point of an item survives with probability 0.57.
+pub const MAX_LOAD_FACTOR: f64 = 1.0;
+
+/// Cheapest way to reconcile, as recommended by `advise`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReconcileStrategy {
+    /// Exchange the sketches at their current level.
+    ThisLevel,
+    /// Rebuild and exchange sketches at the given finer level.
+    FinerLevel(u64),
+    /// Send every item instead of a sketch.
+    FullExchange,
+}
+
+/// Cost estimates for reconciling with a peer, computed from a compact summary of its
+/// sketch before the full sketches are exchanged.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Advice {
+    /// Estimated size of the symmetric difference, `None` if the summary is saturated.
+    pub estimated_difference: Option<usize>,
+    /// Whether decoding a diff at the local sketch's level is expected to succeed.
+    pub decode_likely: bool,
+    pub bytes_this_level: usize,
+    /// Smallest finer level at which decoding is expected to succeed, and its size.
+    pub finer_level: Option<(u64, usize)>,
+    /// Size of sending every local item as an 8 byte digest.
+    pub bytes_full_exchange: usize,
+    pub recommendation: ReconcileStrategy,
+}
+
+impl BinaryCountSketch {
+    /// Estimates how many distinct items are toggled into this sketch, e.g. the size of
+    /// the difference a diffed sketch holds, from its fraction of set bits. Returns
+    /// infinity when the sketch is too saturated for an estimate.
+    pub fn estimate_diff_size(&self) -> f64 {
+        // Each point flips a bit, so a fraction `p` of set bits after `n` flips of `l`
+        // bits satisfies `1 - 2p = (1 - 2 / l)^n`. Once `1 - 2p` is within a few standard
+        // deviations (about `1 / sqrt(l)`) of zero the sketch is saturated.
+        let l = self.bits() as f64;
+        let p = self.density();
+        if 1.0 - 2.0 * p > 3.0 / l.sqrt() {
+            (1.0 - 2.0 * p).ln() / (1.0 - 2.0 / l).ln() / self.points as f64
+        } else {
+            f64::INFINITY
+        }
+    }
+
+    pub(crate) fn density(&self) -> f64 {
+        let ones: u32 = self.words.iter().map(|w| w.count_ones()).sum();
+        ones as f64 / self.bits() as f64
+    }
+
+    /// Lowest decode threshold at which an item that is not in this diffed sketch is
+    /// expected to reach the threshold with probability at most `target_fp_rate`. Each
+    /// of its points hits a set bit with probability equal to the fraction of set bits,
+    /// so its score is binomial. Returns the sketch's points if no lower threshold meets
+    /// the target.
+    pub fn suggest_threshold(&self, target_fp_rate: f64) -> usize {
+        let p = self.density();
+        (1..=self.points as usize).find(|t| binomial_at_least(self.points, p, *t) <= target_fp_rate).unwrap_or(self.points as usize)
+    }
+
+    /// Deterministic counterpart of `estimate_stats`: the probability that an item not
+    /// in the sketch reaches `threshold` set points, and the probability that one of
+    /// `encoded_items` items toggled into a sketch of this size has fewer than
+    /// `threshold` of its points still set, from the expected occupancy.
+    pub fn theoretical_stats(&self, encoded_items: usize, threshold: usize) -> Result<(f64, f64), BinaryCountSketchError> {
+        if threshold > self.points as usize { return Err(BinaryCountSketchError::with_kind(ErrorKind::InvalidArgument, "Incorrect threshold")); }
+
+        // A bit stays set if an odd number of the `n` flips hit it, which happens with
+        // probability `(1 - (1 - 2/l)^n) / 2`.
+        let keep = 1.0 - 2.0 / self.bits() as f64;
+        let flips = (encoded_items as u64 * self.points) as i32;
+        let p = (1.0 - keep.powi(flips)) / 2.0;
+        // A point of an encoded item stays set if the other flips hit it an even number of times.
+        let q = (1.0 + keep.powi(flips - self.points as i32).min(1.0)) / 2.0;
+
+        Ok((binomial_at_least(self.points, p, threshold), 1.0 - binomial_at_least(self.points, q, threshold)))
+    }
+
+    /// Estimated points toggled into this sketch per bit, from `estimate_diff_size`.
+    /// Infinite when the sketch is too saturated for an estimate.
+    pub fn load_factor(&self) -> f64 {
+        self.estimate_diff_size() * self.points as f64 / self.bits() as f64
+    }
+
+    /// True when the sketch holds more than `MAX_LOAD_FACTOR` points per bit, beyond
+    /// which most items have lost points to collisions and decoding mostly yields false
+    /// positives.
+    pub fn is_saturated(&self) -> bool {
+        self.load_factor() > MAX_LOAD_FACTOR
+    }
+
+    /// `estimate_diff_size` rounded, or `None` when the sketch is saturated.
+    pub fn estimate_difference(&self) -> Option<usize> {
+        let estimate = self.estimate_diff_size();
+        estimate.is_finite().then(|| estimate.round() as usize)
+    }
+}
+
+/// Probability that at least `t` of `k` independent trials succeed, each with
+/// probability `p`.
+fn binomial_at_least(k: u64, p: f64, t: usize) -> f64 {
+    let k = k as i32;
+    let mut binomial = 1.0;
+    let mut tail = 0.0;
+    for i in (t as i32..=k).rev() {
+        // `binomial` is `k choose i`, updated from `k choose (i + 1)`.
+        if i < k {
+            binomial = binomial * (i + 1) as f64 / (k - i) as f64;
+        }
+        tail += binomial * p.powi(i) * (1.0 - p).powi(k - i);
+    }
+    tail
+}
+
+fn bytes_at(local: &BinaryCountSketch, level: u64) -> usize {
+    ((local.base_length << level) * 8) as usize
+}
+
+fn decodable(difference: usize, points: u64, bits: f64) -> bool {
+    (difference as f64) * (points as f64) * BITS_PER_POINT <= bits
+}
+
+/// Estimates the cost of reconciling `local` with the peer whose sketch folds down to
+/// `remote_summary`, e.g. the level 0 sketch a peer sends as a header. `items` is the
+/// number of items in the local set.
+pub fn advise(local: &BinaryCountSketch, remote_summary: &BinaryCountSketch, items: usize) -> Result<Advice, BinaryCountSketchError> {
+    if remote_summary.level > local.level { return Err(BinaryCountSketchError::with_kind(ErrorKind::Compatibility, "Incorrect level")); }
+
+    let mut diff = local.at_level(remote_summary.level)?;
+    diff.diff_with(remote_summary)?;
+    let estimated_difference = diff.estimate_difference();
+
+    let fits = |level: u64| estimated_difference.is_some_and(|d| decodable(d, local.points, (local.base_length << level) as f64 * 64.0));
+    let decode_likely = fits(local.level);
+    let finer_level = (local.level + 1..=local.level + MAX_ESCALATION).find(|level| fits(*level)).map(|level| (level, bytes_at(local, level)));
+    let bytes_this_level = bytes_at(local, local.level);
+    let bytes_full_exchange = items * 8;
+
+    let recommendation = match finer_level {
+        _ if decode_likely && bytes_this_level <= bytes_full_exchange => ReconcileStrategy::ThisLevel,
+        Some((level, bytes)) if !decode_likely && bytes <= bytes_full_exchange => ReconcileStrategy::FinerLevel(level),
+        _ => ReconcileStrategy::FullExchange,
+    };
+
+    Ok(Advice {
+        estimated_difference,
+        decode_likely,
+        bytes_this_level,
+        finer_level,
+        bytes_full_exchange,
+        recommendation,
+    })
+}
+
+#[cfg(all(test, feature = "rand"))]
+mod tests {
+    use super::*;
+    use crate::testing::{Peers, Scenario};
+    use crate::SketchParams;
+
+    #[test]
+    fn test_estimate_diff_size() {
+        let local = Scenario::new(SketchParams::new(100, 2, 5), 1000, 0).with_sides(0, 200).peers(0).diff();
+        let estimate = local.estimate_diff_size();
+        assert!((150.0..=250.0).contains(&estimate), "{}", estimate);
+        assert_eq!(BinaryCountSketch::new(100, 2, 5).estimate_diff_size(), 0.0);
+
+        let local = Scenario::new(SketchParams::new(100, 2, 5), 0, 0).with_sides(0, 20000).peers(0).diff();
+        assert_eq!(local.estimate_diff_size(), f64::INFINITY);
+        assert_eq!(local.estimate_difference(), None);
+    }
+
+    #[test]
+    fn test_suggest_threshold() {
+        let local = Scenario::new(SketchParams::new(100, 2, 5), 1000, 0).with_sides(0, 20).peers(0).diff();
+        assert_eq!(local.suggest_threshold(1e-3), 2);
+        assert_eq!(local.suggest_threshold(1e-8), 4);
+        assert_eq!(local.suggest_threshold(0.0), 5);
+
+        let local = Scenario::new(SketchParams::new(100, 2, 5), 0, 0).with_sides(0, 2000).peers(0).diff();
+        assert_eq!(local.suggest_threshold(1e-3), 5);
+    }
+
+    #[test]
+    fn test_theoretical_stats() {
+        let sketch = BinaryCountSketch::new(10, 2, 5);
+        let (fp, fneg) = sketch.theoretical_stats(0, 5).expect("No errors");
+        assert_eq!((fp, fneg), (0.0, 0.0));
+
+        // 500 flips of 2560 bits leave about 16% of them set.
+        let (fp, fneg) = sketch.theoretical_stats(100, 4).expect("No errors");
+        assert!(fp > 0.001 && fp < 0.01, "{}", fp);
+        assert!(fneg > 0.1 && fneg < 0.3, "{}", fneg);
+        let (strict_fp, strict_fneg) = sketch.theoretical_stats(100, 5).expect("No errors");
+        assert!(strict_fp < fp && strict_fneg > fneg);
+
+        assert!(sketch.theoretical_stats(100, 6).is_err());
+    }
+
+    #[test]
+    fn test_advise_small_difference() {
+        let Peers { local, remote, .. } = Scenario::new(SketchParams::new(100, 2, 5), 5000, 0).with_sides(0, 20).peers(0);
+        let advice = advise(&local, &remote.level_down(0).expect("No errors"), 5000).expect("No errors");
+
+        let estimate = advice.estimated_difference.expect("Not saturated");
+        assert!((10..=40).contains(&estimate), "{}", estimate);
+        assert!(advice.decode_likely);
+        assert_eq!(advice.bytes_this_level, 3200);
+        assert_eq!(advice.recommendation, ReconcileStrategy::ThisLevel);
+
+        assert!(advise(&local.level_down(0).expect("No errors"), &remote, 5000).is_err());
+    }
+
+    #[test]
+    fn test_advise_large_difference() {
+        let Peers { local, remote, .. } = Scenario::new(SketchParams::new(100, 2, 5), 1000, 0).with_sides(0, 3000).peers(0);
+
+        let advice = advise(&local, &remote, 1_000_000).expect("No errors");
+        let estimate = advice.estimated_difference.expect("Not saturated");
+        assert!((2500..=3500).contains(&estimate), "{}", estimate);
+        assert!(!advice.decode_likely);
+        assert_eq!(advice.finer_level, Some((5, 25600)));
+        assert_eq!(advice.recommendation, ReconcileStrategy::FinerLevel(5));
+
+        let advice = advise(&local, &remote, 1000).expect("No errors");
+        assert_eq!(advice.recommendation, ReconcileStrategy::FullExchange);
+
+        // A level 0 summary is saturated by this many differences.
+        let advice = advise(&local, &remote.level_down(0).expect("No errors"), 1000).expect("No errors");
+        assert_eq!(advice.estimated_difference, None);
+        assert_eq!(advice.recommendation, ReconcileStrategy::FullExchange);
+    }
+}
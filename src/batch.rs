@@ -0,0 +1,231 @@
+use alloc::vec::Vec;
+use core::ops::Deref;
+
+use crate::{code_index, BinaryCountSketch, BinaryCountSketchError, ErrorKind, Item};
+
+/// Guard returned by `BinaryCountSketch::begin_batch`. Toggles are applied immediately
+/// and journaled; unless the batch is committed, dropping it XORs the journaled bits
+/// back so the sketch returns to its state before the batch.
+pub struct ToggleBatch<'a> {
+    sketch: &'a mut BinaryCountSketch,
+    journal: Vec<usize>,
+    toggles: usize,
+}
+
+impl ToggleBatch<'_> {
+    pub fn toggle<V: Item>(&mut self, v: &V) {
+        let l = self.sketch.bits();
+        for i in 0..self.sketch.points_of(v) {
+            let b = code_index(v, i, l);
+            self.sketch.words[b / 64] ^= 1 << (b % 64);
+            self.journal.push(b);
+        }
+        self.toggles += 1;
+    }
+
+    /// Number of items toggled in this batch.
+    pub fn len(&self) -> usize {
+        self.toggles
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.toggles == 0
+    }
+
+    pub fn commit(mut self) {
+        self.journal.clear();
+    }
+
+    pub fn rollback(self) {}
+}
+
+impl Deref for ToggleBatch<'_> {
+    type Target = BinaryCountSketch;
+
+    fn deref(&self) -> &BinaryCountSketch {
+        self.sketch
+    }
+}
+
+impl Drop for ToggleBatch<'_> {
+    fn drop(&mut self) {
+        for b in self.journal.drain(..) {
+            self.sketch.words[b / 64] ^= 1 << (b % 64);
+        }
+    }
+}
+
+impl BinaryCountSketch {
+    pub fn begin_batch(&mut self) -> ToggleBatch<'_> {
+        ToggleBatch { sketch: self, journal: Vec::new(), toggles: 0 }
+    }
+}
+
+/// Position in a `JournaledSketch`'s journal that `rollback_to` can return to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Checkpoint {
+    generation: u64,
+    position: usize,
+    /// Number of rollbacks made before the checkpoint was taken.
+    rollbacks: usize,
+}
+
+/// Sketch that journals the bits it toggles since the last `commit`, so speculative
+/// toggles, e.g. of a transaction batch that may still fail, can be undone without
+/// cloning the words. Unlike `ToggleBatch` it owns the sketch, so it can be kept in a
+/// long-running service. The journal takes a `usize` per toggled point until committed.
+pub struct JournaledSketch {
+    sketch: BinaryCountSketch,
+    journal: Vec<usize>,
+    /// Incremented by every `commit`, so checkpoints taken before it are rejected.
+    generation: u64,
+    /// Journal lengths rolled back to since the last `commit`, so checkpoints whose
+    /// toggles were undone are rejected even once the journal grows past them again.
+    rollbacks: Vec<usize>,
+}
+
+impl JournaledSketch {
+    pub fn new(sketch: BinaryCountSketch) -> Self {
+        JournaledSketch { sketch, journal: Vec::new(), generation: 0, rollbacks: Vec::new() }
+    }
+
+    pub fn toggle<V: Item>(&mut self, v: &V) {
+        let l = self.sketch.bits();
+        for i in 0..self.sketch.points_of(v) {
+            let b = code_index(v, i, l);
+            self.sketch.words[b / 64] ^= 1 << (b % 64);
+            self.journal.push(b);
+        }
+    }
+
+    /// Marks the current state, to return to it with `rollback_to`.
+    pub fn checkpoint(&self) -> Checkpoint {
+        Checkpoint { generation: self.generation, position: self.journal.len(), rollbacks: self.rollbacks.len() }
+    }
+
+    /// Undoes the toggles made since `checkpoint` was taken. Fails if the journal was
+    /// committed or rolled back past it since.
+    pub fn rollback_to(&mut self, checkpoint: Checkpoint) -> Result<(), BinaryCountSketchError> {
+        let undone = self.rollbacks.get(checkpoint.rollbacks..).is_none_or(|later| later.iter().any(|p| *p < checkpoint.position));
+        if checkpoint.generation != self.generation || undone { return Err(BinaryCountSketchError::with_kind(ErrorKind::InvalidArgument, "Incorrect checkpoint")); }
+
+        self.rollbacks.push(checkpoint.position);
+        for b in self.journal.drain(checkpoint.position..) {
+            self.sketch.words[b / 64] ^= 1 << (b % 64);
+        }
+        Ok(())
+    }
+
+    /// Undoes every toggle since the last `commit`.
+    pub fn rollback(&mut self) {
+        self.rollbacks.push(0);
+        for b in self.journal.drain(..) {
+            self.sketch.words[b / 64] ^= 1 << (b % 64);
+        }
+    }
+
+    /// Keeps the toggles made so far, freeing the journal and invalidating checkpoints.
+    pub fn commit(&mut self) {
+        self.journal.clear();
+        self.rollbacks.clear();
+        self.generation += 1;
+    }
+
+    /// Number of toggled points journaled since the last `commit`.
+    pub fn journal_len(&self) -> usize {
+        self.journal.len()
+    }
+
+    pub fn sketch(&self) -> &BinaryCountSketch {
+        &self.sketch
+    }
+
+    /// Returns the sketch with the uncommitted toggles kept.
+    pub fn into_sketch(self) -> BinaryCountSketch {
+        self.sketch
+    }
+}
+
+#[cfg(all(test, feature = "rand"))]
+mod tests {
+    use super::*;
+    use crate::TestItem;
+
+    #[test]
+    fn test_batch_commit() {
+        let items: Vec<TestItem> = (0..10).map(|_| TestItem::new()).collect();
+        let mut sketch = BinaryCountSketch::new(10, 2, 3);
+        let mut expected = BinaryCountSketch::new(10, 2, 3);
+
+        let mut batch = sketch.begin_batch();
+        for item in &items {
+            batch.toggle(item);
+            expected.toggle(item);
+        }
+        assert_eq!(batch.len(), 10);
+        assert_eq!(batch.check(&items[0]), expected.check(&items[0]));
+        batch.commit();
+
+        assert_eq!(sketch.words, expected.words);
+    }
+
+    #[test]
+    fn test_batch_rollback() {
+        let before = TestItem::new();
+        let mut sketch = BinaryCountSketch::new(10, 2, 3);
+        sketch.toggle(&before);
+        let words = sketch.words.clone();
+
+        let mut batch = sketch.begin_batch();
+        batch.toggle(&TestItem::new());
+        batch.rollback();
+        assert_eq!(sketch.words, words);
+
+        // A failing ingest drops the batch on the error path.
+        let ingest = |sketch: &mut BinaryCountSketch| -> Result<(), BinaryCountSketchError> {
+            let mut batch = sketch.begin_batch();
+            batch.toggle(&TestItem::new());
+            batch.toggle(&TestItem::new());
+            Err(BinaryCountSketchError::new("Source of truth rejected the batch"))?;
+            batch.commit();
+            Ok(())
+        };
+        assert!(ingest(&mut sketch).is_err());
+        assert_eq!(sketch.words, words);
+    }
+
+    #[test]
+    fn test_journaled_checkpoints() {
+        let items: Vec<TestItem> = (0..30).map(TestItem::from_seed).collect();
+        let mut journaled = JournaledSketch::new(BinaryCountSketch::new(10, 2, 3));
+        for item in &items[..10] {
+            journaled.toggle(item);
+        }
+        let first = journaled.checkpoint();
+        let at_first = journaled.sketch().clone();
+        for item in &items[10..20] {
+            journaled.toggle(item);
+        }
+        let second = journaled.checkpoint();
+        for item in &items[20..] {
+            journaled.toggle(item);
+        }
+        assert_eq!(journaled.journal_len(), 90);
+
+        journaled.rollback_to(first).expect("No errors");
+        assert_eq!(journaled.sketch(), &at_first);
+        assert_eq!(journaled.rollback_to(second).expect_err("Error").kind(), ErrorKind::InvalidArgument);
+        for item in &items[10..] {
+            journaled.toggle(item);
+        }
+        assert!(journaled.rollback_to(second).is_err());
+        journaled.rollback_to(first).expect("No errors");
+
+        journaled.commit();
+        assert_eq!(journaled.journal_len(), 0);
+        assert!(journaled.rollback_to(first).is_err());
+        journaled.toggle(&items[0]);
+        journaled.rollback();
+        assert_eq!(journaled.into_sketch(), at_first);
+    }
+}
@@ -0,0 +1,153 @@
+use std::collections::HashMap;
+
+use crate::{code_index, BinaryCountSketch, Item};
+
+/// Bound on the log-likelihood ratios of the messages, so certain beliefs stay finite.
+const MAX_LLR: f64 = 30.0;
+
+/// Outcome of `decode_bp`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BpReport<V> {
+    /// Candidates believed to be in the diff.
+    pub decoded: Vec<V>,
+    /// Final probability of each candidate being in the diff, in candidate order.
+    pub likelihoods: Vec<f64>,
+    pub iterations: usize,
+    /// Whether the decoded candidates explain the parity of every bit they touch.
+    pub converged: bool,
+}
+
+impl BinaryCountSketch {
+    /// Decodes this diffed sketch by belief propagation over the bipartite graph of
+    /// candidates and the bits they touch: each bit tells every candidate on it how
+    /// likely it is to be in the diff given the beliefs about the other candidates on the
+    /// bit and its parity, and each candidate combines what its bits say with the prior
+    /// from the estimated size of the difference. Runs at most `iterations` rounds and
+    /// stops early once the decoded candidates explain every touched bit.
+    ///
+    /// Unlike peeling, a bit shared by several candidates still contributes evidence, so
+    /// more of the difference is recovered near capacity. Items of the difference that
+    /// are not among the candidates act as noise on the bits they touch.
+    pub fn decode_bp<V: Item + Clone>(&self, candidates: &[V], iterations: usize) -> BpReport<V> {
+        let l = self.bits();
+
+        // Cells are the bits touched by the candidates, and edges link a candidate to
+        // each bit its points hit an odd number of times.
+        let mut cell_ids = HashMap::new();
+        let mut cell_bits = Vec::new();
+        let mut item_edges = Vec::with_capacity(candidates.len());
+        let mut edge_cell = Vec::new();
+        for v in candidates {
+            let mut bits: Vec<usize> = (0..self.points_of(v)).map(|i| code_index(v, i, l)).collect();
+            bits.sort_unstable();
+            let start = edge_cell.len();
+            for same in bits.chunk_by(|a, b| a == b).filter(|same| same.len() % 2 == 1) {
+                let cell = *cell_ids.entry(same[0]).or_insert_with(|| {
+                    cell_bits.push(same[0]);
+                    cell_bits.len() - 1
+                });
+                edge_cell.push(cell);
+            }
+            item_edges.push(start..edge_cell.len());
+        }
+        let mut cell_edges = vec![Vec::new(); cell_bits.len()];
+        for (e, cell) in edge_cell.iter().enumerate() {
+            cell_edges[*cell].push(e);
+        }
+        let parity: Vec<bool> = cell_bits.iter().map(|b| self.words[b / 64] & (1 << (b % 64)) != 0).collect();
+
+        let difference = self.estimate_diff_size().min(candidates.len() as f64).max(1.0);
+        let prior = (difference / candidates.len().max(1) as f64).clamp(1e-9, 1.0 - 1e-9);
+        // Log-likelihood ratio of an item being out of the diff rather than in it.
+        let prior_llr = ((1.0 - prior) / prior).ln();
+
+        let mut to_cell = vec![prior_llr; edge_cell.len()];
+        let mut to_item = vec![0.0; edge_cell.len()];
+        let mut beliefs = vec![prior_llr; candidates.len()];
+        let mut rounds = 0;
+        let mut converged = false;
+
+        while rounds < iterations {
+            rounds += 1;
+
+            // A bit of parity `s` tells each of its candidates the odds that the others
+            // leave it at `s`, by the tanh rule.
+            for (cell, edges) in cell_edges.iter().enumerate() {
+                let sign = if parity[cell] { -1.0 } else { 1.0 };
+                for e in edges {
+                    let product: f64 = edges.iter().filter(|f| *f != e).map(|f| (to_cell[*f] / 2.0).tanh()).product();
+                    let product = product.clamp(-1.0 + 1e-12, 1.0 - 1e-12);
+                    to_item[*e] = (sign * 2.0 * product.atanh()).clamp(-MAX_LLR, MAX_LLR);
+                }
+            }
+
+            for (i, edges) in item_edges.iter().enumerate() {
+                let total: f64 = prior_llr + edges.clone().map(|e| to_item[e]).sum::<f64>();
+                beliefs[i] = total;
+                for e in edges.clone() {
+                    to_cell[e] = (total - to_item[e]).clamp(-MAX_LLR, MAX_LLR);
+                }
+            }
+
+            let mut decided = vec![false; cell_bits.len()];
+            for (i, edges) in item_edges.iter().enumerate() {
+                if beliefs[i] < 0.0 {
+                    for e in edges.clone() {
+                        decided[edge_cell[e]] ^= true;
+                    }
+                }
+            }
+            if decided == parity {
+                converged = true;
+                break;
+            }
+        }
+
+        BpReport {
+            decoded: candidates.iter().zip(&beliefs).filter(|(_, b)| **b < 0.0).map(|(v, _)| v.clone()).collect(),
+            likelihoods: beliefs.iter().map(|b| 1.0 / (1.0 + b.exp())).collect(),
+            iterations: rounds,
+            converged,
+        }
+    }
+}
+
+#[cfg(all(test, feature = "rand"))]
+mod tests {
+    use super::*;
+    use crate::{PeelingDecoder, TestItem};
+
+    #[test]
+    fn test_decode_bp() {
+        let candidates: Vec<TestItem> = (0..2000).map(TestItem::from_seed).collect();
+        let mut sketch = BinaryCountSketch::new(10, 2, 5);
+        sketch.toggle_all(&candidates[..10]);
+
+        let report = sketch.decode_bp(&candidates, 20);
+        assert!(report.converged);
+        assert_eq!(report.decoded, candidates[..10].to_vec());
+        assert_eq!(report.likelihoods.len(), candidates.len());
+        assert!(report.likelihoods[0] > 0.99 && report.likelihoods[10] < 0.01);
+        assert!(BinaryCountSketch::new(10, 2, 5).decode_bp(&candidates, 20).decoded.is_empty());
+    }
+
+    #[test]
+    fn test_decode_bp_near_capacity() {
+        let candidates: Vec<TestItem> = (0..2000).map(TestItem::from_seed).collect();
+        let mut sketch = BinaryCountSketch::new(10, 2, 5);
+        sketch.toggle_all(&candidates[..200]);
+
+        let report = sketch.decode_bp(&candidates, 50);
+        let found = report.decoded.iter().filter(|v| candidates[..200].contains(v)).count();
+        let wrong = report.decoded.len() - found;
+
+        let peeled = PeelingDecoder::new(4).decode(&mut sketch.clone(), &candidates).expect("No errors");
+        let peeled_found = peeled.decoded.iter().filter(|v| candidates[..200].contains(v)).count();
+
+        // Peeling stalls once no candidate clears the threshold, belief propagation
+        // keeps using the bits shared by several candidates.
+        assert!(report.converged);
+        assert_eq!((found, wrong), (200, 0));
+        assert!(peeled_found < 200);
+    }
+}
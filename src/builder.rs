@@ -0,0 +1,104 @@
+use std::thread;
+
+use crate::{code_index, BinaryCountSketch, Item, SketchParams};
+
+/// Builds a sketch from a stream of items, e.g. tens of millions of rows from a cursor.
+/// Items are buffered in batches of `batch_size`; the codes of a batch are computed on
+/// `threads` scoped threads and then applied to the sketch.
+pub struct SketchBuilder<V> {
+    sketch: BinaryCountSketch,
+    batch: Vec<V>,
+    batch_size: usize,
+    threads: usize,
+}
+
+impl<V: Item + Sync> SketchBuilder<V> {
+    pub fn new(params: SketchParams) -> Self {
+        SketchBuilder {
+            sketch: BinaryCountSketch::from_params(params),
+            batch: Vec::new(),
+            batch_size: 4096,
+            threads: 1,
+        }
+    }
+
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    pub fn with_threads(mut self, threads: usize) -> Self {
+        self.threads = threads.max(1);
+        self
+    }
+
+    pub fn push(&mut self, v: V) {
+        self.batch.push(v);
+        if self.batch.len() >= self.batch_size {
+            self.flush();
+        }
+    }
+
+    fn flush(&mut self) {
+        let sketch = &self.sketch;
+        let l = sketch.bits();
+        let bits_of = |items: &[V]| -> Vec<usize> { items.iter().flat_map(|v| (0..sketch.points_of(v)).map(move |i| code_index(v, i, l))).collect() };
+
+        let bits = if self.threads == 1 {
+            bits_of(&self.batch)
+        } else {
+            let per_thread = self.batch.len().div_ceil(self.threads).max(1);
+            thread::scope(|s| {
+                let handles: Vec<_> = self.batch.chunks(per_thread).map(|chunk| s.spawn(move || bits_of(chunk))).collect();
+                handles.into_iter().flat_map(|h| h.join().unwrap()).collect()
+            })
+        };
+
+        self.sketch.toggle_bits(&bits);
+        self.batch.clear();
+    }
+
+    pub fn build(mut self) -> BinaryCountSketch {
+        self.flush();
+        self.sketch
+    }
+}
+
+impl<V: Item + Sync> Extend<V> for SketchBuilder<V> {
+    fn extend<I: IntoIterator<Item = V>>(&mut self, iter: I) {
+        for v in iter {
+            self.push(v);
+        }
+    }
+}
+
+/// Toggles every item in turn. `FromIterator` is not provided since a sketch's
+/// parameters cannot be inferred from its items; use `SketchBuilder` instead.
+impl<V: Item> Extend<V> for BinaryCountSketch {
+    fn extend<I: IntoIterator<Item = V>>(&mut self, iter: I) {
+        for v in iter {
+            self.toggle(&v);
+        }
+    }
+}
+
+#[cfg(all(test, feature = "rand"))]
+mod tests {
+    use super::*;
+    use crate::TestItem;
+
+    #[test]
+    fn test_builder_matches_toggle() {
+        let items: Vec<TestItem> = (0..1000).map(|_| TestItem::new()).collect();
+        let params = SketchParams::new(10, 2, 5);
+
+        let mut expected = BinaryCountSketch::from_params(params);
+        expected.extend(items.iter());
+
+        for threads in [1, 3] {
+            let mut builder = SketchBuilder::new(params).with_batch_size(64).with_threads(threads);
+            builder.extend(items.iter().cloned());
+            assert_eq!(builder.build().words, expected.words);
+        }
+    }
+}
@@ -0,0 +1,246 @@
+use alloc::vec::Vec;
+
+use crate::{code_index, BinaryCountSketch, BinaryCountSketchError, ComposedSketch, ErrorKind, Item, SketchParams};
+
+/// The same set sketched at several levels at once, for receivers with different
+/// bandwidth budgets. Every toggle updates each level, so no folding is needed when a
+/// subset of the levels is sent.
+pub struct SketchBundle {
+    sketches: Vec<BinaryCountSketch>,
+}
+
+impl SketchBundle {
+    pub fn new(base_length: u64, levels: &[u64], points: u64) -> Result<Self, BinaryCountSketchError> {
+        if levels.is_empty() || levels.windows(2).any(|w| w[0] >= w[1]) { return Err(BinaryCountSketchError::with_kind(ErrorKind::InvalidArgument, "Incorrect levels")); }
+
+        Ok(SketchBundle {
+            sketches: levels.iter().map(|level| BinaryCountSketch::new(base_length, *level, points)).collect(),
+        })
+    }
+
+    pub fn levels(&self) -> Vec<u64> {
+        self.sketches.iter().map(|s| s.level).collect()
+    }
+
+    pub fn toggle<V: Item>(&mut self, v: &V) {
+        for sketch in &mut self.sketches {
+            sketch.toggle(v);
+        }
+    }
+
+    /// Adds a level to the bundle, rebuilt from `items` so that it starts in sync with
+    /// the others. `items` must be exactly the items toggled so far.
+    pub fn add_level<V: Item>(&mut self, level: u64, items: &[V]) -> Result<(), BinaryCountSketchError> {
+        let i = match self.sketches.binary_search_by_key(&level, |s| s.level) {
+            Ok(_) => return Err(BinaryCountSketchError::with_kind(ErrorKind::InvalidArgument, "Incorrect level")),
+            Err(i) => i,
+        };
+        let finest = self.sketches.last().expect("At least one level");
+        let sketch = finest.rebuild_at_level(items, level)?;
+        self.sketches.insert(i, sketch);
+        Ok(())
+    }
+
+    pub fn level(&self, level: u64) -> Option<&BinaryCountSketch> {
+        self.sketches.iter().find(|s| s.level == level)
+    }
+
+    /// Composes the requested levels into a single message.
+    pub fn select(&self, levels: &[u64]) -> Result<ComposedSketch, BinaryCountSketchError> {
+        let parts = levels
+            .iter()
+            .map(|level| self.level(*level).ok_or_else(|| BinaryCountSketchError::with_kind(ErrorKind::InvalidArgument, "Incorrect level")))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(BinaryCountSketch::concat(&parts))
+    }
+
+    /// Encodes the requested levels as one message, the `ComposedSketch::to_bytes` of
+    /// `select`, so a receiver gets the levels its bandwidth allows in a single frame.
+    pub fn select_bytes(&self, levels: &[u64]) -> Result<Vec<u8>, BinaryCountSketchError> {
+        Ok(self.select(levels)?.to_bytes())
+    }
+
+    /// Rebuilds a bundle from a message produced by `select_bytes`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, BinaryCountSketchError> {
+        SketchBundle::from_composed(&ComposedSketch::from_bytes(bytes)?)
+    }
+
+    /// Rebuilds a bundle from a message produced by `select`.
+    pub fn from_composed(composed: &ComposedSketch) -> Result<Self, BinaryCountSketchError> {
+        let sketches = composed.split()?;
+        let first = sketches.first().ok_or_else(|| BinaryCountSketchError::with_kind(ErrorKind::Parse, "Incorrect levels"))?;
+        if sketches.iter().any(|s| s.base_length != first.base_length || s.points != first.points || s.seed != first.seed) { return Err(BinaryCountSketchError::with_kind(ErrorKind::Parse, "Incorrect parameters")); }
+        if sketches.windows(2).any(|w| w[0].level >= w[1].level) { return Err(BinaryCountSketchError::with_kind(ErrorKind::Parse, "Incorrect levels")); }
+
+        Ok(SketchBundle { sketches })
+    }
+}
+
+/// Sketch maintained at every level from 0 up to the level of its parameters, so a
+/// sender can pick how many bits to send after seeing how large the difference is,
+/// while keeping the finest level to decode with later.
+///
+/// The bit of a point at a level is its bit at the top level folded as by `level_down`,
+/// so each point is hashed once per toggle and every level equals the top level folded
+/// down to it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MultiLevelSketch {
+    levels: Vec<BinaryCountSketch>,
+}
+
+impl MultiLevelSketch {
+    pub fn new(base_length: u64, max_level: u64, points: u64) -> Self {
+        MultiLevelSketch::from_params(SketchParams::new(base_length, max_level, points))
+    }
+
+    /// Sketch with levels 0 to `params.level`.
+    pub fn from_params(params: SketchParams) -> Self {
+        MultiLevelSketch {
+            levels: (0..=params.level).map(|level| BinaryCountSketch::from_params(SketchParams { level, ..params })).collect(),
+        }
+    }
+
+    pub fn max_level(&self) -> u64 {
+        self.levels.len() as u64 - 1
+    }
+
+    pub fn toggle<V: Item>(&mut self, v: &V) {
+        let top = self.levels.last().expect("At least one level");
+        let (l, points) = (top.bits(), top.points_of(v));
+        for i in 0..points {
+            let b = code_index(v, i, l);
+            for sketch in &mut self.levels {
+                let b = b % (sketch.words.len() * 64);
+                sketch.words[b / 64] ^= 1 << (b % 64);
+            }
+        }
+    }
+
+    pub fn toggle_all<V: Item>(&mut self, items: &[V]) {
+        for v in items {
+            self.toggle(v);
+        }
+    }
+
+    pub fn level(&self, level: u64) -> Option<&BinaryCountSketch> {
+        self.levels.get(level as usize)
+    }
+
+    pub fn levels(&self) -> &[BinaryCountSketch] {
+        &self.levels
+    }
+
+    /// Composes the requested levels into a single message, as `SketchBundle::select`.
+    pub fn select(&self, levels: &[u64]) -> Result<ComposedSketch, BinaryCountSketchError> {
+        let parts = levels
+            .iter()
+            .map(|level| self.level(*level).ok_or_else(|| BinaryCountSketchError::with_kind(ErrorKind::InvalidArgument, "Incorrect level")))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(BinaryCountSketch::concat(&parts))
+    }
+
+    /// Diffs every level with the same level of `other`.
+    pub fn diff_with(&mut self, other: &MultiLevelSketch) -> Result<(), BinaryCountSketchError> {
+        if self.levels.len() != other.levels.len() { return Err(BinaryCountSketchError::with_mismatch("level", self.max_level(), other.max_level())); }
+        for (sketch, other) in self.levels.iter_mut().zip(&other.levels) {
+            sketch.diff_with(other)?;
+        }
+        Ok(())
+    }
+
+    pub fn into_level(mut self, level: u64) -> Option<BinaryCountSketch> {
+        if level > self.max_level() {
+            return None;
+        }
+        Some(self.levels.swap_remove(level as usize))
+    }
+}
+
+#[cfg(all(test, feature = "rand"))]
+mod tests {
+    use super::*;
+    use crate::TestItem;
+
+    #[test]
+    fn test_bundle_levels() {
+        assert!(SketchBundle::new(10, &[], 3).is_err());
+        assert!(SketchBundle::new(10, &[2, 1], 3).is_err());
+
+        let mut bundle = SketchBundle::new(10, &[0, 2, 4], 3).expect("No errors");
+        let mut single = BinaryCountSketch::new(10, 4, 3);
+        for _ in 0..100 {
+            let item = TestItem::new();
+            bundle.toggle(&item);
+            single.toggle(&item);
+        }
+
+        // Maintained incrementally, each level equals the folded finest sketch.
+        for level in [0, 2] {
+            assert_eq!(bundle.level(level).expect("Present").words, single.level_down(level).expect("No errors").words);
+        }
+        assert!(bundle.level(1).is_none());
+
+        let message = bundle.select(&[0, 4]).expect("No errors");
+        assert!(bundle.select(&[3]).is_err());
+        let received = SketchBundle::from_composed(&message).expect("No errors");
+        assert_eq!(received.levels(), vec![0, 4]);
+        assert_eq!(received.level(4).expect("Present").words, single.words);
+
+        assert!(SketchBundle::from_composed(&bundle.select(&[2, 0]).expect("No errors")).is_err());
+
+        let bytes = bundle.select_bytes(&[0, 2]).expect("No errors");
+        assert_eq!(bytes, bundle.select(&[0, 2]).expect("No errors").to_bytes());
+        let received = SketchBundle::from_bytes(&bytes).expect("No errors");
+        assert_eq!(received.levels(), vec![0, 2]);
+        assert_eq!(received.level(2).expect("Present").words, single.level_down(2).expect("No errors").words);
+        assert!(bundle.select_bytes(&[3]).is_err());
+        assert!(SketchBundle::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn test_bundle_add_level() {
+        let items: Vec<TestItem> = (0..100).map(TestItem::from_seed).collect();
+        let mut bundle = SketchBundle::new(10, &[0, 2], 3).expect("No errors");
+        for item in &items {
+            bundle.toggle(item);
+        }
+
+        bundle.add_level(5, &items).expect("No errors");
+        bundle.add_level(1, &items).expect("No errors");
+        assert!(bundle.add_level(2, &items).is_err());
+        assert!(bundle.add_level(6, &items[1..]).is_err());
+        assert_eq!(bundle.levels(), vec![0, 1, 2, 5]);
+
+        // New levels stay in sync with the others as items are toggled.
+        let item = TestItem::from_seed(1000);
+        bundle.toggle(&item);
+        let finest = bundle.level(5).expect("Present");
+        for level in [0, 1, 2] {
+            assert_eq!(bundle.level(level).expect("Present").words, finest.level_down(level).expect("No errors").words);
+        }
+    }
+
+    #[test]
+    fn test_multi_level() {
+        let items: Vec<TestItem> = (0..200).map(TestItem::from_seed).collect();
+        let mut multi = MultiLevelSketch::new(10, 3, 5);
+        multi.toggle_all(&items);
+        assert_eq!(multi.max_level(), 3);
+
+        let mut single = BinaryCountSketch::new(10, 3, 5);
+        single.toggle_all(&items);
+        assert_eq!(*multi.level(3).expect("Present"), single);
+        for level in 0..3 {
+            assert_eq!(*multi.level(level).expect("Present"), single.level_down(level).expect("No errors"));
+        }
+        assert!(multi.level(4).is_none());
+        assert!(multi.select(&[4]).is_err());
+
+        let mut other = MultiLevelSketch::new(10, 3, 5);
+        other.toggle_all(&items[1..]);
+        multi.diff_with(&other).expect("No errors");
+        assert!(multi.diff_with(&MultiLevelSketch::new(10, 2, 5)).is_err());
+        let coarsest = multi.into_level(0).expect("Present");
+        assert_eq!(coarsest.check(&items[0]), 5);
+    }
+}
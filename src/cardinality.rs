@@ -0,0 +1,165 @@
+use crate::{route_key, splitmix64, BinaryCountSketch, BinaryCountSketchError, ErrorKind, Item, SketchParams};
+
+/// Mixed into the routing key of an item, so the register an item lands in is
+/// independent of its shard or partition.
+const HLL_KEY: u64 = 0x484c_4c00;
+
+/// HyperLogLog estimator of the number of distinct items inserted, hashed from the same
+/// item codes as the sketches, so peers can compare set sizes with a few hundred bytes
+/// before exchanging sketches. Unlike a sketch, an item cannot be removed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HyperLogLog {
+    precision: u32,
+    registers: Vec<u8>,
+}
+
+impl HyperLogLog {
+    /// Estimator with `2^precision` registers, for a relative error of about
+    /// `1.04 / 2^(precision / 2)`. `precision` must be between 4 and 16.
+    pub fn new(precision: u32) -> Result<Self, BinaryCountSketchError> {
+        if !(4..=16).contains(&precision) { return Err(BinaryCountSketchError::with_kind(ErrorKind::InvalidArgument, "Incorrect precision")); }
+        Ok(HyperLogLog { precision, registers: vec![0; 1 << precision] })
+    }
+
+    pub fn precision(&self) -> u32 {
+        self.precision
+    }
+
+    pub fn registers(&self) -> &[u8] {
+        &self.registers
+    }
+
+    pub fn insert<V: Item>(&mut self, v: &V) {
+        let h = splitmix64(route_key(v) ^ HLL_KEY);
+        let i = (h >> (64 - self.precision)) as usize;
+        // Rank of the first set bit of the remaining bits, counting from 1.
+        let rank = ((h << self.precision) | (1 << (self.precision - 1))).leading_zeros() as u8 + 1;
+        self.registers[i] = self.registers[i].max(rank);
+    }
+
+    /// Estimated number of distinct items inserted.
+    pub fn estimate(&self) -> f64 {
+        let m = self.registers.len() as f64;
+        let alpha = match self.registers.len() {
+            16 => 0.673,
+            32 => 0.697,
+            64 => 0.709,
+            _ => 0.7213 / (1.0 + 1.079 / m),
+        };
+        let sum: f64 = self.registers.iter().map(|r| 1.0 / (1u64 << r) as f64).sum();
+        let raw = alpha * m * m / sum;
+
+        // Small cardinalities leave registers empty, and linear counting is more accurate.
+        let zeros = self.registers.iter().filter(|r| **r == 0).count();
+        if raw <= 2.5 * m && zeros > 0 {
+            m * (m / zeros as f64).ln()
+        } else {
+            raw
+        }
+    }
+
+    /// Merges `other` in, so this estimates the size of the union of both sets.
+    pub fn merge_with(&mut self, other: &Self) -> Result<(), BinaryCountSketchError> {
+        if self.precision != other.precision { return Err(BinaryCountSketchError::with_mismatch("precision", self.precision as u64, other.precision as u64)); }
+        for (r, o) in self.registers.iter_mut().zip(&other.registers) {
+            *r = (*r).max(*o);
+        }
+        Ok(())
+    }
+
+    /// Estimated Jaccard similarity of both sets, from the sizes of each set and of their
+    /// union.
+    pub fn jaccard(&self, other: &Self) -> Result<f64, BinaryCountSketchError> {
+        let mut union = self.clone();
+        union.merge_with(other)?;
+        let union_len = union.estimate();
+        if union_len == 0.0 {
+            return Ok(1.0);
+        }
+        let intersection = (self.estimate() + other.estimate() - union_len).max(0.0);
+        Ok((intersection / union_len).min(1.0))
+    }
+}
+
+/// Digest of a set that a peer sends first: its estimated size, to compare against the
+/// local set and size the exchange, and its sketch.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SetDigest {
+    pub cardinality: HyperLogLog,
+    pub sketch: BinaryCountSketch,
+}
+
+impl SetDigest {
+    pub fn new(params: SketchParams, precision: u32) -> Result<Self, BinaryCountSketchError> {
+        Ok(SetDigest { cardinality: HyperLogLog::new(precision)?, sketch: BinaryCountSketch::from_params(params) })
+    }
+
+    pub fn from_items<V: Item>(params: SketchParams, precision: u32, items: &[V]) -> Result<Self, BinaryCountSketchError> {
+        let mut digest = SetDigest::new(params, precision)?;
+        for v in items {
+            digest.insert(v);
+        }
+        Ok(digest)
+    }
+
+    /// Adds an item of the set. Each item must be inserted once: the sketch would cancel
+    /// a second toggle, and the estimator cannot remove an item.
+    pub fn insert<V: Item>(&mut self, v: &V) {
+        self.cardinality.insert(v);
+        self.sketch.toggle(v);
+    }
+
+    /// Estimated size of the symmetric difference with the peer's set, from the sizes of
+    /// both sets and of their union: `2 |A ∪ B| - |A| - |B|`.
+    pub fn estimate_difference(&self, other: &SetDigest) -> Result<f64, BinaryCountSketchError> {
+        let mut union = self.cardinality.clone();
+        union.merge_with(&other.cardinality)?;
+        Ok((2.0 * union.estimate() - self.cardinality.estimate() - other.cardinality.estimate()).max(0.0))
+    }
+}
+
+#[cfg(all(test, feature = "rand"))]
+mod tests {
+    use super::*;
+    use crate::TestItem;
+
+    #[test]
+    fn test_hyperloglog_estimate() {
+        assert!(HyperLogLog::new(3).is_err());
+        let items: Vec<TestItem> = (0..20000).map(TestItem::from_seed).collect();
+
+        let mut small = HyperLogLog::new(12).expect("No errors");
+        for item in &items[..100] {
+            small.insert(item);
+            small.insert(item);
+        }
+        assert!((small.estimate() - 100.0).abs() < 5.0);
+
+        let (mut a, mut b) = (HyperLogLog::new(12).expect("No errors"), HyperLogLog::new(12).expect("No errors"));
+        for item in &items[..15000] {
+            a.insert(item);
+        }
+        for item in &items[5000..] {
+            b.insert(item);
+        }
+        assert!((a.estimate() - 15000.0).abs() < 750.0);
+        // The sets share 10000 of 20000 items.
+        assert!((a.jaccard(&b).expect("No errors") - 0.5).abs() < 0.05);
+        assert!(a.merge_with(&HyperLogLog::new(10).expect("No errors")).is_err());
+        a.merge_with(&b).expect("No errors");
+        assert!((a.estimate() - 20000.0).abs() < 1000.0);
+    }
+
+    #[test]
+    fn test_set_digest() {
+        let items: Vec<TestItem> = (0..5100).map(TestItem::from_seed).collect();
+        let params = SketchParams::new(100, 2, 5);
+        let local = SetDigest::from_items(params, 12, &items[..5000]).expect("No errors");
+        let remote = SetDigest::from_items(params, 12, &items[100..]).expect("No errors");
+
+        let estimate = local.estimate_difference(&remote).expect("No errors");
+        assert!((estimate - 200.0).abs() < 150.0);
+        let diff = local.sketch.diff(&remote.sketch).expect("No errors");
+        assert_eq!(diff.check(&items[0]), 5);
+    }
+}
@@ -0,0 +1,128 @@
+use std::io;
+
+use bytes::{Buf, BufMut, BytesMut};
+use tokio_util::codec::{Decoder, Encoder};
+
+use crate::wire::HEADER_LEN;
+use crate::BinaryCountSketch;
+
+/// Length-prefixed framing of sketches for `tokio_util::codec::Framed`. Each frame is a
+/// little-endian `u32` payload length followed by the sketch's `to_bytes` encoding.
+#[derive(Clone, Copy, Debug)]
+pub struct SketchCodec {
+    max_frame_len: usize,
+}
+
+impl SketchCodec {
+    pub fn new() -> Self {
+        SketchCodec { max_frame_len: 64 << 20 }
+    }
+
+    /// Frames longer than `max_frame_len` bytes are rejected by both the encoder and the
+    /// decoder, so a peer cannot make us buffer arbitrarily large frames.
+    pub fn with_max_frame_len(max_frame_len: usize) -> Self {
+        SketchCodec { max_frame_len }
+    }
+}
+
+impl Default for SketchCodec {
+    fn default() -> Self {
+        SketchCodec::new()
+    }
+}
+
+fn invalid(details: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, details)
+}
+
+impl Encoder<&BinaryCountSketch> for SketchCodec {
+    type Error = io::Error;
+
+    fn encode(&mut self, sketch: &BinaryCountSketch, dst: &mut BytesMut) -> Result<(), io::Error> {
+        let len = HEADER_LEN + sketch.words.len() * 8;
+        if len > self.max_frame_len || len > u32::MAX as usize { return Err(invalid("Frame too long")); }
+
+        dst.reserve(4 + len);
+        dst.put_u32_le(len as u32);
+        dst.put_slice(&sketch.to_bytes());
+        Ok(())
+    }
+}
+
+impl Encoder<BinaryCountSketch> for SketchCodec {
+    type Error = io::Error;
+
+    fn encode(&mut self, sketch: BinaryCountSketch, dst: &mut BytesMut) -> Result<(), io::Error> {
+        self.encode(&sketch, dst)
+    }
+}
+
+impl Decoder for SketchCodec {
+    type Item = BinaryCountSketch;
+    type Error = io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<BinaryCountSketch>, io::Error> {
+        if src.len() < 4 {
+            return Ok(None);
+        }
+        let len = u32::from_le_bytes(src[..4].try_into().unwrap()) as usize;
+        if len > self.max_frame_len { return Err(invalid("Frame too long")); }
+        if src.len() < 4 + len {
+            src.reserve(4 + len - src.len());
+            return Ok(None);
+        }
+
+        src.advance(4);
+        let frame = src.split_to(len);
+        BinaryCountSketch::from_bytes(&frame).map(Some).map_err(|e| invalid(&e.to_string()))
+    }
+}
+
+#[cfg(all(test, feature = "rand"))]
+mod tests {
+    use super::*;
+    use crate::{SketchParams, TestItem};
+
+    #[test]
+    fn test_codec_roundtrip() {
+        let mut sketch = BinaryCountSketch::new(10, 2, 3);
+        sketch.toggle(&TestItem::new());
+
+        let mut codec = SketchCodec::new();
+        let mut buf = BytesMut::new();
+        codec.encode(&sketch, &mut buf).expect("No errors");
+        codec.encode(BinaryCountSketch::new(1, 0, 2), &mut buf).expect("No errors");
+        assert_eq!(buf.len(), 2 * (4 + HEADER_LEN) + 41 * 8);
+
+        // Partial frames are buffered until complete.
+        let mut partial = buf.split_to(100);
+        assert!(codec.decode(&mut partial).expect("No errors").is_none());
+        partial.unsplit(buf);
+
+        let first = codec.decode(&mut partial).expect("No errors").expect("Complete frame");
+        assert_eq!(first.params(), sketch.params());
+        assert_eq!(first.words(), sketch.words());
+        let second = codec.decode(&mut partial).expect("No errors").expect("Complete frame");
+        assert_eq!(second.params(), SketchParams::new(1, 0, 2));
+        assert!(partial.is_empty());
+    }
+
+    #[test]
+    fn test_codec_rejects_bad_frames() {
+        let sketch = BinaryCountSketch::new(10, 2, 3);
+        let mut buf = BytesMut::new();
+        assert!(SketchCodec::with_max_frame_len(100).encode(&sketch, &mut buf).is_err());
+
+        SketchCodec::new().encode(&sketch, &mut buf).expect("No errors");
+        assert!(SketchCodec::with_max_frame_len(100).decode(&mut buf.clone()).is_err());
+
+        let mut corrupt = buf.clone();
+        corrupt[4] = b'X';
+        assert!(SketchCodec::new().decode(&mut corrupt).is_err());
+
+        // Parameters that disagree with the number of words.
+        let mut mismatched = buf.clone();
+        mismatched[9] = 11;
+        assert!(SketchCodec::new().decode(&mut mismatched).is_err());
+    }
+}
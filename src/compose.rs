@@ -0,0 +1,192 @@
+use alloc::vec::Vec;
+use core::borrow::Borrow;
+
+use crate::{BinaryCountSketch, BinaryCountSketchError, ErrorKind, SketchParams};
+
+const MAGIC: &[u8; 4] = b"BCSC";
+const VERSION: u8 = 1;
+
+/// Length of the header: magic, version and the number of sketches.
+const HEADER_LEN: usize = 4 + 1 + 4;
+
+/// Length of a directory entry: the three parameters and the seed.
+const ENTRY_LEN: usize = 3 * 8 + 16;
+
+fn parse_error(details: &str) -> BinaryCountSketchError {
+    BinaryCountSketchError::with_kind(ErrorKind::Parse, details)
+}
+
+/// Location and parameters of one sketch inside a `ComposedSketch`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DirectoryEntry {
+    pub base_length: u64,
+    pub level: u64,
+    pub points: u64,
+    pub seed: [u8; 16],
+    pub offset: usize,
+}
+
+/// Several sketches packed into one word array, with a directory describing each of
+/// them, so per-partition sketches can travel as a single structure.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ComposedSketch {
+    directory: Vec<DirectoryEntry>,
+    words: Vec<u64>,
+}
+
+impl ComposedSketch {
+    pub fn directory(&self) -> &[DirectoryEntry] {
+        &self.directory
+    }
+
+    pub fn words(&self) -> &[u64] {
+        &self.words
+    }
+
+    pub fn len(&self) -> usize {
+        self.directory.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.directory.is_empty()
+    }
+
+    pub fn part(&self, i: usize) -> Result<BinaryCountSketch, BinaryCountSketchError> {
+        let entry = self.directory.get(i).ok_or_else(|| BinaryCountSketchError::with_kind(ErrorKind::InvalidArgument, "Incorrect partition"))?;
+        let len = (entry.base_length << entry.level) as usize;
+        if entry.offset + len > self.words.len() { return Err(BinaryCountSketchError::with_kind(ErrorKind::Parse, "Incorrect words length")); }
+
+        Ok(BinaryCountSketch {
+            base_length: entry.base_length,
+            level: entry.level,
+            points: entry.points,
+            seed: entry.seed,
+            words: self.words[entry.offset..entry.offset + len].to_vec(),
+        })
+    }
+
+    pub fn split(&self) -> Result<Vec<BinaryCountSketch>, BinaryCountSketchError> {
+        (0..self.directory.len()).map(|i| self.part(i)).collect()
+    }
+
+    /// Encodes the structure as the magic bytes `BCSC`, a version byte, the number of
+    /// sketches as a little-endian `u32`, the directory and the words of every sketch
+    /// as little-endian `u64`, in directory order. Each directory entry is the
+    /// `base_length`, `level` and `points` of a sketch as little-endian `u64` and its 16
+    /// byte seed; offsets follow from the lengths of the sketches before it.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(HEADER_LEN + self.directory.len() * ENTRY_LEN + self.words.len() * 8);
+        out.extend_from_slice(MAGIC);
+        out.push(VERSION);
+        out.extend_from_slice(&(self.directory.len() as u32).to_le_bytes());
+        for entry in &self.directory {
+            for v in [entry.base_length, entry.level, entry.points] {
+                out.extend_from_slice(&v.to_le_bytes());
+            }
+            out.extend_from_slice(&entry.seed);
+        }
+        for word in &self.words {
+            out.extend_from_slice(&word.to_le_bytes());
+        }
+        out
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, BinaryCountSketchError> {
+        if bytes.len() < HEADER_LEN || &bytes[..4] != MAGIC { return Err(parse_error("Incorrect magic")); }
+        if !(bytes[4] == VERSION) { return Err(parse_error("Incorrect version")); }
+        let count = u32::from_le_bytes(bytes[5..HEADER_LEN].try_into().unwrap()) as usize;
+        let words_start = match count.checked_mul(ENTRY_LEN).and_then(|len| len.checked_add(HEADER_LEN)) {
+            Some(start) if start <= bytes.len() => start,
+            _ => return Err(parse_error("Incorrect directory")),
+        };
+
+        let mut directory = Vec::with_capacity(count);
+        let mut offset = 0usize;
+        for entry in bytes[HEADER_LEN..words_start].chunks(ENTRY_LEN) {
+            let mut values = entry[..24].chunks(8).map(|c| u64::from_le_bytes(c.try_into().unwrap()));
+            let params = SketchParams::new(values.next().unwrap(), values.next().unwrap(), values.next().unwrap()).with_seed(entry[24..].try_into().unwrap());
+            let len = params.validate()?;
+            directory.push(DirectoryEntry { base_length: params.base_length, level: params.level, points: params.points, seed: params.seed, offset });
+            offset = offset.checked_add(len).ok_or_else(|| parse_error("Incorrect words length"))?;
+        }
+        if !(offset.checked_mul(8) == Some(bytes.len() - words_start)) { return Err(parse_error("Incorrect words length")); }
+
+        let words = bytes[words_start..].chunks(8).map(|c| u64::from_le_bytes(c.try_into().unwrap())).collect();
+        Ok(ComposedSketch { directory, words })
+    }
+}
+
+impl BinaryCountSketch {
+    pub fn concat<S: Borrow<BinaryCountSketch>>(parts: &[S]) -> ComposedSketch {
+        let mut directory = Vec::with_capacity(parts.len());
+        let mut words = Vec::with_capacity(parts.iter().map(|p| p.borrow().words.len()).sum());
+        for part in parts {
+            let part = part.borrow();
+            directory.push(DirectoryEntry {
+                base_length: part.base_length,
+                level: part.level,
+                points: part.points,
+                seed: part.seed,
+                offset: words.len(),
+            });
+            words.extend_from_slice(&part.words);
+        }
+        ComposedSketch { directory, words }
+    }
+}
+
+#[cfg(all(test, feature = "rand"))]
+mod tests {
+    use super::*;
+    use crate::TestItem;
+
+    #[test]
+    fn test_concat_split() {
+        let item = TestItem { points: vec![1, 70, 300, 600] };
+        let mut a = BinaryCountSketch::new(10, 2, 3);
+        let mut b = BinaryCountSketch::new(5, 1, 4);
+        a.toggle(&item);
+        b.toggle(&item);
+
+        let composed = BinaryCountSketch::concat(&[a, b]);
+        assert_eq!(composed.len(), 2);
+        assert_eq!(composed.words().len(), 50);
+        assert_eq!(composed.directory()[1], DirectoryEntry { base_length: 5, level: 1, points: 4, seed: [0; 16], offset: 40 });
+
+        let parts = composed.split().expect("No errors");
+        assert_eq!(parts[0].check(&item), 3);
+        assert_eq!(parts[1].check(&item), 4);
+        assert!(composed.part(2).is_err());
+    }
+
+    #[test]
+    fn test_composed_bytes() {
+        let item = TestItem { points: vec![1, 70, 300, 600] };
+        let mut a = BinaryCountSketch::new(10, 2, 3);
+        let mut b = BinaryCountSketch::with_seed(5, 1, 4, [9; 16]);
+        a.toggle(&item);
+        b.toggle(&item);
+
+        let composed = BinaryCountSketch::concat(&[a.clone(), b.clone()]);
+        let bytes = composed.to_bytes();
+        assert_eq!(&bytes[..9], b"BCSC\x01\x02\x00\x00\x00");
+        assert_eq!(bytes.len(), HEADER_LEN + 2 * ENTRY_LEN + 50 * 8);
+        let received = ComposedSketch::from_bytes(&bytes).expect("No errors");
+        assert_eq!(received, composed);
+        assert_eq!(received.split().expect("No errors"), vec![a, b]);
+
+        let empty = BinaryCountSketch::concat::<BinaryCountSketch>(&[]);
+        assert_eq!(ComposedSketch::from_bytes(&empty.to_bytes()).expect("No errors"), empty);
+
+        let parse = |b: &[u8]| ComposedSketch::from_bytes(b).expect_err("Error").kind();
+        assert_eq!(parse(&bytes[..5]), ErrorKind::Parse);
+        assert_eq!(parse(&bytes[..bytes.len() - 8]), ErrorKind::Parse);
+        assert_eq!(parse(&bytes[..HEADER_LEN + ENTRY_LEN]), ErrorKind::Parse);
+        let mut count = bytes.clone();
+        count[5..9].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(parse(&count), ErrorKind::Parse);
+        let mut level = bytes.clone();
+        level[HEADER_LEN + 8] = 64;
+        assert_eq!(parse(&level), ErrorKind::Parse);
+    }
+}
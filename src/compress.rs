@@ -0,0 +1,143 @@
+use alloc::vec::Vec;
+
+use crate::{BinaryCountSketch, BinaryCountSketchError, ErrorKind, SketchParams};
+
+/// Sparse encoding of a sketch: the positions of its set bits, each as the LEB128 varint
+/// of its distance from the previous one. A diff of two similar sets has few set bits,
+/// so this takes a couple of bytes per differing point instead of the full word array.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CompressedSketch {
+    params: SketchParams,
+    data: Vec<u8>,
+}
+
+fn parse_error(details: &str) -> BinaryCountSketchError {
+    BinaryCountSketchError::with_kind(ErrorKind::Parse, details)
+}
+
+fn write_varint(out: &mut Vec<u8>, mut v: u64) {
+    while v >= 0x80 {
+        out.push(v as u8 | 0x80);
+        v >>= 7;
+    }
+    out.push(v as u8);
+}
+
+fn read_varint(bytes: &[u8], pos: &mut usize) -> Result<u64, BinaryCountSketchError> {
+    let mut v = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = *bytes.get(*pos).ok_or_else(|| parse_error("Incorrect varint"))?;
+        *pos += 1;
+        v |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(v);
+        }
+    }
+    Err(parse_error("Incorrect varint"))
+}
+
+impl CompressedSketch {
+    /// Rebuilds a compressed sketch from its parameters and data, e.g. after receiving it.
+    /// The parameters are checked with `SketchParams::validate` on `decompress`.
+    pub fn from_parts(params: SketchParams, data: Vec<u8>) -> Self {
+        CompressedSketch { params, data }
+    }
+
+    pub fn params(&self) -> SketchParams {
+        self.params
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Size of the encoded bit positions in bytes.
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    pub fn decompress(&self) -> Result<BinaryCountSketch, BinaryCountSketchError> {
+        self.params.validate()?;
+
+        let mut sketch = BinaryCountSketch::from_params(self.params);
+        let l = sketch.bits() as u64;
+        let mut pos = 0;
+        let mut next = 0u64;
+        while pos < self.data.len() {
+            let b = next.checked_add(read_varint(&self.data, &mut pos)?).filter(|b| *b < l).ok_or_else(|| parse_error("Incorrect bit position"))?;
+            sketch.words[(b / 64) as usize] |= 1 << (b % 64);
+            next = b + 1;
+        }
+        Ok(sketch)
+    }
+}
+
+impl BinaryCountSketch {
+    pub fn compress(&self) -> CompressedSketch {
+        let mut data = Vec::new();
+        let mut next = 0;
+        for (i, word) in self.words.iter().enumerate() {
+            let mut w = *word;
+            while w != 0 {
+                let b = i as u64 * 64 + w.trailing_zeros() as u64;
+                write_varint(&mut data, b - next);
+                next = b + 1;
+                w &= w - 1;
+            }
+        }
+        CompressedSketch { params: self.params(), data }
+    }
+}
+
+#[cfg(all(test, feature = "rand"))]
+mod tests {
+    use super::*;
+    use crate::{TestItem, MAX_PARSED_POINTS};
+
+    #[test]
+    fn test_compress_roundtrip() {
+        let items: Vec<TestItem> = (0..1000).map(|_| TestItem::new()).collect();
+        let mut local = BinaryCountSketch::with_seed(1000, 2, 5, [9; 16]);
+        let mut remote = BinaryCountSketch::with_seed(1000, 2, 5, [9; 16]);
+        for item in &items {
+            local.toggle(item);
+            remote.toggle(item);
+        }
+        for _ in 0..10 {
+            remote.toggle(&TestItem::new());
+        }
+        local.diff_with(&remote).expect("No errors");
+
+        let compressed = local.compress();
+        assert!(compressed.len() <= 50 * 3);
+        assert_eq!(compressed.decompress().expect("No errors"), local);
+        assert!(BinaryCountSketch::new(10, 2, 3).compress().is_empty());
+
+        let received = CompressedSketch::from_parts(compressed.params(), compressed.data().to_vec());
+        assert_eq!(received.decompress().expect("No errors"), local);
+    }
+
+    #[test]
+    fn test_decompress_rejects_bad_input() {
+        let params = SketchParams::new(1, 0, 3);
+        let parse = |data: Vec<u8>| CompressedSketch::from_parts(params, data).decompress().expect_err("Error").kind();
+        assert_eq!(parse(vec![0x80]), ErrorKind::Parse);
+        assert_eq!(parse(vec![64]), ErrorKind::Parse);
+        assert_eq!(parse(vec![63, 0]), ErrorKind::Parse);
+        assert_eq!(parse(vec![0xff; 11]), ErrorKind::Parse);
+        assert_eq!(CompressedSketch::from_parts(params, vec![1, 61]).decompress().expect("No errors").words(), [2 | 1 << 63]);
+    }
+
+    #[test]
+    fn test_decompress_rejects_bad_params() {
+        let parse = |params: SketchParams| CompressedSketch::from_parts(params, vec![0]).decompress().expect_err("Error").kind();
+        assert_eq!(parse(SketchParams::new(0, 0, 3)), ErrorKind::Parse);
+        assert_eq!(parse(SketchParams::new(1, 0, MAX_PARSED_POINTS + 1)), ErrorKind::Parse);
+        assert_eq!(parse(SketchParams::new(1 << 60, 0, 3)), ErrorKind::Parse);
+        assert_eq!(parse(SketchParams::new(1, 64, 3)), ErrorKind::Parse);
+    }
+}
@@ -0,0 +1,121 @@
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use crate::hashed::item_key;
+use crate::{code_index, BinaryCountSketch, BytesItem, Item, SketchParams};
+
+/// Sketch whose words are `AtomicU64`, so several threads ingesting a stream can toggle
+/// items through a shared reference without a lock. Toggles are `fetch_xor`s, which
+/// commute, so the final words do not depend on how the threads interleave.
+///
+/// Checks and `snapshot` read the words one at a time, so while toggles are in flight
+/// they can see some points of an item toggled and not others.
+pub struct ConcurrentBinaryCountSketch {
+    params: SketchParams,
+    words: Vec<AtomicU64>,
+}
+
+impl ConcurrentBinaryCountSketch {
+    pub fn new(base_length: u64, level: u64, points: u64) -> Self {
+        ConcurrentBinaryCountSketch::from_params(SketchParams::new(base_length, level, points))
+    }
+
+    pub fn from_params(params: SketchParams) -> Self {
+        let words = (0..params.base_length << params.level).map(|_| AtomicU64::new(0)).collect();
+        ConcurrentBinaryCountSketch { params, words }
+    }
+
+    pub fn params(&self) -> SketchParams {
+        self.params
+    }
+
+    pub fn bits(&self) -> usize {
+        self.words.len() * 64
+    }
+
+    fn points_of<V: Item>(&self, v: &V) -> u64 {
+        v.points(self.params.points).min(self.params.points)
+    }
+
+    pub fn toggle<V: Item>(&self, v: &V) {
+        let l = self.bits();
+        for i in 0..self.points_of(v) {
+            let b = code_index(v, i, l);
+            self.words[b / 64].fetch_xor(1 << (b % 64), Ordering::Relaxed);
+        }
+    }
+
+    /// Same as `BinaryCountSketch::toggle_bytes`: toggles `bytes` hashed with the seed.
+    pub fn toggle_bytes(&self, bytes: &[u8]) {
+        self.toggle(&BytesItem::with_key(bytes, item_key(&self.params.seed)));
+    }
+
+    pub fn check<V: Item>(&self, v: &V) -> usize {
+        let l = self.bits();
+        (0..self.points_of(v))
+            .filter(|i| {
+                let b = code_index(v, *i, l);
+                self.words[b / 64].load(Ordering::Relaxed) & (1 << (b % 64)) != 0
+            })
+            .count()
+    }
+
+    /// Copies the words into a plain sketch, e.g. to diff or send it. Toggles that
+    /// happen-before the call are included; see the type's documentation for toggles
+    /// running concurrently.
+    pub fn snapshot(&self) -> BinaryCountSketch {
+        let words = self.words.iter().map(|w| w.load(Ordering::Relaxed)).collect();
+        BinaryCountSketch::from_parts(self.params, words).expect("Words match the parameters")
+    }
+
+    pub fn into_sketch(self) -> BinaryCountSketch {
+        let words = self.words.into_iter().map(AtomicU64::into_inner).collect();
+        BinaryCountSketch::from_parts(self.params, words).expect("Words match the parameters")
+    }
+}
+
+impl From<BinaryCountSketch> for ConcurrentBinaryCountSketch {
+    fn from(sketch: BinaryCountSketch) -> Self {
+        let params = sketch.params();
+        ConcurrentBinaryCountSketch { params, words: sketch.words.into_iter().map(AtomicU64::new).collect() }
+    }
+}
+
+#[cfg(all(test, feature = "rand"))]
+mod tests {
+    use super::*;
+    use crate::TestItem;
+
+    #[test]
+    fn test_concurrent_toggle() {
+        let items: Vec<TestItem> = (0..4000).map(TestItem::from_seed).collect();
+        let concurrent = ConcurrentBinaryCountSketch::new(10, 2, 5);
+        std::thread::scope(|s| {
+            for chunk in items.chunks(1000) {
+                let concurrent = &concurrent;
+                s.spawn(move || {
+                    for item in chunk {
+                        concurrent.toggle(item);
+                    }
+                });
+            }
+        });
+
+        let mut sequential = BinaryCountSketch::new(10, 2, 5);
+        sequential.toggle_all(&items);
+        assert_eq!(concurrent.snapshot(), sequential);
+        assert_eq!(concurrent.check(&items[0]), sequential.check(&items[0]));
+        assert_eq!(concurrent.into_sketch(), sequential);
+
+        let seeded = ConcurrentBinaryCountSketch::from_params(SketchParams::new(10, 2, 5).with_seed([3; 16]));
+        seeded.toggle_bytes(b"telemetry-1");
+        let mut expected = BinaryCountSketch::with_seed(10, 2, 5, [3; 16]);
+        expected.toggle_bytes(b"telemetry-1");
+        assert_eq!(seeded.into_sketch(), expected);
+
+        let restored = ConcurrentBinaryCountSketch::from(sequential.clone());
+        restored.toggle(&items[0]);
+        sequential.toggle(&items[0]);
+        assert_eq!(restored.into_sketch(), sequential);
+    }
+}
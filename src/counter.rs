@@ -0,0 +1,249 @@
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::{code_index, BinaryCountSketch, BinaryCountSketchError, ErrorKind, Item};
+
+/// Counting variant of `BinaryCountSketch`: every cell holds a signed counter instead of
+/// a bit, so inserting an item twice adds it twice rather than cancelling it out.
+/// Cells are laid out exactly like the bits of a binary sketch with the same parameters,
+/// so `to_binary` reduces the counters to the binary sketch of the same operations.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CounterSketch {
+    base_length: u64,
+    level: u64,
+    points: u64,
+    seed: [u8; 16],
+    counters: Vec<i32>,
+}
+
+impl CounterSketch {
+    pub fn new(base_length: u64, level: u64, points: u64) -> Self {
+        CounterSketch::with_seed(base_length, level, points, [0; 16])
+    }
+
+    pub fn with_seed(base_length: u64, level: u64, points: u64, seed: [u8; 16]) -> Self {
+        CounterSketch {
+            base_length,
+            level,
+            points,
+            seed,
+            counters: vec![0; (base_length << level) as usize * 64],
+        }
+    }
+
+    pub fn cells(&self) -> usize {
+        self.counters.len()
+    }
+
+    pub fn counters(&self) -> &[i32] {
+        &self.counters
+    }
+
+    pub fn points_of<V: Item>(&self, v: &V) -> u64 {
+        v.points(self.points).min(self.points)
+    }
+
+    fn add<V: Item>(&mut self, v: &V, delta: i32) {
+        let l = self.counters.len();
+        for i in 0..self.points_of(v) {
+            let b = code_index(v, i, l);
+            self.counters[b] = self.counters[b].wrapping_add(delta);
+        }
+    }
+
+    pub fn insert<V: Item>(&mut self, v: &V) {
+        self.add(v, 1);
+    }
+
+    pub fn remove<V: Item>(&mut self, v: &V) {
+        self.add(v, -1);
+    }
+
+    /// Number of cells of `v` with a non-zero counter, the counterpart of
+    /// `BinaryCountSketch::check`.
+    pub fn check<V: Item>(&self, v: &V) -> usize {
+        let l = self.counters.len();
+        (0..self.points_of(v)).filter(|i| self.counters[code_index(v, *i, l)] != 0).count()
+    }
+
+    /// Estimated multiplicity of `v`: the median of the counters of its cells, so a
+    /// minority of collisions does not affect it. Negative after a diff if `v` was
+    /// inserted more often in the other sketch.
+    pub fn count<V: Item>(&self, v: &V) -> i32 {
+        let l = self.counters.len();
+        let mut values: Vec<i32> = (0..self.points_of(v)).map(|i| self.counters[code_index(v, i, l)]).collect();
+        values.sort_unstable();
+        values.get(values.len() / 2).copied().unwrap_or(0)
+    }
+
+    pub fn decode<V: Item>(&self, items: &[V]) -> Vec<usize> {
+        items.iter().map(|item| self.check(item)).collect()
+    }
+
+    /// Indices and estimated counts of the `members`, items the caller expects to be
+    /// inserted exactly once, whose count is not 1: 0 if they are missing or were
+    /// removed, 2 or more if they were inserted again. Counts are estimated after
+    /// removing every member once, so collisions between members do not skew them.
+    ///
+    /// Keeping this sketch alongside a `BinaryCountSketch` fed the same items audits a
+    /// pipeline for double toggles, which the binary sketch cannot tell from absence.
+    pub fn verify_against<V: Item>(&self, members: &[V]) -> Vec<(usize, i32)> {
+        let mut residual = self.clone();
+        for v in members {
+            residual.remove(v);
+        }
+        members
+            .iter()
+            .map(|v| residual.count(v) + 1)
+            .enumerate()
+            .filter(|(_, count)| *count != 1)
+            .collect()
+    }
+
+    /// Subtracts `other`, leaving positive counters for items inserted more often here
+    /// and negative ones for items inserted more often in `other`.
+    pub fn diff_with(&mut self, other: &Self) -> Result<(), BinaryCountSketchError> {
+        if self.base_length != other.base_length { return Err(BinaryCountSketchError::with_mismatch("base length", self.base_length, other.base_length)); }
+        if self.level != other.level { return Err(BinaryCountSketchError::with_mismatch("level", self.level, other.level)); }
+        if self.points != other.points { return Err(BinaryCountSketchError::with_mismatch("points", self.points, other.points)); }
+        if self.seed != other.seed { return Err(BinaryCountSketchError::with_mismatch("seed", self.seed, other.seed)); }
+        if self.counters.len() != other.counters.len() { return Err(BinaryCountSketchError::with_mismatch("counters length", self.counters.len(), other.counters.len())); }
+
+        for (counter, val) in self.counters.iter_mut().zip(&other.counters) {
+            *counter = counter.wrapping_sub(*val);
+        }
+
+        Ok(())
+    }
+
+    /// Adds the counters of `other`, giving the exact union of both multisets even when
+    /// they share items.
+    pub fn merge_with(&mut self, other: &Self) -> Result<(), BinaryCountSketchError> {
+        if self.base_length != other.base_length { return Err(BinaryCountSketchError::with_mismatch("base length", self.base_length, other.base_length)); }
+        if self.level != other.level { return Err(BinaryCountSketchError::with_mismatch("level", self.level, other.level)); }
+        if self.points != other.points { return Err(BinaryCountSketchError::with_mismatch("points", self.points, other.points)); }
+        if self.seed != other.seed { return Err(BinaryCountSketchError::with_mismatch("seed", self.seed, other.seed)); }
+        if self.counters.len() != other.counters.len() { return Err(BinaryCountSketchError::with_mismatch("counters length", self.counters.len(), other.counters.len())); }
+
+        for (counter, val) in self.counters.iter_mut().zip(&other.counters) {
+            *counter = counter.wrapping_add(*val);
+        }
+
+        Ok(())
+    }
+
+    /// Merges all of `parts` into a new sketch, see `merge_with`.
+    pub fn try_merge_many(parts: &[Self]) -> Result<Self, BinaryCountSketchError> {
+        let (first, rest) = parts.split_first().ok_or_else(|| BinaryCountSketchError::with_kind(ErrorKind::InvalidArgument, "Incorrect parts"))?;
+        let mut merged = first.clone();
+        for part in rest {
+            merged.merge_with(part)?;
+        }
+        Ok(merged)
+    }
+
+    /// Binary sketch holding the parity of every counter, identical to the one built
+    /// by toggling the same items.
+    pub fn to_binary(&self) -> BinaryCountSketch {
+        let mut sketch = BinaryCountSketch::with_seed(self.base_length, self.level, self.points, self.seed);
+        for (b, counter) in self.counters.iter().enumerate() {
+            if counter & 1 != 0 {
+                sketch.words[b / 64] |= 1 << (b % 64);
+            }
+        }
+        sketch
+    }
+
+    /// True if `sketch` has the same parameters and is the parity of this sketch.
+    pub fn agrees_with(&self, sketch: &BinaryCountSketch) -> bool {
+        let binary = self.to_binary();
+        binary.base_length == sketch.base_length
+            && binary.level == sketch.level
+            && binary.points == sketch.points
+            && binary.seed == sketch.seed
+            && binary.words == sketch.words
+    }
+}
+
+impl From<&BinaryCountSketch> for CounterSketch {
+    /// Counting sketch with a counter of 1 for every set bit of `sketch`.
+    fn from(sketch: &BinaryCountSketch) -> Self {
+        let mut counters = CounterSketch::with_seed(sketch.base_length, sketch.level, sketch.points, sketch.seed);
+        for (b, counter) in counters.counters.iter_mut().enumerate() {
+            *counter = ((sketch.words[b / 64] >> (b % 64)) & 1) as i32;
+        }
+        counters
+    }
+}
+
+#[cfg(all(test, feature = "rand"))]
+mod tests {
+    use super::*;
+    use crate::TestItem;
+
+    #[test]
+    fn test_counter_duplicates() {
+        let item = TestItem { points: vec![1, 70, 300, 600, 900] };
+        let mut binary = BinaryCountSketch::new(10, 2, 5);
+        let mut counters = CounterSketch::new(10, 2, 5);
+        binary.toggle(&item);
+        binary.toggle(&item);
+        counters.insert(&item);
+        counters.insert(&item);
+
+        // The second toggle cancels the first, the second insert does not.
+        assert_eq!(binary.check(&item), 0);
+        assert_eq!(counters.check(&item), 5);
+        assert_eq!(counters.count(&item), 2);
+        assert!(counters.agrees_with(&binary));
+
+        counters.remove(&item);
+        assert_eq!(counters.count(&item), 1);
+
+        // Unlike the binary merge, the counting merge keeps items present in both parts.
+        let merged = CounterSketch::try_merge_many(&[counters.clone(), counters]).expect("No errors");
+        assert_eq!(merged.count(&item), 2);
+    }
+
+    #[test]
+    fn test_counter_diff() {
+        let items: Vec<TestItem> = (0..100).map(|_| TestItem::new()).collect();
+        let mut local = CounterSketch::new(100, 2, 5);
+        let mut remote = CounterSketch::new(100, 2, 5);
+        let mut binary = BinaryCountSketch::new(100, 2, 5);
+        for item in &items[..60] {
+            local.insert(item);
+            binary.toggle(item);
+        }
+        for item in &items[40..] {
+            remote.insert(item);
+            binary.toggle(item);
+        }
+        local.insert(&items[50]);
+
+        local.diff_with(&remote).expect("No errors");
+        assert_eq!(local.count(&items[10]), 1);
+        assert_eq!(local.count(&items[50]), 1);
+        assert_eq!(local.count(&items[90]), -1);
+        assert_eq!(local.count(&items[45]), 0);
+
+        // The extra local insert of `items[50]` is the only change the parity keeps.
+        binary.toggle(&items[50]);
+        assert!(local.agrees_with(&binary));
+        assert_eq!(CounterSketch::from(&binary).to_binary().words, binary.words);
+
+        assert!(local.diff_with(&CounterSketch::new(100, 3, 5)).is_err());
+    }
+
+    #[test]
+    fn test_counter_audit() {
+        let items: Vec<TestItem> = (0..50).map(TestItem::from_seed).collect();
+        let mut counters = CounterSketch::new(10, 2, 5);
+        for item in &items[1..] {
+            counters.insert(item);
+        }
+        counters.insert(&items[20]);
+
+        assert_eq!(counters.verify_against(&items), vec![(0, 0), (20, 2)]);
+    }
+}
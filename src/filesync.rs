@@ -0,0 +1,261 @@
+use core::hash::Hasher;
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, ToSocketAddrs};
+
+use crate::{BinaryCountSketchError, ErrorKind, Peer, Reconciled, SketchParams, StableHasher};
+
+/// Key of the `StableHasher` hashing chunk contents.
+const CHUNK_KEY: u64 = 0x4348_4e4b;
+
+/// Chunk length suited to most trees, 64KiB.
+pub const DEFAULT_CHUNK_LEN: usize = 64 << 10;
+
+/// Hashed bytes, offset and length of an encoded chunk, before its path.
+const CHUNK_HEADER_LEN: usize = 24;
+
+fn io_error(path: &Path, e: io::Error) -> BinaryCountSketchError {
+    BinaryCountSketchError::with_kind(ErrorKind::Other, &format!("{}: {}", path.display(), e))
+}
+
+fn hash_chunk(data: &[u8]) -> u64 {
+    let mut hasher = StableHasher::with_key(CHUNK_KEY);
+    hasher.write(data);
+    hasher.finish()
+}
+
+/// Piece of a file: the bytes at `offset` of the file at `path`, relative to the synced
+/// directory with `/` separators, which hash to `hash`.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Chunk {
+    pub path: String,
+    pub offset: u64,
+    pub len: u64,
+    pub hash: u64,
+}
+
+impl Chunk {
+    /// Encodes the chunk as an item of the `Peer` protocol: the hash, offset and length
+    /// as little-endian `u64`, then the path.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(CHUNK_HEADER_LEN + self.path.len());
+        out.extend_from_slice(&self.hash.to_le_bytes());
+        out.extend_from_slice(&self.offset.to_le_bytes());
+        out.extend_from_slice(&self.len.to_le_bytes());
+        out.extend_from_slice(self.path.as_bytes());
+        out
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, BinaryCountSketchError> {
+        if !(bytes.len() >= CHUNK_HEADER_LEN) { return Err(BinaryCountSketchError::with_kind(ErrorKind::Parse, "Incorrect chunk length")); }
+        let word = |i: usize| u64::from_le_bytes(bytes[i * 8..i * 8 + 8].try_into().unwrap());
+        let path = core::str::from_utf8(&bytes[CHUNK_HEADER_LEN..]).map_err(|_| BinaryCountSketchError::with_kind(ErrorKind::Parse, "Incorrect chunk path"))?;
+        Ok(Chunk { path: path.to_string(), offset: word(1), len: word(2), hash: word(0) })
+    }
+
+    /// Whether `data`, fetched from a peer, is the contents of this chunk.
+    pub fn verify(&self, data: &[u8]) -> bool {
+        data.len() as u64 == self.len && hash_chunk(data) == self.hash
+    }
+}
+
+/// Outcome of reconciling the chunks of two directories.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ChunkDiff {
+    /// Chunks the peer holds and we do not, to fetch from it.
+    pub fetch: Vec<Chunk>,
+    /// Chunks we hold and the peer does not, which it fetches from us.
+    pub serve: Vec<Chunk>,
+    /// Items received from the peer that are not chunks, dropped.
+    pub rejected: usize,
+    /// As `Reconciled::complete`: if false, the sketches were too small for the
+    /// difference and some chunks may be missing from `fetch` and `serve`.
+    pub complete: bool,
+}
+
+/// One side of a directory sync: the files under a root are split into chunks of a
+/// fixed length, hashed, and reconciled with a peer's over the `Peer` protocol, each
+/// chunk an item. Both sides then know which chunks to fetch from the other, e.g. with
+/// `read_chunk` on the peer, checking each with `Chunk::verify` before writing it.
+///
+/// Chunks are keyed by path and offset, so a file changed in place only costs the
+/// chunks that changed. Both sides must use the same chunk length and `SketchParams`.
+/// Chunk hashes are not cryptographic: against a peer that crafts collisions, verify
+/// fetched data with a digest of its own.
+pub struct DirSync {
+    root: PathBuf,
+    chunks: Vec<Chunk>,
+    peer: Peer,
+}
+
+impl DirSync {
+    /// Reads and hashes the files under `root` in chunks of `chunk_len` bytes, with one
+    /// empty chunk for an empty file. Symbolic links are skipped rather than followed,
+    /// so a link cycle cannot recurse forever.
+    pub fn scan(root: &Path, params: SketchParams, chunk_len: usize) -> Result<Self, BinaryCountSketchError> {
+        if !(chunk_len > 0) { return Err(BinaryCountSketchError::with_kind(ErrorKind::InvalidArgument, "Incorrect chunk length")); }
+
+        let mut paths = Vec::new();
+        collect_files(root, root, &mut paths)?;
+        let mut chunks = Vec::new();
+        for rel in paths {
+            let path = rel.components().map(|c| c.as_os_str().to_string_lossy()).collect::<Vec<_>>().join("/");
+            let full = root.join(&rel);
+            let mut file = File::open(&full).map_err(|e| io_error(&full, e))?;
+            let mut offset = 0;
+            let mut data = Vec::with_capacity(chunk_len);
+            loop {
+                data.clear();
+                (&mut file).take(chunk_len as u64).read_to_end(&mut data).map_err(|e| io_error(&full, e))?;
+                if data.is_empty() && offset > 0 { break; }
+                chunks.push(Chunk { path: path.clone(), offset, len: data.len() as u64, hash: hash_chunk(&data) });
+                offset += data.len() as u64;
+                if data.len() < chunk_len { break; }
+            }
+        }
+        chunks.sort();
+
+        let peer = Peer::new(params, chunks.iter().map(Chunk::to_bytes));
+        Ok(DirSync { root: root.to_path_buf(), chunks, peer })
+    }
+
+    /// Our chunks, sorted by path and offset.
+    pub fn chunks(&self) -> &[Chunk] {
+        &self.chunks
+    }
+
+    /// Connects to `addr` and reconciles as the client.
+    pub async fn connect<A: ToSocketAddrs>(&self, addr: A) -> Result<ChunkDiff, BinaryCountSketchError> {
+        self.peer.connect(addr).await.map(chunk_diff)
+    }
+
+    /// Accepts one connection on `listener` and reconciles as the server.
+    pub async fn accept(&self, listener: &TcpListener) -> Result<ChunkDiff, BinaryCountSketchError> {
+        self.peer.accept(listener).await.map(chunk_diff)
+    }
+
+    pub async fn reconcile_client<S: AsyncRead + AsyncWrite + Unpin>(&self, stream: &mut S) -> Result<ChunkDiff, BinaryCountSketchError> {
+        self.peer.reconcile_client(stream).await.map(chunk_diff)
+    }
+
+    pub async fn reconcile_server<S: AsyncRead + AsyncWrite + Unpin>(&self, stream: &mut S) -> Result<ChunkDiff, BinaryCountSketchError> {
+        self.peer.reconcile_server(stream).await.map(chunk_diff)
+    }
+
+    /// Reads the contents of one of our chunks, to serve it to a peer. Chunks we do not
+    /// hold fail with `ErrorKind::InvalidArgument`, so a peer cannot read other files.
+    pub fn read_chunk(&self, chunk: &Chunk) -> Result<Vec<u8>, BinaryCountSketchError> {
+        if self.chunks.binary_search(chunk).is_err() { return Err(BinaryCountSketchError::with_kind(ErrorKind::InvalidArgument, "Unknown chunk")); }
+
+        let full = self.root.join(&chunk.path);
+        let mut file = File::open(&full).map_err(|e| io_error(&full, e))?;
+        file.seek(SeekFrom::Start(chunk.offset)).map_err(|e| io_error(&full, e))?;
+        let mut data = Vec::with_capacity(chunk.len as usize);
+        file.take(chunk.len).read_to_end(&mut data).map_err(|e| io_error(&full, e))?;
+        Ok(data)
+    }
+}
+
+/// Collects the regular files under `dir`, relative to `root`.
+fn collect_files(root: &Path, dir: &Path, out: &mut Vec<PathBuf>) -> Result<(), BinaryCountSketchError> {
+    let entries = fs::read_dir(dir).and_then(|entries| entries.collect::<io::Result<Vec<_>>>()).map_err(|e| io_error(dir, e))?;
+    for entry in entries {
+        let file_type = entry.file_type().map_err(|e| io_error(&entry.path(), e))?;
+        if file_type.is_dir() {
+            collect_files(root, &entry.path(), out)?;
+        } else if file_type.is_file() {
+            out.push(entry.path().strip_prefix(root).expect("Path under root").to_path_buf());
+        }
+    }
+    Ok(())
+}
+
+fn chunk_diff(reconciled: Reconciled) -> ChunkDiff {
+    let parse = |items: Vec<Vec<u8>>| items.iter().filter_map(|item| Chunk::from_bytes(item).ok()).collect::<Vec<_>>();
+    let received = reconciled.received.len();
+    let mut fetch = parse(reconciled.received);
+    let mut serve = parse(reconciled.sent);
+    fetch.sort();
+    serve.sort();
+    ChunkDiff { rejected: received - fetch.len(), fetch, serve, complete: reconciled.complete }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Fresh directory for a test, removed when dropped.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("bcsk-filesync-{}-{}", std::process::id(), name));
+            let _ = fs::remove_dir_all(&path);
+            fs::create_dir_all(&path).expect("Created directory");
+            TempDir(path)
+        }
+
+        fn write(&self, rel: &str, contents: &[u8]) {
+            let path = self.0.join(rel);
+            fs::create_dir_all(path.parent().unwrap()).expect("Created directory");
+            fs::write(path, contents).expect("Written file");
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_dir_sync() {
+        let (a, b) = (TempDir::new("a"), TempDir::new("b"));
+        let shared: Vec<u8> = (0..200u8).collect();
+        let mut changed = shared.clone();
+        changed[40] ^= 1;
+        a.write("shared.bin", &shared);
+        b.write("shared.bin", &shared);
+        a.write("changed.bin", &shared);
+        b.write("changed.bin", &changed);
+        a.write("only-a.txt", b"");
+        b.write("nested/only-b.txt", b"only on b");
+
+        let params = SketchParams::new(100, 2, 5);
+        let (client, server) = (DirSync::scan(&a.0, params, 32).expect("No errors"), DirSync::scan(&b.0, params, 32).expect("No errors"));
+        assert_eq!(client.chunks().len(), 7 + 7 + 1);
+        let (mut client_stream, mut server_stream) = tokio::io::duplex(1 << 16);
+        let (served, synced) = tokio::join!(server.reconcile_server(&mut server_stream), client.reconcile_client(&mut client_stream));
+        let (served, synced) = (served.expect("No errors"), synced.expect("No errors"));
+        assert!(synced.complete && served.complete);
+        assert_eq!((synced.fetch.clone(), synced.serve.clone()), (served.serve.clone(), served.fetch.clone()));
+
+        let names = |chunks: &[Chunk]| chunks.iter().map(|c| (c.path.clone(), c.offset)).collect::<Vec<_>>();
+        assert_eq!(names(&synced.fetch), vec![("changed.bin".to_string(), 32), ("nested/only-b.txt".to_string(), 0)]);
+        assert_eq!(names(&synced.serve), vec![("changed.bin".to_string(), 32), ("only-a.txt".to_string(), 0)]);
+
+        // Fetched chunks are checked against their hash before use.
+        for chunk in &synced.fetch {
+            let data = server.read_chunk(chunk).expect("No errors");
+            assert!(chunk.verify(&data));
+            assert!(!synced.serve[0].verify(&data));
+        }
+        assert_eq!(client.read_chunk(&synced.fetch[1]).map_err(|e| e.kind()).err(), Some(ErrorKind::InvalidArgument));
+        assert!(DirSync::scan(&a.0, params, 0).is_err());
+    }
+
+    #[test]
+    fn test_chunk_bytes() {
+        let chunk = Chunk { path: "dir/file".to_string(), offset: 64, len: 32, hash: 7 };
+        assert_eq!(Chunk::from_bytes(&chunk.to_bytes()).expect("No errors"), chunk);
+        assert_eq!(Chunk::from_bytes(&[0; 23]).map_err(|e| e.kind()).err(), Some(ErrorKind::Parse));
+        assert_eq!(Chunk::from_bytes(&[[0; 24].as_slice(), &[0xff]].concat()).map_err(|e| e.kind()).err(), Some(ErrorKind::Parse));
+
+        let reconciled = Reconciled { received: vec![chunk.to_bytes(), vec![1, 2, 3]], sent: vec![], complete: true };
+        let diff = chunk_diff(reconciled);
+        assert_eq!((diff.fetch, diff.rejected), (vec![chunk], 1));
+    }
+}
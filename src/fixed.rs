@@ -0,0 +1,80 @@
+use crate::{code_index, BinaryCountSketch, BinaryCountSketchError, Item};
+
+/// Sketch with a compile-time number of points `P`, so the `check` and `toggle` loops
+/// have a constant bound and can be unrolled, e.g. for per-packet membership checks.
+/// Items that use fewer points than `P` fall back to the dynamic path.
+pub struct FixedPointsSketch<const P: usize> {
+    sketch: BinaryCountSketch,
+}
+
+impl<const P: usize> FixedPointsSketch<P> {
+    pub fn new(base_length: u64, level: u64) -> Self {
+        FixedPointsSketch { sketch: BinaryCountSketch::new(base_length, level, P as u64) }
+    }
+
+    fn bits_of<V: Item>(&self, v: &V) -> Option<[usize; P]> {
+        if v.points(P as u64) < P as u64 {
+            return None;
+        }
+        let l = self.sketch.bits();
+        Some(core::array::from_fn(|i| code_index(v, i as u64, l)))
+    }
+
+    pub fn toggle<V: Item>(&mut self, v: &V) {
+        match self.bits_of(v) {
+            Some(bits) => {
+                for b in bits {
+                    self.sketch.words[b / 64] ^= 1 << (b % 64);
+                }
+            }
+            None => self.sketch.toggle(v),
+        }
+    }
+
+    pub fn check<V: Item>(&self, v: &V) -> usize {
+        match self.bits_of(v) {
+            Some(bits) => bits.iter().filter(|b| self.sketch.words[*b / 64] & (1 << (*b % 64)) != 0).count(),
+            None => self.sketch.check(v),
+        }
+    }
+
+    pub fn sketch(&self) -> &BinaryCountSketch {
+        &self.sketch
+    }
+
+    pub fn into_sketch(self) -> BinaryCountSketch {
+        self.sketch
+    }
+}
+
+impl<const P: usize> TryFrom<BinaryCountSketch> for FixedPointsSketch<P> {
+    type Error = BinaryCountSketchError;
+
+    fn try_from(sketch: BinaryCountSketch) -> Result<Self, BinaryCountSketchError> {
+        if sketch.points != P as u64 { return Err(BinaryCountSketchError::with_mismatch("points", P as u64, sketch.points)); }
+        Ok(FixedPointsSketch { sketch })
+    }
+}
+
+#[cfg(all(test, feature = "rand"))]
+mod tests {
+    use super::*;
+    use crate::TestItem;
+
+    #[test]
+    fn test_fixed_matches_dynamic() {
+        let mut fixed = FixedPointsSketch::<5>::new(10, 2);
+        let mut dynamic = BinaryCountSketch::new(10, 2, 5);
+        let items: Vec<TestItem> = (0..100).map(|_| TestItem::new()).collect();
+        for item in &items[..50] {
+            fixed.toggle(item);
+            dynamic.toggle(item);
+        }
+        assert_eq!(fixed.sketch().words, dynamic.words);
+        assert!(items.iter().all(|item| fixed.check(item) == dynamic.check(item)));
+
+        assert!(FixedPointsSketch::<4>::try_from(dynamic).is_err());
+        let converted = FixedPointsSketch::<5>::try_from(fixed.into_sketch()).expect("No errors");
+        assert!(items[..50].iter().all(|item| converted.check(item) == converted.sketch().check(item)));
+    }
+}
@@ -0,0 +1,195 @@
+use core::hash::{Hash, Hasher};
+
+use crate::{splitmix64, BinaryCountSketch, BytesItem, Item};
+
+/// 64-bit hasher whose output only depends on the key and the bytes written, unlike
+/// `std::collections::hash_map::DefaultHasher`. Each `write` folds its length and then
+/// its bytes, as little-endian 8-byte words, into the state with splitmix64, and
+/// integers are written as their little-endian bytes, so peers on any platform agree on
+/// the hash of the same writes. `test_stable_hasher` pins the output.
+///
+/// Hashing a value through its `Hash` impl, as `HashedItem` does, is only as stable as
+/// that impl: std does not promise that the writes of e.g. `str`, slices or tuples stay
+/// the same across Rust releases, so peers built with different toolchains should hash a
+/// canonical byte encoding with `Hasher::write` instead. It is not a cryptographic hash
+/// either, and the key does not stop an adversary from crafting colliding values.
+#[derive(Clone, Copy, Debug)]
+pub struct StableHasher {
+    state: u64,
+}
+
+impl StableHasher {
+    pub fn with_key(key: u64) -> Self {
+        StableHasher { state: splitmix64(key) }
+    }
+}
+
+impl Hasher for StableHasher {
+    fn finish(&self) -> u64 {
+        self.state
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        self.state = splitmix64(self.state ^ bytes.len() as u64);
+        for chunk in bytes.chunks(8) {
+            let mut word = [0u8; 8];
+            word[..chunk.len()].copy_from_slice(chunk);
+            self.state = splitmix64(self.state ^ u64::from_le_bytes(word));
+        }
+    }
+
+    fn write_u16(&mut self, i: u16) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_u32(&mut self, i: u32) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_u64(&mut self, i: u64) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_u128(&mut self, i: u128) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_usize(&mut self, i: usize) {
+        self.write_u64(i as u64);
+    }
+}
+
+/// Wraps any `Hash` value, e.g. a `u64`, `String` or `Vec<u8>`, as an `Item`. The value
+/// is hashed once with a `StableHasher`, and code `i` is derived from that hash and `i`.
+/// Peers must use the same key, and `Hash` impls writing the same bytes, to agree on the
+/// codes; see `StableHasher`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct HashedItem<T> {
+    value: T,
+    hash: u64,
+}
+
+impl<T: Hash> HashedItem<T> {
+    pub fn new(value: T) -> Self {
+        HashedItem::with_key(value, 0)
+    }
+
+    pub fn with_key(value: T, key: u64) -> Self {
+        let mut hasher = StableHasher::with_key(key);
+        value.hash(&mut hasher);
+        HashedItem { value, hash: hasher.finish() }
+    }
+}
+
+impl<T> HashedItem<T> {
+    pub fn value(&self) -> &T {
+        &self.value
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value
+    }
+}
+
+impl<T> Item for HashedItem<T> {
+    fn get_code(&self, i: u64) -> u64 {
+        splitmix64(self.hash ^ splitmix64(i))
+    }
+}
+
+/// Key of the items `toggle_bytes` and `keyed_item` derive from a sketch's `seed`.
+pub(crate) fn item_key(seed: &[u8; 16]) -> u64 {
+    let lo = u64::from_le_bytes(seed[..8].try_into().unwrap());
+    let hi = u64::from_le_bytes(seed[8..].try_into().unwrap());
+    lo ^ splitmix64(hi)
+}
+
+impl BinaryCountSketch {
+    fn item_key(&self) -> u64 {
+        item_key(&self.seed)
+    }
+
+    /// Wraps `value` as an item hashed with this sketch's seed through its `Hash` impl.
+    pub fn keyed_item<T: Hash>(&self, value: T) -> HashedItem<T> {
+        HashedItem::with_key(value, self.item_key())
+    }
+
+    /// Wraps `bytes` as an item hashed with this sketch's seed, e.g. to build decode
+    /// candidates matching `toggle_bytes`.
+    pub fn keyed_bytes<'a>(&self, bytes: &'a [u8]) -> BytesItem<'a> {
+        BytesItem::with_key(bytes, self.item_key())
+    }
+
+    /// Toggles `bytes`, hashed as they are with a single `StableHasher::write`, so peers
+    /// in any language agree on its codes without depending on a `Hash` impl.
+    pub fn toggle_bytes(&mut self, bytes: &[u8]) {
+        let item = self.keyed_bytes(bytes);
+        self.toggle(&item);
+    }
+
+    pub fn check_bytes(&self, bytes: &[u8]) -> usize {
+        self.check(&self.keyed_bytes(bytes))
+    }
+}
+
+#[cfg(all(test, feature = "rand"))]
+mod tests {
+    use super::*;
+    use crate::{BinaryCountSketch, PeelingDecoder};
+
+    #[test]
+    fn test_stable_hasher() {
+        // Fixed values: a change here breaks interoperability between peers.
+        let item = HashedItem::new(42u64);
+        assert_eq!(item.hash, 5963455391779255551);
+        assert_ne!(item.hash, HashedItem::with_key(42u64, 1).hash);
+        assert_eq!(HashedItem::new("abc").hash, HashedItem::new(String::from("abc")).hash);
+        assert_ne!(HashedItem::new(("ab", "c")).hash, HashedItem::new(("a", "bc")).hash);
+        assert_ne!(item.get_code(0), item.get_code(1));
+    }
+
+    #[test]
+    fn test_seeded_bytes() {
+        let mut sketch = BinaryCountSketch::with_seed(10, 2, 5, [3; 16]);
+        sketch.toggle_bytes(b"telemetry-1");
+        assert_eq!(sketch.check_bytes(b"telemetry-1"), 5);
+        assert_eq!(sketch.check(&sketch.keyed_bytes(b"telemetry-1")), 5);
+
+        // Fixed values: the bytes are hashed as they are, so peers in other languages
+        // can compute the same codes.
+        let mut hasher = StableHasher::with_key(sketch.item_key());
+        hasher.write(b"telemetry-1");
+        assert_eq!(sketch.keyed_bytes(b"telemetry-1").get_code(0), splitmix64(hasher.finish() ^ splitmix64(0)));
+        assert_eq!(BinaryCountSketch::new(10, 2, 5).keyed_bytes(b"telemetry-1").get_code(0), 6560181681195729210);
+
+        // A peer with another seed hashes the same bytes elsewhere and cannot diff.
+        let mut other = BinaryCountSketch::with_seed(10, 2, 5, [4; 16]);
+        other.toggle_bytes(b"telemetry-1");
+        assert_ne!(other.words, sketch.words);
+        assert!(sketch.diff_with(&other).is_err());
+
+        let mut same = BinaryCountSketch::from_params(sketch.params());
+        same.toggle_bytes(b"telemetry-1");
+        sketch.diff_with(&same).expect("No errors");
+        assert!(sketch.words.iter().all(|w| *w == 0));
+    }
+
+    #[test]
+    fn test_hashed_items_decode() {
+        let mut sketch1 = BinaryCountSketch::new(100, 2, 5);
+        let mut sketch2 = BinaryCountSketch::new(100, 2, 5);
+        let candidates: Vec<HashedItem<String>> = (0..1000).map(|i| HashedItem::new(format!("event-{}", i))).collect();
+        for item in &candidates[..990] {
+            sketch1.toggle(item);
+        }
+        for item in &candidates {
+            sketch2.toggle(item);
+        }
+        sketch1.diff_with(&sketch2).expect("No errors");
+
+        let report = PeelingDecoder::new(4).decode(&mut sketch1, &candidates).expect("No errors");
+        let mut decoded: Vec<&str> = report.decoded.iter().map(|item| item.value().as_str()).collect();
+        decoded.sort();
+        assert_eq!(decoded, (990..1000).map(|i| format!("event-{}", i)).collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,92 @@
+use crate::{BinaryCountSketch, BinaryCountSketchError, DecodeReport, ErrorKind, Item, PeelStrategy, PeelingDecoder};
+
+/// Result of `PeelingDecoder::decode_hierarchical`.
+#[derive(Clone, Debug)]
+pub struct HierarchicalReport<V> {
+    /// Report of the decode at `level`, the last level tried.
+    pub report: DecodeReport<V>,
+    pub level: u64,
+    /// Whether the diff at `level` was fully decoded, leaving an empty sketch.
+    pub complete: bool,
+    /// Total size of the words fetched from the peer over all levels tried.
+    pub bytes_fetched: usize,
+}
+
+impl BinaryCountSketch {
+    /// This sketch folded down to `level`, or a copy of it at its own level, e.g. to
+    /// answer a peer's request for one level of `decode_hierarchical`.
+    pub fn at_level(&self, level: u64) -> Result<Self, BinaryCountSketchError> {
+        if level == self.level {
+            return Ok(self.clone());
+        }
+        self.level_down(level)
+    }
+}
+
+impl<S: PeelStrategy> PeelingDecoder<S> {
+    /// Decodes the difference with a peer level by level, starting at `start_level` and
+    /// only fetching a finer level when the diff at the current one cannot be fully
+    /// decoded, so small differences cost a fraction of the full sketch. `fetch(level)`
+    /// returns the peer's sketch at `level`, e.g. by requesting `at_level(level)` from
+    /// it. Stops at the level of `local` and reports whether the decode completed.
+    pub fn decode_hierarchical<V, F>(&self, local: &BinaryCountSketch, candidates: &[V], start_level: u64, mut fetch: F) -> Result<HierarchicalReport<V>, BinaryCountSketchError>
+    where
+        V: Item + Clone,
+        F: FnMut(u64) -> Result<BinaryCountSketch, BinaryCountSketchError>,
+    {
+        if start_level > local.level { return Err(BinaryCountSketchError::with_kind(ErrorKind::InvalidArgument, "Incorrect level")); }
+
+        let mut bytes_fetched = 0;
+        let mut level = start_level;
+        loop {
+            let remote = fetch(level)?;
+            if remote.level != level { return Err(BinaryCountSketchError::with_mismatch("level", level, remote.level)); }
+            bytes_fetched += remote.words.len() * 8;
+
+            let mut diff = local.at_level(level)?;
+            diff.diff_with(&remote)?;
+            let report = self.decode(&mut diff, candidates)?;
+            let complete = diff.words.iter().all(|w| *w == 0);
+            if complete || level == local.level {
+                return Ok(HierarchicalReport { report, level, complete, bytes_fetched });
+            }
+            level += 1;
+        }
+    }
+}
+
+#[cfg(all(test, feature = "rand"))]
+mod tests {
+    use super::*;
+    use crate::testing::{Peers, Scenario};
+    use crate::{SketchParams, TestItem};
+
+    #[test]
+    fn test_hierarchical_small() {
+        let Peers { mut local, remote, .. } = Scenario::new(SketchParams::new(8, 4, 5), 500, 0).peers(0);
+        let extra = TestItem { points: vec![1, 70, 300, 400, 500] };
+        local.toggle(&extra);
+
+        let mut levels = Vec::new();
+        let result = PeelingDecoder::new(4).decode_hierarchical(&local, core::slice::from_ref(&extra), 0, |level| {
+            levels.push(level);
+            remote.at_level(level)
+        }).expect("No errors");
+        assert!(result.complete);
+        assert_eq!(result.report.decoded, vec![extra]);
+        assert_eq!(levels, vec![0]);
+        assert_eq!(result.bytes_fetched, 64);
+    }
+
+    #[test]
+    fn test_hierarchical_refines() {
+        let Peers { local, remote, local_only: extras, .. } = Scenario::new(SketchParams::new(8, 4, 5), 500, 0).with_sides(60, 0).peers(0);
+        let result = PeelingDecoder::new(4).decode_hierarchical(&local, &extras, 0, |level| remote.at_level(level)).expect("No errors");
+        assert!(result.complete);
+        assert!(result.level > 0);
+        assert_eq!(result.report.decoded.len(), 60);
+
+        assert!(PeelingDecoder::new(4).decode_hierarchical(&local, &extras, 5, |level| remote.at_level(level)).is_err());
+        assert!(PeelingDecoder::new(4).decode_hierarchical(&local, &extras, 0, |_| remote.at_level(4)).is_err());
+    }
+}
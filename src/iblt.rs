@@ -0,0 +1,478 @@
+use alloc::vec;
+use alloc::vec::Vec;
+use core::hash::{Hash, Hasher};
+use core::ops::BitXor;
+
+use crate::{code_index, BinaryCountSketchError, ErrorKind, HashedItem, StableHasher};
+
+/// Key of the `StableHasher` computing the per-key checksum stored in every cell, kept
+/// distinct from the key used to place keys in cells.
+const CHECKSUM_KEY: u64 = 0x4942_4c54;
+
+/// Length of the `to_bytes` header: the hashes and the number of cells.
+const HEADER_LEN: usize = 2 * 8;
+
+fn parse_error(details: &str) -> BinaryCountSketchError {
+    BinaryCountSketchError::with_kind(ErrorKind::Parse, details)
+}
+
+/// Key or value with a fixed-size little-endian encoding, so `Iblt`s and reconciliation
+/// messages holding it can be sent to a peer.
+pub trait FixedBytes: Sized {
+    /// Length of the encoding in bytes.
+    const LEN: usize;
+
+    fn write_bytes(&self, out: &mut Vec<u8>);
+
+    /// Reads a value from exactly `LEN` bytes, or `None` if they encode no value.
+    fn read_bytes(bytes: &[u8]) -> Option<Self>;
+}
+
+impl FixedBytes for bool {
+    const LEN: usize = 1;
+
+    fn write_bytes(&self, out: &mut Vec<u8>) {
+        out.push(*self as u8);
+    }
+
+    fn read_bytes(bytes: &[u8]) -> Option<Self> {
+        match bytes {
+            [0] => Some(false),
+            [1] => Some(true),
+            _ => None,
+        }
+    }
+}
+
+impl FixedBytes for u8 {
+    const LEN: usize = 1;
+
+    fn write_bytes(&self, out: &mut Vec<u8>) {
+        out.push(*self);
+    }
+
+    fn read_bytes(bytes: &[u8]) -> Option<Self> {
+        Some(u8::from_le_bytes(bytes.try_into().ok()?))
+    }
+}
+
+impl FixedBytes for u32 {
+    const LEN: usize = 4;
+
+    fn write_bytes(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.to_le_bytes());
+    }
+
+    fn read_bytes(bytes: &[u8]) -> Option<Self> {
+        Some(u32::from_le_bytes(bytes.try_into().ok()?))
+    }
+}
+
+impl FixedBytes for u64 {
+    const LEN: usize = 8;
+
+    fn write_bytes(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.to_le_bytes());
+    }
+
+    fn read_bytes(bytes: &[u8]) -> Option<Self> {
+        Some(u64::from_le_bytes(bytes.try_into().ok()?))
+    }
+}
+
+impl FixedBytes for u128 {
+    const LEN: usize = 16;
+
+    fn write_bytes(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.to_le_bytes());
+    }
+
+    fn read_bytes(bytes: &[u8]) -> Option<Self> {
+        Some(u128::from_le_bytes(bytes.try_into().ok()?))
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+struct Cell<K, V> {
+    count: i64,
+    key_sum: K,
+    value_sum: V,
+    hash_sum: u64,
+}
+
+/// Entries listed from an `Iblt`, split by the sign of their count.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IbltEntries<K, V> {
+    /// Entries inserted more often than deleted, e.g. only present locally after a
+    /// `subtract`.
+    pub inserted: Vec<(K, V)>,
+    /// Entries deleted more often than inserted, e.g. only present in the peer's table.
+    pub deleted: Vec<(K, V)>,
+}
+
+/// Invertible Bloom Lookup Table: every cell XORs the keys, values and key checksums
+/// mapped to it, so after subtracting a peer's table the differing entries can be listed
+/// exactly, as long as there are fewer of them than about two thirds of the cells.
+/// Keys are placed with the same `HashedItem` codes as sketch items, in `hashes`
+/// disjoint sub-tables so a key never lands twice in the same cell.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Iblt<K, V> {
+    hashes: u64,
+    cells: Vec<Cell<K, V>>,
+}
+
+impl<K, V> Iblt<K, V>
+where
+    K: Copy + Default + Eq + Hash + BitXor<Output = K>,
+    V: Copy + Default + Eq + BitXor<Output = V>,
+{
+    /// Creates a table of at least `cells` cells, rounded up to a multiple of `hashes`.
+    pub fn new(cells: usize, hashes: u64) -> Self {
+        let hashes = hashes.max(1);
+        let sub = cells.div_ceil(hashes as usize).max(1);
+        Iblt { hashes, cells: vec![Cell::default(); sub * hashes as usize] }
+    }
+
+    pub fn cells(&self) -> usize {
+        self.cells.len()
+    }
+
+    pub fn hashes(&self) -> u64 {
+        self.hashes
+    }
+
+    /// True if every cell is empty, e.g. after subtracting an identical table.
+    pub fn is_empty(&self) -> bool {
+        self.cells.iter().all(|cell| *cell == Cell::default())
+    }
+
+    fn checksum(key: &K) -> u64 {
+        let mut hasher = StableHasher::with_key(CHECKSUM_KEY);
+        key.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    fn positions(&self, key: &K) -> impl Iterator<Item = usize> {
+        let item = HashedItem::new(*key);
+        let sub = self.cells.len() / self.hashes as usize;
+        (0..self.hashes).map(move |i| i as usize * sub + code_index(&item, i, sub))
+    }
+
+    fn apply(&mut self, key: K, value: V, delta: i64) {
+        let hash = Self::checksum(&key);
+        for p in self.positions(&key).collect::<Vec<_>>() {
+            let cell = &mut self.cells[p];
+            cell.count += delta;
+            cell.key_sum = cell.key_sum ^ key;
+            cell.value_sum = cell.value_sum ^ value;
+            cell.hash_sum ^= hash;
+        }
+    }
+
+    pub fn insert(&mut self, key: K, value: V) {
+        self.apply(key, value, 1);
+    }
+
+    pub fn delete(&mut self, key: K, value: V) {
+        self.apply(key, value, -1);
+    }
+
+    /// Subtracts `other`, leaving the entries only inserted here with a positive count
+    /// and those only inserted in `other` with a negative one.
+    pub fn subtract(&mut self, other: &Self) -> Result<(), BinaryCountSketchError> {
+        if self.hashes != other.hashes { return Err(BinaryCountSketchError::with_mismatch("hashes", self.hashes, other.hashes)); }
+        if self.cells.len() != other.cells.len() { return Err(BinaryCountSketchError::with_mismatch("cells length", self.cells.len(), other.cells.len())); }
+
+        for (cell, val) in self.cells.iter_mut().zip(&other.cells) {
+            cell.count -= val.count;
+            cell.key_sum = cell.key_sum ^ val.key_sum;
+            cell.value_sum = cell.value_sum ^ val.value_sum;
+            cell.hash_sum ^= val.hash_sum;
+        }
+
+        Ok(())
+    }
+
+    /// Peels the table, listing every entry it still holds. Fails with `ErrorKind::Budget`
+    /// if the table holds too many entries to be fully listed.
+    pub fn list_entries(&self) -> Result<IbltEntries<K, V>, BinaryCountSketchError> {
+        let mut table = self.clone();
+        let mut inserted = Vec::new();
+        let mut deleted = Vec::new();
+
+        // Peeling an entry of a well formed table empties its pure cell for good, as no
+        // other entry maps there, so there are at most as many peels as cells. Only a
+        // crafted table peels more, e.g. one whose key sits in one of its positions only
+        // and would be peeled back and forth forever.
+        let mut peels = table.cells.len();
+        let mut pure: Vec<usize> = (0..table.cells.len()).collect();
+        while let Some(p) = pure.pop() {
+            let cell = table.cells[p];
+            if cell.count.abs() != 1 || cell.hash_sum != Self::checksum(&cell.key_sum) {
+                continue;
+            }
+            if peels == 0 { return Err(BinaryCountSketchError::with_kind(ErrorKind::Budget, "Too many peels")); }
+            peels -= 1;
+            if cell.count == 1 {
+                inserted.push((cell.key_sum, cell.value_sum));
+            } else {
+                deleted.push((cell.key_sum, cell.value_sum));
+            }
+            let positions: Vec<usize> = table.positions(&cell.key_sum).collect();
+            table.apply(cell.key_sum, cell.value_sum, -cell.count);
+            pure.extend(positions);
+        }
+
+        if !table.is_empty() { return Err(BinaryCountSketchError::with_kind(ErrorKind::Budget, "Too many entries to list")); }
+        Ok(IbltEntries { inserted, deleted })
+    }
+}
+
+impl<K, V> Iblt<K, V>
+where
+    K: Copy + Default + Eq + Hash + BitXor<Output = K> + FixedBytes,
+    V: Copy + Default + Eq + BitXor<Output = V> + FixedBytes,
+{
+    /// Encodes the table as its `hashes` and number of cells as little-endian `u64`,
+    /// then every cell as its count as a little-endian `i64`, its key sum, its value sum
+    /// and its checksum as a little-endian `u64`.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(HEADER_LEN + self.cells.len() * (16 + K::LEN + V::LEN));
+        out.extend_from_slice(&self.hashes.to_le_bytes());
+        out.extend_from_slice(&(self.cells.len() as u64).to_le_bytes());
+        for cell in &self.cells {
+            out.extend_from_slice(&cell.count.to_le_bytes());
+            cell.key_sum.write_bytes(&mut out);
+            cell.value_sum.write_bytes(&mut out);
+            out.extend_from_slice(&cell.hash_sum.to_le_bytes());
+        }
+        out
+    }
+
+    /// Reads a table encoded by `to_bytes`. The number of cells must be a nonzero
+    /// multiple of `hashes` and match the length of the input, which bounds the
+    /// allocation by the input received.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, BinaryCountSketchError> {
+        if bytes.len() < HEADER_LEN { return Err(parse_error("Incorrect table")); }
+        let hashes = u64::from_le_bytes(bytes[..8].try_into().unwrap());
+        let cells = u64::from_le_bytes(bytes[8..HEADER_LEN].try_into().unwrap());
+        if !(hashes > 0 && cells > 0 && cells % hashes == 0) { return Err(parse_error("Incorrect hashes")); }
+        let cell_len = 16 + K::LEN + V::LEN;
+        if !(usize::try_from(cells).ok().and_then(|c| c.checked_mul(cell_len)) == Some(bytes.len() - HEADER_LEN)) { return Err(parse_error("Incorrect cells length")); }
+
+        let cells = bytes[HEADER_LEN..].chunks(cell_len).map(|c| {
+            let (count, rest) = c.split_at(8);
+            let (key_sum, rest) = rest.split_at(K::LEN);
+            let (value_sum, hash_sum) = rest.split_at(V::LEN);
+            Some(Cell {
+                count: i64::from_le_bytes(count.try_into().unwrap()),
+                key_sum: K::read_bytes(key_sum)?,
+                value_sum: V::read_bytes(value_sum)?,
+                hash_sum: u64::from_le_bytes(hash_sum.try_into().unwrap()),
+            })
+        }).collect::<Option<Vec<_>>>().ok_or_else(|| parse_error("Incorrect cell"))?;
+        Ok(Iblt { hashes, cells })
+    }
+}
+
+/// Key of the `StableHasher` computing the checksum of a `CellSketch` key.
+const CELL_CHECKSUM_KEY: u64 = 0x4345_4c4c;
+
+/// Cell-listing counterpart of `BinaryCountSketch`: every cell XORs the `u64` keys
+/// toggled into it and their checksums, so the diff of two peers' sketches can be
+/// listed without a candidate list, e.g. to recover the ids of items only the peer has.
+///
+/// Toggling works as in a binary sketch, so a key toggled twice cancels out and the diff
+/// holds the symmetric difference, but each cell is 128 bits instead of one. Listing
+/// succeeds while the difference has fewer keys than about two thirds of the cells.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CellSketch {
+    hashes: u64,
+    key_sums: Vec<u64>,
+    hash_sums: Vec<u64>,
+}
+
+impl CellSketch {
+    /// Creates a sketch of at least `cells` cells, rounded up to a multiple of `hashes`.
+    pub fn new(cells: usize, hashes: u64) -> Self {
+        let hashes = hashes.max(1);
+        let cells = cells.div_ceil(hashes as usize).max(1) * hashes as usize;
+        CellSketch { hashes, key_sums: vec![0; cells], hash_sums: vec![0; cells] }
+    }
+
+    pub fn cells(&self) -> usize {
+        self.key_sums.len()
+    }
+
+    pub fn hashes(&self) -> u64 {
+        self.hashes
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hash_sums.iter().all(|h| *h == 0) && self.key_sums.iter().all(|k| *k == 0)
+    }
+
+    fn checksum(key: u64) -> u64 {
+        let mut hasher = StableHasher::with_key(CELL_CHECKSUM_KEY);
+        key.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    fn positions(&self, key: u64) -> impl Iterator<Item = usize> {
+        let item = HashedItem::new(key);
+        let sub = self.key_sums.len() / self.hashes as usize;
+        (0..self.hashes).map(move |i| i as usize * sub + code_index(&item, i, sub))
+    }
+
+    pub fn toggle(&mut self, key: u64) {
+        let hash = Self::checksum(key);
+        for p in self.positions(key).collect::<Vec<_>>() {
+            self.key_sums[p] ^= key;
+            self.hash_sums[p] ^= hash;
+        }
+    }
+
+    pub fn diff_with(&mut self, other: &Self) -> Result<(), BinaryCountSketchError> {
+        if self.hashes != other.hashes { return Err(BinaryCountSketchError::with_mismatch("hashes", self.hashes, other.hashes)); }
+        if self.key_sums.len() != other.key_sums.len() { return Err(BinaryCountSketchError::with_mismatch("cells length", self.key_sums.len(), other.key_sums.len())); }
+
+        for (sum, val) in self.key_sums.iter_mut().zip(&other.key_sums) {
+            *sum ^= *val;
+        }
+        for (sum, val) in self.hash_sums.iter_mut().zip(&other.hash_sums) {
+            *sum ^= *val;
+        }
+
+        Ok(())
+    }
+
+    /// Lists every key toggled an odd number of times into this sketch, e.g. the
+    /// symmetric difference after a `diff_with`, without candidates. Fails with
+    /// `ErrorKind::Budget` if it holds too many keys to be fully listed.
+    pub fn decode_unknown(&self) -> Result<Vec<u64>, BinaryCountSketchError> {
+        let mut sketch = self.clone();
+        let mut keys = Vec::new();
+
+        let mut pure: Vec<usize> = (0..sketch.cells()).collect();
+        while let Some(p) = pure.pop() {
+            let key = sketch.key_sums[p];
+            if sketch.hash_sums[p] == 0 || sketch.hash_sums[p] != Self::checksum(key) {
+                continue;
+            }
+            keys.push(key);
+            let positions: Vec<usize> = sketch.positions(key).collect();
+            sketch.toggle(key);
+            pure.extend(positions);
+        }
+
+        if !sketch.is_empty() { return Err(BinaryCountSketchError::with_kind(ErrorKind::Budget, "Too many keys to list")); }
+        Ok(keys)
+    }
+}
+
+#[cfg(all(test, feature = "rand"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_iblt_list_entries() {
+        let mut local = Iblt::<u64, u64>::new(60, 3);
+        let mut remote = Iblt::<u64, u64>::new(60, 3);
+        assert_eq!(local.cells(), 60);
+        for key in 0..1000u64 {
+            local.insert(key, key * 2);
+            remote.insert(key, key * 2);
+        }
+        for key in 1000..1010u64 {
+            local.insert(key, key * 2);
+        }
+        for key in 2000..2010u64 {
+            remote.insert(key, key * 2);
+        }
+        remote.delete(5, 10);
+
+        local.subtract(&remote).expect("No errors");
+        let mut entries = local.list_entries().expect("No errors");
+        entries.inserted.sort_unstable();
+        entries.deleted.sort_unstable();
+        assert_eq!(entries.inserted, [5].into_iter().chain(1000..1010).map(|k| (k, k * 2)).collect::<Vec<_>>());
+        assert_eq!(entries.deleted, (2000..2010).map(|k| (k, k * 2)).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_iblt_overloaded() {
+        let mut table = Iblt::<u64, u8>::new(10, 3);
+        for key in 0..100u64 {
+            table.insert(key, 1);
+        }
+        assert_eq!(table.list_entries().map_err(|e| e.kind()), Err(ErrorKind::Budget));
+        assert!(table.subtract(&Iblt::new(20, 3)).is_err());
+    }
+
+    #[test]
+    fn test_iblt_crafted() {
+        // A key that sits in only one of its positions would be peeled back and forth
+        // forever.
+        let mut table = Iblt::<u64, bool>::new(9, 3);
+        let p = table.positions(&7).next().unwrap();
+        table.cells[p] = Cell { count: 1, key_sum: 7, value_sum: false, hash_sum: Iblt::<u64, bool>::checksum(&7) };
+        assert_eq!(table.list_entries().map_err(|e| e.kind()), Err(ErrorKind::Budget));
+    }
+
+    #[test]
+    fn test_iblt_bytes() {
+        let mut table = Iblt::<u64, bool>::new(9, 3);
+        for key in 0..4u64 {
+            table.insert(key, key % 2 == 0);
+        }
+        table.delete(10, true);
+        let bytes = table.to_bytes();
+        assert_eq!(bytes.len(), 16 + 9 * 25);
+        assert_eq!(Iblt::from_bytes(&bytes).expect("No errors"), table);
+
+        let parse = |b: &[u8]| Iblt::<u64, bool>::from_bytes(b).expect_err("Error").kind();
+        assert_eq!(parse(&bytes[..10]), ErrorKind::Parse);
+        assert_eq!(parse(&bytes[..bytes.len() - 1]), ErrorKind::Parse);
+        let mut hashes = bytes.clone();
+        hashes[0] = 0;
+        assert_eq!(parse(&hashes), ErrorKind::Parse);
+        hashes[0] = 2;
+        assert_eq!(parse(&hashes), ErrorKind::Parse);
+        // A cell count far beyond the input is refused before anything is allocated.
+        let mut cells = bytes.clone();
+        cells[8..16].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert_eq!(parse(&cells), ErrorKind::Parse);
+        let mut value = bytes.clone();
+        value[16 + 16] = 2;
+        assert_eq!(parse(&value), ErrorKind::Parse);
+    }
+
+    #[test]
+    fn test_cell_sketch_decode_unknown() {
+        let mut local = CellSketch::new(60, 3);
+        let mut remote = CellSketch::new(60, 3);
+        for key in 0..1000u64 {
+            local.toggle(key);
+            remote.toggle(key);
+        }
+        for key in 1000..1010u64 {
+            local.toggle(key);
+        }
+        for key in 2000..2010u64 {
+            remote.toggle(key);
+        }
+        // Toggling a key again removes it, as in a binary sketch.
+        local.toggle(5);
+
+        local.diff_with(&remote).expect("No errors");
+        let mut keys = local.decode_unknown().expect("No errors");
+        keys.sort_unstable();
+        assert_eq!(keys, [5].into_iter().chain(1000..1010).chain(2000..2010).collect::<Vec<_>>());
+
+        for key in 0..100u64 {
+            local.toggle(key);
+        }
+        assert_eq!(local.decode_unknown().map_err(|e| e.kind()), Err(ErrorKind::Budget));
+        assert!(local.diff_with(&CellSketch::new(60, 4)).is_err());
+    }
+}
@@ -0,0 +1,210 @@
+use std::collections::HashSet;
+use std::mem;
+
+use crate::{AsyncCandidateSource, BinaryCountSketch, BinaryCountSketchError, CandidateSource, Item, PeelStrategy, PeelingDecoder, StrictFirst};
+
+/// Peeling decode over candidates that arrive in batches, e.g. paged from storage.
+///
+/// Candidates that could not be decoded are kept between calls to `feed`. They are only
+/// re-scored when a new batch removes items from the sketch, since otherwise their
+/// scores cannot have changed.
+///
+/// Candidates with the same codes as an item already decoded are skipped, so an item
+/// supplied in several batches is only toggled out of the sketch once.
+///
+/// By default every undecoded candidate is kept. With `with_retain_threshold`, pending
+/// candidates scoring below the given score are discarded instead, which bounds memory
+/// when driving the decoder from a very large `CandidateSource`.
+pub struct IncrementalDecoder<V, S = StrictFirst> {
+    decoder: PeelingDecoder<S>,
+    sketch: BinaryCountSketch,
+    decoded: Vec<V>,
+    decoded_codes: HashSet<Vec<u64>>,
+    pending: Vec<V>,
+    retain_threshold: usize,
+    discarded: usize,
+    duplicates_skipped: usize,
+}
+
+impl<V: Item + Clone, S: PeelStrategy> IncrementalDecoder<V, S> {
+    pub fn new(decoder: PeelingDecoder<S>, sketch: BinaryCountSketch) -> Self {
+        IncrementalDecoder {
+            decoder,
+            sketch,
+            decoded: Vec::new(),
+            decoded_codes: HashSet::new(),
+            pending: Vec::new(),
+            retain_threshold: 0,
+            discarded: 0,
+            duplicates_skipped: 0,
+        }
+    }
+
+    pub fn with_retain_threshold(mut self, min_score: usize) -> Self {
+        self.retain_threshold = min_score;
+        self
+    }
+
+    /// Decodes a new batch of candidates, returning the items decoded by this call.
+    pub fn feed(&mut self, batch: &[V]) -> Result<&[V], BinaryCountSketchError> {
+        let before = self.decoded.len();
+
+        let fresh: Vec<V> = batch.iter().filter(|item| !self.decoded_codes.contains(&self.codes(*item))).cloned().collect();
+        self.duplicates_skipped += batch.len() - fresh.len();
+        let report = self.decoder.decode(&mut self.sketch, &fresh)?;
+        let progress = !report.decoded.is_empty();
+        self.record(report.decoded);
+        let mut undecoded = report.undecoded;
+
+        if progress && !self.pending.is_empty() {
+            let mut candidates = mem::take(&mut self.pending);
+            candidates.append(&mut undecoded);
+            let report = self.decoder.decode(&mut self.sketch, &candidates)?;
+            self.record(report.decoded);
+            undecoded = report.undecoded;
+        }
+
+        for item in undecoded {
+            if self.retain_threshold == 0 || self.sketch.check(&item) >= self.retain_threshold {
+                self.pending.push(item);
+            } else {
+                self.discarded += 1;
+            }
+        }
+
+        Ok(&self.decoded[before..])
+    }
+
+    fn codes(&self, item: &V) -> Vec<u64> {
+        (0..self.sketch.points_of(item)).map(|i| item.get_code(i)).collect()
+    }
+
+    fn record(&mut self, decoded: Vec<V>) {
+        for item in decoded {
+            self.decoded_codes.insert(self.codes(&item));
+            self.decoded.push(item);
+        }
+    }
+
+    /// Feeds every page of `source`, returning the number of items decoded.
+    pub fn drain<C: CandidateSource<Item = V>>(&mut self, source: &mut C) -> Result<usize, BinaryCountSketchError> {
+        let before = self.decoded.len();
+        while let Some(page) = source.next_page()? {
+            self.feed(&page)?;
+        }
+        Ok(self.decoded.len() - before)
+    }
+
+    /// Asynchronous version of `drain`.
+    pub async fn drain_async<C: AsyncCandidateSource<Item = V>>(&mut self, source: &mut C) -> Result<usize, BinaryCountSketchError> {
+        let before = self.decoded.len();
+        while let Some(page) = source.next_page().await? {
+            self.feed(&page)?;
+        }
+        Ok(self.decoded.len() - before)
+    }
+
+    pub fn decoded(&self) -> &[V] {
+        &self.decoded
+    }
+
+    pub fn pending(&self) -> &[V] {
+        &self.pending
+    }
+
+    /// Number of candidates dropped for scoring below the retain threshold.
+    pub fn discarded(&self) -> usize {
+        self.discarded
+    }
+
+    /// Number of candidates skipped for having the codes of an item already decoded.
+    pub fn duplicates_skipped(&self) -> usize {
+        self.duplicates_skipped
+    }
+
+    pub fn sketch(&self) -> &BinaryCountSketch {
+        &self.sketch
+    }
+
+    pub fn into_sketch(self) -> BinaryCountSketch {
+        self.sketch
+    }
+}
+
+#[cfg(all(test, feature = "rand"))]
+mod tests {
+    use super::*;
+    use crate::testing::Scenario;
+    use crate::{FixedThreshold, IterSource, SketchParams, TestItem};
+
+    #[test]
+    fn test_incremental_batches() {
+        let (sketch, candidates, _) = Scenario::new(SketchParams::new(100, 2, 5), 2000, 20).peers(0).decode_inputs();
+        let expected = PeelingDecoder::new(4).decode(&mut sketch.clone(), &candidates).expect("No errors");
+
+        let mut decoder = IncrementalDecoder::new(PeelingDecoder::new(4), sketch);
+        let mut total = 0;
+        for batch in candidates.chunks(300) {
+            total += decoder.feed(batch).expect("No errors").len();
+        }
+
+        assert_eq!(total, decoder.decoded().len());
+        assert_eq!(decoder.decoded().len(), expected.decoded.len());
+        assert_eq!(decoder.decoded().len() + decoder.pending().len(), candidates.len());
+    }
+
+    #[test]
+    fn test_incremental_drain_source() {
+        let (sketch, candidates, extra) = Scenario::new(SketchParams::new(100, 2, 5), 2000, 20).peers(0).decode_inputs();
+
+        let mut decoder = IncrementalDecoder::new(PeelingDecoder::new(4), sketch).with_retain_threshold(1);
+        let decoded = decoder.drain(&mut IterSource::new(candidates.iter().cloned(), 100)).expect("No errors");
+
+        assert_eq!(decoded, decoder.decoded().len());
+        assert!(decoder.decoded().iter().all(|item| extra.contains(item)));
+        assert_eq!(decoder.decoded().len() + decoder.pending().len() + decoder.discarded(), candidates.len());
+        assert!(decoder.pending().len() < 100);
+    }
+
+    #[test]
+    fn test_incremental_retries_pending() {
+        // Bits 3 and 4 cancel out, so `b` only reaches the threshold once `a` is removed.
+        let a = TestItem { points: vec![1, 2, 3] };
+        let b = TestItem { points: vec![3, 4, 5] };
+        let c = TestItem { points: vec![4, 6, 7] };
+
+        let mut sketch = BinaryCountSketch::new(1, 0, 3);
+        sketch.toggle(&a);
+        sketch.toggle(&b);
+        sketch.toggle(&c);
+
+        let mut decoder = IncrementalDecoder::new(PeelingDecoder::new(2), sketch);
+        assert!(decoder.feed(std::slice::from_ref(&b)).expect("No errors").is_empty());
+        assert_eq!(decoder.pending(), std::slice::from_ref(&b));
+
+        assert_eq!(decoder.feed(std::slice::from_ref(&a)).expect("No errors"), &[a.clone(), b.clone()]);
+        assert!(decoder.pending().is_empty());
+
+        assert_eq!(decoder.feed(std::slice::from_ref(&c)).expect("No errors"), &[c]);
+        assert!(decoder.into_sketch().words.iter().all(|w| *w == 0));
+    }
+
+    #[test]
+    fn test_incremental_skips_decoded() {
+        // Once `a` is removed, bit 3 of `e` is set and `a` would score 1 again.
+        let a = TestItem { points: vec![1, 2, 3] };
+        let e = TestItem { points: vec![3, 8, 9] };
+
+        let mut sketch = BinaryCountSketch::new(1, 0, 3);
+        sketch.toggle(&a);
+        sketch.toggle(&e);
+
+        let mut decoder = IncrementalDecoder::new(PeelingDecoder::with_strategy(FixedThreshold(1)), sketch);
+        assert_eq!(decoder.feed(std::slice::from_ref(&a)).expect("No errors"), std::slice::from_ref(&a));
+        assert!(decoder.feed(&[a.clone(), a]).expect("No errors").is_empty());
+        assert_eq!(decoder.duplicates_skipped(), 2);
+
+        assert_eq!(decoder.feed(std::slice::from_ref(&e)).expect("No errors"), &[e]);
+        assert!(decoder.into_sketch().words.iter().all(|w| *w == 0));
+    }
+}
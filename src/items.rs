@@ -0,0 +1,163 @@
+use core::hash::{Hash, Hasher};
+
+use crate::{splitmix64, Item, StableHasher};
+
+fn stable_hash<T: Hash>(value: &T, key: u64) -> u64 {
+    let mut hasher = StableHasher::with_key(key);
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+fn code(hash: u64, i: u64) -> u64 {
+    splitmix64(hash ^ splitmix64(i))
+}
+
+/// Borrowed byte string as an `Item`, e.g. a key or an encoded record. The bytes are
+/// hashed as they are with a single `StableHasher::write`, not through the `Hash` impl
+/// of slices, so the codes only depend on the bytes and the key. These are the items
+/// of `BinaryCountSketch::toggle_bytes`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct BytesItem<'a> {
+    bytes: &'a [u8],
+    hash: u64,
+}
+
+impl<'a> BytesItem<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        BytesItem::with_key(bytes, 0)
+    }
+
+    pub fn with_key(bytes: &'a [u8], key: u64) -> Self {
+        let mut hasher = StableHasher::with_key(key);
+        hasher.write(bytes);
+        BytesItem { bytes, hash: hasher.finish() }
+    }
+
+    pub fn bytes(&self) -> &'a [u8] {
+        self.bytes
+    }
+}
+
+impl<'a> From<&'a [u8]> for BytesItem<'a> {
+    fn from(bytes: &'a [u8]) -> Self {
+        BytesItem::new(bytes)
+    }
+}
+
+impl Item for BytesItem<'_> {
+    fn get_code(&self, i: u64) -> u64 {
+        code(self.hash, i)
+    }
+}
+
+/// Integer identifier as an `Item`, e.g. a row id or sequence number. Its codes are
+/// those of `HashedItem::with_key(value, key)`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct U64Item {
+    value: u64,
+    hash: u64,
+}
+
+impl U64Item {
+    pub fn new(value: u64) -> Self {
+        U64Item::with_key(value, 0)
+    }
+
+    pub fn with_key(value: u64, key: u64) -> Self {
+        U64Item { value, hash: stable_hash(&value, key) }
+    }
+
+    pub fn value(&self) -> u64 {
+        self.value
+    }
+}
+
+impl From<u64> for U64Item {
+    fn from(value: u64) -> Self {
+        U64Item::new(value)
+    }
+}
+
+impl Item for U64Item {
+    fn get_code(&self, i: u64) -> u64 {
+        code(self.hash, i)
+    }
+}
+
+/// 16 byte identifier as an `Item`, e.g. a UUID or a truncated transaction hash. Its
+/// codes are those of `HashedItem::with_key(bytes, key)`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct UuidItem {
+    bytes: [u8; 16],
+    hash: u64,
+}
+
+impl UuidItem {
+    pub fn new(bytes: [u8; 16]) -> Self {
+        UuidItem::with_key(bytes, 0)
+    }
+
+    pub fn with_key(bytes: [u8; 16], key: u64) -> Self {
+        UuidItem { bytes, hash: stable_hash(&bytes, key) }
+    }
+
+    /// Same as `new` with the bytes of `uuid` in big-endian order, as UUIDs are printed.
+    pub fn from_u128(uuid: u128) -> Self {
+        UuidItem::new(uuid.to_be_bytes())
+    }
+
+    pub fn bytes(&self) -> &[u8; 16] {
+        &self.bytes
+    }
+}
+
+impl From<[u8; 16]> for UuidItem {
+    fn from(bytes: [u8; 16]) -> Self {
+        UuidItem::new(bytes)
+    }
+}
+
+impl Item for UuidItem {
+    fn get_code(&self, i: u64) -> u64 {
+        code(self.hash, i)
+    }
+}
+
+#[cfg(all(test, feature = "rand"))]
+mod tests {
+    use super::*;
+    use crate::{BinaryCountSketch, HashedItem, PeelingDecoder};
+
+    #[test]
+    fn test_items_match_hashed_item() {
+        let bytes = b"tx-7f3a".as_slice();
+        let mut hasher = StableHasher::with_key(3);
+        hasher.write(bytes);
+        for i in 0..5 {
+            assert_eq!(BytesItem::with_key(bytes, 3).get_code(i), code(hasher.finish(), i));
+            assert_eq!(U64Item::new(42).get_code(i), HashedItem::new(42u64).get_code(i));
+            assert_eq!(UuidItem::new([1; 16]).get_code(i), HashedItem::new([1u8; 16]).get_code(i));
+        }
+        assert_ne!(U64Item::new(42).get_code(0), U64Item::with_key(42, 1).get_code(0));
+        assert_eq!(UuidItem::from_u128(0x0102).bytes()[14..], [1, 2]);
+    }
+
+    #[test]
+    fn test_u64_items_decode() {
+        let mut sketch1 = BinaryCountSketch::new(100, 2, 5);
+        let mut sketch2 = BinaryCountSketch::new(100, 2, 5);
+        let candidates: Vec<U64Item> = (0..1000).map(U64Item::new).collect();
+        for item in &candidates[..995] {
+            sketch1.toggle(item);
+        }
+        for item in &candidates[5..] {
+            sketch2.toggle(item);
+        }
+        sketch1.diff_with(&sketch2).expect("No errors");
+
+        let report = PeelingDecoder::new(4).decode(&mut sketch1, &candidates).expect("No errors");
+        let mut decoded: Vec<u64> = report.decoded.iter().map(U64Item::value).collect();
+        decoded.sort();
+        assert_eq!(decoded, [0, 1, 2, 3, 4, 995, 996, 997, 998, 999]);
+    }
+}
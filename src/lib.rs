@@ -1,20 +1,281 @@
-#![feature(test)]
+#![cfg_attr(not(feature = "std"), no_std)]
+// Argument checks state the precondition they require, as `if !(expected) { return Err(..) }`.
+#![allow(clippy::nonminimal_bool)]
+
+extern crate alloc;
+
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cell::RefCell;
+use core::cmp::Ordering;
+use core::error::Error;
+use core::fmt;
+use core::ops::BitXor;
+
+use rand_core::RngCore;
+
+pub mod adaptive;
+#[cfg(feature = "std")]
+pub mod advice;
+pub mod batch;
+#[cfg(feature = "std")]
+pub mod bp;
+#[cfg(feature = "std")]
+pub mod builder;
+pub mod bundle;
+#[cfg(feature = "std")]
+pub mod cardinality;
+#[cfg(feature = "codec")]
+pub mod codec;
+pub mod compose;
+pub mod compress;
+#[cfg(target_has_atomic = "64")]
+pub mod concurrent;
+pub mod counter;
+#[cfg(feature = "net")]
+pub mod filesync;
+pub mod fixed;
+pub mod hashed;
+#[cfg(feature = "std")]
+pub mod hierarchy;
+pub mod iblt;
+#[cfg(feature = "std")]
+pub mod incremental;
+pub mod items;
+#[cfg(feature = "std")]
+pub mod lightclient;
+#[cfg(feature = "net")]
+pub mod net;
+pub mod params;
+pub mod partition;
+#[cfg(feature = "std")]
+pub mod peel;
+pub mod presence;
+pub mod progressive;
+#[cfg(feature = "proto")]
+pub mod proto;
+#[cfg(feature = "quic")]
+pub mod quic;
+#[cfg(feature = "std")]
+pub mod reconcile;
+#[cfg(feature = "serde")]
+mod serde_impl;
+pub mod shard;
+pub mod slice;
+#[cfg(feature = "std")]
+pub mod soft;
+pub mod source;
+pub mod strata;
+#[cfg(any(feature = "testing", all(test, feature = "rand")))]
+pub mod testing;
+#[cfg(feature = "std")]
+pub mod tracked;
+pub mod view;
+pub mod window;
+pub mod wire;
+
+pub use adaptive::{AdaptiveSketch, ResizeEvent};
+#[cfg(feature = "std")]
+pub use advice::{advise, Advice, ReconcileStrategy, MAX_LOAD_FACTOR};
+pub use batch::{Checkpoint, JournaledSketch, ToggleBatch};
+#[cfg(feature = "std")]
+pub use bp::BpReport;
+#[cfg(feature = "std")]
+pub use builder::SketchBuilder;
+pub use bundle::{MultiLevelSketch, SketchBundle};
+#[cfg(feature = "std")]
+pub use cardinality::{HyperLogLog, SetDigest};
+#[cfg(feature = "codec")]
+pub use codec::SketchCodec;
+pub use compose::{ComposedSketch, DirectoryEntry};
+pub use compress::CompressedSketch;
+#[cfg(target_has_atomic = "64")]
+pub use concurrent::ConcurrentBinaryCountSketch;
+pub use counter::CounterSketch;
+#[cfg(feature = "net")]
+pub use filesync::{Chunk, ChunkDiff, DirSync};
+pub use fixed::FixedPointsSketch;
+pub use hashed::{HashedItem, StableHasher};
+#[cfg(feature = "std")]
+pub use hierarchy::HierarchicalReport;
+pub use iblt::{CellSketch, FixedBytes, Iblt, IbltEntries};
+#[cfg(feature = "std")]
+pub use incremental::IncrementalDecoder;
+pub use items::{BytesItem, U64Item, UuidItem};
+#[cfg(feature = "std")]
+pub use lightclient::{FullNode, LightClient, SyncReport};
+#[cfg(feature = "net")]
+pub use net::{Peer, Reconciled, Timeouts};
+pub use params::{SketchParams, MAX_PARSED_POINTS};
+pub use partition::PartitionedSketch;
+#[cfg(feature = "std")]
+pub use peel::{BackOffOnRejects, CancellationToken, DecodeBudget, DEFAULT_FP_RATE, DecodeReport, DecodeStatus, DecodeTrace, FixedThreshold, PeelStrategy, PeelingDecoder, PeelingResult, ReconcileResult, Recovered, RoundTrace, StrictFirst};
+pub use presence::BloomFilter;
+pub use progressive::LevelDelta;
+#[cfg(feature = "std")]
+#[cfg(feature = "quic")]
+pub use quic::{receive_shards, send_shards, ReceivedShards};
+#[cfg(feature = "std")]
+pub use reconcile::{Message, ReconcileMetrics, Reconciler, ReconcilerBuilder};
+pub use shard::{jump_consistent_hash, ShardAssigner, ShardedSketch};
+pub use slice::SketchSlice;
+#[cfg(feature = "std")]
+pub use soft::select_by_likelihood;
+pub use source::{AsyncCandidateSource, CandidateSource, IterSource};
+pub use strata::StrataEstimator;
+#[cfg(feature = "std")]
+pub use tracked::{SymmetricDifference, TrackedSet};
+pub use view::BinaryCountSketchView;
+pub use window::{RotatingSketch, WindowedSketch};
+
+pub(crate) fn splitmix64(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+/// Key used to route an item to a partition or shard, identical on every peer.
+pub(crate) fn route_key<V: Item>(v: &V) -> u64 {
+    splitmix64(v.get_code(0))
+}
 
-use std::fmt;
-use std::error::Error;
+/// Bit of a sketch of `bits` bits that point `i` of `v` is encoded in.
+pub(crate) fn code_index<V: Item + ?Sized>(v: &V, i: u64, bits: usize) -> usize {
+    (v.get_code(i) % bits as u64) as usize
+}
+
+/// Items per chunk of `BinaryCountSketch::toggle_all`, bounding its buffer of bit indices.
+const TOGGLE_CHUNK: usize = 1 << 16;
 
-extern crate test;
+/// Bits per block `toggle_all` groups flips into, 32KiB of words to fit an L1 cache.
+const TOGGLE_BLOCK_BITS: usize = 1 << 18;
 
 pub trait Item {
-    fn get_code(&self, i: u64) -> usize;
+    /// Code of point `i` of the item, reduced modulo the number of bits of a sketch to
+    /// the bit the point is encoded in. Codes are `u64` so peers on 32-bit and 64-bit
+    /// targets encode items in the same bits.
+    fn get_code(&self, i: u64) -> u64;
+
+    /// Number of points this item is encoded with in a sketch of `max` points. Items
+    /// returning fewer points than `max` are toggled and checked with only their first
+    /// points, and decode thresholds are scaled to their point count.
+    fn points(&self, max: u64) -> u64 {
+        max
+    }
+}
+
+impl<T: Item + ?Sized> Item for &T {
+    fn get_code(&self, i: u64) -> u64 {
+        (**self).get_code(i)
+    }
+
+    fn points(&self, max: u64) -> u64 {
+        (**self).points(max)
+    }
+}
+
+/// Broad category of a `BinaryCountSketchError`, stable across releases so callers can
+/// match on it rather than on error messages.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    /// Sketches or parts with mismatched parameters were combined.
+    Compatibility,
+    /// Serialized data was malformed or inconsistent.
+    Parse,
+    /// An argument was out of range for the sketch it was used with.
+    InvalidArgument,
+    /// A work or size budget was exceeded.
+    Budget,
+    /// Sending or receiving a sketch failed.
+    Transport,
+    /// A sketch held too many items to decode; see `BinaryCountSketchError::estimated_items`.
+    Overloaded,
+    /// A deadline passed before an exchange with a peer finished.
+    TimedOut,
+    /// Any other error, including those created by applications with `new`.
+    Other,
+}
+
+/// Parameter that differed between two sketches, tables or parts combined together.
+/// `expected` is the value on the side the operation was called on, `got` the value on
+/// the other side. Seeds are compared as little-endian integers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ParameterMismatch {
+    /// Name of the parameter as it appears in the error message, e.g. `"base length"`.
+    pub field: &'static str,
+    pub expected: u128,
+    pub got: u128,
+}
+
+/// Parameter value that can be reported in a `ParameterMismatch`.
+pub(crate) trait MismatchValue {
+    fn to_u128(self) -> u128;
+}
+
+impl MismatchValue for u64 {
+    fn to_u128(self) -> u128 {
+        self as u128
+    }
+}
+
+impl MismatchValue for usize {
+    fn to_u128(self) -> u128 {
+        self as u128
+    }
+}
+
+impl MismatchValue for [u8; 16] {
+    fn to_u128(self) -> u128 {
+        u128::from_le_bytes(self)
+    }
 }
 
 #[derive(Debug)]
-pub struct BinaryCountSketchError { details: String }
+pub struct BinaryCountSketchError { kind: ErrorKind, details: String, mismatch: Option<ParameterMismatch>, estimated_items: Option<usize> }
 
 impl BinaryCountSketchError {
     pub fn new(details:&str) -> Self {
-        BinaryCountSketchError { details: details.to_string() }
+        BinaryCountSketchError::with_kind(ErrorKind::Other, details)
+    }
+
+    pub fn with_kind(kind: ErrorKind, details: &str) -> Self {
+        BinaryCountSketchError { kind, details: details.to_string(), mismatch: None, estimated_items: None }
+    }
+
+    /// `Compatibility` error for a parameter that differs, displayed as "Incorrect `field`".
+    pub(crate) fn with_mismatch<T: MismatchValue>(field: &'static str, expected: T, got: T) -> Self {
+        BinaryCountSketchError {
+            kind: ErrorKind::Compatibility,
+            details: alloc::format!("Incorrect {}", field),
+            mismatch: Some(ParameterMismatch { field, expected: expected.to_u128(), got: got.to_u128() }),
+            estimated_items: None,
+        }
+    }
+
+    /// `Overloaded` error for a sketch holding about `estimated_items` items, `None` if
+    /// too many to estimate.
+    #[cfg(feature = "std")]
+    pub(crate) fn overloaded(estimated_items: Option<usize>) -> Self {
+        BinaryCountSketchError { estimated_items, ..BinaryCountSketchError::with_kind(ErrorKind::Overloaded, "Too many items to decode") }
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+
+    /// The differing parameter, for `Compatibility` errors raised when combining sketches
+    /// or parts with different parameters.
+    pub fn mismatch(&self) -> Option<&ParameterMismatch> {
+        self.mismatch.as_ref()
+    }
+
+    /// Estimated number of items in the sketch, for `Overloaded` errors. `None` if the
+    /// sketch is too saturated to estimate it.
+    pub fn estimated_items(&self) -> Option<usize> {
+        self.estimated_items
     }
 }
 
@@ -26,29 +287,64 @@ impl fmt::Display for BinaryCountSketchError {
 
 impl Error for BinaryCountSketchError {}
 
+#[derive(Clone, PartialEq, Eq)]
 pub struct BinaryCountSketch {
     base_length: u64,
     level: u64,
     points: u64,
+    seed: [u8; 16],
     words: Vec<u64>,
 }
 
 impl BinaryCountSketch {
     pub fn new(base_length: u64, level: u64, points: u64) -> Self {
+        BinaryCountSketch::with_seed(base_length, level, points, [0; 16])
+    }
+
+    /// Creates a sketch whose `toggle_bytes`, `check_bytes`, `keyed_item` and
+    /// `keyed_bytes` derive item codes from `seed`. Only sketches with the same seed can
+    /// be diffed.
+    pub fn with_seed(base_length: u64, level: u64, points: u64, seed: [u8; 16]) -> Self {
         BinaryCountSketch {
             base_length,
             level,
             points,
+            seed,
             words: vec![0; (base_length << level) as usize],
         }
     }
 
+    pub fn base_length(&self) -> u64 {
+        self.base_length
+    }
+
+    pub fn level(&self) -> u64 {
+        self.level
+    }
+
+    pub fn points(&self) -> u64 {
+        self.points
+    }
+
     pub fn bits(&self) -> usize {
         self.words.len() * 64
     }
 
+    /// Stable 64-bit digest of the parameters and words, for cheaply detecting
+    /// whether two sketches differ.
+    pub fn digest(&self) -> u64 {
+        let mut h = splitmix64(self.base_length ^ splitmix64(self.level ^ splitmix64(self.points)));
+        for half in self.seed.chunks(8) {
+            h = splitmix64(h ^ u64::from_le_bytes(half.try_into().unwrap()));
+        }
+        for word in &self.words {
+            h = splitmix64(h ^ *word);
+        }
+        h
+    }
+
     pub fn level_down(&self, new_level: u64) -> Result<Self,BinaryCountSketchError> {
-        if !(new_level < self.level) { return Err(BinaryCountSketchError::new("Incorrect level")); }
+        if !(new_level < self.level) { return Err(BinaryCountSketchError::with_kind(ErrorKind::InvalidArgument, "Incorrect level")); }
 
         let mut new_words = vec![0; (self.base_length << new_level) as usize];
         let l = new_words.len();
@@ -61,15 +357,37 @@ impl BinaryCountSketch {
             base_length: self.base_length,
             level: new_level,
             points: self.points,
+            seed: self.seed,
             words: new_words,
         })
     }
 
+    /// Sketch of `items` at `new_level`, with the same base length, points and seed.
+    ///
+    /// Unlike `level_down`, going up a level needs the items: each bit of this sketch
+    /// is the parity of several bits of the larger one, which it cannot be unfolded into.
+    /// Returns an error if `items` do not fold back into exactly this sketch, e.g. if
+    /// some were toggled since, so the larger sketch never silently disagrees with it.
+    pub fn rebuild_at_level<V: Item>(&self, items: &[V], new_level: u64) -> Result<Self, BinaryCountSketchError> {
+        let mut rebuilt = BinaryCountSketch::with_seed(self.base_length, new_level, self.points, self.seed);
+        rebuilt.toggle_all(items);
+
+        let matches = match new_level.cmp(&self.level) {
+            Ordering::Greater => rebuilt.level_down(self.level)?.words == self.words,
+            Ordering::Equal => rebuilt.words == self.words,
+            Ordering::Less => self.level_down(new_level)?.words == rebuilt.words,
+        };
+        if !matches { return Err(BinaryCountSketchError::with_kind(ErrorKind::InvalidArgument, "Incorrect items")); }
+
+        Ok(rebuilt)
+    }
+
     pub fn diff_with(&mut self, other: &Self) -> Result<(),BinaryCountSketchError> {
-        if !(self.base_length == other.base_length) { return Err(BinaryCountSketchError::new("Incorrect base length")); }
-        if !(self.level == other.level) { return Err(BinaryCountSketchError::new("Incorrect level")); }
-        if !(self.points == other.points) { return Err(BinaryCountSketchError::new("Incorrect points")); }
-        if !(self.words.len() == other.words.len()) { return Err(BinaryCountSketchError::new("Incorrect words length")); }
+        if !(self.base_length == other.base_length) { return Err(BinaryCountSketchError::with_mismatch("base length", self.base_length, other.base_length)); }
+        if !(self.level == other.level) { return Err(BinaryCountSketchError::with_mismatch("level", self.level, other.level)); }
+        if !(self.points == other.points) { return Err(BinaryCountSketchError::with_mismatch("points", self.points, other.points)); }
+        if !(self.seed == other.seed) { return Err(BinaryCountSketchError::with_mismatch("seed", self.seed, other.seed)); }
+        if !(self.words.len() == other.words.len()) { return Err(BinaryCountSketchError::with_mismatch("words length", self.words.len(), other.words.len())); }
 
         for (i, val) in other.words.iter().enumerate() {
             self.words[i] ^= *val;
@@ -78,10 +396,84 @@ impl BinaryCountSketch {
         Ok(())
     }
 
+    /// Same as `diff_with`, returning the diff as a new sketch instead of updating this one.
+    pub fn diff(&self, other: &Self) -> Result<Self,BinaryCountSketchError> {
+        let mut diff = self.clone();
+        diff.diff_with(other)?;
+        Ok(diff)
+    }
+
+    /// Adds the items of `other` to this sketch. For sketches of disjoint sets, e.g.
+    /// per-shard sketches built in parallel, the result is the sketch of their union.
+    /// Items present in both cancel out, as with `diff_with`; use `CounterSketch` to
+    /// keep them.
+    pub fn merge_with(&mut self, other: &Self) -> Result<(),BinaryCountSketchError> {
+        self.diff_with(other)
+    }
+
+    /// Merges all of `parts` into a new sketch, see `merge_with`.
+    pub fn try_merge_many(parts: &[Self]) -> Result<Self,BinaryCountSketchError> {
+        let (first, rest) = parts.split_first().ok_or_else(|| BinaryCountSketchError::with_kind(ErrorKind::InvalidArgument, "Incorrect parts"))?;
+        let mut merged = first.clone();
+        for part in rest {
+            merged.merge_with(part)?;
+        }
+        Ok(merged)
+    }
+
+    /// Number of points `v` uses in this sketch, at most the sketch's points.
+    pub fn points_of<V: Item>(&self, v: &V) -> u64 {
+        v.points(self.points).min(self.points)
+    }
+
     pub fn toggle<V: Item>(&mut self, v: &V) {
         let l = self.words.len() * 64;
-        for i in 0..self.points {
-            let b = v.get_code(i) % l;
+        for i in 0..self.points_of(v) {
+            let b = code_index(v, i, l);
+            self.words[b / 64] ^= 1 << (b % 64);
+        }
+    }
+
+    /// Same as toggling each of `items`, but the bit indices of a chunk of items are
+    /// grouped by region of the sketch before being applied, which avoids cache misses
+    /// on sketches larger than the CPU caches.
+    pub fn toggle_all<V: Item>(&mut self, items: &[V]) {
+        let l = self.bits();
+        let mut bits = Vec::with_capacity(items.len().min(TOGGLE_CHUNK) * self.points as usize);
+        for chunk in items.chunks(TOGGLE_CHUNK) {
+            bits.clear();
+            for v in chunk {
+                bits.extend((0..self.points_of(v)).map(|i| code_index(v, i, l)));
+            }
+            self.toggle_bits(&bits);
+        }
+    }
+
+    /// Flips every bit of `bits`, grouped with a counting sort into blocks of
+    /// `TOGGLE_BLOCK_BITS` so each block is only brought into cache once.
+    pub(crate) fn toggle_bits(&mut self, bits: &[usize]) {
+        let blocks = self.bits().div_ceil(TOGGLE_BLOCK_BITS);
+        if blocks <= 1 {
+            for b in bits {
+                self.words[b / 64] ^= 1 << (b % 64);
+            }
+            return;
+        }
+
+        let mut starts = vec![0; blocks + 1];
+        for b in bits {
+            starts[b / TOGGLE_BLOCK_BITS + 1] += 1;
+        }
+        for i in 1..=blocks {
+            starts[i] += starts[i - 1];
+        }
+        let mut grouped = vec![0; bits.len()];
+        for b in bits {
+            let slot = &mut starts[b / TOGGLE_BLOCK_BITS];
+            grouped[*slot] = *b;
+            *slot += 1;
+        }
+        for b in grouped {
             self.words[b / 64] ^= 1 << (b % 64);
         }
     }
@@ -89,10 +481,9 @@ impl BinaryCountSketch {
     pub fn check<V: Item>(&self, v: &V) -> usize {
         let l = self.words.len();
 
-        (0..self.points)
-            .into_iter()
+        (0..self.points_of(v))
             .map(|i| {
-                let b = v.get_code(i) % (l * 64);
+                let b = code_index(v, i, l * 64);
                 if self.words[b / 64] & (1 << (b % 64)) != 0 {
                     1usize
                 } else {
@@ -106,16 +497,44 @@ impl BinaryCountSketch {
         items.iter().map(|item| self.check(item)).collect()
     }
 
-    pub fn estimate_stats(&self, samples: usize, threshold: usize) -> Result<(usize, usize), BinaryCountSketchError> {
-        if !(threshold <= self.points as usize) { return Err(BinaryCountSketchError::new("Incorrect threshold")); }
+    /// Indices of the `members`, items the caller expects to be in the sketch exactly
+    /// once, that appear not to be, e.g. because they were toggled twice or never.
+    ///
+    /// Toggling every member out of a sketch holding exactly the members leaves it empty;
+    /// the members flagged are those whose points are all set in what remains. Bits left
+    /// set by no flagged member come from items that are not members at all. Members
+    /// toggled an even number of times are not told apart from missing ones, which
+    /// `CounterSketch::verify_against` does.
+    pub fn verify_against<V: Item>(&self, members: &[V]) -> Vec<usize> {
+        let mut residual = self.clone();
+        for v in members {
+            residual.toggle(v);
+        }
+        members
+            .iter()
+            .enumerate()
+            .filter(|(_, v)| residual.check(*v) == residual.points_of(*v) as usize)
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    /// Same as `decode`, checking the items on the rayon thread pool.
+    #[cfg(feature = "rayon")]
+    pub fn decode_par<V: Item + Sync>(&self, items: &[V]) -> Vec<usize> {
+        use rayon::prelude::*;
+        items.par_iter().map(|item| self.check(item)).collect()
+    }
+
+    pub fn estimate_stats<R: RngCore + ?Sized>(&self, rng: &mut R, samples: usize, threshold: usize) -> Result<(usize, usize), BinaryCountSketchError> {
+        if threshold > self.points as usize { return Err(BinaryCountSketchError::with_kind(ErrorKind::InvalidArgument, "Incorrect threshold")); }
 
-        struct Rand;
-        impl Item for Rand {
-            fn get_code(&self, _i: u64) -> usize {
-                rand::random::<usize>()
+        struct Rand<'a, R: ?Sized>(RefCell<&'a mut R>);
+        impl<R: RngCore + ?Sized> Item for Rand<'_, R> {
+            fn get_code(&self, _i: u64) -> u64 {
+                self.0.borrow_mut().next_u64()
             }
         }
-        let r = Rand;
+        let r = Rand(RefCell::new(rng));
 
         let mut false_pos = 0;
         let mut false_neg = 0;
@@ -131,47 +550,107 @@ impl BinaryCountSketch {
 
         Ok((false_pos, false_neg))
     }
+
+    /// Same as `estimate_stats`, drawing the samples from a splitmix64 sequence seeded
+    /// with `seed`, so the result is reproducible across runs and platforms.
+    pub fn estimate_stats_seeded(&self, seed: u64, samples: usize, threshold: usize) -> Result<(usize, usize), BinaryCountSketchError> {
+        self.estimate_stats(&mut SplitMix(seed), samples, threshold)
+    }
 }
 
-#[derive(Clone, PartialEq, Eq, Hash)]
+/// `&a ^ &b` is `a.diff(&b)`.
+impl BitXor for &BinaryCountSketch {
+    type Output = Result<BinaryCountSketch, BinaryCountSketchError>;
+
+    fn bitxor(self, other: &BinaryCountSketch) -> Self::Output {
+        self.diff(other)
+    }
+}
+
+/// Summarizes the words by their number of set bits and digest, since a sketch can span
+/// megabytes.
+impl fmt::Debug for BinaryCountSketch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BinaryCountSketch")
+            .field("base_length", &self.base_length)
+            .field("level", &self.level)
+            .field("points", &self.points)
+            .field("seed", &self.seed)
+            .field("bits", &self.bits())
+            .field("ones", &self.words.iter().map(|w| w.count_ones()).sum::<u32>())
+            .field("digest", &format_args!("{:016x}", self.digest()))
+            .finish()
+    }
+}
+
+/// Counter-based generator returning `splitmix64` of successive states.
+struct SplitMix(u64);
+
+impl RngCore for SplitMix {
+    fn next_u32(&mut self) -> u32 {
+        self.next_u64() as u32
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(1);
+        splitmix64(self.0)
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        rand_core::impls::fill_bytes_via_next(self, dest)
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+/// Number of points a `TestItem` has codes for.
+#[cfg(feature = "rand")]
+const TEST_ITEM_POINTS: usize = 15;
+
+#[cfg(feature = "rand")]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct TestItem {
-    points: Vec<usize>,
+    points: Vec<u64>,
 }
 
+#[cfg(feature = "rand")]
 impl TestItem {
     pub fn new() -> Self {
-        TestItem {
-            points: vec![
-                rand::random::<usize>(),
-                rand::random::<usize>(),
-                rand::random::<usize>(),
-                rand::random::<usize>(),
-                rand::random::<usize>(),
-                rand::random::<usize>(),
-                rand::random::<usize>(),
-                rand::random::<usize>(),
-                rand::random::<usize>(),
-                rand::random::<usize>(),
-                rand::random::<usize>(),
-                rand::random::<usize>(),
-                rand::random::<usize>(),
-                rand::random::<usize>(),
-                rand::random::<usize>(),
-            ],
-        }
+        TestItem::from_rng(&mut rand::thread_rng())
+    }
+
+    /// Item whose codes only depend on `seed`, so tests and simulations can be replayed
+    /// on any platform.
+    pub fn from_seed(seed: u64) -> Self {
+        TestItem::from_rng(&mut SplitMix(splitmix64(seed)))
+    }
+
+    pub fn from_rng<R: RngCore + ?Sized>(rng: &mut R) -> Self {
+        TestItem { points: (0..TEST_ITEM_POINTS).map(|_| rng.next_u64()).collect() }
     }
 }
 
+#[cfg(feature = "rand")]
+impl Default for TestItem {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "rand")]
 impl Item for TestItem {
-    fn get_code(&self, i: u64) -> usize {
+    fn get_code(&self, i: u64) -> u64 {
         self.points[i as usize]
     }
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "rand"))]
+#[allow(clippy::cloned_ref_to_slice_refs)]
 mod tests {
     use super::*;
-    use test::Bencher;
 
     #[test]
     fn test_basics() {
@@ -216,11 +695,85 @@ mod tests {
         sketch.toggle(&item);
         assert_eq!(sketch.decode(&[item.clone()]), vec![3]);
 
-        let (fpos, fneg) = sketch.estimate_stats(100, 2).expect("No errors");
+        let (fpos, fneg) = sketch.estimate_stats(&mut rand::thread_rng(), 100, 2).expect("No errors");
         assert!(fpos < 5);
         assert!(fneg < 5)
     }
 
+    #[test]
+    fn test_item_from_seed() {
+        assert_eq!(TestItem::from_seed(1), TestItem::from_seed(1));
+        assert_ne!(TestItem::from_seed(1), TestItem::from_seed(2));
+
+        let mut sketch = BinaryCountSketch::new(10, 2, 3);
+        sketch.toggle(&TestItem::from_seed(1));
+        assert_eq!(sketch.check(&TestItem::from_seed(1)), 3);
+    }
+
+    #[test]
+    fn test_stats_seeded() {
+        let mut sketch = BinaryCountSketch::new(10, 2, 5);
+        for _ in 0..200 {
+            sketch.toggle(&TestItem::new());
+        }
+
+        let stats = sketch.estimate_stats_seeded(7, 1000, 4).expect("No errors");
+        assert_eq!(sketch.estimate_stats_seeded(7, 1000, 4).expect("No errors"), stats);
+        assert!(stats.0 > 0 && stats.0 < 200);
+        assert!(sketch.estimate_stats_seeded(7, 1000, 6).is_err());
+    }
+
+    #[test]
+    fn test_clone_eq_debug() {
+        let mut sketch = BinaryCountSketch::new(10, 2, 3);
+        sketch.toggle(&TestItem { points: vec![1, 70, 300] });
+        let snapshot = sketch.clone();
+        assert_eq!(snapshot, sketch);
+        assert_eq!((sketch.base_length(), sketch.level(), sketch.points()), (10, 2, 3));
+
+        sketch.diff_with(&snapshot).expect("No errors");
+        assert_ne!(snapshot, sketch);
+        assert_eq!(snapshot.words[..5], [2, 64, 0, 0, 1 << 44]);
+
+        let debug = format!("{:?}", snapshot);
+        assert!(debug.starts_with("BinaryCountSketch { base_length: 10, level: 2, points: 3, "));
+        assert!(debug.contains("bits: 2560, ones: 3, digest: "));
+    }
+
+    #[test]
+    fn test_diff_new_sketch() {
+        let items: Vec<TestItem> = (0..20).map(|_| TestItem::new()).collect();
+        let mut a = BinaryCountSketch::new(10, 2, 3);
+        let mut b = BinaryCountSketch::new(10, 2, 3);
+        for item in &items[..15] {
+            a.toggle(item);
+        }
+        for item in &items[5..] {
+            b.toggle(item);
+        }
+
+        let diff = (&a ^ &b).expect("No errors");
+        assert_eq!(a.diff(&b).expect("No errors"), diff);
+        let mut expected = a.clone();
+        expected.diff_with(&b).expect("No errors");
+        assert_eq!(diff, expected);
+        assert!((&a ^ &BinaryCountSketch::new(10, 3, 3)).is_err());
+    }
+
+    #[test]
+    fn test_toggle_all() {
+        let items: Vec<TestItem> = (0..10_000).map(|_| TestItem::new()).collect();
+        for base_length in [10, 4096] {
+            let mut batched = BinaryCountSketch::new(base_length, 2, 5);
+            let mut single = BinaryCountSketch::new(base_length, 2, 5);
+            batched.toggle_all(&items);
+            for item in &items {
+                single.toggle(item);
+            }
+            assert_eq!(batched, single);
+        }
+    }
+
     #[test]
     fn test_diff() {
         let item: TestItem = TestItem::new();
@@ -242,6 +795,97 @@ mod tests {
         assert_eq!(sketch1.decode(&[item3.clone()]), vec![3]);
     }
 
+    #[test]
+    fn test_verify_against() {
+        let items: Vec<TestItem> = (0..50).map(TestItem::from_seed).collect();
+        let mut sketch = BinaryCountSketch::new(10, 2, 5);
+        sketch.toggle_all(&items);
+        assert!(sketch.verify_against(&items).is_empty());
+
+        // A second toggle of the same item silently removes it.
+        sketch.toggle(&items[7]);
+        assert_eq!(sketch.verify_against(&items), vec![7]);
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn test_decode_par() {
+        let items: Vec<TestItem> = (0..1000).map(|_| TestItem::new()).collect();
+        let mut sketch = BinaryCountSketch::new(10, 2, 5);
+        for item in &items[..100] {
+            sketch.toggle(item);
+        }
+        assert_eq!(sketch.decode_par(&items), sketch.decode(&items));
+    }
+
+    #[test]
+    fn test_rebuild_at_level() {
+        let items: Vec<TestItem> = (0..100).map(TestItem::from_seed).collect();
+        let mut sketch = BinaryCountSketch::new(10, 2, 5);
+        sketch.toggle_all(&items);
+
+        let larger = sketch.rebuild_at_level(&items, 4).expect("No errors");
+        assert_eq!(larger.level(), 4);
+        assert_eq!(larger.level_down(2).expect("No errors"), sketch);
+        assert_eq!(sketch.rebuild_at_level(&items, 2).expect("No errors"), sketch);
+        assert_eq!(sketch.rebuild_at_level(&items, 1).expect("No errors"), sketch.level_down(1).expect("No errors"));
+
+        // Items that no longer match the sketch are refused.
+        let err = sketch.rebuild_at_level(&items[1..], 4).expect_err("Error");
+        assert_eq!(err.kind(), ErrorKind::InvalidArgument);
+    }
+
+    #[test]
+    fn test_error_kind() {
+        let mut sketch = BinaryCountSketch::new(10, 2, 3);
+        let other = BinaryCountSketch::new(10, 3, 3);
+        let err = sketch.diff_with(&other).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Compatibility);
+        assert_eq!(err.mismatch(), Some(&ParameterMismatch { field: "level", expected: 2, got: 3 }));
+        assert_eq!(err.to_string(), "Sketch Error: Incorrect level");
+        let seeded = BinaryCountSketch::with_seed(10, 2, 3, [1; 16]);
+        assert_eq!(sketch.diff_with(&seeded).unwrap_err().mismatch().expect("Mismatch").field, "seed");
+        assert_eq!(sketch.level_down(2).expect_err("Error").kind(), ErrorKind::InvalidArgument);
+        assert_eq!(BinaryCountSketch::from_parts(sketch.params(), vec![0; 3]).expect_err("Error").kind(), ErrorKind::Parse);
+        assert_eq!(BinaryCountSketchError::new("Application error").kind(), ErrorKind::Other);
+    }
+
+    #[test]
+    fn test_merge() {
+        let items: Vec<TestItem> = (0..90).map(|_| TestItem::new()).collect();
+        let mut whole = BinaryCountSketch::new(10, 2, 3);
+        for item in &items {
+            whole.toggle(item);
+        }
+        let shards: Vec<BinaryCountSketch> = items.chunks(30).map(|chunk| {
+            let mut shard = BinaryCountSketch::new(10, 2, 3);
+            for item in chunk {
+                shard.toggle(item);
+            }
+            shard
+        }).collect();
+
+        let merged = BinaryCountSketch::try_merge_many(&shards).expect("No errors");
+        assert_eq!(merged.words, whole.words);
+        assert!(BinaryCountSketch::try_merge_many(&[]).is_err());
+        assert!(BinaryCountSketch::try_merge_many(&[merged, BinaryCountSketch::new(10, 1, 3)]).is_err());
+    }
+
+    #[test]
+    fn test_digest() {
+        let item: TestItem = TestItem::new();
+        let mut sketch1 = BinaryCountSketch::new(10, 6, 3);
+        let sketch2 = BinaryCountSketch::new(10, 6, 3);
+        assert_eq!(sketch1.digest(), sketch2.digest());
+        assert_ne!(sketch1.digest(), BinaryCountSketch::new(10, 6, 4).digest());
+        assert_ne!(sketch1.digest(), BinaryCountSketch::with_seed(10, 6, 3, [1; 16]).digest());
+
+        sketch1.toggle(&item);
+        assert_ne!(sketch1.digest(), sketch2.digest());
+        sketch1.toggle(&item);
+        assert_eq!(sketch1.digest(), sketch2.digest());
+    }
+
     #[test]
     fn test_stats_bad() {
         let mut sketch = BinaryCountSketch::new(1, 0, 3);
@@ -256,9 +900,15 @@ mod tests {
         assert!(sketch.words.len() == 1);
         assert!(sketch.words[0] != 0);
 
-        let (fpos, fneg) = sketch.estimate_stats(100, 2).expect("No errors");
+        let (fpos, fneg) = sketch.estimate_stats(&mut rand::thread_rng(), 100, 2).expect("No errors");
         assert!(fpos > 10);
-        assert!(fneg > 10)
+        assert!(fneg > 10);
+
+        // The overload is detected up front rather than producing garbage.
+        assert!(sketch.is_saturated());
+        let err = sketch.reconcile(&[TestItem::new()]).expect_err("Error");
+        assert_eq!(err.kind(), ErrorKind::Overloaded);
+        assert_eq!(err.estimated_items(), None);
     }
 
     #[test]
@@ -285,7 +935,7 @@ mod tests {
         }
 
         sketch2.diff_with(&sketch1).expect("No errors");
-        let (fpos, fneg) = sketch2.estimate_stats(100, 4).expect("no errors");
+        let (fpos, fneg) = sketch2.estimate_stats(&mut rand::thread_rng(), 100, 4).expect("no errors");
 
         println!("{} bits {} bytes", sketch2.bits(), sketch2.bits() / 8);
         println!("{} {}", fpos, fneg);
@@ -311,42 +961,4 @@ mod tests {
         assert!(fpos < 10);
         assert!(fneg < 10);
     }
-
-    #[bench]
-    fn bench_toggle(b: &mut Bencher) {
-        let item = TestItem::new();
-        let mut sketch1 = BinaryCountSketch::new(100, 2, 5);
-
-        b.iter(|| {
-            let _n = test::black_box(1000);
-            sketch1.toggle(&item);
-        });
-    }
-
-    #[bench]
-    fn bench_check(b: &mut Bencher) {
-        let item = TestItem::new();
-        let mut sketch1 = BinaryCountSketch::new(100, 2, 5);
-        sketch1.toggle(&item);
-
-        b.iter(|| {
-            let _n = test::black_box(1000);
-            sketch1.check(&item);
-        });
-    }
-
-    #[bench]
-    fn bench_decode(b: &mut Bencher) {
-        let items: Vec<_> = (1..1000).into_iter().map(|_| TestItem::new()).collect();
-        let mut sketch1 = BinaryCountSketch::new(100, 2, 5);
-
-        for item in items.clone() {
-            sketch1.toggle(&item);
-        }
-
-        b.iter(|| {
-            let _n = test::black_box(1000);
-            sketch1.decode(&items);
-        });
-    }
 }
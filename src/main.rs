@@ -1,101 +1,645 @@
-use bcsk::{BinaryCountSketch, TestItem};
-use std::{env, collections::HashSet};
+use bcsk::{BinaryCountSketch, ConcurrentBinaryCountSketch, HashedItem, PeelingDecoder, SketchParams, StableHasher, TestItem, DEFAULT_FP_RATE};
+use std::{env, collections::{HashMap, HashSet}, fs, hash::Hasher, io::{self, BufRead, Write}, mem, path::{Path, PathBuf}, process, str::FromStr, sync::{mpsc, Arc, Mutex}, thread};
 
-fn main() {
+const DIR_PARAMS: SketchParams = SketchParams { base_length: 100, level: 2, points: 5, seed: [0; 16] };
+const DIR_THRESHOLD: usize = 4;
+
+/// Lines of an items file sent to a hashing worker at a time.
+const BATCH_LINES: usize = 1024;
+
+/// Points and sizes `tune` sweeps: sizes grow by a factor of about sqrt(2) per step from
+/// one bit per point of the difference.
+const TUNE_POINTS: std::ops::RangeInclusive<u64> = 2..=8;
+const TUNE_SIZE_STEPS: u32 = 16;
+
+/// Smallest base length `tune` folds a sketch down to, as in `SketchParams::for_expected_diff`.
+const TUNE_MIN_BASE_LENGTH: u64 = 16;
+
+/// Message reported to the user, followed by the usage of the subcommand.
+type CliResult<T> = Result<T, String>;
 
+/// A subcommand, the number of positional arguments and the options it takes.
+struct Command {
+    name: &'static str,
+    positional: usize,
+    options: &'static [&'static str],
+    usage: &'static str,
+    run: fn(&Args) -> CliResult<()>,
+}
+
+const COMMANDS: &[Command] = &[
+    Command { name: "build", positional: 0, options: &["input", "format", "out", "base-length", "level", "points"], usage: "build [--input <items.txt>] [--format string|hex] [--out <sketch.bin>] [--base-length N] [--level L] [--points K]", run: build },
+    Command { name: "diff", positional: 2, options: &["out"], usage: "diff <a.bin> <b.bin> [--out <diff.bin>]", run: diff },
+    Command { name: "decode", positional: 1, options: &["candidates", "format", "threshold"], usage: "decode <diff.bin> --candidates <items.txt|-> [--format string|hex] [--threshold T]", run: decode },
+    Command { name: "estimate", positional: 1, options: &[], usage: "estimate <sketch.bin>", run: estimate },
+    Command { name: "simulate", positional: 0, options: &["base-length", "level", "points", "common", "uncommon", "samples", "threshold", "seed", "output"], usage: "simulate [--base-length N] [--level L] [--points K] [--common C] [--uncommon U] [--samples S] [--threshold T] [--seed X] [--output csv|json]", run: simulate },
+    Command { name: "tune", positional: 0, options: &["diff-size", "target-fp", "samples", "seed", "threads"], usage: "tune --diff-size N [--target-fp F] [--samples S] [--seed X] [--threads T]", run: tune },
+    Command { name: "dir-sketch", positional: 1, options: &[], usage: "dir-sketch <path>", run: dir_sketch },
+    Command { name: "dir-diff", positional: 2, options: &["candidates"], usage: "dir-diff <a.bcsk> <b.bcsk> --candidates <path>", run: dir_diff },
+];
+
+fn main() {
     let args: Vec<String> = env::args().collect();
 
-    let base_lenth : u64 = args[1].parse().expect("Base lengh as u64");
-    let level : u64 = args[2].parse().expect("Level as u64");
-    let point : u64 = args[3].parse().expect("Point as u64");
-    let common_num : u64 = args[4].parse().expect("Common items as u64");
-    let uncommon_num : u64 = args[5].parse().expect("Non common items as u64");
-    let samples_num : u64 = args[6].parse().expect("Stats samples as u64");
-    let threshold : u64 = args[7].parse().expect("Min threshold as u64");
-    
-
-    let mut sketch1 = BinaryCountSketch::new(base_lenth, level, point);
-    let mut sketch2 = BinaryCountSketch::new(base_lenth, level, point);
-
-    // Add to filter
-    let mut common = vec![];
-    for _ in 0..common_num {
-        let item: TestItem = TestItem::new();
-        sketch1.toggle(&item);
-        sketch2.toggle(&item);
-        common.push(item);
-    }
-
-    let mut extra1 = vec![];
-    for _ in 0..uncommon_num {
-        let item: TestItem = TestItem::new();
-        sketch1.toggle(&item);
-        extra1.push(item);
-        let item: TestItem = TestItem::new();
-        sketch2.toggle(&item);
-    }
-
-    sketch2.diff_with(&sketch1).expect("No errors");
-    let (fpos, fneg) = sketch2.estimate_stats(samples_num as usize, threshold as usize).expect("No errors");
-
-    let mut candidates = vec![];
-    candidates.append(&mut common.clone());
-    candidates.append(&mut extra1.clone());
-
-    let mut found = Vec::new();
-    
-    println!("{} bits {} bytes", sketch2.bits(), sketch2.bits() / 8);
-
-    println!("Naive scheme: {} bytes", 8 * (uncommon_num + common_num) );
-    println!("IBLT scheme: {} bytes", 4 * uncommon_num * 24 );
-
-    println!("Estimate TP rate: {} / {}", samples_num as usize - fneg, samples_num);
-    println!("Estimate FP rate:  {} / {}", fpos, samples_num);
-
-    let mut tmp_threshold = point;
-
-    loop {
-        let mut not_found = Vec::new();
-        for (score, item) in sketch2.decode(&candidates).into_iter().zip(&candidates) {
-            if score >= tmp_threshold as usize {
-                found.push(item.clone());
-                sketch2.toggle(item);
-             
+    let Some(command) = args.get(1).and_then(|name| COMMANDS.iter().find(|c| c.name == name)) else {
+        let names: Vec<_> = COMMANDS.iter().map(|c| c.name).collect();
+        eprintln!("Usage: bcsk <{}> ...", names.join("|"));
+        process::exit(2);
+    };
+    if let Err(e) = Args::parse(&args[2..], command).and_then(|args| (command.run)(&args)) {
+        eprintln!("bcsk {}: {}\nUsage: bcsk {}", command.name, e, command.usage);
+        process::exit(1);
+    }
+}
+
+/// Positional arguments and `--name value` options of a subcommand.
+struct Args {
+    positional: Vec<String>,
+    options: HashMap<String, String>,
+}
+
+impl Args {
+    fn parse(args: &[String], command: &Command) -> CliResult<Self> {
+        let mut positional = Vec::new();
+        let mut options = HashMap::new();
+        let mut iter = args.iter();
+        while let Some(arg) = iter.next() {
+            match arg.strip_prefix("--") {
+                Some(name) => {
+                    if !command.options.contains(&name) { return Err(format!("Unknown option --{}", name)); }
+                    let value = iter.next().ok_or_else(|| format!("Missing value for --{}", name))?;
+                    options.insert(name.to_string(), value.clone());
+                }
+                None => positional.push(arg.clone()),
             }
-            else {
-            not_found.push(item.clone());
+        }
+        if positional.len() > command.positional { return Err(format!("Unexpected argument {}", positional[command.positional])); }
+        Ok(Args { positional, options })
+    }
+
+    fn positional(&self, i: usize, what: &str) -> CliResult<&str> {
+        self.positional.get(i).map(String::as_str).ok_or_else(|| format!("Missing {}", what))
+    }
+
+    fn option(&self, name: &str) -> Option<&str> {
+        self.options.get(name).map(String::as_str)
+    }
+
+    fn required(&self, name: &str) -> CliResult<&str> {
+        self.option(name).ok_or_else(|| format!("Missing --{}", name))
+    }
+
+    fn parsed_or<T: FromStr>(&self, name: &str, default: T) -> CliResult<T> {
+        match self.option(name) {
+            Some(v) => v.parse().map_err(|_| format!("Incorrect value for --{}: {}", name, v)),
+            None => Ok(default),
+        }
+    }
+
+    fn params(&self) -> CliResult<SketchParams> {
+        let params = SketchParams::new(
+            self.parsed_or("base-length", DIR_PARAMS.base_length)?,
+            self.parsed_or("level", DIR_PARAMS.level)?,
+            self.parsed_or("points", DIR_PARAMS.points)?,
+        );
+        params.validate().map_err(|e| e.to_string())?;
+        Ok(params)
+    }
+}
+
+/// A file identified by its path relative to the sketched directory and the hash of its
+/// contents.
+type FileItem = HashedItem<(String, u64)>;
+
+fn workers() -> usize {
+    thread::available_parallelism().map_or(1, |n| n.get())
+}
+
+/// Collects the regular files under `dir`. Symbolic links are skipped rather than
+/// followed, so a link cycle cannot recurse forever.
+fn collect_files(root: &Path, dir: &Path, out: &mut Vec<PathBuf>) -> CliResult<()> {
+    let error = |e: io::Error| format!("{}: {}", dir.display(), e);
+    let mut entries = fs::read_dir(dir).and_then(|entries| entries.collect::<io::Result<Vec<_>>>()).map_err(error)?;
+    entries.sort_by_key(|e| e.path());
+    for entry in entries {
+        let file_type = entry.file_type().map_err(error)?;
+        if file_type.is_dir() {
+            collect_files(root, &entry.path(), out)?;
+        } else if file_type.is_file() {
+            out.push(entry.path().strip_prefix(root).expect("Path under root").to_path_buf());
+        }
+    }
+    Ok(())
+}
+
+fn file_item(root: &Path, rel: &Path) -> CliResult<FileItem> {
+    let path = rel.components().map(|c| c.as_os_str().to_string_lossy()).collect::<Vec<_>>().join("/");
+    let contents = fs::read(root.join(rel)).map_err(|e| format!("{}: {}", root.join(rel).display(), e))?;
+    let mut hasher = StableHasher::with_key(0);
+    hasher.write(&contents);
+    Ok(HashedItem::new((path, hasher.finish())))
+}
+
+/// Reads and hashes the files under `root` on one worker per core, handing each item to
+/// `f` on the calling thread. Bounded channels keep memory flat on large trees.
+fn for_each_file_item(root: &Path, mut f: impl FnMut(FileItem)) -> CliResult<()> {
+    let mut paths = Vec::new();
+    collect_files(root, root, &mut paths)?;
+
+    let workers = workers();
+    let (path_tx, path_rx) = mpsc::sync_channel::<PathBuf>(workers * 4);
+    let (item_tx, item_rx) = mpsc::sync_channel::<CliResult<FileItem>>(workers * 4);
+    let path_rx = Arc::new(Mutex::new(path_rx));
+
+    thread::scope(|s| {
+        for _ in 0..workers {
+            let path_rx = Arc::clone(&path_rx);
+            let item_tx = item_tx.clone();
+            s.spawn(move || loop {
+                let next = path_rx.lock().unwrap().recv();
+                match next {
+                    Ok(rel) => item_tx.send(file_item(root, &rel)).unwrap(),
+                    Err(_) => break,
+                }
+            });
+        }
+        drop(item_tx);
+
+        s.spawn(move || {
+            for rel in paths {
+                path_tx.send(rel).unwrap();
+            }
+        });
+
+        // Keep receiving after an error, so no worker blocks on a full channel.
+        let mut result = Ok(());
+        for item in item_rx {
+            match item {
+                Ok(item) if result.is_ok() => f(item),
+                Ok(_) => {}
+                Err(e) => result = result.and(Err(e)),
             }
         }
+        result
+    })
+}
+
+fn read_sketch(path: &str) -> CliResult<BinaryCountSketch> {
+    let bytes = fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+    BinaryCountSketch::from_bytes(&bytes).map_err(|e| format!("{} is not a sketch file: {}", path, e))
+}
+
+fn write_output(path: Option<&str>, bytes: &[u8]) -> CliResult<()> {
+    match path {
+        Some(path) => fs::write(path, bytes).map_err(|e| format!("{}: {}", path, e)),
+        None => io::stdout().lock().write_all(bytes).map_err(|e| format!("stdout: {}", e)),
+    }
+}
+
+/// How the lines of an items file are turned into the bytes that are hashed into items.
+#[derive(Clone, Copy)]
+enum Format {
+    /// Each line as UTF-8 text, e.g. keys or file names.
+    String,
+    /// Each line as hex-encoded bytes, e.g. transaction ids or content hashes.
+    Hex,
+}
 
-        println!("Decoded {} Remaining {}", found.len(), not_found.len() );
+impl Format {
+    fn from_args(args: &Args) -> CliResult<Self> {
+        match args.option("format").unwrap_or("string") {
+            "string" => Ok(Format::String),
+            "hex" => Ok(Format::Hex),
+            other => Err(format!("--format string or hex, not {}", other)),
+        }
+    }
 
-        if not_found.len() == candidates.len() {
-            if tmp_threshold > threshold {
-                tmp_threshold -= 1;
-            } else 
-            {
-                break
+    fn parse(&self, line: &str) -> CliResult<Vec<u8>> {
+        match self {
+            Format::String => Ok(line.as_bytes().to_vec()),
+            Format::Hex => {
+                let line = line.trim();
+                let digit = |c: u8| (c as char).to_digit(16).map(|d| d as u8).ok_or_else(|| format!("Hex item, not {}", line));
+                if !line.len().is_multiple_of(2) { return Err(format!("Hex item of whole bytes, not {}", line)); }
+                line.as_bytes().chunks(2).map(|pair| Ok(digit(pair[0])? << 4 | digit(pair[1])?)).collect()
             }
         }
+    }
 
-        
-        candidates = not_found;
+    fn display(&self, bytes: &[u8]) -> String {
+        match self {
+            Format::String => String::from_utf8_lossy(bytes).into_owned(),
+            Format::Hex => bytes.iter().map(|b| format!("{:02x}", b)).collect(),
+        }
     }
+}
 
-    let extra_set : HashSet<_> = extra1.clone().into_iter().collect();
+/// Sketches the non-empty lines of `path`, or of stdin if it is `-`, as items in
+/// `format`. The calling thread reads batches of lines into a bounded channel and one
+/// worker per core toggles the items it receives into a single shared sketch with
+/// atomic XORs, so memory stays at one sketch whatever the number of cores. Toggles
+/// commute, so the result does not depend on how the workers interleave.
+fn sketch_items(path: &str, format: Format, params: SketchParams) -> CliResult<BinaryCountSketch> {
+    let reader: Box<dyn BufRead> = if path == "-" {
+        Box::new(io::stdin().lock())
+    } else {
+        Box::new(io::BufReader::new(fs::File::open(path).map_err(|e| format!("{}: {}", path, e))?))
+    };
 
-    println!("Found: {}", found.len());
+    let workers = workers();
+    let (batch_tx, batch_rx) = mpsc::sync_channel::<Vec<String>>(workers * 4);
+    let batch_rx = Arc::new(Mutex::new(batch_rx));
+    let sketch = ConcurrentBinaryCountSketch::from_params(params);
 
-    println!(
-        "Common TP rate: {}",
-        found.iter().filter(|item| extra_set.contains(item)).count() as f64
-            / uncommon_num as f64
-    );
+    let result = thread::scope(|s| {
+        let handles: Vec<_> = (0..workers).map(|_| {
+            let batch_rx = Arc::clone(&batch_rx);
+            let sketch = &sketch;
+            s.spawn(move || {
+                // Keep receiving after an error, so the reader never blocks on a full channel.
+                let mut result = Ok(());
+                loop {
+                    let next = batch_rx.lock().unwrap().recv();
+                    match next {
+                        Ok(lines) => {
+                            for line in &lines {
+                                if result.is_ok() {
+                                    result = format.parse(line).map(|item| sketch.toggle_bytes(&item));
+                                }
+                            }
+                        }
+                        Err(_) => break result,
+                    }
+                }
+            })
+        }).collect();
+
+        let mut read = Ok(());
+        let mut batch = Vec::with_capacity(BATCH_LINES);
+        for line in reader.lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(e) => {
+                    read = Err(format!("{}: {}", path, e));
+                    break;
+                }
+            };
+            if line.is_empty() {
+                continue;
+            }
+            batch.push(line);
+            if batch.len() == BATCH_LINES {
+                batch_tx.send(mem::take(&mut batch)).unwrap();
+            }
+        }
+        batch_tx.send(batch).unwrap();
+        drop(batch_tx);
 
-    println!(
-        "Common FP rate: {}",
-        found.iter().filter(|item| !extra_set.contains(item)).count() as f64
-            / common_num as f64
-    );
+        for handle in handles {
+            handle.join().unwrap()?;
+        }
+        read
+    });
+    result.map(|_| sketch.into_sketch())
+}
+
+/// Maps `items` with `f` on one scoped thread per core, keeping their order.
+fn parallel_map<'a, T: Sync, U: Send>(items: &'a [T], f: impl Fn(&'a T) -> U + Sync) -> Vec<U> {
+    let f = &f;
+    let chunk_len = items.len().div_ceil(workers()).max(1);
+    thread::scope(|s| {
+        let handles: Vec<_> = items.chunks(chunk_len).map(|chunk| s.spawn(move || chunk.iter().map(f).collect::<Vec<_>>())).collect();
+        handles.into_iter().flat_map(|h| h.join().unwrap()).collect()
+    })
+}
+
+/// Reads the non-empty lines of `path`, or of stdin if it is `-`, as items in `format`.
+fn read_items(path: &str, format: Format) -> CliResult<Vec<Vec<u8>>> {
+    let text = if path == "-" { io::read_to_string(io::stdin()) } else { fs::read_to_string(path) };
+    let text = text.map_err(|e| format!("{}: {}", path, e))?;
+    text.lines().filter(|line| !line.is_empty()).map(|line| format.parse(line)).collect()
+}
+
+/// `build [--input <items.txt>] [--format string|hex] [--out <sketch.bin>] [--base-length N] [--level L] [--points K]`:
+/// sketches the non-empty lines of the input, or of stdin without `--input`, to `--out`
+/// or stdout, hashing on every core.
+fn build(args: &Args) -> CliResult<()> {
+    let sketch = sketch_items(args.option("input").unwrap_or("-"), Format::from_args(args)?, args.params()?)?;
+    write_output(args.option("out"), &sketch.to_bytes())
+}
 
+/// `diff <a.bin> <b.bin> [--out <diff.bin>]`: writes the diff of two sketches to `--out`
+/// or stdout.
+fn diff(args: &Args) -> CliResult<()> {
+    let mut sketch = read_sketch(args.positional(0, "first sketch")?)?;
+    sketch.diff_with(&read_sketch(args.positional(1, "second sketch")?)?).map_err(|e| e.to_string())?;
+    write_output(args.option("out"), &sketch.to_bytes())
+}
+
+/// `decode <diff.bin> --candidates <items.txt|-> [--format string|hex] [--threshold T]`:
+/// lists the candidate lines that are in the diffed sketch. The threshold defaults to
+/// `suggest_threshold`.
+fn decode(args: &Args) -> CliResult<()> {
+    let mut sketch = read_sketch(args.positional(0, "diff sketch")?)?;
+    let format = Format::from_args(args)?;
+    let items = read_items(args.required("candidates")?, format)?;
+    let candidates = parallel_map(&items, |item| sketch.keyed_bytes(item));
+
+    let threshold = args.parsed_or("threshold", sketch.suggest_threshold(DEFAULT_FP_RATE))?;
+    let result = sketch.reconcile_with_threshold(&candidates, threshold).map_err(|e| e.to_string())?;
+    for item in &result.decoded {
+        println!("{}", format.display(item.bytes()));
+    }
+    eprintln!("{} decoded of {} candidates in {} rounds at threshold {}", result.decoded.len(), candidates.len(), result.rounds.len(), threshold);
+    Ok(())
+}
+
+/// `estimate <sketch.bin>`: prints the parameters of a sketch and the estimated number of
+/// items toggled into it, e.g. the size of the difference held by a diff.
+fn estimate(args: &Args) -> CliResult<()> {
+    let sketch = read_sketch(args.positional(0, "sketch")?)?;
+    println!("base_length {} level {} points {}", sketch.base_length(), sketch.level(), sketch.points());
+    println!("{} bits {} bytes", sketch.bits(), sketch.bits() / 8);
+    match sketch.estimate_difference() {
+        Some(items) => println!("Estimated items: {}", items),
+        None => println!("Estimated items: saturated"),
+    }
+    println!("Suggested threshold: {}", sketch.suggest_threshold(DEFAULT_FP_RATE));
+    Ok(())
+}
+
+/// `dir-sketch <path>`: writes a sketch of the files under `path` to stdout.
+fn dir_sketch(args: &Args) -> CliResult<()> {
+    let root = Path::new(args.positional(0, "directory to sketch")?);
+
+    let mut sketch = BinaryCountSketch::from_params(DIR_PARAMS);
+    for_each_file_item(root, |item| sketch.toggle(&item))?;
+
+    write_output(None, &sketch.to_bytes())
+}
+
+/// `dir-diff <a.bcsk> <b.bcsk> --candidates <path>`: lists the files under `path` that
+/// are in one of the sketched trees but not the other.
+fn dir_diff(args: &Args) -> CliResult<()> {
+    let mut sketch = read_sketch(args.positional(0, "first sketch")?)?;
+    sketch.diff_with(&read_sketch(args.positional(1, "second sketch")?)?).map_err(|e| e.to_string())?;
+
+    let mut candidates = Vec::new();
+    for_each_file_item(Path::new(args.required("candidates")?), |item| candidates.push(item))?;
+    let report = PeelingDecoder::new(DIR_THRESHOLD).decode(&mut sketch, &candidates).map_err(|e| e.to_string())?;
+
+    let mut paths: Vec<_> = report.decoded.iter().map(|item| &item.value().0).collect();
+    paths.sort();
+    for path in paths {
+        println!("{}", path);
+    }
+    eprintln!("{} differing files of {} candidates", report.decoded.len(), candidates.len());
+    Ok(())
+}
+
+/// Parameters of a synthetic reconciliation experiment: two sketches sharing `common`
+/// random items, each with `uncommon` items of its own.
+#[derive(Clone, Copy)]
+struct Experiment {
+    params: SketchParams,
+    common: u64,
+    uncommon: u64,
+    samples: u64,
+    threshold: u64,
+    /// Seed of the items and stats samples, random items if `None`.
+    seed: Option<u64>,
+}
+
+/// Outcome of an `Experiment`. The rates are fractions of the samples, of the uncommon
+/// items and of the common items respectively.
+struct Simulation {
+    experiment: Experiment,
+    bits: usize,
+    estimated_fp_rate: f64,
+    estimated_tp_rate: f64,
+    rounds: usize,
+    final_threshold: usize,
+    found: usize,
+    decode_tp_rate: f64,
+    decode_fp_rate: f64,
+    trace_json: String,
+}
+
+impl Experiment {
+    fn item(&self, i: u64) -> TestItem {
+        match self.seed {
+            Some(seed) => TestItem::from_seed(seed.wrapping_add(i)),
+            None => TestItem::new(),
+        }
+    }
+
+    fn run(&self) -> CliResult<Simulation> {
+        let mut sketch1 = BinaryCountSketch::from_params(self.params);
+        let mut sketch2 = BinaryCountSketch::from_params(self.params);
+
+        // Add to filter
+        let mut next = 0..;
+        let mut common = vec![];
+        for _ in 0..self.common {
+            let item = self.item(next.next().unwrap());
+            sketch1.toggle(&item);
+            sketch2.toggle(&item);
+            common.push(item);
+        }
+
+        let mut extra1 = vec![];
+        for _ in 0..self.uncommon {
+            let item = self.item(next.next().unwrap());
+            sketch1.toggle(&item);
+            extra1.push(item);
+            let item = self.item(next.next().unwrap());
+            sketch2.toggle(&item);
+        }
+
+        sketch2.diff_with(&sketch1).expect("No errors");
+        let (samples, threshold) = (self.samples as usize, self.threshold as usize);
+        let (fpos, fneg) = match self.seed {
+            Some(seed) => sketch2.estimate_stats_seeded(seed, samples, threshold),
+            None => sketch2.estimate_stats(&mut rand::thread_rng(), samples, threshold),
+        }.map_err(|e| e.to_string())?;
+
+        let mut candidates = common;
+        candidates.extend(extra1.iter().cloned());
+        let bits = sketch2.bits();
+        let (report, trace) = PeelingDecoder::new(threshold)
+            .decode_with_trace(&mut sketch2, &candidates)
+            .map_err(|e| e.to_string())?;
+
+        let extra_set : HashSet<_> = extra1.into_iter().collect();
+        let true_pos = report.decoded.iter().filter(|item| extra_set.contains(item)).count();
+
+        Ok(Simulation {
+            experiment: *self,
+            bits,
+            estimated_fp_rate: fpos as f64 / samples as f64,
+            estimated_tp_rate: (samples - fneg) as f64 / samples as f64,
+            rounds: report.rounds,
+            final_threshold: report.final_threshold,
+            found: report.decoded.len(),
+            decode_tp_rate: true_pos as f64 / self.uncommon as f64,
+            decode_fp_rate: (report.decoded.len() - true_pos) as f64 / self.common as f64,
+            trace_json: trace.to_json(),
+        })
+    }
+}
+
+impl Simulation {
+    /// Column names and values of the CSV output, in order.
+    fn columns(&self) -> Vec<(&'static str, String)> {
+        let e = &self.experiment;
+        vec![
+            ("base_length", e.params.base_length.to_string()),
+            ("level", e.params.level.to_string()),
+            ("points", e.params.points.to_string()),
+            ("common", e.common.to_string()),
+            ("uncommon", e.uncommon.to_string()),
+            ("samples", e.samples.to_string()),
+            ("threshold", e.threshold.to_string()),
+            ("bits", self.bits.to_string()),
+            ("bytes", (self.bits / 8).to_string()),
+            ("naive_bytes", (8 * (e.common + e.uncommon)).to_string()),
+            ("iblt_bytes", (4 * e.uncommon * 24).to_string()),
+            ("estimated_fp_rate", self.estimated_fp_rate.to_string()),
+            ("estimated_tp_rate", self.estimated_tp_rate.to_string()),
+            ("rounds", self.rounds.to_string()),
+            ("final_threshold", self.final_threshold.to_string()),
+            ("found", self.found.to_string()),
+            ("decode_tp_rate", self.decode_tp_rate.to_string()),
+            ("decode_fp_rate", self.decode_fp_rate.to_string()),
+        ]
+    }
+
+    fn csv_header(&self) -> String {
+        self.columns().iter().map(|(name, _)| *name).collect::<Vec<_>>().join(",")
+    }
+
+    fn csv_row(&self) -> String {
+        self.columns().into_iter().map(|(_, value)| value).collect::<Vec<_>>().join(",")
+    }
+
+    /// The CSV columns as a JSON object, with the per-round decode trace under `trace`.
+    fn to_json(&self) -> String {
+        let fields: Vec<String> = self.columns().into_iter().map(|(name, value)| {
+            // Rates of empty sets are NaN, which JSON cannot represent.
+            let value = if value == "NaN" { "null".to_string() } else { value };
+            format!("\"{}\":{}", name, value)
+        }).collect();
+        format!("{{{},\"trace\":{}}}", fields.join(","), self.trace_json)
+    }
+}
+
+fn experiment_from_args(args: &Args) -> CliResult<Experiment> {
+    Ok(Experiment {
+        params: args.params()?,
+        common: args.parsed_or("common", 10_000)?,
+        uncommon: args.parsed_or("uncommon", 100)?,
+        samples: args.parsed_or("samples", 1_000)?,
+        threshold: args.parsed_or("threshold", DIR_THRESHOLD as u64)?,
+        seed: args.option("seed").map(|_| args.parsed_or("seed", 0)).transpose()?,
+    })
+}
+
+/// `simulate [--base-length N] [--level L] [--points K] [--common C] [--uncommon U]
+/// [--samples S] [--threshold T] [--seed X] [--output csv|json]`: runs a synthetic
+/// reconciliation and prints its parameters and results as a CSV header and row, or as
+/// a JSON object.
+fn simulate(args: &Args) -> CliResult<()> {
+    let output = args.option("output").unwrap_or("csv");
+    if !(output == "csv" || output == "json") { return Err(format!("--output csv or json, not {}", output)); }
+    let simulation = experiment_from_args(args)?.run()?;
+    match output {
+        "csv" => println!("{}\n{}", simulation.csv_header(), simulation.csv_row()),
+        _ => println!("{}", simulation.to_json()),
+    }
+    Ok(())
+}
+
+/// Accuracy of one parameter choice swept by `tune`.
+struct Candidate {
+    params: SketchParams,
+    threshold: usize,
+    fp_rate: f64,
+    fn_rate: f64,
+}
+
+impl Candidate {
+    fn bytes(&self) -> u64 {
+        (self.params.base_length << self.params.level) * 8
+    }
+
+    fn error(&self) -> f64 {
+        self.fp_rate + self.fn_rate
+    }
+}
+
+/// Sketches a difference of `diff_size` seeded items with `params` and estimates the
+/// error rates at the threshold suggested for `target_fp`.
+fn evaluate(params: SketchParams, diff_size: u64, target_fp: f64, samples: usize, seed: u64) -> CliResult<Candidate> {
+    let mut sketch = BinaryCountSketch::from_params(params);
+    for i in 0..diff_size {
+        sketch.toggle(&TestItem::from_seed(seed.wrapping_add(i)));
+    }
+    let threshold = sketch.suggest_threshold(target_fp);
+    let (fpos, fneg) = sketch.estimate_stats_seeded(seed, samples, threshold).map_err(|e| e.to_string())?;
+    Ok(Candidate { params, threshold, fp_rate: fpos as f64 / samples as f64, fn_rate: fneg as f64 / samples as f64 })
+}
+
+/// `tune --diff-size N [--target-fp F] [--samples S] [--seed X] [--threads T]`: sweeps
+/// points and sketch sizes for a difference of `N` items, estimating the false positive
+/// and negative rates of each at the threshold `suggest_threshold` picks for `F`, and
+/// prints as CSV the Pareto frontier of size against the sum of both rates. The level of
+/// each size keeps a base length of at least 16, as it does not change the accuracy.
+fn tune(args: &Args) -> CliResult<()> {
+    args.required("diff-size")?;
+    let diff_size: u64 = args.parsed_or("diff-size", 0)?;
+    let target_fp = args.parsed_or("target-fp", 0.01)?;
+    let samples = args.parsed_or("samples", 10_000)?;
+    let seed = args.parsed_or("seed", 0)?;
+    let threads = args.parsed_or("threads", thread::available_parallelism().map_or(1, |n| n.get()))?.max(1);
+
+    let mut grid = Vec::new();
+    for points in TUNE_POINTS {
+        let smallest = (diff_size.max(1) * points).div_ceil(64) as f64;
+        for step in 0..TUNE_SIZE_STEPS {
+            let words = (smallest * 2f64.powf(step as f64 / 2.0)).ceil() as u64;
+            let level = (words / TUNE_MIN_BASE_LENGTH).max(1).ilog2() as u64;
+            grid.push(SketchParams::new(words.div_ceil(1 << level), level, points));
+        }
+    }
+    grid.sort_by_key(|p| (p.base_length << p.level, p.points));
+    grid.dedup();
+
+    let next = Mutex::new(grid.iter());
+    let results = Mutex::new(Vec::with_capacity(grid.len()));
+    thread::scope(|s| {
+        for _ in 0..threads {
+            s.spawn(|| loop {
+                let params = match next.lock().unwrap().next() {
+                    Some(params) => *params,
+                    None => break,
+                };
+                let candidate = evaluate(params, diff_size, target_fp, samples, seed);
+                results.lock().unwrap().push(candidate);
+            });
+        }
+    });
+
+    let mut results = results.into_inner().unwrap().into_iter().collect::<CliResult<Vec<_>>>()?;
+    results.sort_by(|a, b| a.bytes().cmp(&b.bytes()).then(a.error().total_cmp(&b.error())));
+    println!("base_length,level,points,bytes,threshold,fp_rate,fn_rate,meets_target");
+    let mut best = f64::INFINITY;
+    for c in results {
+        if c.error() >= best {
+            continue;
+        }
+        best = c.error();
+        let meets_target = c.fp_rate <= target_fp && c.fn_rate <= target_fp;
+        println!("{},{},{},{},{},{},{},{}", c.params.base_length, c.params.level, c.params.points, c.bytes(), c.threshold, c.fp_rate, c.fn_rate, meets_target);
+    }
+    Ok(())
 }
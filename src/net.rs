@@ -0,0 +1,540 @@
+use std::future::Future;
+use std::io;
+use std::time::Duration;
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream, ToSocketAddrs};
+use tokio::time::{self, Instant};
+
+use crate::wire::parse_header;
+use crate::{BinaryCountSketch, BinaryCountSketchError, ErrorKind, SketchParams, DEFAULT_FP_RATE};
+
+const MAGIC: &[u8; 4] = b"BCSN";
+
+/// Protocol versions this side speaks. A client offers the range and the server answers
+/// with the highest version both support, or 0 if there is none. Version 1 sent every
+/// sketch and item list in a single frame.
+const MIN_VERSION: u8 = 2;
+const MAX_VERSION: u8 = 2;
+
+const DEFAULT_MAX_FRAME_LEN: usize = 4 << 20;
+
+/// Most words per frame of a streamed sketch, 512KiB.
+const SLICE_WORDS: usize = 1 << 16;
+
+/// Size above which a frame of items is sent rather than grown with the next item.
+const ITEMS_FRAME_LEN: usize = 1 << 20;
+
+fn transport(e: io::Error) -> BinaryCountSketchError {
+    BinaryCountSketchError::with_kind(ErrorKind::Transport, &e.to_string())
+}
+
+fn protocol_error(details: &str) -> BinaryCountSketchError {
+    BinaryCountSketchError::with_kind(ErrorKind::Transport, details)
+}
+
+/// Limits on how long a session and each of its phases may take, so a stuck or slow
+/// peer cannot hold a session open forever. `None` waits without limit.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Timeouts {
+    /// Connecting and agreeing on a protocol version.
+    pub handshake: Option<Duration>,
+    /// Sending our sketch and receiving the peer's.
+    pub sketch_exchange: Option<Duration>,
+    /// Decoding the diff, sending the peer its missing items and receiving ours.
+    pub item_exchange: Option<Duration>,
+    /// The whole session, across all phases.
+    pub session: Option<Duration>,
+}
+
+/// Outcome of a reconciliation with a peer.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Reconciled {
+    /// Items the peer holds and we did not.
+    pub received: Vec<Vec<u8>>,
+    /// Items we hold and the peer did not.
+    pub sent: Vec<Vec<u8>>,
+    /// Whether the items of both sides explained every set bit of the diff. If not, the
+    /// sketches were too small for the difference and some items may be missing.
+    pub complete: bool,
+}
+
+/// One side of the reference reconciliation protocol over any ordered and reliable
+/// byte stream, e.g. TCP, or QUIC with the `quic` feature.
+///
+/// Every message is a frame of a little-endian `u32` length and a payload. The client
+/// opens with `BCSN` and the lowest and highest protocol versions it speaks, and the
+/// server answers with `BCSN` and the highest version both speak. Then both sides send
+/// their sketch: the header of its `to_bytes` encoding in a frame of its own, then its
+/// words as little-endian `u64` in frames of up to 64Ki words. Each side decodes the
+/// diff against its own items and sends the peer the items it lacks, in frames of a
+/// `u32` count followed by length-prefixed items, ended by a frame of no items. The
+/// client always writes first, so neither side blocks on a full buffer.
+///
+/// Sketches are diffed frame by frame as they arrive, and writes wait on the stream's
+/// backpressure, so a session holds a copy of our sketch to diff into and one frame of
+/// at most `max_frame_len` bytes at a time, besides the items exchanged. A server
+/// accepting the client's parameters also holds our items sketched with them.
+///
+/// Items are byte strings, toggled into the sketch with `toggle_bytes`. Both peers must
+/// use the same `SketchParams`, unless the server accepts the client's parameters with
+/// `with_accepted_params`.
+pub struct Peer {
+    sketch: BinaryCountSketch,
+    items: Vec<Vec<u8>>,
+    accepted_params: Vec<SketchParams>,
+    max_frame_len: usize,
+    timeouts: Timeouts,
+}
+
+impl Peer {
+    pub fn new<I: IntoIterator<Item = Vec<u8>>>(params: SketchParams, items: I) -> Self {
+        let items: Vec<Vec<u8>> = items.into_iter().collect();
+        let sketch = sketch_of(params, &items);
+        Peer { sketch, items, accepted_params: Vec::new(), max_frame_len: DEFAULT_MAX_FRAME_LEN, timeouts: Timeouts::default() }
+    }
+
+    /// As a server, also reconciles with clients whose sketch has `params`, re-sketching
+    /// our items with them for the session. Parameters are upgraded across a fleet by
+    /// first deploying servers accepting the new parameters, then switching the clients
+    /// to them, without either side losing its items.
+    pub fn with_accepted_params(mut self, params: SketchParams) -> Self {
+        self.accepted_params.push(params);
+        self
+    }
+
+    /// Frames longer than `max_frame_len` bytes are rejected, so a peer cannot make us
+    /// buffer arbitrarily large frames. Our own sketches and item lists are split into
+    /// frames no longer than that; a single item longer than it cannot be sent.
+    pub fn with_max_frame_len(mut self, max_frame_len: usize) -> Self {
+        self.max_frame_len = max_frame_len;
+        self
+    }
+
+    /// Fails a session with `ErrorKind::TimedOut`, naming the phase it was in, once one
+    /// of the `timeouts` passes.
+    pub fn with_timeouts(mut self, timeouts: Timeouts) -> Self {
+        self.timeouts = timeouts;
+        self
+    }
+
+    pub fn sketch(&self) -> &BinaryCountSketch {
+        &self.sketch
+    }
+
+    /// Connects to `addr` and reconciles as the client.
+    pub async fn connect<A: ToSocketAddrs>(&self, addr: A) -> Result<Reconciled, BinaryCountSketchError> {
+        let session = self.session_deadline();
+        let connect = async { TcpStream::connect(addr).await.map_err(transport) };
+        let mut stream = self.phase("handshake", self.timeouts.handshake, session, connect).await?;
+        self.client_session(&mut stream, session).await
+    }
+
+    /// Accepts one connection on `listener` and reconciles as the server.
+    pub async fn accept(&self, listener: &TcpListener) -> Result<Reconciled, BinaryCountSketchError> {
+        let (mut stream, _) = listener.accept().await.map_err(transport)?;
+        self.reconcile_server(&mut stream).await
+    }
+
+    pub async fn reconcile_client<S: AsyncRead + AsyncWrite + Unpin>(&self, stream: &mut S) -> Result<Reconciled, BinaryCountSketchError> {
+        self.client_session(stream, self.session_deadline()).await
+    }
+
+    pub async fn reconcile_server<S: AsyncRead + AsyncWrite + Unpin>(&self, stream: &mut S) -> Result<Reconciled, BinaryCountSketchError> {
+        self.server_session(stream, self.session_deadline()).await
+    }
+
+    async fn client_session<S: AsyncRead + AsyncWrite + Unpin>(&self, stream: &mut S, session: Option<Instant>) -> Result<Reconciled, BinaryCountSketchError> {
+        let handshake = async {
+            let mut hello = MAGIC.to_vec();
+            hello.extend_from_slice(&[MIN_VERSION, MAX_VERSION]);
+            self.write_frame(stream, &hello).await?;
+            match self.read_frame(stream).await?.as_slice() {
+                [m @ .., version] if m == MAGIC && (MIN_VERSION..=MAX_VERSION).contains(version) => Ok(()),
+                [m @ .., 0] if m == MAGIC => Err(protocol_error("No common protocol version")),
+                _ => Err(protocol_error("Incorrect handshake")),
+            }
+        };
+        self.phase("handshake", self.timeouts.handshake, session, handshake).await?;
+
+        let sketches = async {
+            self.write_sketch(stream, &self.sketch).await?;
+            check_params(self.sketch.params(), self.read_header(stream).await?)?;
+            let mut diff = self.sketch.clone();
+            self.read_words_into(stream, &mut diff).await?;
+            Ok(diff)
+        };
+        let diff = self.phase("sketch exchange", self.timeouts.sketch_exchange, session, sketches).await?;
+
+        let items = async {
+            let (sent, residual) = self.missing_from(diff)?;
+            self.write_items(stream, &sent).await?;
+            let received = self.read_items(stream).await?;
+            Ok(reconciled(received, sent, residual))
+        };
+        self.phase("item exchange", self.timeouts.item_exchange, session, items).await
+    }
+
+    async fn server_session<S: AsyncRead + AsyncWrite + Unpin>(&self, stream: &mut S, session: Option<Instant>) -> Result<Reconciled, BinaryCountSketchError> {
+        let handshake = async {
+            let version = match self.read_frame(stream).await?.as_slice() {
+                [m @ .., min, max] if m == MAGIC && min <= max && *min <= MAX_VERSION && *max >= MIN_VERSION => MAX_VERSION.min(*max),
+                [m @ .., _, _] if m == MAGIC => 0,
+                _ => return Err(protocol_error("Incorrect handshake")),
+            };
+            let mut ack = MAGIC.to_vec();
+            ack.push(version);
+            self.write_frame(stream, &ack).await?;
+            if version == 0 { return Err(protocol_error("No common protocol version")); }
+            Ok(())
+        };
+        self.phase("handshake", self.timeouts.handshake, session, handshake).await?;
+
+        let sketches = async {
+            let remote = self.read_header(stream).await?;
+            let accepted = remote != self.sketch.params() && self.accepted_params.contains(&remote);
+            let resketched = if accepted { Some(sketch_of(remote, &self.items)) } else { None };
+            let local = resketched.as_ref().unwrap_or(&self.sketch);
+            // Fail before the client streams its words, rather than after.
+            check_params(local.params(), remote)?;
+            let mut diff = local.clone();
+            self.read_words_into(stream, &mut diff).await?;
+            self.write_sketch(stream, local).await?;
+            Ok(diff)
+        };
+        let diff = self.phase("sketch exchange", self.timeouts.sketch_exchange, session, sketches).await?;
+
+        let items = async {
+            let received = self.read_items(stream).await?;
+            let (sent, residual) = self.missing_from(diff)?;
+            self.write_items(stream, &sent).await?;
+            Ok(reconciled(received, sent, residual))
+        };
+        self.phase("item exchange", self.timeouts.item_exchange, session, items).await
+    }
+
+    fn session_deadline(&self) -> Option<Instant> {
+        self.timeouts.session.map(|timeout| Instant::now() + timeout)
+    }
+
+    /// Runs the `phase` future until the earlier of its `timeout` and the `session`
+    /// deadline, failing with `ErrorKind::TimedOut` if it has not finished by then.
+    async fn phase<T>(&self, phase: &str, timeout: Option<Duration>, session: Option<Instant>, future: impl Future<Output = Result<T, BinaryCountSketchError>>) -> Result<T, BinaryCountSketchError> {
+        let deadline = match (timeout.map(|timeout| Instant::now() + timeout), session) {
+            (Some(a), Some(b)) => a.min(b),
+            (a, b) => match a.or(b) {
+                Some(deadline) => deadline,
+                None => return future.await,
+            },
+        };
+        match time::timeout_at(deadline, future).await {
+            Ok(result) => result,
+            Err(_) => Err(BinaryCountSketchError::with_kind(ErrorKind::TimedOut, &format!("Timed out in {}", phase))),
+        }
+    }
+
+    /// Our items that are not in the peer's sketch, decoded from the `diff` of both, and
+    /// what is left of the diff once they are removed.
+    fn missing_from(&self, mut diff: BinaryCountSketch) -> Result<(Vec<Vec<u8>>, BinaryCountSketch), BinaryCountSketchError> {
+        let candidates: Vec<_> = self.items.iter().map(|item| diff.keyed_bytes(item)).collect();
+        let threshold = diff.suggest_threshold(DEFAULT_FP_RATE);
+        let result = diff.reconcile_with_threshold(&candidates, threshold)?;
+        Ok((result.decoded.iter().map(|item| item.bytes().to_vec()).collect(), diff))
+    }
+
+    /// Sends the header of `sketch`, then its words in frames of at most `SLICE_WORDS`
+    /// words and `max_frame_len` bytes.
+    async fn write_sketch<S: AsyncWrite + Unpin>(&self, stream: &mut S, sketch: &BinaryCountSketch) -> Result<(), BinaryCountSketchError> {
+        let mut header = Vec::new();
+        sketch.write_header(&mut header);
+        self.write_frame(stream, &header).await?;
+
+        let mut frame = Vec::new();
+        for words in sketch.words.chunks((self.max_frame_len / 8).clamp(1, SLICE_WORDS)) {
+            frame.clear();
+            for word in words {
+                frame.extend_from_slice(&word.to_le_bytes());
+            }
+            self.write_frame(stream, &frame).await?;
+        }
+        Ok(())
+    }
+
+    /// Parameters of the sketch the peer is about to stream.
+    async fn read_header<S: AsyncRead + Unpin>(&self, stream: &mut S) -> Result<SketchParams, BinaryCountSketchError> {
+        let frame = self.read_frame(stream).await?;
+        let (params, header_len) = parse_header(&frame)?;
+        if !(frame.len() == header_len) { return Err(protocol_error("Incorrect sketch header")); }
+        Ok(params)
+    }
+
+    /// XORs the words the peer streams into `diff`, a sketch of the parameters it sent.
+    async fn read_words_into<S: AsyncRead + Unpin>(&self, stream: &mut S, diff: &mut BinaryCountSketch) -> Result<(), BinaryCountSketchError> {
+        let mut start = 0;
+        while start < diff.words.len() {
+            let frame = self.read_frame(stream).await?;
+            if !(!frame.is_empty() && frame.len() % 8 == 0 && frame.len() / 8 <= diff.words.len() - start) { return Err(protocol_error("Incorrect sketch words")); }
+            for (word, bytes) in diff.words[start..].iter_mut().zip(frame.chunks(8)) {
+                *word ^= u64::from_le_bytes(bytes.try_into().unwrap());
+            }
+            start += frame.len() / 8;
+        }
+        Ok(())
+    }
+
+    /// Sends `items` in frames of about `ITEMS_FRAME_LEN` bytes, then a frame of none.
+    async fn write_items<S: AsyncWrite + Unpin>(&self, stream: &mut S, items: &[Vec<u8>]) -> Result<(), BinaryCountSketchError> {
+        let mut start = 0;
+        while start < items.len() {
+            let mut end = start + 1;
+            let mut len = 8 + items[start].len();
+            while end < items.len() && len + 4 + items[end].len() <= ITEMS_FRAME_LEN.min(self.max_frame_len) {
+                len += 4 + items[end].len();
+                end += 1;
+            }
+            self.write_frame(stream, &encode_items(&items[start..end])).await?;
+            start = end;
+        }
+        self.write_frame(stream, &encode_items(&[])).await
+    }
+
+    async fn read_items<S: AsyncRead + Unpin>(&self, stream: &mut S) -> Result<Vec<Vec<u8>>, BinaryCountSketchError> {
+        let mut items = Vec::new();
+        loop {
+            let batch = decode_items(&self.read_frame(stream).await?)?;
+            if batch.is_empty() {
+                return Ok(items);
+            }
+            items.extend(batch);
+        }
+    }
+
+    async fn write_frame<S: AsyncWrite + Unpin>(&self, stream: &mut S, payload: &[u8]) -> Result<(), BinaryCountSketchError> {
+        if payload.len() > self.max_frame_len || payload.len() > u32::MAX as usize { return Err(protocol_error("Frame too long")); }
+        stream.write_u32_le(payload.len() as u32).await.map_err(transport)?;
+        stream.write_all(payload).await.map_err(transport)?;
+        stream.flush().await.map_err(transport)
+    }
+
+    async fn read_frame<S: AsyncRead + Unpin>(&self, stream: &mut S) -> Result<Vec<u8>, BinaryCountSketchError> {
+        let len = stream.read_u32_le().await.map_err(transport)? as usize;
+        if len > self.max_frame_len { return Err(protocol_error("Frame too long")); }
+        let mut payload = vec![0; len];
+        stream.read_exact(&mut payload).await.map_err(transport)?;
+        Ok(payload)
+    }
+}
+
+/// Fails as `BinaryCountSketch::diff_with` would for sketches of these parameters.
+fn check_params(local: SketchParams, remote: SketchParams) -> Result<(), BinaryCountSketchError> {
+    if local.base_length != remote.base_length { return Err(BinaryCountSketchError::with_mismatch("base length", local.base_length, remote.base_length)); }
+    if local.level != remote.level { return Err(BinaryCountSketchError::with_mismatch("level", local.level, remote.level)); }
+    if local.points != remote.points { return Err(BinaryCountSketchError::with_mismatch("points", local.points, remote.points)); }
+    if local.seed != remote.seed { return Err(BinaryCountSketchError::with_mismatch("seed", local.seed, remote.seed)); }
+    Ok(())
+}
+
+fn sketch_of(params: SketchParams, items: &[Vec<u8>]) -> BinaryCountSketch {
+    let mut sketch = BinaryCountSketch::from_params(params);
+    for item in items {
+        sketch.toggle_bytes(item);
+    }
+    sketch
+}
+
+/// The diff is fully explained once the peer's items are also removed from `residual`.
+fn reconciled(received: Vec<Vec<u8>>, sent: Vec<Vec<u8>>, mut residual: BinaryCountSketch) -> Reconciled {
+    for item in &received {
+        residual.toggle_bytes(item);
+    }
+    let complete = residual.words().iter().all(|w| *w == 0);
+    Reconciled { received, sent, complete }
+}
+
+fn encode_items(items: &[Vec<u8>]) -> Vec<u8> {
+    let mut out = (items.len() as u32).to_le_bytes().to_vec();
+    for item in items {
+        out.extend_from_slice(&(item.len() as u32).to_le_bytes());
+        out.extend_from_slice(item);
+    }
+    out
+}
+
+fn decode_items(bytes: &[u8]) -> Result<Vec<Vec<u8>>, BinaryCountSketchError> {
+    let parse_error = || BinaryCountSketchError::with_kind(ErrorKind::Parse, "Incorrect items");
+    let mut rest = bytes;
+    let mut take = |n: usize| -> Result<&[u8], BinaryCountSketchError> {
+        if rest.len() < n { return Err(parse_error()); }
+        let (head, tail) = rest.split_at(n);
+        rest = tail;
+        Ok(head)
+    };
+    let count = u32::from_le_bytes(take(4)?.try_into().unwrap()) as usize;
+    // Each item takes at least its 4 byte length, which bounds the allocation.
+    let mut items = Vec::with_capacity(count.min(bytes.len() / 4));
+    for _ in 0..count {
+        let len = u32::from_le_bytes(take(4)?.try_into().unwrap()) as usize;
+        items.push(take(len)?.to_vec());
+    }
+    if !rest.is_empty() { return Err(parse_error()); }
+    Ok(items)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(range: std::ops::Range<u32>) -> Vec<Vec<u8>> {
+        range.map(|i| format!("key-{}", i).into_bytes()).collect()
+    }
+
+    #[tokio::test]
+    async fn test_reconcile_over_tcp() {
+        let params = SketchParams::new(100, 2, 5);
+        let server = Peer::new(params, keys(0..1000));
+        let client = Peer::new(params, keys(5..1003));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("Bound listener");
+        let addr = listener.local_addr().expect("Local address");
+        let (served, connected) = tokio::join!(server.accept(&listener), client.connect(addr));
+        let (served, mut connected) = (served.expect("No errors"), connected.expect("No errors"));
+
+        connected.received.sort();
+        assert_eq!(connected.received, keys(0..5));
+        let mut received = served.received.clone();
+        received.sort();
+        assert_eq!(received, keys(1000..1003));
+        assert_eq!(served.sent.len(), 5);
+        assert!(served.complete && connected.complete);
+    }
+
+    #[tokio::test]
+    async fn test_accepted_params() {
+        let (old, new) = (SketchParams::new(100, 2, 5), SketchParams::new(50, 3, 4));
+        let server = Peer::new(old, keys(0..500)).with_accepted_params(new);
+        let client = Peer::new(new, keys(2..500));
+
+        let (mut client_stream, mut server_stream) = tokio::io::duplex(1 << 16);
+        let (served, connected) = tokio::join!(server.reconcile_server(&mut server_stream), client.reconcile_client(&mut client_stream));
+        assert_eq!(served.expect("No errors").received, Vec::<Vec<u8>>::new());
+        let mut received = connected.expect("No errors").received;
+        received.sort();
+        assert_eq!(received, keys(0..2));
+        assert_eq!(server.sketch().params(), old);
+
+        // Parameters the server does not accept still fail the diff.
+        let other = Peer::new(SketchParams::new(10, 0, 3), keys(0..10));
+        let (mut client_stream, server_stream) = tokio::io::duplex(1 << 16);
+        let served = async {
+            let mut server_stream = server_stream;
+            server.reconcile_server(&mut server_stream).await
+        };
+        let (served, connected) = tokio::join!(served, other.reconcile_client(&mut client_stream));
+        assert_eq!(served.expect_err("Error").kind(), ErrorKind::Compatibility);
+        assert_eq!(connected.expect_err("Error").kind(), ErrorKind::Transport);
+    }
+
+    #[tokio::test]
+    async fn test_version_negotiation() {
+        let peer = Peer::new(SketchParams::new(10, 0, 3), keys(0..10));
+        let (mut client, mut server) = tokio::io::duplex(1 << 16);
+
+        // A client that only speaks a future version is refused.
+        let mut hello = MAGIC.to_vec();
+        hello.extend_from_slice(&[MAX_VERSION + 1, MAX_VERSION + 2]);
+        let (served, ack) = tokio::join!(peer.reconcile_server(&mut server), async {
+            peer.write_frame(&mut client, &hello).await.expect("No errors");
+            peer.read_frame(&mut client).await.expect("No errors")
+        });
+        assert_eq!(served.expect_err("Error").kind(), ErrorKind::Transport);
+        assert_eq!(ack, b"BCSN\x00");
+    }
+
+    #[tokio::test]
+    async fn test_timeouts() {
+        let peer = Peer::new(SketchParams::new(10, 0, 3), keys(0..10));
+        let with = |timeouts| Peer::new(SketchParams::new(10, 0, 3), keys(0..10)).with_timeouts(timeouts);
+        let timeouts = Timeouts { handshake: Some(Duration::from_millis(20)), ..Default::default() };
+
+        // A client that never says hello.
+        let (_client, mut server) = tokio::io::duplex(1 << 16);
+        let err = with(timeouts).reconcile_server(&mut server).await.expect_err("Error");
+        assert_eq!(err.kind(), ErrorKind::TimedOut);
+        assert!(err.to_string().contains("handshake"), "{}", err);
+
+        // A server that completes the handshake, then never sends its sketch.
+        let (mut client, mut server) = tokio::io::duplex(1 << 16);
+        let timeouts = Timeouts { sketch_exchange: Some(Duration::from_millis(20)), ..Default::default() };
+        let timed = with(timeouts);
+        let (connected, _) = tokio::join!(timed.reconcile_client(&mut client), async {
+            peer.read_frame(&mut server).await.expect("No errors");
+            peer.write_frame(&mut server, &[b'B', b'C', b'S', b'N', MAX_VERSION]).await.expect("No errors");
+        });
+        let err = connected.expect_err("Error");
+        assert_eq!(err.kind(), ErrorKind::TimedOut);
+        assert!(err.to_string().contains("sketch exchange"), "{}", err);
+
+        // The session deadline applies across phases, even without phase timeouts.
+        let (mut client, mut server) = tokio::io::duplex(1 << 16);
+        let timeouts = Timeouts { session: Some(Duration::from_millis(20)), ..Default::default() };
+        let timed = with(timeouts);
+        let (connected, _) = tokio::join!(timed.reconcile_client(&mut client), async {
+            peer.read_frame(&mut server).await.expect("No errors");
+            time::sleep(Duration::from_millis(5)).await;
+            peer.write_frame(&mut server, &[b'B', b'C', b'S', b'N', MAX_VERSION]).await.expect("No errors");
+        });
+        let err = connected.expect_err("Error");
+        assert_eq!(err.kind(), ErrorKind::TimedOut);
+        assert!(err.to_string().contains("sketch exchange"), "{}", err);
+
+        // Timeouts generous enough let the session through.
+        let timeouts = Timeouts { handshake: Some(Duration::from_secs(10)), session: Some(Duration::from_secs(10)), ..Default::default() };
+        let (mut client, mut server) = tokio::io::duplex(1 << 16);
+        let other = Peer::new(SketchParams::new(10, 0, 3), keys(2..10)).with_timeouts(timeouts);
+        let timed = with(timeouts);
+        let (served, connected) = tokio::join!(timed.reconcile_server(&mut server), other.reconcile_client(&mut client));
+        let mut sent = served.expect("No errors").sent;
+        sent.sort();
+        assert_eq!(sent, keys(0..2));
+        assert!(connected.expect("No errors").complete);
+    }
+
+    #[tokio::test]
+    async fn test_streaming() {
+        // Frames of at most 64 bytes split the sketches into 8 word slices and the items
+        // into frames of a few each.
+        let params = SketchParams::new(10, 2, 5);
+        let server = Peer::new(params, keys(0..100)).with_max_frame_len(64);
+        let client = Peer::new(params, keys(3..104)).with_max_frame_len(64);
+        let (mut client_stream, mut server_stream) = tokio::io::duplex(256);
+        let (served, connected) = tokio::join!(server.reconcile_server(&mut server_stream), client.reconcile_client(&mut client_stream));
+        let (served, connected) = (served.expect("No errors"), connected.expect("No errors"));
+        assert_eq!(served.received.len(), 4);
+        assert_eq!(connected.received.len(), 3);
+        assert!(served.complete && connected.complete);
+
+        // A slice past the end of the announced sketch is refused.
+        let small = Peer::new(SketchParams::new(1, 0, 5), keys(0..10));
+        let (mut client, mut server) = tokio::io::duplex(1 << 16);
+        let (served, _) = tokio::join!(small.reconcile_server(&mut server), async {
+            let mut hello = MAGIC.to_vec();
+            hello.extend_from_slice(&[MIN_VERSION, MAX_VERSION]);
+            let mut header = Vec::new();
+            small.sketch().write_header(&mut header);
+            for frame in [&hello[..], &header, &[0; 16]] {
+                small.write_frame(&mut client, frame).await.expect("No errors");
+            }
+        });
+        assert_eq!(served.expect_err("Error").to_string(), "Sketch Error: Incorrect sketch words");
+    }
+
+    #[test]
+    fn test_items_encoding() {
+        let items = keys(0..3);
+        assert_eq!(decode_items(&encode_items(&items)).expect("No errors"), items);
+        assert_eq!(decode_items(&encode_items(&[])).expect("No errors"), Vec::<Vec<u8>>::new());
+        let bytes = encode_items(&items);
+        assert!(decode_items(&bytes[..bytes.len() - 1]).is_err());
+        assert!(decode_items(&[255, 255, 255, 255]).is_err());
+    }
+}
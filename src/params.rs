@@ -0,0 +1,204 @@
+use alloc::vec::Vec;
+
+use crate::{BinaryCountSketch, BinaryCountSketchError, ErrorKind, Item};
+
+/// Parameters of a `BinaryCountSketch`. Two sketches can only be diffed when their
+/// parameters are equal.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SketchParams {
+    pub base_length: u64,
+    pub level: u64,
+    pub points: u64,
+    pub seed: [u8; 16],
+}
+
+/// Largest number of points of a sketch read from serialized data. Every `check` and
+/// `toggle` costs one hash per point, so a hostile header with a huge point count would
+/// stall every operation on the sketch.
+pub const MAX_PARSED_POINTS: u64 = 1024;
+
+/// Largest number of points `SketchParams::for_expected_diff` considers.
+#[cfg(feature = "std")]
+const MAX_POINTS: u64 = 16;
+
+/// Smallest base length `SketchParams::for_expected_diff` folds a sketch down to, so
+/// its level 0 summary still gives a usable difference estimate.
+#[cfg(feature = "std")]
+const MIN_BASE_LENGTH: u64 = 16;
+
+impl SketchParams {
+    pub fn new(base_length: u64, level: u64, points: u64) -> Self {
+        SketchParams { base_length, level, points, seed: [0; 16] }
+    }
+
+    pub fn with_seed(self, seed: [u8; 16]) -> Self {
+        SketchParams { seed, ..self }
+    }
+
+    /// Number of words of a sketch with these parameters, or a `Parse` error if they
+    /// describe no words, more bits than a `usize` can index or more than
+    /// `MAX_PARSED_POINTS` points, as parameters read from untrusted input may.
+    pub fn validate(&self) -> Result<usize, BinaryCountSketchError> {
+        if !(self.base_length > 0) { return Err(BinaryCountSketchError::with_kind(ErrorKind::Parse, "Incorrect base length")); }
+        if !(self.points <= MAX_PARSED_POINTS) { return Err(BinaryCountSketchError::with_kind(ErrorKind::Parse, "Incorrect points")); }
+        let words = self.base_length.checked_shl(self.level as u32).filter(|w| self.level < 64 && w >> self.level == self.base_length);
+        match words.and_then(|w| usize::try_from(w).ok()).filter(|w| w.checked_mul(64).is_some()) {
+            Some(words) => Ok(words),
+            None => Err(BinaryCountSketchError::with_kind(ErrorKind::Parse, "Incorrect level")),
+        }
+    }
+
+    /// Smallest parameters for which a diff of `expected_diff` items decodes at a
+    /// threshold of one less than the points with false positive and false negative
+    /// rates per item of at most `target_error`. The words are split into as many levels
+    /// as keep a base length of at least 16, for `level_down` summaries.
+    #[cfg(feature = "std")]
+    pub fn for_expected_diff(expected_diff: usize, target_error: f64) -> Result<Self, BinaryCountSketchError> {
+        if !(target_error > 0.0 && target_error < 1.0) { return Err(BinaryCountSketchError::with_kind(ErrorKind::InvalidArgument, "Incorrect target error")); }
+
+        let d = expected_diff.max(1) as f64;
+        let (points, bits) = (2..=MAX_POINTS).map(|k| {
+            let k_f = k as f64;
+            // With a fraction `p` of set bits, a non-member reaches `k - 1` set points
+            // with probability about `k p^(k-1)`, and a member misses two of its points
+            // with probability about `(k choose 2) p^2`.
+            let p = (target_error / k_f).powf(1.0 / (k_f - 1.0))
+                .min((target_error / (k_f * (k_f - 1.0) / 2.0)).sqrt())
+                .min(0.25);
+            // `k d` flips of `l` bits leave a fraction `p = (1 - (1 - 2/l)^(k d)) / 2` set.
+            (k, -2.0 * k_f * d / (1.0 - 2.0 * p).ln())
+        }).min_by(|a, b| a.1.total_cmp(&b.1)).unwrap();
+
+        let words = (bits / 64.0).ceil() as u64;
+        let level = (words / MIN_BASE_LENGTH).max(1).ilog2() as u64;
+        Ok(SketchParams::new(words.div_ceil(1 << level), level, points))
+    }
+}
+
+impl BinaryCountSketch {
+    /// Empty sketch sized with `SketchParams::for_expected_diff`.
+    #[cfg(feature = "std")]
+    pub fn for_expected_diff(expected_diff: usize, target_error: f64) -> Result<Self, BinaryCountSketchError> {
+        Ok(BinaryCountSketch::from_params(SketchParams::for_expected_diff(expected_diff, target_error)?))
+    }
+
+    pub fn params(&self) -> SketchParams {
+        SketchParams::new(self.base_length, self.level, self.points).with_seed(self.seed)
+    }
+
+    pub fn from_params(params: SketchParams) -> Self {
+        BinaryCountSketch::with_seed(params.base_length, params.level, params.points, params.seed)
+    }
+
+    /// Rebuilds a sketch from its parameters and words, e.g. after reading it from disk.
+    pub fn from_parts(params: SketchParams, words: Vec<u64>) -> Result<Self, BinaryCountSketchError> {
+        if !(words.len() == params.validate()?) { return Err(BinaryCountSketchError::with_kind(ErrorKind::Parse, "Incorrect words length")); }
+
+        Ok(BinaryCountSketch {
+            base_length: params.base_length,
+            level: params.level,
+            points: params.points,
+            seed: params.seed,
+            words,
+        })
+    }
+
+    pub fn words(&self) -> &[u64] {
+        &self.words
+    }
+
+    /// Re-encodes the items this sketch was built from into a sketch with new
+    /// parameters. The items are first checked to reproduce this sketch exactly, so a
+    /// stale or partial item source is reported instead of silently losing state. Peers
+    /// reconciling over `net` can switch parameters with `Peer::with_accepted_params`.
+    pub fn migrate<I>(&self, items: I, new_params: SketchParams) -> Result<Self, BinaryCountSketchError>
+    where
+        I: IntoIterator,
+        I::Item: Item,
+    {
+        let mut old = BinaryCountSketch::from_params(self.params());
+        let mut new = BinaryCountSketch::from_params(new_params);
+        for item in items {
+            old.toggle(&item);
+            new.toggle(&item);
+        }
+
+        if old.words != self.words { return Err(BinaryCountSketchError::with_kind(ErrorKind::Compatibility, "Items do not match sketch")); }
+        Ok(new)
+    }
+}
+
+#[cfg(all(test, feature = "rand"))]
+mod tests {
+    use super::*;
+    use crate::TestItem;
+
+    #[test]
+    fn test_params() {
+        let sketch = BinaryCountSketch::new(10, 2, 3);
+        assert_eq!(sketch.params(), SketchParams::new(10, 2, 3));
+        assert_eq!(BinaryCountSketch::from_params(sketch.params()).bits(), sketch.bits());
+    }
+
+    #[test]
+    fn test_for_expected_diff() {
+        let params = SketchParams::for_expected_diff(100, 1e-3).expect("No errors");
+        assert_eq!(params.points, 3);
+        assert!(params.base_length >= 16);
+        let larger = SketchParams::for_expected_diff(1000, 1e-3).expect("No errors");
+        assert!(BinaryCountSketch::from_params(larger).bits() > BinaryCountSketch::from_params(params).bits());
+        assert!(SketchParams::for_expected_diff(100, 0.0).is_err());
+        assert!(SketchParams::for_expected_diff(100, 1.0).is_err());
+
+        let mut sketch = BinaryCountSketch::for_expected_diff(100, 1e-3).expect("No errors");
+        assert_eq!(sketch.params(), params);
+        let members: Vec<TestItem> = (0..100).map(|_| TestItem::new()).collect();
+        for item in &members {
+            sketch.toggle(item);
+        }
+        let threshold = params.points as usize - 1;
+        assert!(members.iter().filter(|item| sketch.check(*item) < threshold).count() < 5);
+        assert!((0..1000).filter(|_| sketch.check(&TestItem::new()) >= threshold).count() < 10);
+    }
+
+    #[test]
+    fn test_from_parts() {
+        let mut sketch = BinaryCountSketch::new(10, 2, 3);
+        sketch.toggle(&TestItem::new());
+
+        let rebuilt = BinaryCountSketch::from_parts(sketch.params(), sketch.words().to_vec()).expect("No errors");
+        assert_eq!(rebuilt.words(), sketch.words());
+        assert!(BinaryCountSketch::from_parts(SketchParams::new(10, 3, 3), sketch.words().to_vec()).is_err());
+
+        // Parameters describing no words, or too many to index, are rejected up front.
+        let parse = |params: SketchParams| BinaryCountSketch::from_parts(params, vec![]).expect_err("Error").kind();
+        assert_eq!(parse(SketchParams::new(0, 2, 3)), ErrorKind::Parse);
+        assert_eq!(parse(SketchParams::new(10, 64, 3)), ErrorKind::Parse);
+        assert_eq!(parse(SketchParams::new(10, u64::MAX, 3)), ErrorKind::Parse);
+        assert_eq!(parse(SketchParams::new(1 << 40, 30, 3)), ErrorKind::Parse);
+        assert_eq!(parse(SketchParams::new(1, 60, 3)), ErrorKind::Parse);
+        assert_eq!(parse(SketchParams::new(10, 2, MAX_PARSED_POINTS + 1)), ErrorKind::Parse);
+    }
+
+    #[test]
+    fn test_migrate() {
+        let items: Vec<TestItem> = (0..100).map(|_| TestItem::new()).collect();
+        let mut sketch = BinaryCountSketch::new(10, 2, 3);
+        for item in &items {
+            sketch.toggle(item);
+        }
+
+        let new_params = SketchParams::new(20, 3, 5);
+        let migrated = sketch.migrate(&items, new_params).expect("No errors");
+        assert_eq!(migrated.params(), new_params);
+
+        let mut expected = BinaryCountSketch::from_params(new_params);
+        for item in &items {
+            expected.toggle(item);
+        }
+        assert_eq!(migrated.words, expected.words);
+
+        assert!(sketch.migrate(&items[1..], new_params).is_err());
+    }
+}
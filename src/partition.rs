@@ -0,0 +1,164 @@
+use alloc::vec::Vec;
+
+use crate::{route_key, BinaryCountSketch, BinaryCountSketchError, ComposedSketch, ErrorKind, Item, SketchParams};
+#[cfg(feature = "std")]
+use crate::{DecodeReport, PeelStrategy, PeelingDecoder};
+
+/// A set of sub-sketches, one per key prefix, so that peers only need to exchange and
+/// decode the partitions that actually differ.
+///
+/// An item's prefix is taken from the top `prefix_bits` bits of a mixed hash of its
+/// first code, so both peers route every item to the same partition.
+pub struct PartitionedSketch {
+    prefix_bits: u32,
+    partitions: Vec<BinaryCountSketch>,
+}
+
+impl PartitionedSketch {
+    pub fn new(prefix_bits: u32, params: SketchParams) -> Result<Self, BinaryCountSketchError> {
+        if prefix_bits > 16 { return Err(BinaryCountSketchError::with_kind(ErrorKind::InvalidArgument, "Incorrect prefix bits")); }
+
+        Ok(PartitionedSketch {
+            prefix_bits,
+            partitions: (0..1usize << prefix_bits).map(|_| BinaryCountSketch::from_params(params)).collect(),
+        })
+    }
+
+    pub fn fanout(&self) -> usize {
+        self.partitions.len()
+    }
+
+    pub fn partition_of<V: Item>(&self, v: &V) -> usize {
+        if self.prefix_bits == 0 {
+            return 0;
+        }
+        (route_key(v) >> (64 - self.prefix_bits)) as usize
+    }
+
+    pub fn partition(&self, i: usize) -> &BinaryCountSketch {
+        &self.partitions[i]
+    }
+
+    pub fn toggle<V: Item>(&mut self, v: &V) {
+        let i = self.partition_of(v);
+        self.partitions[i].toggle(v);
+    }
+
+    pub fn check<V: Item>(&self, v: &V) -> usize {
+        self.partitions[self.partition_of(v)].check(v)
+    }
+
+    pub fn digests(&self) -> Vec<u64> {
+        self.partitions.iter().map(|p| p.digest()).collect()
+    }
+
+    /// Indices of the partitions whose digest differs from the peer's.
+    pub fn differing_partitions(&self, remote_digests: &[u64]) -> Result<Vec<usize>, BinaryCountSketchError> {
+        if remote_digests.len() != self.partitions.len() { return Err(BinaryCountSketchError::with_mismatch("partitions length", self.partitions.len(), remote_digests.len())); }
+
+        Ok(self
+            .digests()
+            .into_iter()
+            .zip(remote_digests)
+            .enumerate()
+            .filter(|(_, (local, remote))| local != *remote)
+            .map(|(i, _)| i)
+            .collect())
+    }
+
+    pub fn diff_partition(&mut self, i: usize, other: &BinaryCountSketch) -> Result<(), BinaryCountSketchError> {
+        if i >= self.partitions.len() { return Err(BinaryCountSketchError::with_kind(ErrorKind::InvalidArgument, "Incorrect partition")); }
+        self.partitions[i].diff_with(other)
+    }
+
+    /// Decodes partition `i` of a diffed sketch, ignoring candidates routed elsewhere.
+    #[cfg(feature = "std")]
+    pub fn decode_partition<V: Item + Clone, S: PeelStrategy>(&mut self, i: usize, decoder: &PeelingDecoder<S>, candidates: &[V]) -> Result<DecodeReport<V>, BinaryCountSketchError> {
+        if i >= self.partitions.len() { return Err(BinaryCountSketchError::with_kind(ErrorKind::InvalidArgument, "Incorrect partition")); }
+
+        let candidates: Vec<V> = candidates.iter().filter(|v| self.partition_of(*v) == i).cloned().collect();
+        decoder.decode(&mut self.partitions[i], &candidates)
+    }
+
+    pub fn compose(&self) -> ComposedSketch {
+        BinaryCountSketch::concat(&self.partitions)
+    }
+
+    /// Rebuilds a partitioned sketch from the composition of its partitions.
+    pub fn from_composed(prefix_bits: u32, composed: &ComposedSketch) -> Result<Self, BinaryCountSketchError> {
+        if composed.len() != 1 << prefix_bits { return Err(BinaryCountSketchError::with_kind(ErrorKind::Parse, "Incorrect partitions length")); }
+
+        Ok(PartitionedSketch {
+            prefix_bits,
+            partitions: composed.split()?,
+        })
+    }
+}
+
+#[cfg(all(test, feature = "rand"))]
+mod tests {
+    use super::*;
+    use crate::testing::Scenario;
+    use crate::TestItem;
+
+    #[test]
+    fn test_partition_routing() {
+        let sketch = PartitionedSketch::new(3, SketchParams::new(10, 2, 3)).expect("No errors");
+        assert_eq!(sketch.fanout(), 8);
+        assert!(PartitionedSketch::new(17, SketchParams::new(10, 2, 3)).is_err());
+
+        let mut seen = [false; 8];
+        for _ in 0..200 {
+            let item = TestItem::new();
+            let i = sketch.partition_of(&item);
+            assert_eq!(i, sketch.partition_of(&item));
+            seen[i] = true;
+        }
+        assert!(seen.iter().all(|s| *s));
+    }
+
+    #[test]
+    fn test_partition_exchange() {
+        let peers = Scenario::new(SketchParams::new(100, 2, 5), 2000, 0).with_sides(0, 3).peers(0);
+        let mut local = PartitionedSketch::new(4, SketchParams::new(100, 2, 5)).expect("No errors");
+        let mut remote = PartitionedSketch::new(4, SketchParams::new(100, 2, 5)).expect("No errors");
+        for item in &peers.common {
+            local.toggle(item);
+            remote.toggle(item);
+        }
+        for item in &peers.remote_only {
+            remote.toggle(item);
+        }
+        let (candidates, extra) = (peers.candidates(), peers.remote_only);
+
+        let differing = local.differing_partitions(&remote.digests()).expect("No errors");
+        let mut expected: Vec<usize> = extra.iter().map(|item| local.partition_of(item)).collect();
+        expected.sort();
+        expected.dedup();
+        assert_eq!(differing, expected);
+
+        let decoder = PeelingDecoder::new(4);
+        let mut decoded = vec![];
+        for i in differing {
+            local.diff_partition(i, remote.partition(i)).expect("No errors");
+            decoded.extend(local.decode_partition(i, &decoder, &candidates).expect("No errors").decoded);
+        }
+
+        assert_eq!(decoded.len(), extra.len());
+        assert!(extra.iter().all(|item| decoded.contains(item)));
+    }
+
+    #[test]
+    fn test_compose_partitioned() {
+        let mut sketch = PartitionedSketch::new(2, SketchParams::new(10, 1, 3)).expect("No errors");
+        let items: Vec<TestItem> = (0..20).map(|_| TestItem::new()).collect();
+        for item in &items {
+            sketch.toggle(item);
+        }
+
+        let composed = sketch.compose();
+        assert!(PartitionedSketch::from_composed(3, &composed).is_err());
+        let received = PartitionedSketch::from_composed(2, &composed).expect("No errors");
+        assert_eq!(received.digests(), sketch.digests());
+    }
+}
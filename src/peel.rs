@@ -0,0 +1,1063 @@
+use std::collections::HashSet;
+use std::fmt::Write;
+use std::future::Future;
+use std::pin::{pin, Pin};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant};
+
+use crate::{BinaryCountSketch, BinaryCountSketchError, ErrorKind, Item};
+
+/// Number of candidates scored between two cancellation checks (and, for
+/// `decode_async`, between two yields to the executor).
+const CANCEL_CHECK_INTERVAL: usize = 4096;
+
+/// Returns `Pending` once, so the executor can run other tasks before resuming us.
+struct YieldNow(bool);
+
+impl Future for YieldNow {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.0 {
+            Poll::Ready(())
+        } else {
+            self.0 = true;
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    }
+}
+
+/// Statistics recorded for a single peeling round.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RoundTrace {
+    pub threshold: usize,
+    /// `histogram[s]` is the number of candidates that scored exactly `s`.
+    pub histogram: Vec<usize>,
+    pub removed: usize,
+    pub remaining: usize,
+    /// Selected candidates refused by the verification hook: false positives at this
+    /// threshold, caught before they were toggled out.
+    pub rejected: usize,
+}
+
+/// Per-round record of a peeling decode, for offline analysis of stalled decodes.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DecodeTrace {
+    pub points: usize,
+    pub rounds: Vec<RoundTrace>,
+}
+
+impl DecodeTrace {
+    pub fn to_json(&self) -> String {
+        let mut out = String::new();
+        write!(out, "{{\"points\":{},\"rounds\":[", self.points).unwrap();
+        for (i, round) in self.rounds.iter().enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+            write!(out, "{{\"threshold\":{},\"histogram\":[", round.threshold).unwrap();
+            for (j, count) in round.histogram.iter().enumerate() {
+                if j > 0 {
+                    out.push(',');
+                }
+                write!(out, "{}", count).unwrap();
+            }
+            write!(out, "],\"removed\":{},\"remaining\":{},\"rejected\":{}}}", round.removed, round.remaining, round.rejected).unwrap();
+        }
+        out.push_str("]}");
+        out
+    }
+}
+
+/// Why a peeling decode stopped.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DecodeStatus {
+    /// The strategy ran out of thresholds to try.
+    Complete,
+    /// A `DecodeBudget` limit or the `decode_anytime` deadline was hit; the report holds
+    /// the partial result.
+    BudgetExceeded,
+    /// The decoder's `CancellationToken` was cancelled; the report holds the partial result.
+    Cancelled,
+}
+
+/// Shared flag used to abort a running decode from another thread. The decoder checks
+/// it between rounds and between chunks of candidates, so an in-progress round is
+/// abandoned before any of its toggles are applied.
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        CancellationToken::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Limits on the work a `PeelingDecoder` may do. Limits are checked between rounds.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DecodeBudget {
+    pub max_rounds: Option<usize>,
+    /// Maximum number of candidate scores computed, summed over all rounds.
+    pub max_evaluations: Option<usize>,
+    pub max_duration: Option<Duration>,
+}
+
+/// Outcome of a peeling decode.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DecodeReport<V> {
+    pub status: DecodeStatus,
+    pub decoded: Vec<V>,
+    /// Score of each item of `decoded` in the round it was removed, in the same order.
+    pub scores: Vec<usize>,
+    pub undecoded: Vec<V>,
+    pub rounds: usize,
+    pub final_threshold: usize,
+    pub toggles_applied: usize,
+    /// Removals postponed to a later round because they shared bits with another
+    /// removal in the same round (parallel decoding only).
+    pub conflicts_deferred: usize,
+    /// Selected candidates accepted by the verification hook (`decode_verified` only).
+    pub verified: usize,
+    /// Selected candidates refused by the verification hook. They are never toggled
+    /// out of the sketch and are returned in `undecoded`.
+    pub rejected: usize,
+    /// Candidates dropped because an earlier candidate had the same codes. Toggling both
+    /// would cancel them out of the sketch; they are in neither `decoded` nor `undecoded`.
+    pub duplicates_skipped: usize,
+}
+
+/// How a decode is driven, beyond the decoder's own budget.
+#[derive(Clone, Copy)]
+struct RunMode {
+    /// Yield to the executor between chunks of candidates.
+    yielding: bool,
+    deadline: Option<Instant>,
+}
+
+/// Checks a selected candidate against a source of truth before it is toggled out.
+trait Verifier<V> {
+    /// Returns `None` when no check was made.
+    async fn verify(&mut self, item: &V) -> Option<bool>;
+}
+
+struct NoVerify;
+
+impl<V> Verifier<V> for NoVerify {
+    async fn verify(&mut self, _item: &V) -> Option<bool> {
+        None
+    }
+}
+
+struct SyncVerify<F>(F);
+
+impl<V, F: FnMut(&V) -> bool> Verifier<V> for SyncVerify<F> {
+    async fn verify(&mut self, item: &V) -> Option<bool> {
+        Some((self.0)(item))
+    }
+}
+
+struct AsyncVerify<F>(F);
+
+impl<V, F: FnMut(&V) -> Fut, Fut: Future<Output = bool>> Verifier<V> for AsyncVerify<F> {
+    async fn verify(&mut self, item: &V) -> Option<bool> {
+        Some((self.0)(item).await)
+    }
+}
+
+/// How the rounds of a peeling decode score candidates and remove the selected ones.
+trait RoundExecutor<V> {
+    fn chunk_len(&self) -> usize;
+
+    fn score(&self, sketch: &BinaryCountSketch, items: &[&V]) -> Vec<usize>;
+
+    /// Toggles the selected items out of the sketch. Returns, for each item, whether
+    /// it was removed or must be re-scored in a later round.
+    fn remove(&self, sketch: &mut BinaryCountSketch, selected: &[&V]) -> Vec<bool>;
+}
+
+struct Serial;
+
+impl<V: Item> RoundExecutor<V> for Serial {
+    fn chunk_len(&self) -> usize {
+        CANCEL_CHECK_INTERVAL
+    }
+
+    fn score(&self, sketch: &BinaryCountSketch, items: &[&V]) -> Vec<usize> {
+        items.iter().map(|item| sketch.check(*item)).collect()
+    }
+
+    fn remove(&self, sketch: &mut BinaryCountSketch, selected: &[&V]) -> Vec<bool> {
+        for item in selected {
+            sketch.toggle(*item);
+        }
+        vec![true; selected.len()]
+    }
+}
+
+/// Scores and toggles on a rayon pool, `None` for the global one, so worker threads
+/// are started at most once per decode rather than once per round. Within a round, a
+/// selected item sharing a bit with an earlier selected item is deferred rather than
+/// toggled, since its score was computed before the earlier removal changed that bit.
+#[cfg(feature = "rayon")]
+struct Parallel(Option<rayon::ThreadPool>);
+
+#[cfg(feature = "rayon")]
+impl Parallel {
+    fn install<R: Send, F: FnOnce() -> R + Send>(&self, f: F) -> R {
+        match &self.0 {
+            Some(pool) => pool.install(f),
+            None => f(),
+        }
+    }
+
+    fn threads(&self) -> usize {
+        self.install(rayon::current_num_threads)
+    }
+}
+
+#[cfg(feature = "rayon")]
+impl<V: Item + Sync> RoundExecutor<V> for Parallel {
+    fn chunk_len(&self) -> usize {
+        CANCEL_CHECK_INTERVAL * self.threads()
+    }
+
+    fn score(&self, sketch: &BinaryCountSketch, items: &[&V]) -> Vec<usize> {
+        use rayon::prelude::*;
+
+        self.install(|| items.par_iter().map(|item| sketch.check(*item)).collect())
+    }
+
+    fn remove(&self, sketch: &mut BinaryCountSketch, selected: &[&V]) -> Vec<bool> {
+        use rayon::prelude::*;
+
+        let l = sketch.bits();
+        let mut claimed = HashSet::new();
+        let mut flips = Vec::new();
+        let mut removed = Vec::with_capacity(selected.len());
+        for item in selected {
+            let bits: Vec<usize> = (0..sketch.points_of(*item)).map(|i| crate::code_index(item, i, l)).collect();
+            if bits.iter().any(|b| claimed.contains(b)) {
+                removed.push(false);
+                continue;
+            }
+            claimed.extend(bits.iter().copied());
+            flips.extend(bits);
+            removed.push(true);
+        }
+        if flips.is_empty() { return removed; }
+
+        // Each task owns a contiguous range of words and applies the flips falling in it.
+        let words_per_task = sketch.words.len().div_ceil(self.threads()).max(1);
+        let mut buckets = vec![Vec::new(); sketch.words.len().div_ceil(words_per_task)];
+        for b in flips {
+            buckets[b / 64 / words_per_task].push(b);
+        }
+        self.install(|| {
+            sketch.words.par_chunks_mut(words_per_task).zip(buckets).enumerate().for_each(|(i, (words, bucket))| {
+                let base = i * words_per_task * 64;
+                for b in bucket {
+                    words[(b - base) / 64] ^= 1 << (b % 64);
+                }
+            })
+        });
+
+        removed
+    }
+}
+
+/// Threshold schedule followed by the `PeelingDecoder`.
+pub trait PeelStrategy {
+    /// Threshold of the first round, or an error if the strategy cannot be used with
+    /// a sketch of `points` points.
+    fn initial_threshold(&self, points: usize) -> Result<usize, BinaryCountSketchError>;
+
+    /// Threshold of the round following `round`, or `None` to stop decoding.
+    fn next_threshold(&self, round: &RoundTrace) -> Option<usize>;
+}
+
+/// Starts at the full point count and lowers the threshold by one, down to
+/// `min_threshold`, whenever a round makes no progress.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StrictFirst {
+    pub min_threshold: usize,
+}
+
+impl PeelStrategy for StrictFirst {
+    fn initial_threshold(&self, points: usize) -> Result<usize, BinaryCountSketchError> {
+        if self.min_threshold > points { return Err(BinaryCountSketchError::with_kind(ErrorKind::InvalidArgument, "Incorrect threshold")); }
+        Ok(points)
+    }
+
+    fn next_threshold(&self, round: &RoundTrace) -> Option<usize> {
+        if round.removed > 0 {
+            Some(round.threshold)
+        } else if round.threshold > self.min_threshold {
+            Some(round.threshold - 1)
+        } else {
+            None
+        }
+    }
+}
+
+/// As `StrictFirst`, but raises the threshold by one, up to the point count, after a
+/// round in which the verification hook of `decode_verified` rejected candidates, as
+/// those are actual false positives of the current threshold.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BackOffOnRejects {
+    pub min_threshold: usize,
+}
+
+impl PeelStrategy for BackOffOnRejects {
+    fn initial_threshold(&self, points: usize) -> Result<usize, BinaryCountSketchError> {
+        StrictFirst { min_threshold: self.min_threshold }.initial_threshold(points)
+    }
+
+    fn next_threshold(&self, round: &RoundTrace) -> Option<usize> {
+        // Rejected candidates are not retried, so this cannot go on forever. A trace
+        // without a histogram gives no point count, so the threshold is not raised.
+        let points = round.histogram.len().checked_sub(1).unwrap_or(round.threshold);
+        if round.rejected > 0 && round.threshold < points {
+            Some(round.threshold + 1)
+        } else {
+            StrictFirst { min_threshold: self.min_threshold }.next_threshold(round)
+        }
+    }
+}
+
+/// Peels at a single threshold until a round makes no progress.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FixedThreshold(pub usize);
+
+impl PeelStrategy for FixedThreshold {
+    fn initial_threshold(&self, points: usize) -> Result<usize, BinaryCountSketchError> {
+        if self.0 > points { return Err(BinaryCountSketchError::with_kind(ErrorKind::InvalidArgument, "Incorrect threshold")); }
+        Ok(self.0)
+    }
+
+    fn next_threshold(&self, round: &RoundTrace) -> Option<usize> {
+        if round.removed > 0 { Some(round.threshold) } else { None }
+    }
+}
+
+/// Iteratively decodes a diffed sketch against a candidate list: candidates scoring at
+/// or above the current threshold are toggled out of the sketch, and the `PeelStrategy`
+/// picks the threshold of the next round.
+pub struct PeelingDecoder<S = StrictFirst> {
+    strategy: S,
+    budget: DecodeBudget,
+    cancel: Option<CancellationToken>,
+}
+
+impl PeelingDecoder {
+    pub fn new(min_threshold: usize) -> Self {
+        PeelingDecoder::with_strategy(StrictFirst { min_threshold })
+    }
+}
+
+impl<S: PeelStrategy> PeelingDecoder<S> {
+    pub fn with_strategy(strategy: S) -> Self {
+        PeelingDecoder { strategy, budget: DecodeBudget::default(), cancel: None }
+    }
+
+    pub fn with_budget(mut self, budget: DecodeBudget) -> Self {
+        self.budget = budget;
+        self
+    }
+
+    pub fn with_cancellation(mut self, token: CancellationToken) -> Self {
+        self.cancel = Some(token);
+        self
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.cancel.as_ref().is_some_and(|token| token.is_cancelled())
+    }
+
+    pub fn decode<V: Item + Clone>(&self, sketch: &mut BinaryCountSketch, candidates: &[V]) -> Result<DecodeReport<V>, BinaryCountSketchError> {
+        self.run(sketch, candidates.iter().collect(), None, &Serial, NoVerify, None)
+    }
+
+    /// Runs peeling rounds until `deadline`, abandoning the current round when it passes,
+    /// and returns the partial result along with an estimate of the number of
+    /// differences still in the sketch.
+    pub fn decode_anytime<V: Item + Clone>(&self, sketch: &mut BinaryCountSketch, candidates: &[V], deadline: Instant) -> Result<(DecodeReport<V>, Option<usize>), BinaryCountSketchError> {
+        let report = self.run(sketch, candidates.iter().collect(), None, &Serial, NoVerify, Some(deadline))?;
+        Ok((report, sketch.estimate_difference()))
+    }
+
+    /// Same as `decode`, but candidates for which `exclude` returns true, e.g. differences
+    /// already resolved in a previous round, are neither scored nor toggled. They are
+    /// left out of both `decoded` and `undecoded`.
+    pub fn decode_excluding<V: Item + Clone, F: Fn(&V) -> bool>(&self, sketch: &mut BinaryCountSketch, candidates: &[V], exclude: F) -> Result<DecodeReport<V>, BinaryCountSketchError> {
+        self.run(sketch, candidates.iter().filter(|v| !exclude(v)).collect(), None, &Serial, NoVerify, None)
+    }
+
+    /// Same as `decode`, but every candidate selected for removal is first passed to
+    /// `verify`. Rejected candidates are left in the sketch and not retried.
+    pub fn decode_verified<V: Item + Clone, F: FnMut(&V) -> bool>(&self, sketch: &mut BinaryCountSketch, candidates: &[V], verify: F) -> Result<DecodeReport<V>, BinaryCountSketchError> {
+        self.run(sketch, candidates.iter().collect(), None, &Serial, SyncVerify(verify), None)
+    }
+
+    /// Asynchronous version of `decode_verified`, e.g. for checks against a database.
+    pub async fn decode_verified_async<V: Item + Clone, F: FnMut(&V) -> Fut, Fut: Future<Output = bool>>(&self, sketch: &mut BinaryCountSketch, candidates: &[V], verify: F) -> Result<DecodeReport<V>, BinaryCountSketchError> {
+        self.run_inner(sketch, candidates.iter().collect(), None, &Serial, &mut AsyncVerify(verify), RunMode { yielding: true, deadline: None }).await
+    }
+
+    /// Same as `decode`, but scores candidates and applies removals on a rayon pool of
+    /// `threads` threads, started once for the decode. Removals that conflict within a
+    /// round are deferred to the next round.
+    #[cfg(feature = "rayon")]
+    pub fn decode_parallel<V: Item + Clone + Sync>(&self, sketch: &mut BinaryCountSketch, candidates: &[V], threads: usize) -> Result<DecodeReport<V>, BinaryCountSketchError> {
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(threads.max(1)).build().map_err(|e| BinaryCountSketchError::new(&e.to_string()))?;
+        self.run(sketch, candidates.iter().collect(), None, &Parallel(Some(pool)), NoVerify, None)
+    }
+
+    pub fn decode_with_trace<V: Item + Clone>(&self, sketch: &mut BinaryCountSketch, candidates: &[V]) -> Result<(DecodeReport<V>, DecodeTrace), BinaryCountSketchError> {
+        let mut trace = DecodeTrace {
+            points: sketch.points as usize,
+            rounds: Vec::new(),
+        };
+        let report = self.run(sketch, candidates.iter().collect(), Some(&mut trace), &Serial, NoVerify, None)?;
+        Ok((report, trace))
+    }
+
+    /// Same as `decode`, but yields to the executor between chunks of candidates so
+    /// that scoring a large candidate list does not block other tasks.
+    pub async fn decode_async<V: Item + Clone>(&self, sketch: &mut BinaryCountSketch, candidates: &[V]) -> Result<DecodeReport<V>, BinaryCountSketchError> {
+        self.run_inner(sketch, candidates.iter().collect(), None, &Serial, &mut NoVerify, RunMode { yielding: true, deadline: None }).await
+    }
+
+    fn run<V: Item + Clone, E: RoundExecutor<V>, W: Verifier<V>>(&self, sketch: &mut BinaryCountSketch, candidates: Vec<&V>, trace: Option<&mut DecodeTrace>, executor: &E, mut verifier: W, deadline: Option<Instant>) -> Result<DecodeReport<V>, BinaryCountSketchError> {
+        // Without yielding the future never returns `Pending`, so one poll completes it.
+        let mut decode = pin!(self.run_inner(sketch, candidates, trace, executor, &mut verifier, RunMode { yielding: false, deadline }));
+        match decode.as_mut().poll(&mut Context::from_waker(Waker::noop())) {
+            Poll::Ready(result) => result,
+            Poll::Pending => unreachable!("decode only yields when asked to"),
+        }
+    }
+
+    async fn run_inner<V: Item + Clone, E: RoundExecutor<V>, W: Verifier<V>>(&self, sketch: &mut BinaryCountSketch, mut remaining: Vec<&V>, mut trace: Option<&mut DecodeTrace>, executor: &E, verifier: &mut W, mode: RunMode) -> Result<DecodeReport<V>, BinaryCountSketchError> {
+        let mut threshold = self.strategy.initial_threshold(sketch.points as usize)?;
+
+        let duplicates_skipped = {
+            let mut seen = HashSet::new();
+            let before = remaining.len();
+            remaining.retain(|v| seen.insert((0..sketch.points_of(*v)).map(|i| v.get_code(i)).collect::<Vec<_>>()));
+            before - remaining.len()
+        };
+        let mut found = Vec::new();
+        let mut found_scores = Vec::new();
+        let mut rounds = 0;
+        let mut toggles_applied = 0;
+        let mut conflicts_deferred = 0;
+        let mut verified = 0;
+        let mut rejected = Vec::new();
+        let mut evaluations = 0;
+        let start = Instant::now();
+        let mut status = DecodeStatus::Complete;
+
+        'rounds: loop {
+            let over_rounds = self.budget.max_rounds.is_some_and(|max| rounds >= max);
+            let over_evaluations = self.budget.max_evaluations.is_some_and(|max| evaluations + remaining.len() > max);
+            let over_time = self.budget.max_duration.is_some_and(|max| start.elapsed() >= max) || mode.deadline.is_some_and(|d| Instant::now() >= d);
+            if over_rounds || over_evaluations || over_time {
+                status = DecodeStatus::BudgetExceeded;
+                break;
+            }
+
+            let mut scores = Vec::with_capacity(remaining.len());
+            for chunk in remaining.chunks(executor.chunk_len()) {
+                if self.is_cancelled() {
+                    status = DecodeStatus::Cancelled;
+                    break 'rounds;
+                }
+                if mode.deadline.is_some_and(|d| Instant::now() >= d) {
+                    status = DecodeStatus::BudgetExceeded;
+                    break 'rounds;
+                }
+                scores.extend(executor.score(sketch, chunk));
+                if mode.yielding {
+                    YieldNow(false).await;
+                }
+            }
+
+            rounds += 1;
+            evaluations += remaining.len();
+            let mut histogram = vec![0; sketch.points as usize + 1];
+
+            // Thresholds are relative to the sketch's points, so an item with fewer
+            // points must reach the same fraction of its own points.
+            let max = sketch.points as usize;
+            let chosen: Vec<bool> = scores.iter().zip(remaining.iter()).map(|(score, item)| score * max >= threshold * sketch.points_of(*item) as usize).collect();
+
+            let mut selected = Vec::new();
+            let mut refused = vec![false; remaining.len()];
+            for (i, item) in remaining.iter().enumerate() {
+                if !chosen[i] {
+                    continue;
+                }
+                match verifier.verify(*item).await {
+                    Some(false) => refused[i] = true,
+                    Some(true) => {
+                        verified += 1;
+                        selected.push(*item);
+                    }
+                    None => selected.push(*item),
+                }
+            }
+            let mut removed = executor.remove(sketch, &selected).into_iter();
+
+            let mut not_found = Vec::new();
+            let mut removed_count = 0;
+            let rejected_before = rejected.len();
+            for (((score, item), refused), chosen) in scores.into_iter().zip(remaining.iter()).zip(refused).zip(chosen) {
+                histogram[score] += 1;
+                if refused {
+                    rejected.push(*item);
+                } else if chosen && removed.next() == Some(true) {
+                    removed_count += 1;
+                    found.push((*item).clone());
+                    found_scores.push(score);
+                    toggles_applied += 1;
+                } else {
+                    if chosen {
+                        conflicts_deferred += 1;
+                    }
+                    not_found.push(*item);
+                }
+            }
+
+            let round = RoundTrace {
+                threshold,
+                histogram,
+                removed: removed_count,
+                remaining: not_found.len(),
+                rejected: rejected.len() - rejected_before,
+            };
+            remaining = not_found;
+
+            let next = self.strategy.next_threshold(&round);
+            if let Some(trace) = trace.as_deref_mut() {
+                trace.rounds.push(round);
+            }
+
+            match next {
+                Some(t) => threshold = t,
+                None => break,
+            }
+        }
+
+        Ok(DecodeReport {
+            status,
+            decoded: found,
+            scores: found_scores,
+            undecoded: remaining.into_iter().chain(rejected.iter().copied()).cloned().collect(),
+            rounds,
+            final_threshold: threshold,
+            toggles_applied,
+            conflicts_deferred,
+            verified,
+            rejected: rejected.len(),
+            duplicates_skipped,
+        })
+    }
+}
+
+/// Outcome of `BinaryCountSketch::reconcile`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ReconcileResult<V> {
+    pub decoded: Vec<V>,
+    pub remaining: Vec<V>,
+    pub rounds: Vec<RoundTrace>,
+}
+
+/// Target false positive rate per candidate of the threshold `reconcile` picks.
+pub const DEFAULT_FP_RATE: f64 = 1e-4;
+
+/// Item recovered by `BinaryCountSketch::decode_with_peeling`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Recovered<V> {
+    pub item: V,
+    /// Fraction of the item's points that were set when it was removed, 1.0 for an item
+    /// found at the strict threshold.
+    pub confidence: f64,
+}
+
+/// Outcome of `BinaryCountSketch::decode_with_peeling`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PeelingResult<V> {
+    pub recovered: Vec<Recovered<V>>,
+    pub remaining: Vec<V>,
+    /// Per-round thresholds and score histograms, for tuning sketch parameters.
+    pub trace: DecodeTrace,
+    /// Whether every set bit was explained by a recovered item.
+    pub complete: bool,
+}
+
+impl BinaryCountSketch {
+    fn check_load(&self) -> Result<(), BinaryCountSketchError> {
+        if self.is_saturated() { return Err(BinaryCountSketchError::overloaded(self.estimate_difference())); }
+        Ok(())
+    }
+
+    /// Peels `candidates` out of this diffed sketch, with the threshold chosen by
+    /// `suggest_threshold` for a false positive rate of `DEFAULT_FP_RATE`.
+    pub fn reconcile<V: Item + Clone>(&mut self, candidates: &[V]) -> Result<ReconcileResult<V>, BinaryCountSketchError> {
+        let min_threshold = self.suggest_threshold(DEFAULT_FP_RATE);
+        self.reconcile_with_threshold(candidates, min_threshold)
+    }
+
+    /// Peels `candidates` out of this diffed sketch with the `StrictFirst` schedule,
+    /// lowering the threshold down to `min_threshold`. Use a `PeelingDecoder` directly for
+    /// other strategies, budgets or parallel decoding. Fails with `ErrorKind::Overloaded`,
+    /// leaving the sketch unchanged, if it `is_saturated`.
+    pub fn reconcile_with_threshold<V: Item + Clone>(&mut self, candidates: &[V], min_threshold: usize) -> Result<ReconcileResult<V>, BinaryCountSketchError> {
+        self.check_load()?;
+        let (report, trace) = PeelingDecoder::new(min_threshold).decode_with_trace(self, candidates)?;
+        Ok(ReconcileResult {
+            decoded: report.decoded,
+            remaining: report.undecoded,
+            rounds: trace.rounds,
+        })
+    }
+
+    /// Same as `reconcile`, additionally reporting how confidently each item was
+    /// recovered and statistics on every round.
+    pub fn decode_with_peeling<V: Item + Clone>(&mut self, candidates: &[V]) -> Result<PeelingResult<V>, BinaryCountSketchError> {
+        self.check_load()?;
+        let min_threshold = self.suggest_threshold(DEFAULT_FP_RATE);
+        let (report, trace) = PeelingDecoder::new(min_threshold).decode_with_trace(self, candidates)?;
+        let recovered = report.decoded.into_iter().zip(report.scores).map(|(item, score)| {
+            let confidence = score as f64 / self.points_of(&item) as f64;
+            Recovered { item, confidence }
+        }).collect();
+        Ok(PeelingResult {
+            recovered,
+            remaining: report.undecoded,
+            trace,
+            complete: self.words.iter().all(|w| *w == 0),
+        })
+    }
+
+    /// Same as `reconcile_with_threshold`, scoring candidates and applying removals on as many threads
+    /// as the rayon pool has. Removals that conflict within a round are deferred.
+    #[cfg(feature = "rayon")]
+    pub fn reconcile_par<V: Item + Clone + Sync>(&mut self, candidates: &[V], min_threshold: usize) -> Result<ReconcileResult<V>, BinaryCountSketchError> {
+        self.check_load()?;
+        let mut trace = DecodeTrace::default();
+        let decoder = PeelingDecoder::new(min_threshold);
+        let report = decoder.run(self, candidates.iter().collect(), Some(&mut trace), &Parallel(None), NoVerify, None)?;
+        Ok(ReconcileResult {
+            decoded: report.decoded,
+            remaining: report.undecoded,
+            rounds: trace.rounds,
+        })
+    }
+}
+
+#[cfg(all(test, feature = "rand"))]
+mod tests {
+    use super::*;
+    use crate::testing::Scenario;
+    use crate::{SketchParams, TestItem};
+
+    #[test]
+    fn test_peel_decode() {
+        let (mut sketch, candidates, extra) = Scenario::new(SketchParams::new(100, 2, 5), 1000, 20).peers(0).decode_inputs();
+
+        let report = PeelingDecoder::new(4).decode(&mut sketch, &candidates).expect("No errors");
+        assert_eq!(report.decoded.len(), extra.len());
+        assert!(extra.iter().all(|item| report.decoded.contains(item)));
+        assert_eq!(report.undecoded.len(), candidates.len() - extra.len());
+        assert_eq!(report.toggles_applied, extra.len());
+        assert_eq!(report.final_threshold, 4);
+        assert!(report.rounds >= 2);
+        assert!(sketch.words.iter().all(|w| *w == 0));
+    }
+
+    #[test]
+    fn test_reconcile() {
+        let (mut sketch, candidates, extra) = Scenario::new(SketchParams::new(100, 2, 5), 1000, 20).peers(0).decode_inputs();
+
+        assert!(sketch.clone().reconcile_with_threshold(&candidates, 6).is_err());
+        let result = sketch.clone().reconcile_with_threshold(&candidates, 4).expect("No errors");
+        assert_eq!(result.decoded.len(), extra.len());
+
+        let result = sketch.reconcile(&candidates).expect("No errors");
+        assert_eq!(result.decoded.len(), extra.len());
+        assert_eq!(result.remaining.len(), candidates.len() - extra.len());
+        assert_eq!(result.rounds.iter().map(|r| r.removed).sum::<usize>(), extra.len());
+        assert!(sketch.words.iter().all(|w| *w == 0));
+    }
+
+    #[test]
+    fn test_decode_with_peeling() {
+        let (mut sketch, candidates, extra) = Scenario::new(SketchParams::new(100, 2, 5), 1000, 20).peers(0).decode_inputs();
+
+        let result = sketch.decode_with_peeling(&candidates).expect("No errors");
+        assert!(result.complete);
+        assert_eq!(result.recovered.len(), extra.len());
+        assert!(result.recovered.iter().all(|r| extra.contains(&r.item) && r.confidence > 0.0 && r.confidence <= 1.0));
+        assert!(result.recovered.iter().any(|r| r.confidence == 1.0));
+        assert_eq!(result.remaining.len(), candidates.len() - extra.len());
+        assert_eq!(result.trace.rounds.iter().map(|r| r.removed).sum::<usize>(), extra.len());
+    }
+
+    #[test]
+    fn test_reconcile_overloaded() {
+        let items: Vec<TestItem> = (0..1500).map(TestItem::from_seed).collect();
+        let mut sketch = BinaryCountSketch::new(100, 0, 5);
+        sketch.toggle_all(&items[..1000]);
+        assert!(!sketch.is_saturated());
+        assert!((sketch.load_factor() - 0.78).abs() < 0.05);
+
+        sketch.toggle_all(&items[1000..]);
+        assert!(sketch.is_saturated());
+        let before = sketch.clone();
+        let err = sketch.decode_with_peeling(&items).expect_err("Error");
+        assert_eq!(err.kind(), ErrorKind::Overloaded);
+        assert!(err.estimated_items().is_some_and(|n| n.abs_diff(1500) < 150));
+        assert_eq!(sketch, before);
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn test_reconcile_par() {
+        let (sketch, candidates, extra) = Scenario::new(SketchParams::new(100, 2, 5), 5000, 50).peers(0).decode_inputs();
+
+        let mut parallel_sketch = sketch.clone();
+        let result = parallel_sketch.reconcile_par(&candidates, 4).expect("No errors");
+        assert_eq!(result.decoded.len(), extra.len());
+        assert!(parallel_sketch.words.iter().all(|w| *w == 0));
+    }
+
+    #[test]
+    fn test_peel_bad_threshold() {
+        let (mut sketch, candidates, _) = Scenario::new(SketchParams::new(100, 2, 5), 10, 1).peers(0).decode_inputs();
+        assert!(PeelingDecoder::new(6).decode(&mut sketch, &candidates).is_err());
+    }
+
+    #[test]
+    fn test_peel_fixed_strategy() {
+        let (mut sketch, candidates, extra) = Scenario::new(SketchParams::new(100, 2, 5), 1000, 20).peers(0).decode_inputs();
+
+        assert!(PeelingDecoder::with_strategy(FixedThreshold(6)).decode(&mut sketch, &candidates).is_err());
+
+        let report = PeelingDecoder::with_strategy(FixedThreshold(4)).decode(&mut sketch, &candidates).expect("No errors");
+        assert_eq!(report.decoded.len(), extra.len());
+        assert_eq!(report.final_threshold, 4);
+    }
+
+    #[test]
+    fn test_peel_custom_strategy() {
+        // Accepts nothing: a single round at an unreachable threshold.
+        struct Never;
+        impl PeelStrategy for Never {
+            fn initial_threshold(&self, points: usize) -> Result<usize, BinaryCountSketchError> {
+                Ok(points + 1)
+            }
+            fn next_threshold(&self, _round: &RoundTrace) -> Option<usize> {
+                None
+            }
+        }
+
+        let (mut sketch, candidates, _) = Scenario::new(SketchParams::new(100, 2, 5), 100, 5).peers(0).decode_inputs();
+        let report = PeelingDecoder::with_strategy(Never).decode(&mut sketch, &candidates).expect("No errors");
+        assert_eq!(report.rounds, 1);
+        assert!(report.decoded.is_empty());
+        assert_eq!(report.undecoded.len(), candidates.len());
+    }
+
+    #[test]
+    fn test_peel_budget() {
+        let (sketch, candidates, extra) = Scenario::new(SketchParams::new(100, 2, 5), 1000, 20).peers(0).decode_inputs();
+
+        let report = PeelingDecoder::new(4)
+            .with_budget(DecodeBudget { max_rounds: Some(1), ..Default::default() })
+            .decode(&mut sketch.clone(), &candidates)
+            .expect("No errors");
+        assert_eq!(report.status, DecodeStatus::BudgetExceeded);
+        assert_eq!(report.rounds, 1);
+        assert_eq!(report.final_threshold, 5);
+
+        let report = PeelingDecoder::new(4)
+            .with_budget(DecodeBudget { max_evaluations: Some(candidates.len()), ..Default::default() })
+            .decode(&mut sketch.clone(), &candidates)
+            .expect("No errors");
+        assert_eq!(report.status, DecodeStatus::BudgetExceeded);
+        assert_eq!(report.rounds, 1);
+        assert_eq!(report.decoded.len() + report.undecoded.len(), candidates.len());
+
+        let report = PeelingDecoder::new(4)
+            .with_budget(DecodeBudget { max_duration: Some(Duration::ZERO), ..Default::default() })
+            .decode(&mut sketch.clone(), &candidates)
+            .expect("No errors");
+        assert_eq!(report.status, DecodeStatus::BudgetExceeded);
+        assert_eq!(report.rounds, 0);
+        assert_eq!(report.undecoded.len(), candidates.len());
+
+        let report = PeelingDecoder::new(4)
+            .with_budget(DecodeBudget { max_rounds: Some(100), ..Default::default() })
+            .decode(&mut sketch.clone(), &candidates)
+            .expect("No errors");
+        assert_eq!(report.status, DecodeStatus::Complete);
+        assert_eq!(report.decoded.len(), extra.len());
+    }
+
+    #[test]
+    fn test_peel_anytime() {
+        let (sketch, candidates, extra) = Scenario::new(SketchParams::new(100, 2, 5), 1000, 20).peers(0).decode_inputs();
+
+        let (report, remaining) = PeelingDecoder::new(4).decode_anytime(&mut sketch.clone(), &candidates, Instant::now()).expect("No errors");
+        assert_eq!(report.status, DecodeStatus::BudgetExceeded);
+        assert!(report.decoded.is_empty());
+        assert!((10..=30).contains(&remaining.expect("Not saturated")));
+
+        let deadline = Instant::now() + Duration::from_secs(60);
+        let (report, remaining) = PeelingDecoder::new(4).decode_anytime(&mut sketch.clone(), &candidates, deadline).expect("No errors");
+        assert_eq!(report.status, DecodeStatus::Complete);
+        assert_eq!(report.decoded.len(), extra.len());
+        assert_eq!(remaining, Some(0));
+    }
+
+    #[test]
+    fn test_peel_cancel() {
+        let (mut sketch, candidates, extra) = Scenario::new(SketchParams::new(100, 2, 5), 1000, 20).peers(0).decode_inputs();
+
+        let token = CancellationToken::new();
+        let decoder = PeelingDecoder::new(4).with_cancellation(token.clone());
+        token.cancel();
+
+        let report = decoder.decode(&mut sketch.clone(), &candidates).expect("No errors");
+        assert_eq!(report.status, DecodeStatus::Cancelled);
+        assert_eq!(report.rounds, 0);
+        assert!(report.decoded.is_empty());
+        assert_eq!(report.undecoded.len(), candidates.len());
+
+        let report = PeelingDecoder::new(4)
+            .with_cancellation(CancellationToken::new())
+            .decode(&mut sketch, &candidates)
+            .expect("No errors");
+        assert_eq!(report.status, DecodeStatus::Complete);
+        assert_eq!(report.decoded.len(), extra.len());
+    }
+
+    #[test]
+    fn test_peel_async() {
+        let (mut sketch, candidates, extra) = Scenario::new(SketchParams::new(100, 2, 5), 10_000, 20).peers(0).decode_inputs();
+        let decoder = PeelingDecoder::new(4);
+
+        // Busy-polling executor that counts how often the decode yielded.
+        let mut decode = pin!(decoder.decode_async(&mut sketch, &candidates));
+        let mut cx = Context::from_waker(Waker::noop());
+        let mut yields = 0;
+        let report = loop {
+            match decode.as_mut().poll(&mut cx) {
+                Poll::Ready(result) => break result.expect("No errors"),
+                Poll::Pending => yields += 1,
+            }
+        };
+
+        assert_eq!(report.decoded.len(), extra.len());
+        assert!(yields >= report.rounds * candidates.len().div_ceil(CANCEL_CHECK_INTERVAL) - report.rounds);
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn test_peel_parallel_matches_serial() {
+        for threads in [1, 2, 3, 8] {
+            let (sketch, candidates, extra) = Scenario::new(SketchParams::new(100, 2, 5), 5000, 50).peers(0).decode_inputs();
+            let decoder = PeelingDecoder::new(4);
+
+            let mut serial_sketch = sketch.clone();
+            let serial = decoder.decode(&mut serial_sketch, &candidates).expect("No errors");
+            let mut parallel_sketch = sketch.clone();
+            let parallel = decoder.decode_parallel(&mut parallel_sketch, &candidates, threads).expect("No errors");
+
+            let serial_set: HashSet<_> = serial.decoded.iter().collect();
+            let parallel_set: HashSet<_> = parallel.decoded.iter().collect();
+            assert_eq!(serial_set, parallel_set);
+            assert_eq!(parallel.decoded.len(), extra.len());
+            assert_eq!(parallel.toggles_applied, serial.toggles_applied);
+            assert_eq!(parallel_sketch.words, serial_sketch.words);
+        }
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn test_peel_parallel_defers_conflicts() {
+        // `a` and `b` share bit 3, so it cancels out and both score 2 of 3.
+        let a = TestItem { points: vec![1, 2, 3] };
+        let b = TestItem { points: vec![3, 4, 5] };
+        let c = TestItem { points: vec![6, 7, 8] };
+        let items = vec![a, b, c];
+
+        let mut sketch = BinaryCountSketch::new(1, 0, 3);
+        for item in &items {
+            sketch.toggle(item);
+        }
+
+        let report = PeelingDecoder::with_strategy(FixedThreshold(2)).decode_parallel(&mut sketch, &items, 2).expect("No errors");
+        assert_eq!(report.decoded, vec![items[0].clone(), items[2].clone(), items[1].clone()]);
+        assert_eq!(report.conflicts_deferred, 1);
+        assert!(sketch.words.iter().all(|w| *w == 0));
+    }
+
+    #[test]
+    fn test_peel_mixed_points() {
+        // Low-value items encoded with only three of the sketch's five points.
+        #[derive(Clone, Debug, PartialEq)]
+        struct Light(TestItem);
+        impl Item for Light {
+            fn get_code(&self, i: u64) -> u64 {
+                self.0.get_code(i)
+            }
+            fn points(&self, max: u64) -> u64 {
+                max.min(3)
+            }
+        }
+
+        let mut sketch = BinaryCountSketch::new(100, 2, 5);
+        let mut candidates = vec![];
+        let mut extra = 0;
+        for i in 0..1000 {
+            let item = Light(TestItem::new());
+            assert_eq!(sketch.points_of(&item), 3);
+            if i % 50 == 0 && sketch.check(&item) == 0 {
+                // Keep only extras whose bits do not collide, so all of them decode.
+                sketch.toggle(&item);
+                if sketch.check(&item) != 3 {
+                    sketch.toggle(&item);
+                    continue;
+                }
+                extra += 1;
+            }
+            candidates.push(item);
+        }
+        assert_eq!(sketch.words.iter().map(|w| w.count_ones()).sum::<u32>(), 3 * extra);
+
+        let report = PeelingDecoder::new(4).decode(&mut sketch, &candidates).expect("No errors");
+        assert_eq!(report.decoded.len(), extra as usize);
+        assert!(sketch.words.iter().all(|w| *w == 0));
+    }
+
+    #[test]
+    fn test_peel_duplicates() {
+        let (sketch, mut candidates, extra) = Scenario::new(SketchParams::new(100, 2, 5), 1000, 20).peers(0).decode_inputs();
+        candidates.extend(extra.iter().cloned());
+        candidates.push(candidates[0].clone());
+
+        let mut sketch = sketch.clone();
+        let report = PeelingDecoder::new(4).decode(&mut sketch, &candidates).expect("No errors");
+        assert_eq!(report.duplicates_skipped, 21);
+        assert_eq!(report.decoded.len(), extra.len());
+        assert_eq!(report.decoded.len() + report.undecoded.len(), candidates.len() - 21);
+        assert!(sketch.words.iter().all(|w| *w == 0));
+    }
+
+    #[test]
+    fn test_peel_excluding() {
+        let (sketch, candidates, extra) = Scenario::new(SketchParams::new(100, 2, 5), 1000, 20).peers(0).decode_inputs();
+
+        // The first five differences were already handled and toggled out.
+        let mut sketch = sketch.clone();
+        for item in &extra[..5] {
+            sketch.toggle(item);
+        }
+        let handled: HashSet<_> = extra[..5].iter().collect();
+
+        let report = PeelingDecoder::new(4).decode_excluding(&mut sketch, &candidates, |item| handled.contains(item)).expect("No errors");
+        assert_eq!(report.decoded.len(), 15);
+        assert!(report.decoded.iter().all(|item| !handled.contains(item)));
+        assert_eq!(report.decoded.len() + report.undecoded.len(), candidates.len() - 5);
+        assert!(sketch.words.iter().all(|w| *w == 0));
+    }
+
+    #[test]
+    fn test_peel_verified() {
+        let (sketch, candidates, extra) = Scenario::new(SketchParams::new(100, 2, 5), 1000, 20).peers(0).decode_inputs();
+        let truth: HashSet<_> = extra.iter().skip(5).collect();
+
+        let report = PeelingDecoder::new(4).decode_verified(&mut sketch.clone(), &candidates, |item| truth.contains(item)).expect("No errors");
+        assert_eq!(report.rejected, 5);
+        assert_eq!(report.verified, report.decoded.len());
+        assert!(report.decoded.iter().all(|item| truth.contains(item)));
+        assert!(extra.iter().take(5).all(|item| report.undecoded.contains(item)));
+        assert_eq!(report.decoded.len() + report.undecoded.len(), candidates.len());
+
+        let report = PeelingDecoder::new(4).decode(&mut sketch.clone(), &candidates).expect("No errors");
+        assert_eq!((report.verified, report.rejected), (0, 0));
+
+        // The hook may await, e.g. on a database lookup.
+        let decoder = PeelingDecoder::new(4);
+        let mut sketch = sketch.clone();
+        let mut decode = pin!(decoder.decode_verified_async(&mut sketch, &candidates, |item| {
+            let ok = truth.contains(item);
+            async move {
+                YieldNow(false).await;
+                ok
+            }
+        }));
+        let report = loop {
+            if let Poll::Ready(result) = decode.as_mut().poll(&mut Context::from_waker(Waker::noop())) {
+                break result.expect("No errors");
+            }
+        };
+        assert_eq!(report.rejected, 5);
+        assert_eq!(report.decoded.len(), extra.len() - 5);
+    }
+
+    #[test]
+    fn test_peel_back_off_on_rejects() {
+        let strategy = BackOffOnRejects { min_threshold: 3 };
+        assert!(strategy.initial_threshold(2).is_err());
+        let round = |threshold, removed, rejected| RoundTrace { threshold, histogram: vec![0; 6], removed, remaining: 10, rejected };
+        assert_eq!(strategy.next_threshold(&round(4, 2, 1)), Some(5));
+        assert_eq!(strategy.next_threshold(&round(5, 0, 1)), Some(4));
+        assert_eq!(strategy.next_threshold(&round(4, 0, 0)), Some(3));
+        assert_eq!(strategy.next_threshold(&round(3, 0, 0)), None);
+        let empty = RoundTrace { histogram: Vec::new(), ..round(4, 0, 1) };
+        assert_eq!(strategy.next_threshold(&empty), Some(3));
+
+        // Candidates the hook knows are not in the difference are refused, and the
+        // threshold backs off after the rounds that refused them.
+        let (sketch, candidates, extra) = Scenario::new(SketchParams::new(100, 2, 5), 1000, 20).peers(0).decode_inputs();
+        let truth: HashSet<_> = extra.iter().skip(5).collect();
+        let decoder = PeelingDecoder::with_strategy(strategy);
+        let report = decoder.decode_verified(&mut sketch.clone(), &candidates, |item| truth.contains(item)).expect("No errors");
+        assert_eq!(report.rejected, 5);
+        assert_eq!(report.decoded.len(), extra.len() - 5);
+        assert!(report.decoded.iter().all(|item| truth.contains(item)));
+    }
+
+    #[test]
+    fn test_peel_trace() {
+        let (mut sketch, candidates, extra) = Scenario::new(SketchParams::new(100, 2, 5), 1000, 20).peers(0).decode_inputs();
+
+        let (report, trace) = PeelingDecoder::new(4).decode_with_trace(&mut sketch, &candidates).expect("No errors");
+        assert_eq!(trace.rounds.len(), report.rounds);
+        assert_eq!(trace.points, 5);
+        assert_eq!(trace.rounds[0].threshold, 5);
+        assert_eq!(trace.rounds[0].histogram.len(), 6);
+        assert_eq!(trace.rounds[0].histogram.iter().sum::<usize>(), candidates.len());
+        assert_eq!(trace.rounds.iter().map(|r| r.removed).sum::<usize>(), report.decoded.len());
+        assert_eq!(trace.rounds.last().unwrap().remaining, candidates.len() - extra.len());
+
+        assert!(trace.rounds.iter().all(|r| r.rejected == 0));
+
+        let json = trace.to_json();
+        assert!(json.starts_with("{\"points\":5,\"rounds\":[{\"threshold\":5,\"histogram\":["));
+        assert!(json.ends_with(",\"rejected\":0}]}"));
+    }
+}
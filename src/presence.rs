@@ -0,0 +1,134 @@
+use crate::{code_index, BinaryCountSketch, BinaryCountSketchError, Item, SketchParams};
+
+/// Presence mode: instead of toggling, items set their bits and replicas are merged with
+/// a bitwise OR, so the sketch behaves as a Bloom filter over the union of the replicas'
+/// sets. Storage, hashing and serialization are unchanged, but a sketch built this way
+/// cannot be diffed or decoded, and items can no longer be removed.
+impl BinaryCountSketch {
+    /// Sets every bit of `v`.
+    pub fn insert_present<V: Item>(&mut self, v: &V) {
+        let l = self.words.len() * 64;
+        for i in 0..self.points_of(v) {
+            let b = code_index(v, i, l);
+            self.words[b / 64] |= 1 << (b % 64);
+        }
+    }
+
+    /// True if every bit of `v` is set. False positives are possible, false negatives
+    /// are not.
+    pub fn may_contain<V: Item>(&self, v: &V) -> bool {
+        self.check(v) == self.points_of(v) as usize
+    }
+
+    /// Merges a replica built in presence mode into this one.
+    pub fn union_or(&mut self, other: &Self) -> Result<(), BinaryCountSketchError> {
+        if self.base_length != other.base_length { return Err(BinaryCountSketchError::with_mismatch("base length", self.base_length, other.base_length)); }
+        if self.level != other.level { return Err(BinaryCountSketchError::with_mismatch("level", self.level, other.level)); }
+        if self.points != other.points { return Err(BinaryCountSketchError::with_mismatch("points", self.points, other.points)); }
+        if self.seed != other.seed { return Err(BinaryCountSketchError::with_mismatch("seed", self.seed, other.seed)); }
+        if self.words.len() != other.words.len() { return Err(BinaryCountSketchError::with_mismatch("words length", self.words.len(), other.words.len())); }
+
+        for (word, val) in self.words.iter_mut().zip(&other.words) {
+            *word |= *val;
+        }
+
+        Ok(())
+    }
+}
+
+/// Bloom filter over the same word storage and item hashing as `BinaryCountSketch`,
+/// using its presence mode: items only set bits, so inserting an item twice or on two
+/// replicas never cancels it out, and membership queries have no false negatives.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BloomFilter {
+    sketch: BinaryCountSketch,
+}
+
+impl BloomFilter {
+    pub fn new(base_length: u64, level: u64, points: u64) -> Self {
+        BloomFilter { sketch: BinaryCountSketch::new(base_length, level, points) }
+    }
+
+    pub fn from_params(params: SketchParams) -> Self {
+        BloomFilter { sketch: BinaryCountSketch::from_params(params) }
+    }
+
+    pub fn insert<V: Item>(&mut self, v: &V) {
+        self.sketch.insert_present(v);
+    }
+
+    pub fn contains<V: Item>(&self, v: &V) -> bool {
+        self.sketch.may_contain(v)
+    }
+
+    /// Adds every item of `other`, which must have the same parameters.
+    pub fn union(&mut self, other: &Self) -> Result<(), BinaryCountSketchError> {
+        self.sketch.union_or(&other.sketch)
+    }
+
+    pub fn sketch(&self) -> &BinaryCountSketch {
+        &self.sketch
+    }
+
+    pub fn into_sketch(self) -> BinaryCountSketch {
+        self.sketch
+    }
+}
+
+impl From<BinaryCountSketch> for BloomFilter {
+    /// Uses the set bits of `sketch` as the filter. For a sketch built by toggling, an
+    /// item toggled in once is reported present unless another item cancelled one of
+    /// its points.
+    fn from(sketch: BinaryCountSketch) -> Self {
+        BloomFilter { sketch }
+    }
+}
+
+#[cfg(all(test, feature = "rand"))]
+mod tests {
+    use super::*;
+    use crate::TestItem;
+
+    #[test]
+    fn test_presence_union() {
+        let items: Vec<TestItem> = (0..200).map(|_| TestItem::new()).collect();
+        let mut replica1 = BinaryCountSketch::new(100, 2, 5);
+        let mut replica2 = BinaryCountSketch::new(100, 2, 5);
+        for item in &items[..120] {
+            replica1.insert_present(item);
+        }
+        for item in &items[80..] {
+            replica2.insert_present(item);
+        }
+
+        // Items present on both replicas stay present, unlike with `diff_with`.
+        replica1.union_or(&replica2).expect("No errors");
+        assert!(items.iter().all(|item| replica1.may_contain(item)));
+
+        let absent = (0..1000).filter(|_| replica1.may_contain(&TestItem::new())).count();
+        assert!(absent < 10);
+
+        assert!(replica1.union_or(&BinaryCountSketch::new(100, 3, 5)).is_err());
+    }
+
+    #[test]
+    fn test_bloom_filter() {
+        let item = TestItem { points: vec![1, 70, 300, 600, 900] };
+        let mut filter = BloomFilter::new(100, 2, 5);
+        filter.insert(&item);
+        filter.insert(&item);
+        assert!(filter.contains(&item));
+
+        let mut sketch = BinaryCountSketch::new(100, 2, 5);
+        sketch.toggle(&item);
+        assert_eq!(BloomFilter::from(sketch).sketch(), filter.sketch());
+
+        let other = TestItem { points: vec![2, 71, 301, 601, 901] };
+        let mut replica = BloomFilter::from_params(SketchParams::new(100, 2, 5));
+        replica.insert(&other);
+        filter.union(&replica).expect("No errors");
+        assert!(filter.contains(&item) && filter.contains(&other));
+        assert!(!replica.contains(&item));
+        assert!(filter.union(&BloomFilter::new(100, 3, 5)).is_err());
+    }
+}
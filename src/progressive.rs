@@ -0,0 +1,127 @@
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::{BinaryCountSketch, BinaryCountSketchError, ErrorKind, SketchParams};
+
+/// Words a receiver holding a sketch at level `low` needs to reconstruct the same sketch
+/// at a higher level: the words of the higher level past the length of the lower one.
+///
+/// A word of the lower level is the XOR of the words of the higher level at the same
+/// index modulo its length, so the remaining words of the higher level follow from the
+/// lower level and the delta. Sending a coarse level first and then deltas costs no more
+/// words in total than sending the finest level needed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LevelDelta {
+    params: SketchParams,
+    low: u64,
+    words: Vec<u64>,
+}
+
+impl LevelDelta {
+    /// Rebuilds a delta received by other means; `params` are those of the higher level.
+    pub fn from_parts(params: SketchParams, low: u64, words: Vec<u64>) -> Result<Self, BinaryCountSketchError> {
+        if low >= params.level { return Err(BinaryCountSketchError::with_kind(ErrorKind::InvalidArgument, "Incorrect level")); }
+        let len = ((params.base_length << params.level) - (params.base_length << low)) as usize;
+        if words.len() != len { return Err(BinaryCountSketchError::with_mismatch("words length", len, words.len())); }
+
+        Ok(LevelDelta { params, low, words })
+    }
+
+    /// Parameters of the sketch the delta reconstructs.
+    pub fn params(&self) -> SketchParams {
+        self.params
+    }
+
+    pub fn low(&self) -> u64 {
+        self.low
+    }
+
+    pub fn high(&self) -> u64 {
+        self.params.level
+    }
+
+    pub fn words(&self) -> &[u64] {
+        &self.words
+    }
+}
+
+impl BinaryCountSketch {
+    /// Delta taking this sketch folded to level `low` up to level `high`, which must not
+    /// exceed the level of this sketch.
+    pub fn delta_between_levels(&self, low: u64, high: u64) -> Result<LevelDelta, BinaryCountSketchError> {
+        if low >= high || high > self.level { return Err(BinaryCountSketchError::with_kind(ErrorKind::InvalidArgument, "Incorrect level")); }
+
+        let start = (self.base_length << low) as usize;
+        let end = (self.base_length << high) as usize;
+        let mut words = vec![0; end - start];
+        // Fold this sketch to level `high`, keeping only the words past the lower level.
+        for (i, val) in self.words.iter().enumerate() {
+            let j = i % end;
+            if j >= start {
+                words[j - start] ^= *val;
+            }
+        }
+
+        Ok(LevelDelta {
+            params: SketchParams { level: high, ..self.params() },
+            low,
+            words,
+        })
+    }
+
+    /// Reconstructs the sketch at the higher level of `delta` from this sketch at its
+    /// lower level.
+    pub fn apply_delta(&self, delta: &LevelDelta) -> Result<BinaryCountSketch, BinaryCountSketchError> {
+        if self.base_length != delta.params.base_length { return Err(BinaryCountSketchError::with_mismatch("base length", self.base_length, delta.params.base_length)); }
+        if self.level != delta.low { return Err(BinaryCountSketchError::with_mismatch("level", self.level, delta.low)); }
+        if self.points != delta.params.points { return Err(BinaryCountSketchError::with_mismatch("points", self.points, delta.params.points)); }
+        if self.seed != delta.params.seed { return Err(BinaryCountSketchError::with_mismatch("seed", self.seed, delta.params.seed)); }
+
+        let start = self.words.len();
+        let mut words = self.words.clone();
+        words.extend_from_slice(&delta.words);
+        for i in start..words.len() {
+            words[i % start] ^= words[i];
+        }
+
+        BinaryCountSketch::from_parts(delta.params, words)
+    }
+}
+
+#[cfg(all(test, feature = "rand"))]
+mod tests {
+    use super::*;
+    use crate::TestItem;
+
+    #[test]
+    fn test_progressive_levels() {
+        let items: Vec<TestItem> = (0..300).map(TestItem::from_seed).collect();
+        let mut sender = BinaryCountSketch::new(10, 4, 5);
+        sender.toggle_all(&items);
+
+        // The receiver starts from level 1 and asks for the next levels one at a time.
+        let mut received = sender.level_down(1).expect("No errors");
+        for high in 2..=4 {
+            let delta = sender.delta_between_levels(received.level(), high).expect("No errors");
+            assert_eq!(delta.words().len(), (10 << high) - (10 << (high - 1)));
+            received = received.apply_delta(&delta).expect("No errors");
+            if high < 4 {
+                assert_eq!(received, sender.level_down(high).expect("No errors"));
+            }
+        }
+        assert_eq!(received, sender);
+
+        // Skipping levels works too, and deltas only apply at their own lower level.
+        let delta = sender.delta_between_levels(0, 3).expect("No errors");
+        let coarse = sender.level_down(0).expect("No errors");
+        assert_eq!(coarse.apply_delta(&delta).expect("No errors"), sender.level_down(3).expect("No errors"));
+        assert_eq!(received.apply_delta(&delta).expect_err("Error").kind(), ErrorKind::Compatibility);
+        assert!(sender.delta_between_levels(2, 5).is_err());
+        assert!(sender.delta_between_levels(2, 2).is_err());
+
+        let rebuilt = LevelDelta::from_parts(delta.params(), delta.low(), delta.words().to_vec()).expect("No errors");
+        assert_eq!(rebuilt, delta);
+        assert!(LevelDelta::from_parts(delta.params(), delta.low(), vec![]).is_err());
+    }
+}
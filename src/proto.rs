@@ -0,0 +1,119 @@
+use alloc::vec::Vec;
+
+use crate::{BinaryCountSketch, BinaryCountSketchError, ErrorKind};
+
+/// `bcsk.v1.SketchParams`.
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct SketchParams {
+    #[prost(uint64, tag = "1")]
+    pub base_length: u64,
+    #[prost(uint64, tag = "2")]
+    pub level: u64,
+    #[prost(uint64, tag = "3")]
+    pub points: u64,
+    #[prost(bytes = "vec", tag = "4")]
+    pub seed: Vec<u8>,
+}
+
+/// `bcsk.v1.Sketch` of `proto/bcsk.proto`, for exchanging sketches with services written
+/// in other languages. The types are written out as `prost-build` would generate them, so
+/// building the crate does not need `protoc`.
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Sketch {
+    #[prost(message, optional, tag = "1")]
+    pub params: Option<SketchParams>,
+    #[prost(fixed64, repeated, tag = "2")]
+    pub words: Vec<u64>,
+}
+
+fn parse_error(details: &str) -> BinaryCountSketchError {
+    BinaryCountSketchError::with_kind(ErrorKind::Parse, details)
+}
+
+impl From<crate::SketchParams> for SketchParams {
+    fn from(params: crate::SketchParams) -> Self {
+        SketchParams {
+            base_length: params.base_length,
+            level: params.level,
+            points: params.points,
+            seed: params.seed.to_vec(),
+        }
+    }
+}
+
+impl TryFrom<SketchParams> for crate::SketchParams {
+    type Error = BinaryCountSketchError;
+
+    fn try_from(params: SketchParams) -> Result<Self, BinaryCountSketchError> {
+        let seed = match params.seed.len() {
+            0 => [0; 16],
+            _ => params.seed.as_slice().try_into().map_err(|_| parse_error("Incorrect seed"))?,
+        };
+        if params.level >= 64 || params.base_length.leading_zeros() < params.level as u32 { return Err(parse_error("Incorrect level")); }
+        Ok(crate::SketchParams::new(params.base_length, params.level, params.points).with_seed(seed))
+    }
+}
+
+impl From<&BinaryCountSketch> for Sketch {
+    fn from(sketch: &BinaryCountSketch) -> Self {
+        Sketch { params: Some(sketch.params().into()), words: sketch.words.clone() }
+    }
+}
+
+impl From<BinaryCountSketch> for Sketch {
+    fn from(sketch: BinaryCountSketch) -> Self {
+        Sketch { params: Some(sketch.params().into()), words: sketch.words }
+    }
+}
+
+/// Goes through `from_parts`, so words that do not match the parameters are rejected.
+impl TryFrom<Sketch> for BinaryCountSketch {
+    type Error = BinaryCountSketchError;
+
+    fn try_from(sketch: Sketch) -> Result<Self, BinaryCountSketchError> {
+        let params = sketch.params.ok_or_else(|| parse_error("Incorrect params"))?.try_into()?;
+        BinaryCountSketch::from_parts(params, sketch.words)
+    }
+}
+
+#[cfg(all(test, feature = "rand"))]
+mod tests {
+    use prost::Message;
+
+    use super::*;
+    use crate::TestItem;
+
+    #[test]
+    fn test_proto_roundtrip() {
+        let item = TestItem::new();
+        let mut sketch = BinaryCountSketch::with_seed(10, 2, 3, [4; 16]);
+        sketch.toggle(&item);
+
+        let bytes = Sketch::from(&sketch).encode_to_vec();
+        let received = BinaryCountSketch::try_from(Sketch::decode(bytes.as_slice()).expect("No errors")).expect("No errors");
+        assert_eq!(received, sketch);
+        assert_eq!(received.check(&item), 3);
+
+        // An unset seed is the all-zero seed.
+        let mut unseeded = Sketch::from(BinaryCountSketch::new(10, 2, 3));
+        unseeded.params.as_mut().unwrap().seed.clear();
+        assert_eq!(BinaryCountSketch::try_from(unseeded).expect("No errors"), BinaryCountSketch::new(10, 2, 3));
+    }
+
+    #[test]
+    fn test_proto_rejects_bad_messages() {
+        let parse = |m: Sketch| BinaryCountSketch::try_from(m).expect_err("Error").kind();
+        let valid = Sketch::from(BinaryCountSketch::new(10, 2, 3));
+
+        assert_eq!(parse(Sketch { params: None, ..valid.clone() }), ErrorKind::Parse);
+        assert_eq!(parse(Sketch { words: vec![0; 3], ..valid.clone() }), ErrorKind::Parse);
+
+        let mut seed = valid.clone();
+        seed.params.as_mut().unwrap().seed = vec![1; 3];
+        assert_eq!(parse(seed), ErrorKind::Parse);
+
+        let mut level = valid;
+        level.params.as_mut().unwrap().level = 70;
+        assert_eq!(parse(level), ErrorKind::Parse);
+    }
+}
@@ -0,0 +1,177 @@
+use core::fmt::Display;
+use std::time::Duration;
+
+use quinn::Connection;
+use tokio::time::{self, Instant};
+
+use crate::{BinaryCountSketch, BinaryCountSketchError, ErrorKind, Peer, Reconciled, ShardedSketch};
+
+fn transport(e: impl Display) -> BinaryCountSketchError {
+    BinaryCountSketchError::with_kind(ErrorKind::Transport, &e.to_string())
+}
+
+impl Peer {
+    /// Opens a bidirectional stream on a QUIC `connection` and reconciles over it as the
+    /// client, as `reconcile_client` does over TCP.
+    pub async fn reconcile_quic_client(&self, connection: &Connection) -> Result<Reconciled, BinaryCountSketchError> {
+        let (send, recv) = connection.open_bi().await.map_err(transport)?;
+        let mut stream = tokio::io::join(recv, send);
+        let reconciled = self.reconcile_client(&mut stream).await?;
+        let (_, mut send) = stream.into_inner();
+        send.finish().map_err(transport)?;
+        Ok(reconciled)
+    }
+
+    /// Accepts the client's bidirectional stream on a QUIC `connection` and reconciles
+    /// over it as the server. Our items are the last data sent, so the connection must
+    /// stay open until the client is done, e.g. until `Connection::closed` returns.
+    pub async fn reconcile_quic_server(&self, connection: &Connection) -> Result<Reconciled, BinaryCountSketchError> {
+        let (send, recv) = connection.accept_bi().await.map_err(transport)?;
+        let mut stream = tokio::io::join(recv, send);
+        let reconciled = self.reconcile_server(&mut stream).await?;
+        let (_, mut send) = stream.into_inner();
+        send.finish().map_err(transport)?;
+        Ok(reconciled)
+    }
+}
+
+/// Shards of a peer's `ShardedSketch` received as datagrams, diffed with ours.
+#[derive(Clone, Debug)]
+pub struct ReceivedShards {
+    /// Our sketch, with every received shard diffed with the peer's.
+    pub diff: ShardedSketch,
+    /// Shards lost or late, still holding our sketch alone and not to be decoded.
+    pub missing: Vec<usize>,
+}
+
+/// Sends every shard of `sketch` in a datagram of its own, as a little-endian `u32`
+/// index followed by the shard's `BinaryCountSketch::to_bytes`. Datagrams are
+/// unreliable: a lost one only delays the decode of its shard, which can be sent again
+/// while the others decode, rather than stall the whole sketch as on a stream. Each
+/// shard must fit in `Connection::max_datagram_size`, about 1.2KB on most paths.
+pub async fn send_shards(connection: &Connection, sketch: &ShardedSketch) -> Result<(), BinaryCountSketchError> {
+    let max_len = connection.max_datagram_size().ok_or_else(|| BinaryCountSketchError::with_kind(ErrorKind::Transport, "Datagrams not supported"))?;
+    for (i, shard) in sketch.shards().iter().enumerate() {
+        let mut datagram = (i as u32).to_le_bytes().to_vec();
+        datagram.extend_from_slice(&shard.to_bytes());
+        if !(datagram.len() <= max_len) { return Err(BinaryCountSketchError::with_kind(ErrorKind::InvalidArgument, "Shard too large for a datagram")); }
+        connection.send_datagram_wait(datagram.into()).await.map_err(transport)?;
+    }
+    Ok(())
+}
+
+/// Receives the shards sent by the peer's `send_shards` for up to `timeout`, diffing
+/// each with the matching shard of `local` as it arrives. Shards received again are
+/// ignored, so the peer may resend them all; those still missing after `timeout` are
+/// reported rather than waited for.
+pub async fn receive_shards(connection: &Connection, local: &ShardedSketch, timeout: Duration) -> Result<ReceivedShards, BinaryCountSketchError> {
+    let deadline = Instant::now() + timeout;
+    let mut diff = local.clone();
+    let mut received = vec![false; local.len()];
+    let mut remaining = local.len();
+    while remaining > 0 {
+        let datagram = match time::timeout_at(deadline, connection.read_datagram()).await {
+            Ok(datagram) => datagram.map_err(transport)?,
+            Err(_) => break,
+        };
+        if !(datagram.len() >= 4) { return Err(BinaryCountSketchError::with_kind(ErrorKind::Parse, "Incorrect datagram")); }
+        let i = u32::from_le_bytes(datagram[..4].try_into().unwrap()) as usize;
+        let shard = BinaryCountSketch::from_bytes(&datagram[4..])?;
+        if received.get(i) == Some(&true) { continue; }
+        diff.diff_shard(i, &shard)?;
+        received[i] = true;
+        remaining -= 1;
+    }
+    let missing = (0..received.len()).filter(|i| !received[*i]).collect();
+    Ok(ReceivedShards { diff, missing })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, SocketAddr};
+    use std::sync::Arc;
+
+    use quinn::rustls::pki_types::{CertificateDer, PrivatePkcs8KeyDer};
+    use quinn::{ClientConfig, Endpoint, ServerConfig};
+
+    use super::*;
+    use crate::{BytesItem, PeelingDecoder, SketchParams};
+
+    fn keys(range: std::ops::Range<u32>) -> Vec<Vec<u8>> {
+        range.map(|i| format!("key-{}", i).into_bytes()).collect()
+    }
+
+    /// Connected client and server endpoints on the loopback, with a self-signed
+    /// certificate.
+    async fn connect() -> (Endpoint, Connection, Endpoint, Connection) {
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).expect("Certificate");
+        let der = CertificateDer::from(cert.cert);
+        let key = PrivatePkcs8KeyDer::from(cert.key_pair.serialize_der());
+        let server_config = ServerConfig::with_single_cert(vec![der.clone()], key.into()).expect("Server config");
+        let server = Endpoint::server(server_config, SocketAddr::from((Ipv4Addr::LOCALHOST, 0))).expect("Server endpoint");
+
+        let mut roots = quinn::rustls::RootCertStore::empty();
+        roots.add(der).expect("Root certificate");
+        let mut client = Endpoint::client(SocketAddr::from((Ipv4Addr::LOCALHOST, 0))).expect("Client endpoint");
+        client.set_default_client_config(ClientConfig::with_root_certificates(Arc::new(roots)).expect("Client config"));
+
+        let addr = server.local_addr().expect("Local address");
+        let accepted = async { server.accept().await.expect("Incoming").await.expect("Accepted") };
+        let connected = async { client.connect(addr, "localhost").expect("Connecting").await.expect("Connected") };
+        let (accepted, connected) = tokio::join!(accepted, connected);
+        (client, connected, server, accepted)
+    }
+
+    #[tokio::test]
+    async fn test_reconcile_over_quic() {
+        let (_client, connected, _server, accepted) = connect().await;
+        let params = SketchParams::new(100, 2, 5);
+        let server = Peer::new(params, keys(0..1000));
+        let client = Peer::new(params, keys(5..1003));
+
+        let (served, reconciled) = tokio::join!(server.reconcile_quic_server(&accepted), client.reconcile_quic_client(&connected));
+        let (served, mut reconciled) = (served.expect("No errors"), reconciled.expect("No errors"));
+        reconciled.received.sort();
+        assert_eq!(reconciled.received, keys(0..5));
+        let mut received = served.received;
+        received.sort();
+        assert_eq!(received, keys(1000..1003));
+        assert!(served.complete && reconciled.complete);
+    }
+
+    #[tokio::test]
+    async fn test_shard_datagrams() {
+        let (_client, connected, _server, accepted) = connect().await;
+        let params = SketchParams::new(16, 0, 5);
+        let sketch = |range: std::ops::Range<u32>| {
+            let mut sketch = ShardedSketch::new(8, params).expect("No errors");
+            for key in keys(range) {
+                sketch.toggle(&BytesItem::new(&key));
+            }
+            sketch
+        };
+        let (local, remote) = (sketch(0..1000), sketch(10..1000));
+
+        send_shards(&connected, &remote).await.expect("No errors");
+        let mut received = receive_shards(&accepted, &local, Duration::from_secs(5)).await.expect("No errors");
+        assert!(received.missing.is_empty());
+        let candidates = keys(0..1000);
+        let candidates: Vec<BytesItem> = candidates.iter().map(|key| BytesItem::new(key)).collect();
+        let reports = received.diff.decode(&PeelingDecoder::new(4), &candidates).expect("No errors");
+        let mut decoded: Vec<&[u8]> = reports.iter().flat_map(|report| report.decoded.iter().map(|item| item.bytes())).collect();
+        decoded.sort();
+        let expected = keys(0..10);
+        assert_eq!(decoded, expected.iter().map(Vec::as_slice).collect::<Vec<_>>());
+
+        // Shards that do not arrive in time are reported missing, the others diffed.
+        let mut partial = ShardedSketch::new(8, params).expect("No errors");
+        for key in keys(0..1000).iter().filter(|key| remote.shard_of(&BytesItem::new(key)) == 3) {
+            partial.toggle(&BytesItem::new(key));
+        }
+        let datagram = [&3u32.to_le_bytes()[..], &partial.shard(3).to_bytes()].concat();
+        connected.send_datagram(datagram.into()).expect("No errors");
+        let received = receive_shards(&accepted, &local, Duration::from_millis(200)).await.expect("No errors");
+        assert_eq!(received.missing, vec![0, 1, 2, 4, 5, 6, 7]);
+        assert!(received.diff.shard(3).words.iter().all(|word| *word == 0));
+    }
+}
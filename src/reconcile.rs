@@ -0,0 +1,907 @@
+use core::cmp::Ordering;
+use core::hash::{Hash, Hasher};
+use core::mem;
+use core::ops::BitXor;
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+use crate::{BinaryCountSketch, BinaryCountSketchError, ErrorKind, FixedBytes, Iblt, SketchParams, StableHasher};
+
+/// Cells per estimated difference in the `Iblt` sent to the initiator, above the 1.22
+/// peeling threshold of three hashes to absorb estimation error.
+const CELLS_PER_DIFFERENCE: f64 = 2.0;
+
+/// Cells added to every table, so small differences still peel reliably.
+const EXTRA_CELLS: usize = 16;
+
+const HASHES: u64 = 3;
+
+/// Number of times the initiator asks for a larger table before giving up.
+const MAX_RESIZES: u32 = 4;
+
+/// Factor by which every resize grows the table.
+const GROWTH: usize = 2;
+
+/// Most cells of a table the responder builds, whatever the initiator asks for.
+const MAX_CELLS: usize = 1 << 22;
+
+/// Estimation sketch of 4096 bits, used unless `ReconcilerBuilder::with_params` is set.
+const PARAMS: SketchParams = SketchParams { base_length: 64, level: 0, points: 3, seed: [0; 16] };
+
+/// Key of the `StableHasher` computing the fingerprints of decoded keys.
+const FINGERPRINT_KEY: u64 = 0x0041_434b;
+
+/// Cells per key above which a table of 3 to 7 hashes peels with high probability.
+const PEELING_THRESHOLDS: [f64; 5] = [1.222, 1.295, 1.425, 1.570, 1.721];
+
+/// Message exchanged by two `Reconciler`s.
+#[derive(Clone, Debug)]
+pub enum Message<K> {
+    /// Opens the exchange with the initiator's estimation sketch and set size.
+    Estimate { sketch: BinaryCountSketch, items: usize },
+    /// The responder's keys, in a table sized from the estimated difference.
+    Table(Iblt<K, bool>),
+    /// Asks the responder for a table with this many cells, after the last one could not
+    /// be listed.
+    Resize { cells: usize },
+    /// Keys the initiator holds and the responder does not, and fingerprints of the keys
+    /// it decoded as held by the responder alone, for the responder to confirm. With a
+    /// prover, `proofs` holds a proof of every key, in order, and is empty otherwise.
+    Entries { keys: Vec<K>, decoded: Vec<u64>, proofs: Vec<Vec<u8>> },
+    /// Closes the exchange with the fingerprints of the initiator's decodes the
+    /// responder could not confirm: keys it was sent but already holds, and decoded
+    /// keys it does not hold. Both sides drop them. With a prover, `proofs` holds a
+    /// proof of every decoded key the responder confirms, by fingerprint.
+    Ack { spurious: Vec<u64>, proofs: Vec<(u64, Vec<u8>)> },
+}
+
+/// Tags of the encoded messages, in the order of the variants.
+const TAG_ESTIMATE: u8 = 0;
+const TAG_TABLE: u8 = 1;
+const TAG_RESIZE: u8 = 2;
+const TAG_ENTRIES: u8 = 3;
+const TAG_ACK: u8 = 4;
+
+fn parse_error(details: &str) -> BinaryCountSketchError {
+    BinaryCountSketchError::with_kind(ErrorKind::Parse, details)
+}
+
+/// Fields of an encoded message, read in order.
+struct Fields<'a>(&'a [u8]);
+
+impl<'a> Fields<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], BinaryCountSketchError> {
+        if !(len <= self.0.len()) { return Err(parse_error("Incorrect message length")); }
+        let (head, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(head)
+    }
+
+    fn word(&mut self) -> Result<u64, BinaryCountSketchError> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    /// Reads a little-endian `u64` count or size as a `usize`.
+    fn len(&mut self) -> Result<usize, BinaryCountSketchError> {
+        usize::try_from(self.word()?).map_err(|_| parse_error("Incorrect message"))
+    }
+
+    /// Reads a count of entries of at least `min_len` bytes each, which must fit in the
+    /// rest of the message, so nothing is allocated beyond the input.
+    fn count(&mut self, min_len: usize) -> Result<usize, BinaryCountSketchError> {
+        let count = self.len()?;
+        if !(count.checked_mul(min_len).is_some_and(|len| len <= self.0.len())) { return Err(parse_error("Incorrect message length")); }
+        Ok(count)
+    }
+
+    /// Reads a count and that many values of `len` bytes each.
+    fn values<T>(&mut self, len: usize, read: impl Fn(&[u8]) -> Option<T>) -> Result<Vec<T>, BinaryCountSketchError> {
+        let count = self.count(len)?;
+        let bytes = self.take(count * len)?;
+        bytes.chunks(len).map(read).collect::<Option<Vec<_>>>().ok_or_else(|| parse_error("Incorrect key"))
+    }
+
+    /// Reads a size and that many bytes.
+    fn bytes(&mut self) -> Result<Vec<u8>, BinaryCountSketchError> {
+        let len = self.len()?;
+        Ok(self.take(len)?.to_vec())
+    }
+
+    fn finish(self) -> Result<(), BinaryCountSketchError> {
+        if !self.0.is_empty() { return Err(parse_error("Incorrect message length")); }
+        Ok(())
+    }
+}
+
+fn write_word(out: &mut Vec<u8>, word: usize) {
+    out.extend_from_slice(&(word as u64).to_le_bytes());
+}
+
+fn write_proof(out: &mut Vec<u8>, proof: &[u8]) {
+    write_word(out, proof.len());
+    out.extend_from_slice(proof);
+}
+
+impl<K> Message<K>
+where
+    K: Copy + Default + Eq + Hash + BitXor<Output = K> + FixedBytes,
+{
+    /// Encodes the message as a tag byte and its fields, with sizes, counts and
+    /// fingerprints as little-endian `u64` and every list prefixed by its count:
+    /// - `Estimate`: the set size and the `BinaryCountSketch::to_bytes` of the sketch.
+    /// - `Table`: the `Iblt::to_bytes` of the table.
+    /// - `Resize`: the cells.
+    /// - `Entries`: the keys, the fingerprints, and the proofs, each prefixed by its size.
+    /// - `Ack`: the fingerprints, and the proofs, each after its fingerprint and size.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        match self {
+            Message::Estimate { sketch, items } => {
+                out.push(TAG_ESTIMATE);
+                write_word(&mut out, *items);
+                out.extend_from_slice(&sketch.to_bytes());
+            }
+            Message::Table(table) => {
+                out.push(TAG_TABLE);
+                out.extend_from_slice(&table.to_bytes());
+            }
+            Message::Resize { cells } => {
+                out.push(TAG_RESIZE);
+                write_word(&mut out, *cells);
+            }
+            Message::Entries { keys, decoded, proofs } => {
+                out.push(TAG_ENTRIES);
+                write_word(&mut out, keys.len());
+                for key in keys {
+                    key.write_bytes(&mut out);
+                }
+                write_word(&mut out, decoded.len());
+                for fingerprint in decoded {
+                    out.extend_from_slice(&fingerprint.to_le_bytes());
+                }
+                write_word(&mut out, proofs.len());
+                for proof in proofs {
+                    write_proof(&mut out, proof);
+                }
+            }
+            Message::Ack { spurious, proofs } => {
+                out.push(TAG_ACK);
+                write_word(&mut out, spurious.len());
+                for fingerprint in spurious {
+                    out.extend_from_slice(&fingerprint.to_le_bytes());
+                }
+                write_word(&mut out, proofs.len());
+                for (fingerprint, proof) in proofs {
+                    out.extend_from_slice(&fingerprint.to_le_bytes());
+                    write_proof(&mut out, proof);
+                }
+            }
+        }
+        out
+    }
+
+    /// Reads a message encoded by `to_bytes`. Sketches and tables are checked as by
+    /// their own `from_bytes`, and every count against the rest of the input, so the
+    /// size of a message is bounded by the input; the cells of a `Resize` are only
+    /// checked against the limits of the `Reconciler` handling it.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, BinaryCountSketchError> {
+        let (tag, rest) = bytes.split_first().ok_or_else(|| parse_error("Incorrect message"))?;
+        let mut fields = Fields(rest);
+        let fingerprint = |b: &[u8]| Some(u64::from_le_bytes(b.try_into().ok()?));
+        let message = match *tag {
+            TAG_ESTIMATE => {
+                let items = fields.len()?;
+                let sketch = BinaryCountSketch::from_bytes(fields.take(fields.0.len())?)?;
+                Message::Estimate { sketch, items }
+            }
+            TAG_TABLE => Message::Table(Iblt::from_bytes(fields.take(fields.0.len())?)?),
+            TAG_RESIZE => Message::Resize { cells: fields.len()? },
+            TAG_ENTRIES => {
+                let keys = fields.values(K::LEN, K::read_bytes)?;
+                let decoded = fields.values(8, fingerprint)?;
+                let count = fields.count(8)?;
+                let proofs = (0..count).map(|_| fields.bytes()).collect::<Result<_, _>>()?;
+                Message::Entries { keys, decoded, proofs }
+            }
+            TAG_ACK => {
+                let spurious = fields.values(8, fingerprint)?;
+                let count = fields.count(16)?;
+                let proofs = (0..count).map(|_| Ok((fields.word()?, fields.bytes()?))).collect::<Result<_, BinaryCountSketchError>>()?;
+                Message::Ack { spurious, proofs }
+            }
+            _ => return Err(parse_error("Incorrect message tag")),
+        };
+        fields.finish()?;
+        Ok(message)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum State {
+    Idle,
+    AwaitingTable,
+    AwaitingEntries,
+    AwaitingAck,
+    Done,
+}
+
+/// Two-round set reconciliation: the initiator sends a small `BinaryCountSketch` from
+/// which the responder estimates the size of the symmetric difference, and the
+/// responder answers with an `Iblt` of its keys sized accordingly. The initiator lists
+/// the difference exactly from that table and sends the responder the keys it lacks.
+/// If the estimate was too low the initiator asks for a larger table instead. The
+/// responder acknowledges the decodes, so both sides drop those that were spurious, as
+/// when table checksums collide, before closing the exchange.
+///
+/// Against a peer that crafts its messages, e.g. a table listing keys it does not
+/// hold, acknowledgements are not enough: set `ReconcilerBuilder::with_prover` and
+/// `with_proof_check` so keys are only treated as missing with a proof the peer cannot
+/// forge, such as a signature of their digest or a Merkle proof against a known root.
+///
+/// Both sides must use the same estimation `SketchParams`. The transport is left to
+/// the application: pass every message returned by one side to the other's `handle`
+/// until it returns `None`, e.g. encoded with `Message::to_bytes` when the keys are
+/// `FixedBytes`. Tables are limited by `ReconcilerBuilder::with_max_cells` whatever
+/// the peer asks for.
+pub struct Reconciler<K> {
+    keys: Vec<K>,
+    sketch: BinaryCountSketch,
+    state: State,
+    resizes: u32,
+    max_cells: usize,
+    missing: Vec<K>,
+    options: ReconcilerBuilder<K>,
+    bytes_sent: usize,
+    deadline: Option<Instant>,
+    rejected: usize,
+    spurious: usize,
+}
+
+/// Receives the events of a `Reconciler`'s exchange, e.g. to export them as metrics.
+/// Every method does nothing unless overridden.
+pub trait ReconcileMetrics {
+    /// A message of about `bytes` bytes is sent to the peer.
+    fn sent(&mut self, _bytes: usize) {}
+
+    /// The responder estimated a difference of `difference` keys.
+    fn estimated(&mut self, _difference: usize) {}
+
+    /// The initiator could not list a table and asks for one of `cells` cells.
+    fn resized(&mut self, _cells: usize) {}
+
+    /// The verification hook or the proof check rejected `keys` keys received from the
+    /// peer.
+    fn rejected(&mut self, _keys: usize) {}
+
+    /// The acknowledgement found `keys` spurious decodes.
+    fn spurious(&mut self, _keys: usize) {}
+
+    /// The exchange finished with `missing` keys received from the peer.
+    fn finished(&mut self, _missing: usize) {}
+}
+
+/// Hook deciding whether a key received from the peer is kept.
+type Verifier<K> = Box<dyn Fn(&K) -> bool + Send>;
+
+/// Hook proving to the peer that a key is held.
+type Prover<K> = Box<dyn Fn(&K) -> Vec<u8> + Send>;
+
+/// Hook checking a proof sent by the peer for a key.
+type ProofCheck<K> = Box<dyn Fn(&K, &[u8]) -> bool + Send>;
+
+/// Tunables of a `Reconciler`, checked for consistency by `build`. Both sides must use
+/// the same estimation parameters and hashes.
+pub struct ReconcilerBuilder<K> {
+    params: SketchParams,
+    cells_per_difference: f64,
+    extra_cells: usize,
+    hashes: u64,
+    max_resizes: u32,
+    growth: usize,
+    max_cells: usize,
+    byte_budget: Option<usize>,
+    timeout: Option<Duration>,
+    verifier: Option<Verifier<K>>,
+    prover: Option<Prover<K>>,
+    proof_check: Option<ProofCheck<K>>,
+    metrics: Option<Box<dyn ReconcileMetrics + Send>>,
+}
+
+impl<K> ReconcilerBuilder<K>
+where
+    K: Copy + Default + Eq + Hash + BitXor<Output = K>,
+{
+    pub fn with_params(self, params: SketchParams) -> Self {
+        ReconcilerBuilder { params, ..self }
+    }
+
+    /// Sizes tables at `cells_per_difference` cells per estimated difference, plus
+    /// `extra_cells`.
+    pub fn with_cells(self, cells_per_difference: f64, extra_cells: usize) -> Self {
+        ReconcilerBuilder { cells_per_difference, extra_cells, ..self }
+    }
+
+    pub fn with_hashes(self, hashes: u64) -> Self {
+        ReconcilerBuilder { hashes, ..self }
+    }
+
+    /// Escalation when a table cannot be listed: ask for a table `growth` times larger,
+    /// at most `max_resizes` times, before failing. The responder grants as many resizes
+    /// as its own settings allow, so both sides should use the same.
+    pub fn with_resizes(self, max_resizes: u32, growth: usize) -> Self {
+        ReconcilerBuilder { max_resizes, growth, ..self }
+    }
+
+    /// Fails with `ErrorKind::Budget` rather than build or ask for a table of more than
+    /// `cells` cells. The responder also refuses tables larger than its first one grown
+    /// by every resize, with the first one sized for a difference of both sets whole.
+    pub fn with_max_cells(self, cells: usize) -> Self {
+        ReconcilerBuilder { max_cells: cells, ..self }
+    }
+
+    /// Fails with `ErrorKind::Budget` rather than send more than `bytes` bytes of
+    /// messages, as counted by `Reconciler::bytes_sent`.
+    pub fn with_byte_budget(self, bytes: usize) -> Self {
+        ReconcilerBuilder { byte_budget: Some(bytes), ..self }
+    }
+
+    /// Fails with `ErrorKind::TimedOut` if a message is handled more than `timeout`
+    /// after the exchange started on this side.
+    pub fn with_timeout(self, timeout: Duration) -> Self {
+        ReconcilerBuilder { timeout: Some(timeout), ..self }
+    }
+
+    /// Drops keys received from the peer for which `verify` returns false, e.g. keys
+    /// that are not well formed and so can only come from a spurious decode.
+    pub fn with_verifier(self, verify: impl Fn(&K) -> bool + Send + 'static) -> Self {
+        ReconcilerBuilder { verifier: Some(Box::new(verify)), ..self }
+    }
+
+    /// Sends `prove(key)` with every key this side vouches for: the keys it sends, and
+    /// the decoded keys it confirms as the responder.
+    pub fn with_prover(self, prove: impl Fn(&K) -> Vec<u8> + Send + 'static) -> Self {
+        ReconcilerBuilder { prover: Some(Box::new(prove)), ..self }
+    }
+
+    /// Drops keys received from the peer, or decoded from its table, unless the peer
+    /// sent a proof for which `check` returns true. The initiator checks its decodes once
+    /// the responder acknowledges them, so `Reconciler::missing` only holds them then.
+    pub fn with_proof_check(self, check: impl Fn(&K, &[u8]) -> bool + Send + 'static) -> Self {
+        ReconcilerBuilder { proof_check: Some(Box::new(check)), ..self }
+    }
+
+    pub fn with_metrics(self, metrics: impl ReconcileMetrics + Send + 'static) -> Self {
+        ReconcilerBuilder { metrics: Some(Box::new(metrics)), ..self }
+    }
+
+    /// Creates a reconciler for the set of `keys`, or an `InvalidArgument` error if the
+    /// tunables do not fit together: the estimation parameters must describe a sketch,
+    /// tables must have 3 to 7 hashes and more cells per difference than their peeling
+    /// threshold, resizes must grow the table, the smallest table must fit the cell
+    /// limit, and the budget must fit the estimate and the smallest table.
+    pub fn build<I: IntoIterator<Item = K>>(self, keys: I) -> Result<Reconciler<K>, BinaryCountSketchError> {
+        let words = self.params.validate().map_err(|_| BinaryCountSketchError::with_kind(ErrorKind::InvalidArgument, "Incorrect params"))?;
+        if !((3..=7).contains(&self.hashes)) { return Err(BinaryCountSketchError::with_kind(ErrorKind::InvalidArgument, "Incorrect hashes")); }
+        if self.cells_per_difference.partial_cmp(&PEELING_THRESHOLDS[self.hashes as usize - 3]) != Some(Ordering::Greater) { return Err(BinaryCountSketchError::with_kind(ErrorKind::InvalidArgument, "Incorrect cells per difference")); }
+        if !(self.growth >= 2 || self.max_resizes == 0) { return Err(BinaryCountSketchError::with_kind(ErrorKind::InvalidArgument, "Incorrect growth")); }
+        if !(self.max_cells >= self.extra_cells.max(self.hashes as usize)) { return Err(BinaryCountSketchError::with_kind(ErrorKind::InvalidArgument, "Incorrect max cells")); }
+        if !(self.byte_budget.is_none_or(|budget| budget >= words * 8 + 8 && budget >= table_bytes::<K>(self.extra_cells))) { return Err(BinaryCountSketchError::with_kind(ErrorKind::InvalidArgument, "Incorrect byte budget")); }
+        if !(self.timeout.is_none_or(|timeout| !timeout.is_zero())) { return Err(BinaryCountSketchError::with_kind(ErrorKind::InvalidArgument, "Incorrect timeout")); }
+
+        Ok(Reconciler::with_options(keys, self))
+    }
+}
+
+/// Approximate size of a table of `cells` cells: a key, a count, a checksum and a flag
+/// per cell.
+fn table_bytes<K>(cells: usize) -> usize {
+    cells.saturating_mul(mem::size_of::<K>() + 17)
+}
+
+fn fingerprint<K: Hash>(key: &K) -> u64 {
+    let mut hasher = StableHasher::with_key(FINGERPRINT_KEY);
+    key.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Approximate size of `message` on the wire.
+fn message_bytes<K: Copy + Default + Eq + Hash + BitXor<Output = K>>(message: &Message<K>) -> usize {
+    match message {
+        Message::Estimate { sketch, .. } => sketch.words.len() * 8 + 8,
+        Message::Table(table) => table_bytes::<K>(table.cells()),
+        Message::Resize { .. } => 8,
+        Message::Entries { keys, decoded, proofs } => keys.len() * mem::size_of::<K>() + decoded.len() * 8 + proofs.iter().map(|proof| proof.len() + 8).sum::<usize>() + 24,
+        Message::Ack { spurious, proofs } => spurious.len() * 8 + proofs.iter().map(|(_, proof)| proof.len() + 16).sum::<usize>() + 16,
+    }
+}
+
+impl<K> Reconciler<K>
+where
+    K: Copy + Default + Eq + Hash + BitXor<Output = K>,
+{
+    /// Creates a reconciler for the set of `keys`, estimating the difference with a
+    /// sketch built with `params`.
+    pub fn new<I: IntoIterator<Item = K>>(keys: I, params: SketchParams) -> Self {
+        Reconciler::with_options(keys, Reconciler::builder().with_params(params))
+    }
+
+    /// Builder for a reconciler with other tunables than `new`'s defaults.
+    pub fn builder() -> ReconcilerBuilder<K> {
+        ReconcilerBuilder {
+            params: PARAMS,
+            cells_per_difference: CELLS_PER_DIFFERENCE,
+            extra_cells: EXTRA_CELLS,
+            hashes: HASHES,
+            max_resizes: MAX_RESIZES,
+            growth: GROWTH,
+            max_cells: MAX_CELLS,
+            byte_budget: None,
+            timeout: None,
+            verifier: None,
+            prover: None,
+            proof_check: None,
+            metrics: None,
+        }
+    }
+
+    fn with_options<I: IntoIterator<Item = K>>(keys: I, options: ReconcilerBuilder<K>) -> Self {
+        let keys: Vec<K> = keys.into_iter().collect();
+        let mut sketch = BinaryCountSketch::from_params(options.params);
+        for key in &keys {
+            let item = sketch.keyed_item(*key);
+            sketch.toggle(&item);
+        }
+        let max_cells = options.max_cells;
+        Reconciler { keys, sketch, state: State::Idle, resizes: 0, max_cells, missing: Vec::new(), options, bytes_sent: 0, deadline: None, rejected: 0, spurious: 0 }
+    }
+
+    /// Starts the exchange on the initiator's side.
+    pub fn initiate(&mut self) -> Result<Message<K>, BinaryCountSketchError> {
+        if self.state != State::Idle { return Err(BinaryCountSketchError::with_kind(ErrorKind::InvalidArgument, "Incorrect state")); }
+
+        self.start();
+        self.state = State::AwaitingTable;
+        self.send(Message::Estimate { sketch: self.sketch.clone(), items: self.keys.len() })
+    }
+
+    /// Handles a message from the peer, returning the reply to send back, or `None`
+    /// once this side is done.
+    pub fn handle(&mut self, message: Message<K>) -> Result<Option<Message<K>>, BinaryCountSketchError> {
+        self.start();
+        if self.deadline.is_some_and(|deadline| Instant::now() > deadline) { return Err(BinaryCountSketchError::with_kind(ErrorKind::TimedOut, "Reconciliation timed out")); }
+
+        match (self.state, message) {
+            (State::Idle, Message::Estimate { sketch, items }) => {
+                let mut diff = self.sketch.clone();
+                diff.diff_with(&sketch)?;
+                // The difference is at most the size of both sets, which is all a saturated
+                // sketch tells. `items` comes from the peer, so tables are limited to those
+                // of such a difference grown by every resize, and to the cell limit.
+                let bound = items.saturating_add(self.keys.len());
+                let difference = diff.estimate_difference().map_or(bound, |d| d.min(bound));
+                let growth = self.options.growth.saturating_pow(self.options.max_resizes);
+                self.max_cells = self.cells_for(bound).saturating_mul(growth).min(self.options.max_cells);
+                self.metrics(|m| m.estimated(difference));
+                self.state = State::AwaitingEntries;
+                let table = self.table(self.cells_for(difference))?;
+                self.send(Message::Table(table)).map(Some)
+            }
+            (State::AwaitingEntries, Message::Resize { cells }) => {
+                if !(self.resizes < self.options.max_resizes) { return Err(BinaryCountSketchError::with_kind(ErrorKind::Budget, "Too many resizes")); }
+                let table = self.table(cells)?;
+                self.resizes += 1;
+                self.send(Message::Table(table)).map(Some)
+            }
+            (State::AwaitingTable, Message::Table(remote)) => {
+                if !(remote.hashes() == self.options.hashes) { return Err(BinaryCountSketchError::with_mismatch("hashes", self.options.hashes, remote.hashes())); }
+                let mut local = self.table(remote.cells())?;
+                local.subtract(&remote)?;
+                match local.list_entries() {
+                    Ok(entries) => {
+                        self.receive(entries.deleted.into_iter().map(|(key, _)| key).collect());
+                        self.state = State::AwaitingAck;
+                        let keys: Vec<K> = entries.inserted.into_iter().map(|(key, _)| key).collect();
+                        let decoded = self.missing.iter().map(fingerprint).collect();
+                        let proofs = self.options.prover.as_ref().map_or_else(Vec::new, |prove| keys.iter().map(prove).collect());
+                        self.send(Message::Entries { keys, decoded, proofs }).map(Some)
+                    }
+                    Err(e) if e.kind() == ErrorKind::Budget && self.resizes < self.options.max_resizes => {
+                        self.resizes += 1;
+                        let cells = remote.cells().saturating_mul(self.options.growth);
+                        if !(cells <= self.max_cells) { return Err(BinaryCountSketchError::with_kind(ErrorKind::Budget, "Table too large")); }
+                        self.metrics(|m| m.resized(cells));
+                        self.send(Message::Resize { cells }).map(Some)
+                    }
+                    Err(e) => Err(e),
+                }
+            }
+            (State::AwaitingEntries, Message::Entries { mut keys, decoded, proofs }) => {
+                let held: HashSet<K> = self.keys.iter().copied().collect();
+                let fingerprints: HashMap<u64, K> = self.keys.iter().map(|key| (fingerprint(key), *key)).collect();
+                let mut spurious: Vec<u64> = keys.iter().filter(|key| held.contains(key)).map(fingerprint).collect();
+                let (confirmed, unconfirmed): (Vec<u64>, Vec<u64>) = decoded.into_iter().partition(|f| fingerprints.contains_key(f));
+                spurious.extend(unconfirmed);
+                let proofs = keys.iter().map(fingerprint).zip(proofs).collect();
+                keys.retain(|key| !held.contains(key));
+                self.receive(keys);
+                self.confirm(&proofs);
+                self.finish(spurious.len());
+                let proofs = self.options.prover.as_ref().map_or_else(Vec::new, |prove| confirmed.into_iter().map(|f| (f, prove(&fingerprints[&f]))).collect());
+                self.send(Message::Ack { spurious, proofs }).map(Some)
+            }
+            (State::AwaitingAck, Message::Ack { spurious, proofs }) => {
+                let spurious: HashSet<u64> = spurious.into_iter().collect();
+                self.missing.retain(|key| !spurious.contains(&fingerprint(key)));
+                self.confirm(&proofs.into_iter().collect());
+                self.finish(spurious.len());
+                Ok(None)
+            }
+            _ => Err(BinaryCountSketchError::with_kind(ErrorKind::InvalidArgument, "Incorrect message")),
+        }
+    }
+
+    /// Starts the timeout on the first message of the exchange.
+    fn start(&mut self) {
+        if self.deadline.is_none() {
+            self.deadline = self.options.timeout.map(|timeout| Instant::now() + timeout);
+        }
+    }
+
+    /// Counts `message` against the byte budget before it is sent.
+    fn send(&mut self, message: Message<K>) -> Result<Message<K>, BinaryCountSketchError> {
+        let bytes = message_bytes(&message);
+        if !(self.options.byte_budget.is_none_or(|budget| self.bytes_sent + bytes <= budget)) { return Err(BinaryCountSketchError::with_kind(ErrorKind::Budget, "Byte budget exceeded")); }
+
+        self.bytes_sent += bytes;
+        self.metrics(|m| m.sent(bytes));
+        Ok(message)
+    }
+
+    /// Keeps the keys received from the peer that pass the verification hook.
+    fn receive(&mut self, mut keys: Vec<K>) {
+        if let Some(verify) = &self.options.verifier {
+            let received = keys.len();
+            keys.retain(|key| verify(key));
+            self.rejected = received - keys.len();
+            let rejected = self.rejected;
+            self.metrics(|m| m.rejected(rejected));
+        }
+        self.missing = keys;
+    }
+
+    /// Keeps the missing keys with a proof, found by fingerprint, that passes the proof
+    /// check.
+    fn confirm(&mut self, proofs: &HashMap<u64, Vec<u8>>) {
+        if let Some(check) = &self.options.proof_check {
+            let received = self.missing.len();
+            self.missing.retain(|key| proofs.get(&fingerprint(key)).is_some_and(|proof| check(key, proof)));
+            let rejected = received - self.missing.len();
+            self.rejected += rejected;
+            self.metrics(|m| m.rejected(rejected));
+        }
+    }
+
+    fn finish(&mut self, spurious: usize) {
+        self.spurious = spurious;
+        self.state = State::Done;
+        let missing = self.missing.len();
+        self.metrics(|m| m.spurious(spurious));
+        self.metrics(|m| m.finished(missing));
+    }
+
+    fn metrics(&mut self, event: impl FnOnce(&mut (dyn ReconcileMetrics + Send))) {
+        if let Some(metrics) = &mut self.options.metrics {
+            event(metrics.as_mut());
+        }
+    }
+
+    /// Cells of a table sized for a difference of `difference` keys, rounded up to a
+    /// multiple of the hashes as by `Iblt::new`.
+    fn cells_for(&self, difference: usize) -> usize {
+        let hashes = self.options.hashes as usize;
+        let cells = ((difference as f64 * self.options.cells_per_difference) as usize).saturating_add(self.options.extra_cells);
+        cells.div_ceil(hashes).saturating_mul(hashes)
+    }
+
+    /// Table of our keys, or a `Budget` error if it would have more cells than allowed.
+    fn table(&self, cells: usize) -> Result<Iblt<K, bool>, BinaryCountSketchError> {
+        if !(cells <= self.max_cells) { return Err(BinaryCountSketchError::with_kind(ErrorKind::Budget, "Table too large")); }
+
+        let mut table = Iblt::new(cells, self.options.hashes);
+        for key in &self.keys {
+            table.insert(*key, false);
+        }
+        Ok(table)
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.state == State::Done
+    }
+
+    /// Keys the peer holds and this side does not, available once `is_done`.
+    pub fn missing(&self) -> &[K] {
+        &self.missing
+    }
+
+    /// Approximate bytes of the messages sent so far.
+    pub fn bytes_sent(&self) -> usize {
+        self.bytes_sent
+    }
+
+    /// Keys received from the peer and dropped by the verification hook or the proof
+    /// check.
+    pub fn rejected(&self) -> usize {
+        self.rejected
+    }
+
+    /// Decodes the acknowledgement could not confirm, dropped by both sides. Zero once
+    /// `is_done` means the exchange converged.
+    pub fn spurious(&self) -> usize {
+        self.spurious
+    }
+}
+
+#[cfg(all(test, feature = "rand"))]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    /// Runs the exchange, passing every message through its encoding.
+    fn run(initiator: &mut Reconciler<u64>, responder: &mut Reconciler<u64>) -> usize {
+        let wire = |message: Message<u64>| Message::from_bytes(&message.to_bytes()).expect("No errors");
+        let mut message = wire(initiator.initiate().expect("No errors"));
+        let mut messages = 1;
+        let mut sides = [responder, initiator];
+        while let Some(reply) = sides[0].handle(message).expect("No errors").map(wire) {
+            message = reply;
+            messages += 1;
+            sides.swap(0, 1);
+        }
+        messages
+    }
+
+    #[test]
+    fn test_reconciler() {
+        let params = SketchParams::new(4, 0, 3);
+        let mut initiator = Reconciler::new((0..1000u64).chain(5000..5030), params);
+        let mut responder = Reconciler::new((0..1000u64).chain(7000..7050), params);
+
+        assert_eq!(run(&mut initiator, &mut responder), 4);
+        assert!(initiator.is_done() && responder.is_done());
+        assert_eq!((initiator.spurious(), responder.spurious()), (0, 0));
+
+        let mut missing = initiator.missing().to_vec();
+        missing.sort_unstable();
+        assert_eq!(missing, (7000..7050).collect::<Vec<_>>());
+        let mut missing = responder.missing().to_vec();
+        missing.sort_unstable();
+        assert_eq!(missing, (5000..5030).collect::<Vec<_>>());
+
+        assert!(responder.handle(Message::Resize { cells: 10 }).is_err());
+    }
+
+    #[test]
+    fn test_reconciler_saturated() {
+        // The difference saturates this estimation sketch, so the table is sized from
+        // the size of both sets.
+        let params = SketchParams::new(1, 0, 1);
+        let mut initiator = Reconciler::new(0..400u64, params);
+        let mut responder = Reconciler::new(200..600u64, params);
+        assert_eq!(run(&mut initiator, &mut responder), 4);
+        assert_eq!(initiator.missing().len(), 200);
+        assert_eq!(responder.missing().len(), 200);
+
+        let mut identical = Reconciler::new(0..10u64, params);
+        let mut other = Reconciler::new(0..10u64, params);
+        assert_eq!(run(&mut identical, &mut other), 4);
+        assert!(identical.missing().is_empty() && other.missing().is_empty());
+    }
+
+    #[derive(Clone, Default)]
+    struct Counts(Arc<Mutex<(usize, usize, usize)>>);
+
+    impl ReconcileMetrics for Counts {
+        fn sent(&mut self, bytes: usize) {
+            self.0.lock().unwrap().0 += bytes;
+        }
+
+        fn resized(&mut self, _cells: usize) {
+            self.0.lock().unwrap().1 += 1;
+        }
+
+        fn rejected(&mut self, keys: usize) {
+            self.0.lock().unwrap().2 += keys;
+        }
+    }
+
+    #[test]
+    fn test_reconciler_builder() {
+        let params = SketchParams::new(4, 0, 3);
+        let builder = || Reconciler::<u64>::builder().with_params(params);
+        assert!(builder().with_params(SketchParams::new(0, 0, 3)).build(0..10).is_err());
+        assert!(builder().with_hashes(2).build(0..10).is_err());
+        assert!(builder().with_cells(1.2, 16).build(0..10).is_err());
+        assert!(builder().with_cells(f64::NAN, 16).build(0..10).is_err());
+        assert!(builder().with_resizes(4, 1).build(0..10).is_err());
+        assert!(builder().with_resizes(0, 1).build(0..10).is_ok());
+        assert!(builder().with_byte_budget(16).build(0..10).is_err());
+        assert!(builder().with_timeout(Duration::ZERO).build(0..10).is_err());
+        assert!(builder().with_max_cells(8).build(0..10).is_err());
+
+        // A table of one cell per difference rarely peels, so the initiator asks again,
+        // and drops the keys its verification hook rejects.
+        let counts = Counts::default();
+        let mut initiator = builder()
+            .with_cells(1.3, 0)
+            .with_hashes(4)
+            .with_resizes(8, 2)
+            .with_verifier(|key| key % 10 != 0)
+            .with_metrics(counts.clone())
+            .build((0..1000u64).chain(5000..5030))
+            .expect("No errors");
+        let mut responder = builder().with_cells(1.3, 0).with_hashes(4).with_resizes(8, 2).build((0..1000u64).chain(7000..7050)).expect("No errors");
+        let messages = run(&mut initiator, &mut responder);
+        assert!(initiator.is_done() && responder.is_done());
+
+        let mut missing = initiator.missing().to_vec();
+        missing.sort_unstable();
+        assert_eq!(missing, (7000..7050).filter(|key| key % 10 != 0).collect::<Vec<_>>());
+        assert_eq!(initiator.rejected(), 5);
+        assert_eq!(responder.missing().len(), 30);
+        let (bytes, resizes, rejected) = *counts.0.lock().unwrap();
+        assert_eq!((bytes, resizes, rejected), (initiator.bytes_sent(), (messages - 4) / 2, 5));
+
+        // Tables of another number of hashes cannot be subtracted.
+        let mut initiator = builder().build(0..10u64).expect("No errors");
+        let mut responder = builder().with_hashes(4).build(0..10u64).expect("No errors");
+        let table = responder.handle(initiator.initiate().expect("No errors")).expect("No errors").expect("Reply");
+        assert_eq!(initiator.handle(table).map_err(|e| e.kind()).err(), Some(ErrorKind::Compatibility));
+    }
+
+    #[test]
+    fn test_reconciler_limits() {
+        let params = SketchParams::new(4, 0, 3);
+        let mut initiator = Reconciler::builder().with_params(params).build(0..1000u64).expect("No errors");
+        let estimate = initiator.initiate().expect("No errors");
+        assert_eq!(initiator.bytes_sent(), 4 * 8 + 8);
+
+        // The responder cannot afford a table for a difference of 1000 keys.
+        let mut responder = Reconciler::builder().with_params(params).with_byte_budget(4096).build(1000..2000u64).expect("No errors");
+        assert_eq!(responder.handle(estimate.clone()).map_err(|e| e.kind()).err(), Some(ErrorKind::Budget));
+        assert_eq!(responder.bytes_sent(), 0);
+
+        let mut responder = Reconciler::builder().with_params(params).with_timeout(Duration::from_millis(1)).build(1000..2000u64).expect("No errors");
+        let table = responder.handle(estimate).expect("No errors");
+        assert!(table.is_some());
+        std::thread::sleep(Duration::from_millis(5));
+        let entries = Message::Entries { keys: vec![], decoded: vec![], proofs: vec![] };
+        assert_eq!(responder.handle(entries).map_err(|e| e.kind()).err(), Some(ErrorKind::TimedOut));
+
+        // The responder refuses tables larger than the sets could need, and more resizes
+        // than its own settings allow.
+        let mut responder = Reconciler::builder().with_params(params).with_resizes(2, 2).build(1000..2000u64).expect("No errors");
+        let table = responder.handle(Message::Estimate { sketch: BinaryCountSketch::from_params(params), items: 1000 }).expect("No errors");
+        let cells = match table {
+            Some(Message::Table(table)) => table.cells(),
+            other => panic!("Unexpected reply {:?}", other),
+        };
+        assert_eq!(responder.handle(Message::Resize { cells: usize::MAX }).map_err(|e| e.kind()).err(), Some(ErrorKind::Budget));
+        assert!(responder.handle(Message::Resize { cells: cells * 2 }).expect("No errors").is_some());
+        assert!(responder.handle(Message::Resize { cells: cells * 4 }).expect("No errors").is_some());
+        assert_eq!(responder.handle(Message::Resize { cells: cells * 4 }).map_err(|e| e.kind()).err(), Some(ErrorKind::Budget));
+
+        // A set size claimed by the peer cannot get past the cell limit either.
+        let mut responder = Reconciler::builder().with_params(SketchParams::new(1, 0, 1)).with_max_cells(1 << 10).build(0..10u64).expect("No errors");
+        let mut saturated = BinaryCountSketch::from_params(SketchParams::new(1, 0, 1));
+        saturated.words[0] = u64::MAX;
+        let estimate = Message::Estimate { sketch: saturated, items: usize::MAX };
+        assert_eq!(responder.handle(estimate).map_err(|e| e.kind()).err(), Some(ErrorKind::Budget));
+    }
+
+    #[test]
+    fn test_message_bytes() {
+        let params = SketchParams::new(4, 0, 3);
+        let mut initiator = Reconciler::new(0..100u64, params);
+        let mut responder = Reconciler::new(50..150u64, params);
+        let estimate = initiator.initiate().expect("No errors");
+        let table = responder.handle(estimate.clone()).expect("No errors").expect("Reply");
+        let messages = [estimate, table, Message::Resize { cells: 96 }, Message::Entries { keys: vec![1, 2], decoded: vec![3], proofs: vec![vec![6; 3], vec![]] }, Message::Ack { spurious: vec![4, 5], proofs: vec![(7, vec![8])] }];
+        for message in &messages {
+            let bytes = message.to_bytes();
+            assert_eq!(Message::<u64>::from_bytes(&bytes).expect("No errors").to_bytes(), bytes);
+            assert_eq!(Message::<u64>::from_bytes(&bytes[..bytes.len() - 1]).map_err(|e| e.kind()).err(), Some(ErrorKind::Parse));
+        }
+
+        let parse = |bytes: &[u8]| Message::<u64>::from_bytes(bytes).map_err(|e| e.kind()).err();
+        assert_eq!(parse(&[]), Some(ErrorKind::Parse));
+        assert_eq!(parse(&[9]), Some(ErrorKind::Parse));
+        // Counts and sizes beyond the input are refused before anything is allocated.
+        let mut entries = messages[3].to_bytes();
+        entries[1..9].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert_eq!(parse(&entries), Some(ErrorKind::Parse));
+        let mut ack = messages[4].to_bytes();
+        ack[41..49].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert_eq!(parse(&ack), Some(ErrorKind::Parse));
+        let mut ack = messages[4].to_bytes();
+        ack.push(0);
+        assert_eq!(parse(&ack), Some(ErrorKind::Parse));
+        // Tables are checked as by `Iblt::from_bytes`, hashes and cell count included.
+        let mut table = messages[1].to_bytes();
+        table[1] = 2;
+        assert_eq!(parse(&table), Some(ErrorKind::Parse));
+    }
+
+    #[test]
+    fn test_reconciler_ack() {
+        let params = SketchParams::new(4, 0, 3);
+
+        // The responder flags keys it already holds and decoded keys it does not hold.
+        let mut responder = Reconciler::new(0..10u64, params);
+        let mut initiator = Reconciler::new(0..5u64, params);
+        responder.handle(initiator.initiate().expect("No errors")).expect("No errors");
+        let entries = Message::Entries { keys: vec![3, 100], decoded: vec![fingerprint(&5u64), fingerprint(&999u64)], proofs: vec![] };
+        match responder.handle(entries).expect("No errors") {
+            Some(Message::Ack { spurious, .. }) => assert_eq!(spurious, vec![fingerprint(&3u64), fingerprint(&999u64)]),
+            other => panic!("Unexpected reply {:?}", other),
+        }
+        assert_eq!(responder.missing(), &[100]);
+        assert_eq!(responder.spurious(), 2);
+
+        // The initiator drops the decodes the responder did not confirm.
+        let mut initiator = Reconciler::new(0..5u64, params);
+        let mut responder = Reconciler::new(0..10u64, params);
+        let table = responder.handle(initiator.initiate().expect("No errors")).expect("No errors").expect("Reply");
+        assert!(matches!(initiator.handle(table).expect("No errors"), Some(Message::Entries { .. })));
+        assert!(!initiator.is_done());
+        assert_eq!(initiator.missing().len(), 5);
+        assert_eq!(initiator.handle(Message::Ack { spurious: vec![fingerprint(&7u64)], proofs: vec![] }).expect("No errors").map(|_| ()), None);
+        assert!(initiator.is_done());
+        assert_eq!(initiator.spurious(), 1);
+        let mut missing = initiator.missing().to_vec();
+        missing.sort_unstable();
+        assert_eq!(missing, vec![5, 6, 8, 9]);
+    }
+
+    /// Stands for a signature by the holders of authentic keys, which are below 10000.
+    fn sign(key: &u64) -> Vec<u8> {
+        let mut hasher = StableHasher::with_key(0x5349_474e);
+        key.hash(&mut hasher);
+        if *key < 10000 { hasher.finish().to_le_bytes().to_vec() } else { vec![] }
+    }
+
+    fn check_signature(key: &u64, proof: &[u8]) -> bool {
+        *key < 10000 && proof == sign(key)
+    }
+
+    #[test]
+    fn test_reconciler_proofs() {
+        let params = SketchParams::new(4, 0, 3);
+        let builder = || Reconciler::<u64>::builder().with_params(params).with_prover(sign).with_proof_check(check_signature);
+
+        let mut initiator = builder().build((0..1000u64).chain(5000..5030)).expect("No errors");
+        let mut responder = builder().build((0..1000u64).chain(7000..7050)).expect("No errors");
+        assert_eq!(run(&mut initiator, &mut responder), 4);
+        assert_eq!((initiator.missing().len(), responder.missing().len()), (50, 30));
+        assert_eq!((initiator.rejected(), responder.rejected()), (0, 0));
+
+        // A responder listing keys it cannot prove, in a table it crafted, gets none of
+        // them treated as missing.
+        let counts = Counts::default();
+        let mut initiator = builder().with_metrics(counts.clone()).build(0..1000u64).expect("No errors");
+        let mut responder = builder().build((0..1000u64).chain(7000..7010).chain(20000..20020)).expect("No errors");
+        assert_eq!(run(&mut initiator, &mut responder), 4);
+        let mut missing = initiator.missing().to_vec();
+        missing.sort_unstable();
+        assert_eq!(missing, (7000..7010).collect::<Vec<_>>());
+        assert_eq!(initiator.rejected(), 20);
+        assert_eq!(counts.0.lock().unwrap().2, 20);
+
+        // Without a prover on the other side, nothing is confirmed.
+        let mut initiator = Reconciler::builder().with_params(params).build((0..100u64).chain(5000..5005)).expect("No errors");
+        let mut responder = builder().build(0..100u64).expect("No errors");
+        run(&mut initiator, &mut responder);
+        assert!(responder.missing().is_empty());
+        assert_eq!(responder.rejected(), 5);
+    }
+}
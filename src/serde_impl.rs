@@ -0,0 +1,69 @@
+use alloc::vec::Vec;
+
+use serde::de::Error as _;
+use serde::ser::SerializeStruct;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::{BinaryCountSketch, SketchParams};
+
+impl Serialize for BinaryCountSketch {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("BinaryCountSketch", 5)?;
+        state.serialize_field("base_length", &self.base_length)?;
+        state.serialize_field("level", &self.level)?;
+        state.serialize_field("points", &self.points)?;
+        state.serialize_field("seed", &self.seed)?;
+        state.serialize_field("words", &self.words)?;
+        state.end()
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename = "BinaryCountSketch")]
+struct Fields {
+    base_length: u64,
+    level: u64,
+    points: u64,
+    #[serde(default)]
+    seed: [u8; 16],
+    words: Vec<u64>,
+}
+
+/// Deserialization goes through `from_parts`, so parameters that describe no words, too
+/// many words or too many points, and words that do not match the parameters, are
+/// rejected rather than producing a sketch that panics or stalls on use.
+impl<'de> Deserialize<'de> for BinaryCountSketch {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let f = Fields::deserialize(deserializer)?;
+        BinaryCountSketch::from_parts(SketchParams::new(f.base_length, f.level, f.points).with_seed(f.seed), f.words).map_err(D::Error::custom)
+    }
+}
+
+#[cfg(all(test, feature = "rand"))]
+mod tests {
+    use super::*;
+    use crate::TestItem;
+
+    #[test]
+    fn test_serde_roundtrip() {
+        let item = TestItem::new();
+        let mut sketch = BinaryCountSketch::new(10, 2, 3);
+        sketch.toggle(&item);
+
+        let json = serde_json::to_string(&sketch).expect("No errors");
+        assert!(json.starts_with("{\"base_length\":10,\"level\":2,\"points\":3,\"seed\":[0,"));
+        let received: BinaryCountSketch = serde_json::from_str(&json).expect("No errors");
+        assert_eq!(received.params(), sketch.params());
+        assert_eq!(received.check(&item), 3);
+
+        let bad = "{\"base_length\":10,\"level\":2,\"points\":3,\"words\":[0,0]}";
+        assert!(serde_json::from_str::<BinaryCountSketch>(bad).is_err());
+
+        let level = "{\"base_length\":10,\"level\":64,\"points\":3,\"words\":[]}";
+        assert!(serde_json::from_str::<BinaryCountSketch>(level).is_err());
+        let empty = "{\"base_length\":0,\"level\":2,\"points\":3,\"words\":[]}";
+        assert!(serde_json::from_str::<BinaryCountSketch>(empty).is_err());
+        let points = "{\"base_length\":1,\"level\":0,\"points\":18446744073709551615,\"words\":[0]}";
+        assert!(serde_json::from_str::<BinaryCountSketch>(points).is_err());
+    }
+}
@@ -0,0 +1,264 @@
+use alloc::vec::Vec;
+
+use crate::{route_key, BinaryCountSketch, BinaryCountSketchError, ErrorKind, Item, SketchParams};
+#[cfg(feature = "std")]
+use crate::{DecodeReport, PeelStrategy, PeelingDecoder};
+
+/// Jump consistent hash (Lamping and Veach): maps `key` to a bucket in `0..buckets` such
+/// that growing from `n` to `n + 1` buckets only moves about `1 / (n + 1)` of the keys,
+/// all of them into the new bucket.
+///
+/// Panics if `buckets` is zero, as no bucket can hold the key.
+pub fn jump_consistent_hash(mut key: u64, buckets: u32) -> u32 {
+    assert!(buckets > 0, "jump_consistent_hash needs at least one bucket");
+    let mut b: i64 = -1;
+    let mut j: i64 = 0;
+    while j < buckets as i64 {
+        b = j;
+        key = key.wrapping_mul(2862933555777941757).wrapping_add(1);
+        j = ((b + 1) as f64 * ((1u64 << 31) as f64 / ((key >> 33) + 1) as f64)) as i64;
+    }
+    b as u32
+}
+
+/// Assigns items to one of `shards` sketch shards, so that horizontally scaled ingest
+/// nodes agree on which shard sketch each item belongs to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ShardAssigner {
+    shards: u32,
+}
+
+impl ShardAssigner {
+    pub fn new(shards: u32) -> Result<Self, BinaryCountSketchError> {
+        if shards == 0 { return Err(BinaryCountSketchError::with_kind(ErrorKind::InvalidArgument, "Incorrect shards")); }
+        Ok(ShardAssigner { shards })
+    }
+
+    pub fn shards(&self) -> u32 {
+        self.shards
+    }
+
+    pub fn shard_of<V: Item>(&self, v: &V) -> usize {
+        jump_consistent_hash(route_key(v), self.shards) as usize
+    }
+}
+
+/// Sketch of sketches: items are routed by a `ShardAssigner` to one of several
+/// independent sub-sketches of the same parameters, so that a very large difference is
+/// split into many small ones that each decode on their own, in parallel if needed.
+///
+/// Peers must use the same number of shards and parameters to route items alike.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ShardedSketch {
+    assigner: ShardAssigner,
+    shards: Vec<BinaryCountSketch>,
+}
+
+impl ShardedSketch {
+    pub fn new(shards: u32, params: SketchParams) -> Result<Self, BinaryCountSketchError> {
+        let assigner = ShardAssigner::new(shards)?;
+        Ok(ShardedSketch {
+            assigner,
+            shards: (0..shards).map(|_| BinaryCountSketch::from_params(params)).collect(),
+        })
+    }
+
+    pub fn assigner(&self) -> ShardAssigner {
+        self.assigner
+    }
+
+    pub fn params(&self) -> SketchParams {
+        self.shards[0].params()
+    }
+
+    pub fn len(&self) -> usize {
+        self.shards.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.shards.is_empty()
+    }
+
+    pub fn shard_of<V: Item>(&self, v: &V) -> usize {
+        self.assigner.shard_of(v)
+    }
+
+    pub fn shard(&self, i: usize) -> &BinaryCountSketch {
+        &self.shards[i]
+    }
+
+    pub fn shards(&self) -> &[BinaryCountSketch] {
+        &self.shards
+    }
+
+    pub fn toggle<V: Item>(&mut self, v: &V) {
+        let i = self.shard_of(v);
+        self.shards[i].toggle(v);
+    }
+
+    pub fn check<V: Item>(&self, v: &V) -> usize {
+        self.shards[self.shard_of(v)].check(v)
+    }
+
+    pub fn digests(&self) -> Vec<u64> {
+        self.shards.iter().map(|s| s.digest()).collect()
+    }
+
+    /// Diffs every shard with the matching shard of `other`.
+    pub fn diff_with(&mut self, other: &ShardedSketch) -> Result<(), BinaryCountSketchError> {
+        if other.shards.len() != self.shards.len() { return Err(BinaryCountSketchError::with_mismatch("shards", self.shards.len(), other.shards.len())); }
+        for (shard, other) in self.shards.iter_mut().zip(&other.shards) {
+            shard.diff_with(other)?;
+        }
+        Ok(())
+    }
+
+    pub fn diff_shard(&mut self, i: usize, other: &BinaryCountSketch) -> Result<(), BinaryCountSketchError> {
+        if i >= self.shards.len() { return Err(BinaryCountSketchError::with_kind(ErrorKind::InvalidArgument, "Incorrect shard")); }
+        self.shards[i].diff_with(other)
+    }
+
+    /// Candidates grouped by the shard they are routed to.
+    pub fn route<'a, V: Item>(&self, candidates: &'a [V]) -> Vec<Vec<&'a V>> {
+        let mut routed: Vec<Vec<&V>> = (0..self.shards.len()).map(|_| Vec::new()).collect();
+        for v in candidates {
+            routed[self.shard_of(v)].push(v);
+        }
+        routed
+    }
+
+    /// Decodes shard `i` of a diffed sketch, ignoring candidates routed elsewhere.
+    #[cfg(feature = "std")]
+    pub fn decode_shard<V: Item + Clone, S: PeelStrategy>(&mut self, i: usize, decoder: &PeelingDecoder<S>, candidates: &[V]) -> Result<DecodeReport<V>, BinaryCountSketchError> {
+        if i >= self.shards.len() { return Err(BinaryCountSketchError::with_kind(ErrorKind::InvalidArgument, "Incorrect shard")); }
+
+        let candidates: Vec<V> = candidates.iter().filter(|v| self.shard_of(*v) == i).cloned().collect();
+        decoder.decode(&mut self.shards[i], &candidates)
+    }
+
+    /// Decodes every shard of a diffed sketch, returning one report per shard.
+    #[cfg(feature = "std")]
+    pub fn decode<V: Item + Clone, S: PeelStrategy>(&mut self, decoder: &PeelingDecoder<S>, candidates: &[V]) -> Result<Vec<DecodeReport<V>>, BinaryCountSketchError> {
+        let routed = self.route(candidates);
+        self.shards
+            .iter_mut()
+            .zip(routed)
+            .map(|(shard, candidates)| {
+                let candidates: Vec<V> = candidates.into_iter().cloned().collect();
+                decoder.decode(shard, &candidates)
+            })
+            .collect()
+    }
+
+    /// Same as `decode`, decoding the shards on the rayon thread pool.
+    #[cfg(feature = "rayon")]
+    pub fn decode_par<V: Item + Clone + Send + Sync, S: PeelStrategy + Sync>(&mut self, decoder: &PeelingDecoder<S>, candidates: &[V]) -> Result<Vec<DecodeReport<V>>, BinaryCountSketchError> {
+        use rayon::prelude::*;
+
+        let routed = self.route(candidates);
+        self.shards
+            .par_iter_mut()
+            .zip(routed)
+            .map(|(shard, candidates)| {
+                let candidates: Vec<V> = candidates.into_iter().cloned().collect();
+                decoder.decode(shard, &candidates)
+            })
+            .collect()
+    }
+}
+
+#[cfg(all(test, feature = "rand"))]
+mod tests {
+    use super::*;
+    use crate::TestItem;
+
+    #[test]
+    fn test_shard_balance() {
+        let assigner = ShardAssigner::new(8).expect("No errors");
+        assert!(ShardAssigner::new(0).is_err());
+
+        let mut counts = [0; 8];
+        for _ in 0..8000 {
+            counts[assigner.shard_of(&TestItem::new())] += 1;
+        }
+        assert!(counts.iter().all(|c| *c > 800 && *c < 1200));
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_jump_no_buckets() {
+        jump_consistent_hash(7, 0);
+    }
+
+    #[test]
+    fn test_shard_stability() {
+        let before = ShardAssigner::new(10).expect("No errors");
+        let after = ShardAssigner::new(11).expect("No errors");
+
+        let items: Vec<TestItem> = (0..5000).map(|_| TestItem::new()).collect();
+        let mut moved = 0;
+        for item in &items {
+            let (old, new) = (before.shard_of(item), after.shard_of(item));
+            if old != new {
+                assert_eq!(new, 10);
+                moved += 1;
+            }
+        }
+
+        // About 1/11 of the items should move.
+        assert!(moved > 300 && moved < 650);
+    }
+
+    #[test]
+    fn test_sharded_decode() {
+        let params = SketchParams::new(50, 1, 5);
+        let mut local = ShardedSketch::new(8, params).expect("No errors");
+        let mut remote = ShardedSketch::new(8, params).expect("No errors");
+        assert!(ShardedSketch::new(0, params).is_err());
+        assert!(local.diff_with(&ShardedSketch::new(4, params).expect("No errors")).is_err());
+
+        let mut single = ShardedSketch::new(8, params).expect("No errors");
+        single.toggle(&TestItem::from_seed(0));
+        assert_eq!(single.check(&TestItem::from_seed(0)), 5);
+        assert_eq!(single.digests().iter().filter(|d| **d != local.digests()[0]).count(), 1);
+
+        let candidates: Vec<TestItem> = (0..2000).map(TestItem::from_seed).collect();
+        for item in &candidates {
+            remote.toggle(item);
+        }
+        for item in &candidates[..1800] {
+            local.toggle(item);
+        }
+        assert_eq!(local.route(&candidates).iter().map(Vec::len).sum::<usize>(), candidates.len());
+
+        // Each of the eight shards holds about 25 of the 200 differences and decodes them on its own.
+        local.diff_with(&remote).expect("No errors");
+        let mut sequential = local.clone();
+        let reports = sequential.decode(&PeelingDecoder::new(4), &candidates).expect("No errors");
+        let mut decoded: Vec<TestItem> = reports.into_iter().flat_map(|r| r.decoded).collect();
+        decoded.sort_by_key(|item| item.get_code(0));
+        let mut expected = candidates[1800..].to_vec();
+        expected.sort_by_key(|item| item.get_code(0));
+        assert_eq!(decoded, expected);
+
+        let report = local.decode_shard(3, &PeelingDecoder::new(4), &candidates).expect("No errors");
+        assert!(report.decoded.iter().all(|item| local.shard_of(item) == 3));
+        assert!(local.decode_shard(8, &PeelingDecoder::new(4), &candidates).is_err());
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn test_sharded_decode_par() {
+        let params = SketchParams::new(20, 1, 5);
+        let mut sketch = ShardedSketch::new(4, params).expect("No errors");
+        let candidates: Vec<TestItem> = (0..40).map(TestItem::from_seed).collect();
+        for item in &candidates {
+            sketch.toggle(item);
+        }
+
+        let decoder = PeelingDecoder::new(4);
+        let expected = sketch.clone().decode(&decoder, &candidates).expect("No errors");
+        let reports = sketch.decode_par(&decoder, &candidates).expect("No errors");
+        assert_eq!(reports.iter().map(|r| r.decoded.len()).collect::<Vec<_>>(), expected.iter().map(|r| r.decoded.len()).collect::<Vec<_>>());
+    }
+}
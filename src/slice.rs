@@ -0,0 +1,155 @@
+use alloc::vec::Vec;
+use core::ops::Range;
+
+use crate::{code_index, BinaryCountSketch, BinaryCountSketchError, ErrorKind, Item};
+
+/// Borrowed view over a contiguous range of a sketch's words, so very large sketches
+/// can be exchanged and diffed region by region.
+///
+/// Bit indices are still computed modulo the length of the full sketch; points that
+/// fall outside the slice are ignored by `check`.
+#[derive(Clone, Copy, Debug)]
+pub struct SketchSlice<'a> {
+    base_length: u64,
+    level: u64,
+    points: u64,
+    seed: [u8; 16],
+    total_words: usize,
+    start: usize,
+    words: &'a [u64],
+}
+
+impl<'a> SketchSlice<'a> {
+    pub fn range(&self) -> Range<usize> {
+        self.start..self.start + self.words.len()
+    }
+
+    pub fn words(&self) -> &'a [u64] {
+        self.words
+    }
+
+    /// Number of `v`'s points whose bit falls inside this slice.
+    pub fn points_in_range<V: Item>(&self, v: &V) -> usize {
+        let l = self.total_words * 64;
+        let range = self.range();
+        (0..v.points(self.points).min(self.points)).filter(|i| range.contains(&(code_index(v, *i, l) / 64))).count()
+    }
+
+    /// Number of `v`'s points whose bit falls inside this slice and is set.
+    pub fn check<V: Item>(&self, v: &V) -> usize {
+        let l = self.total_words * 64;
+        let range = self.range();
+        (0..v.points(self.points).min(self.points))
+            .map(|i| code_index(v, i, l))
+            .filter(|b| range.contains(&(b / 64)) && self.words[b / 64 - self.start] & (1 << (b % 64)) != 0)
+            .count()
+    }
+}
+
+impl BinaryCountSketch {
+    pub fn slice(&self, words: Range<usize>) -> Result<SketchSlice<'_>, BinaryCountSketchError> {
+        if words.start > words.end || words.end > self.words.len() { return Err(BinaryCountSketchError::with_kind(ErrorKind::InvalidArgument, "Incorrect slice range")); }
+
+        Ok(SketchSlice {
+            base_length: self.base_length,
+            level: self.level,
+            points: self.points,
+            seed: self.seed,
+            total_words: self.words.len(),
+            start: words.start,
+            words: &self.words[words],
+        })
+    }
+
+    /// Word ranges of the level `finer_level` sketch of the same items that fold onto
+    /// `words` of this sketch under `level_down`. When decoding stalls in a saturated
+    /// region, only these slices of the finer sketch need to be exchanged.
+    pub fn refinement_ranges(&self, finer_level: u64, words: Range<usize>) -> Result<Vec<Range<usize>>, BinaryCountSketchError> {
+        if finer_level <= self.level { return Err(BinaryCountSketchError::with_kind(ErrorKind::InvalidArgument, "Incorrect level")); }
+        if words.start > words.end || words.end > self.words.len() { return Err(BinaryCountSketchError::with_kind(ErrorKind::InvalidArgument, "Incorrect slice range")); }
+
+        let l = self.words.len();
+        Ok((0..1usize << (finer_level - self.level)).map(|k| k * l + words.start..k * l + words.end).collect())
+    }
+
+    /// XORs a slice of a compatible sketch into the matching range of this one.
+    pub fn diff_slice(&mut self, other: &SketchSlice) -> Result<(), BinaryCountSketchError> {
+        if self.base_length != other.base_length { return Err(BinaryCountSketchError::with_mismatch("base length", self.base_length, other.base_length)); }
+        if self.level != other.level { return Err(BinaryCountSketchError::with_mismatch("level", self.level, other.level)); }
+        if self.points != other.points { return Err(BinaryCountSketchError::with_mismatch("points", self.points, other.points)); }
+        if self.seed != other.seed { return Err(BinaryCountSketchError::with_mismatch("seed", self.seed, other.seed)); }
+        if self.words.len() != other.total_words { return Err(BinaryCountSketchError::with_mismatch("words length", self.words.len(), other.total_words)); }
+
+        for (i, val) in other.words.iter().enumerate() {
+            self.words[other.start + i] ^= *val;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "rand"))]
+mod tests {
+    use super::*;
+    use crate::TestItem;
+
+    #[test]
+    fn test_slice_check() {
+        let item = TestItem { points: vec![5, 700, 1300, 1900, 2400] };
+        let other = TestItem { points: vec![6, 701, 1301, 1901, 2401] };
+        let mut sketch = BinaryCountSketch::new(10, 2, 5);
+        sketch.toggle(&item);
+
+        assert!(sketch.slice(30..50).is_err());
+        let (low, high) = (sketch.slice(0..20).expect("No errors"), sketch.slice(20..40).expect("No errors"));
+        assert_eq!(low.range(), 0..20);
+        assert_eq!((low.points_in_range(&item), high.points_in_range(&item)), (2, 3));
+        assert_eq!((low.check(&item), high.check(&item)), (2, 3));
+        assert_eq!((low.check(&other), high.check(&other)), (0, 0));
+    }
+
+    #[test]
+    fn test_slice_diff() {
+        let mut sketch1 = BinaryCountSketch::new(10, 2, 5);
+        let mut sketch2 = BinaryCountSketch::new(10, 2, 5);
+        let common = TestItem { points: vec![1, 100, 1000, 2000, 2500] };
+        let extra = TestItem { points: vec![3, 200, 1500, 2100, 2559] };
+        sketch1.toggle(&common);
+        sketch2.toggle(&common);
+        sketch2.toggle(&extra);
+
+        // Exchanging the sketch region by region is the same as a full diff.
+        for start in (0..40).step_by(8) {
+            sketch1.diff_slice(&sketch2.slice(start..start + 8).expect("No errors")).expect("No errors");
+        }
+        assert_eq!(sketch1.check(&common), 0);
+        assert_eq!(sketch1.check(&extra), 5);
+
+        let other = BinaryCountSketch::new(10, 3, 5);
+        assert!(sketch1.diff_slice(&other.slice(0..8).expect("No errors")).is_err());
+    }
+
+    #[test]
+    fn test_refinement_ranges() {
+        let mut fine = BinaryCountSketch::new(10, 3, 5);
+        for _ in 0..200 {
+            fine.toggle(&TestItem::new());
+        }
+        let coarse = fine.level_down(1).expect("No errors");
+
+        assert!(coarse.refinement_ranges(1, 0..4).is_err());
+        assert!(coarse.refinement_ranges(3, 0..21).is_err());
+
+        let ranges = coarse.refinement_ranges(3, 4..9).expect("No errors");
+        assert_eq!(ranges, vec![4..9, 24..29, 44..49, 64..69]);
+
+        // The finer slices of a region fold back onto the coarse words of that region.
+        let mut folded = [0u64; 5];
+        for range in ranges {
+            for (i, word) in fine.slice(range).expect("No errors").words().iter().enumerate() {
+                folded[i] ^= *word;
+            }
+        }
+        assert_eq!(&folded[..], &coarse.words[4..9]);
+    }
+}
@@ -0,0 +1,81 @@
+use std::cmp::Ordering;
+
+use crate::{BinaryCountSketch, Item};
+
+/// Bounds on the probabilities of the soft decode, so a single point can never make an
+/// item certain either way.
+const MIN_PROBABILITY: f64 = 1e-9;
+
+impl BinaryCountSketch {
+    /// Probability that each candidate is in this diffed sketch, from how many of its
+    /// points are set compared to the background density.
+    ///
+    /// A point of an item that is not in the diff is set with the probability of any bit,
+    /// while a point of an item in the diff is set unless the other items flipped it back,
+    /// so each set point is evidence for the item and each clear point against it. The
+    /// estimated size of the difference sets the prior. Unlike a hard threshold, items
+    /// with a point cleared by a collision still rank above items that merely hit set bits,
+    /// which matters when the sketch is near capacity.
+    pub fn soft_decode<V: Item>(&self, candidates: &[V]) -> Vec<f64> {
+        let clamp = |p: f64| p.clamp(MIN_PROBABILITY, 1.0 - MIN_PROBABILITY);
+        let difference = self.estimate_diff_size().min(candidates.len() as f64);
+
+        // As in `theoretical_stats`: a bit is set with probability `p` after the flips of
+        // the difference, and a point of an item of the difference stays set with
+        // probability `q` after the flips of the other items.
+        let keep = 1.0 - 2.0 / self.bits() as f64;
+        let flips = difference * self.points as f64;
+        let p = clamp(self.density());
+        let q = clamp((1.0 + keep.powf((flips - self.points as f64).max(0.0))) / 2.0);
+        let prior = clamp(difference / candidates.len().max(1) as f64);
+
+        let (set, clear) = ((q / p).ln(), ((1.0 - q) / (1.0 - p)).ln());
+        let prior_odds = (prior / (1.0 - prior)).ln();
+        candidates
+            .iter()
+            .map(|v| {
+                let hits = self.check(v) as f64;
+                let misses = self.points_of(v) as f64 - hits;
+                let log_odds = prior_odds + hits * set + misses * clear;
+                1.0 / (1.0 + (-log_odds).exp())
+            })
+            .collect()
+    }
+}
+
+/// Indices of the `budget` most likely items, most likely first, e.g. with `budget` the
+/// estimated difference of the sketch `likelihoods` were computed from. Items with equal
+/// likelihoods keep their order.
+pub fn select_by_likelihood(likelihoods: &[f64], budget: usize) -> Vec<usize> {
+    let mut ranked: Vec<usize> = (0..likelihoods.len()).collect();
+    ranked.sort_by(|a, b| likelihoods[*b].partial_cmp(&likelihoods[*a]).unwrap_or(Ordering::Equal));
+    ranked.truncate(budget);
+    ranked
+}
+
+#[cfg(all(test, feature = "rand"))]
+mod tests {
+    use super::*;
+    use crate::TestItem;
+
+    #[test]
+    fn test_soft_decode_near_capacity() {
+        let candidates: Vec<TestItem> = (0..2000).map(TestItem::from_seed).collect();
+        let mut sketch = BinaryCountSketch::new(10, 2, 5);
+        sketch.toggle_all(&candidates[..200]);
+
+        let likelihoods = sketch.soft_decode(&candidates);
+        assert!(likelihoods.iter().all(|l| (0.0..=1.0).contains(l)));
+
+        let budget = sketch.estimate_difference().expect("Not saturated");
+        let selected = select_by_likelihood(&likelihoods, budget);
+        assert_eq!(selected.len(), budget);
+        assert!(selected.windows(2).all(|w| likelihoods[w[0]] >= likelihoods[w[1]]));
+        let soft_hits = selected.iter().filter(|i| **i < 200).count();
+
+        // At this load many items of the difference lost a point to a collision.
+        let hard_hits = sketch.decode(&candidates).iter().take(200).filter(|s| **s == 5).count();
+        assert!(soft_hits > hard_hits);
+        assert!(soft_hits * 10 >= budget * 8);
+    }
+}
@@ -0,0 +1,78 @@
+use alloc::vec::Vec;
+use core::future::Future;
+
+use crate::{BinaryCountSketchError, Item};
+
+/// Produces decode candidates one page at a time, e.g. from a database cursor, so the
+/// full candidate set never has to be held in memory.
+pub trait CandidateSource {
+    type Item: Item + Clone;
+
+    /// Returns the next page of candidates, or `None` once the source is exhausted.
+    fn next_page(&mut self) -> Result<Option<Vec<Self::Item>>, BinaryCountSketchError>;
+}
+
+/// Asynchronous flavour of `CandidateSource`.
+pub trait AsyncCandidateSource {
+    type Item: Item + Clone;
+
+    /// Returns the next page of candidates, or `None` once the source is exhausted.
+    fn next_page(&mut self) -> impl Future<Output = Result<Option<Vec<Self::Item>>, BinaryCountSketchError>>;
+}
+
+/// Pages an iterator of candidates.
+pub struct IterSource<I> {
+    iter: I,
+    page_size: usize,
+}
+
+impl<I: Iterator> IterSource<I> {
+    pub fn new(iter: I, page_size: usize) -> Self {
+        IterSource { iter, page_size: page_size.max(1) }
+    }
+}
+
+impl<I> CandidateSource for IterSource<I>
+where
+    I: Iterator,
+    I::Item: Item + Clone,
+{
+    type Item = I::Item;
+
+    fn next_page(&mut self) -> Result<Option<Vec<I::Item>>, BinaryCountSketchError> {
+        let page: Vec<_> = self.iter.by_ref().take(self.page_size).collect();
+        Ok(if page.is_empty() { None } else { Some(page) })
+    }
+}
+
+impl<I> AsyncCandidateSource for IterSource<I>
+where
+    I: Iterator,
+    I::Item: Item + Clone,
+{
+    type Item = I::Item;
+
+    async fn next_page(&mut self) -> Result<Option<Vec<I::Item>>, BinaryCountSketchError> {
+        CandidateSource::next_page(self)
+    }
+}
+
+#[cfg(all(test, feature = "rand"))]
+mod tests {
+    use super::*;
+    use crate::TestItem;
+
+    #[test]
+    fn test_iter_source_pages() {
+        let items: Vec<TestItem> = (0..10).map(|_| TestItem::new()).collect();
+        let mut source = IterSource::new(items.clone().into_iter(), 4);
+
+        let mut pages = vec![];
+        while let Some(page) = CandidateSource::next_page(&mut source).expect("No errors") {
+            pages.push(page);
+        }
+
+        assert_eq!(pages.iter().map(|p| p.len()).collect::<Vec<_>>(), vec![4, 4, 2]);
+        assert_eq!(pages.concat(), items);
+    }
+}
@@ -0,0 +1,106 @@
+use alloc::vec::Vec;
+
+use crate::{route_key, splitmix64, BinaryCountSketchError, CellSketch, ErrorKind, Item};
+#[cfg(feature = "std")]
+use crate::SketchParams;
+
+/// Mixed into the routing key of an item, so its stratum is independent of its shard.
+const STRATA_KEY: u64 = 0x5354_5241;
+
+/// Strata and cells per stratum of `StrataEstimator::default`, as suggested by Eppstein
+/// et al. for differences of up to a few million items.
+const DEFAULT_STRATA: usize = 32;
+const DEFAULT_CELLS: usize = 80;
+
+/// Strata estimator of the size of the difference between two sets (Eppstein et al.,
+/// "What's the Difference?"): item `i` goes into stratum `k` with probability `2^-(k+1)`,
+/// and each stratum is a small `CellSketch`. The finest strata of a diff hold few items
+/// and list fully; the first stratum that fails to list bounds how many items were
+/// sampled, so peers can agree on the size of the sketch to exchange before sending it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StrataEstimator {
+    strata: Vec<CellSketch>,
+}
+
+impl StrataEstimator {
+    /// Estimator with `strata` strata, at most 64, of `cells` cells each.
+    pub fn new(strata: usize, cells: usize) -> Result<Self, BinaryCountSketchError> {
+        if strata == 0 || strata > 64 { return Err(BinaryCountSketchError::with_kind(ErrorKind::InvalidArgument, "Incorrect strata")); }
+        Ok(StrataEstimator { strata: (0..strata).map(|_| CellSketch::new(cells, 3)).collect() })
+    }
+
+    pub fn strata(&self) -> &[CellSketch] {
+        &self.strata
+    }
+
+    pub fn toggle<V: Item>(&mut self, v: &V) {
+        let key = splitmix64(route_key(v) ^ STRATA_KEY);
+        let stratum = (key.trailing_zeros() as usize).min(self.strata.len() - 1);
+        self.strata[stratum].toggle(key);
+    }
+
+    /// Estimated number of items in one set and not the other.
+    pub fn estimate_difference(&self, other: &Self) -> Result<usize, BinaryCountSketchError> {
+        if self.strata.len() != other.strata.len() { return Err(BinaryCountSketchError::with_mismatch("strata", self.strata.len(), other.strata.len())); }
+
+        let mut count = 0;
+        for (k, (stratum, other)) in self.strata.iter().zip(&other.strata).enumerate().rev() {
+            let mut diff = stratum.clone();
+            diff.diff_with(other)?;
+            match diff.decode_unknown() {
+                Ok(keys) => count += keys.len(),
+                // Strata `k + 1` and above sampled each item with probability `2^-(k+1)`.
+                Err(_) => return Ok(count << (k + 1)),
+            }
+        }
+        Ok(count)
+    }
+
+    /// Parameters of a sketch sized with `SketchParams::for_expected_diff` for the
+    /// estimated difference with `other`, with some headroom for the estimate's error.
+    #[cfg(feature = "std")]
+    pub fn suggest_params(&self, other: &Self, target_error: f64) -> Result<SketchParams, BinaryCountSketchError> {
+        let estimate = self.estimate_difference(other)?;
+        SketchParams::for_expected_diff(estimate + estimate / 2, target_error)
+    }
+}
+
+impl Default for StrataEstimator {
+    fn default() -> Self {
+        StrataEstimator::new(DEFAULT_STRATA, DEFAULT_CELLS).expect("Default strata are valid")
+    }
+}
+
+#[cfg(all(test, feature = "rand"))]
+mod tests {
+    use super::*;
+    use crate::TestItem;
+
+    #[test]
+    fn test_strata_estimate() {
+        assert!(StrataEstimator::new(0, 80).is_err());
+        assert!(StrataEstimator::new(65, 80).is_err());
+
+        let items: Vec<TestItem> = (0..12000).map(TestItem::from_seed).collect();
+        let mut local = StrataEstimator::default();
+        for item in &items[..10000] {
+            local.toggle(item);
+        }
+        for (difference, tolerance) in [(0, 0), (30, 0), (2000, 800)] {
+            let mut remote = StrataEstimator::default();
+            for item in &items[difference..10000] {
+                remote.toggle(item);
+            }
+            let estimate = local.estimate_difference(&remote).expect("No errors");
+            assert!(estimate.abs_diff(difference) <= tolerance);
+        }
+        assert!(local.estimate_difference(&StrataEstimator::new(16, 80).expect("No errors")).is_err());
+
+        let mut remote = StrataEstimator::default();
+        for item in &items[60..10000] {
+            remote.toggle(item);
+        }
+        let params = local.suggest_params(&remote, 0.01).expect("No errors");
+        assert_eq!(params, SketchParams::for_expected_diff(90, 0.01).expect("No errors"));
+    }
+}
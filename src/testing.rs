@@ -0,0 +1,270 @@
+use std::collections::HashSet;
+
+use crate::{splitmix64, BinaryCountSketch, PeelingDecoder, SketchParams, SplitMix, TestItem};
+
+/// `n` random items drawn from `seed`, the same on every platform, so a failing
+/// scenario can be replayed from its seed.
+pub fn random_items(seed: u64, n: usize) -> Vec<TestItem> {
+    let mut rng = SplitMix(splitmix64(seed));
+    (0..n).map(|_| TestItem::from_rng(&mut rng)).collect()
+}
+
+/// Synthetic reconciliation between two peers sharing `common` items, each with items of
+/// its own, decoded by peeling down to `threshold` against the items of both peers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Scenario {
+    pub params: SketchParams,
+    pub common: usize,
+    pub local_only: usize,
+    pub remote_only: usize,
+    pub threshold: usize,
+}
+
+/// Outcome of one run of a `Scenario`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Outcome {
+    /// Items of the difference, which should be decoded.
+    pub positives: usize,
+    /// Common items, which should not.
+    pub negatives: usize,
+    pub false_positives: usize,
+    pub false_negatives: usize,
+}
+
+impl Outcome {
+    /// True if exactly the difference was decoded.
+    pub fn is_exact(&self) -> bool {
+        self.false_positives == 0 && self.false_negatives == 0
+    }
+}
+
+impl Scenario {
+    /// Scenario with a difference of `difference` items split evenly between the peers,
+    /// decoded down to one point less than `params.points`.
+    pub fn new(params: SketchParams, common: usize, difference: usize) -> Self {
+        Scenario {
+            params,
+            common,
+            local_only: difference / 2,
+            remote_only: difference - difference / 2,
+            threshold: params.points.saturating_sub(1).max(1) as usize,
+        }
+    }
+
+    pub fn with_threshold(self, threshold: usize) -> Self {
+        Scenario { threshold, ..self }
+    }
+
+    /// Scenario with `local_only` and `remote_only` items of each peer's own, in place
+    /// of an even split of the difference.
+    pub fn with_sides(self, local_only: usize, remote_only: usize) -> Self {
+        Scenario { local_only, remote_only, ..self }
+    }
+
+    /// Items drawn from `seed` and the sketches both peers hold of them.
+    pub fn peers(&self, seed: u64) -> Peers {
+        let mut common = random_items(seed, self.common + self.local_only + self.remote_only);
+        let remote_only = common.split_off(self.common + self.local_only);
+        let local_only = common.split_off(self.common);
+
+        let mut local = BinaryCountSketch::from_params(self.params);
+        let mut remote = BinaryCountSketch::from_params(self.params);
+        local.toggle_all(&common);
+        local.toggle_all(&local_only);
+        remote.toggle_all(&common);
+        remote.toggle_all(&remote_only);
+        Peers { common, local_only, remote_only, local, remote }
+    }
+
+    /// Runs the scenario on items drawn from `seed`.
+    pub fn run(&self, seed: u64) -> Outcome {
+        let (mut diff, candidates, difference) = self.peers(seed).decode_inputs();
+        let difference: HashSet<TestItem> = difference.into_iter().collect();
+        let report = PeelingDecoder::new(self.threshold).decode(&mut diff, &candidates).expect("Threshold within the points");
+
+        let true_positives = report.decoded.iter().filter(|item| difference.contains(*item)).count();
+        Outcome {
+            positives: difference.len(),
+            negatives: self.common,
+            false_positives: report.decoded.len() - true_positives,
+            false_negatives: difference.len() - true_positives,
+        }
+    }
+
+    /// Runs the scenario `trials` times, on items drawn from `seed`, `seed + 1`, and so on.
+    pub fn rates(&self, trials: usize, seed: u64) -> Rates {
+        let mut rates = Rates::default();
+        for trial in 0..trials {
+            let outcome = self.run(seed.wrapping_add(trial as u64));
+            rates.trials += 1;
+            rates.exact += outcome.is_exact() as usize;
+            rates.positives += outcome.positives;
+            rates.negatives += outcome.negatives;
+            rates.false_positives += outcome.false_positives;
+            rates.false_negatives += outcome.false_negatives;
+        }
+        rates
+    }
+}
+
+/// Items of a `Scenario` and the sketches each peer holds of them.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Peers {
+    pub common: Vec<TestItem>,
+    pub local_only: Vec<TestItem>,
+    pub remote_only: Vec<TestItem>,
+    pub local: BinaryCountSketch,
+    pub remote: BinaryCountSketch,
+}
+
+impl Peers {
+    /// Items of one peer only.
+    pub fn difference(&self) -> Vec<TestItem> {
+        self.local_only.iter().chain(&self.remote_only).cloned().collect()
+    }
+
+    /// Items of either peer, the common ones first, as decode candidates.
+    pub fn candidates(&self) -> Vec<TestItem> {
+        self.common.iter().chain(&self.local_only).chain(&self.remote_only).cloned().collect()
+    }
+
+    /// Local sketch with the remote one diffed in, holding the difference alone.
+    pub fn diff(&self) -> BinaryCountSketch {
+        let mut diff = self.local.clone();
+        diff.diff_with(&self.remote).expect("Same parameters");
+        diff
+    }
+
+    /// The diffed sketch, the candidates and the difference a decoder is tested on.
+    pub fn decode_inputs(&self) -> (BinaryCountSketch, Vec<TestItem>, Vec<TestItem>) {
+        (self.diff(), self.candidates(), self.difference())
+    }
+}
+
+/// Totals of the `Outcome`s of several runs of a `Scenario`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Rates {
+    pub trials: usize,
+    /// Runs that decoded exactly the difference.
+    pub exact: usize,
+    pub positives: usize,
+    pub negatives: usize,
+    pub false_positives: usize,
+    pub false_negatives: usize,
+}
+
+impl Rates {
+    /// False positives per common item.
+    pub fn fp_rate(&self) -> f64 {
+        self.false_positives as f64 / self.negatives.max(1) as f64
+    }
+
+    /// False negatives per item of the difference.
+    pub fn fn_rate(&self) -> f64 {
+        self.false_negatives as f64 / self.positives.max(1) as f64
+    }
+
+    /// Panics unless the false positives are consistent with a rate of at most `max_rate`.
+    pub fn assert_fp_rate_at_most(&self, max_rate: f64) {
+        assert_rate_at_most("false positive", self.false_positives, self.negatives, max_rate);
+    }
+
+    /// Panics unless the false negatives are consistent with a rate of at most `max_rate`.
+    pub fn assert_fn_rate_at_most(&self, max_rate: f64) {
+        assert_rate_at_most("false negative", self.false_negatives, self.positives, max_rate);
+    }
+}
+
+/// Panics if `events` out of `trials` is more than a `max_rate` rate plausibly gives:
+/// above its mean by more than three standard deviations, plus one event of slack, so
+/// that an assertion with the true rate at `max_rate` fails about once in a thousand runs.
+pub fn assert_rate_at_most(what: &str, events: usize, trials: usize, max_rate: f64) {
+    let mean = trials as f64 * max_rate;
+    let bound = mean + 3.0 * (mean * (1.0 - max_rate)).sqrt() + 1.0;
+    assert!(events as f64 <= bound, "{} rate {}/{} exceeds {} (at most {:.0} events expected)", what, events, trials, max_rate, bound.floor());
+}
+
+/// Proptest strategies for sketch parameters, item sets and scenarios.
+#[cfg(feature = "proptest")]
+pub mod strategies {
+    use proptest::prelude::*;
+
+    use super::{random_items, Scenario};
+    use crate::{SketchParams, TestItem};
+
+    /// Parameters of sketches of 1 to 8192 words with 2 to 8 points.
+    pub fn params() -> impl Strategy<Value = SketchParams> {
+        (1..=64u64, 0..=7u64, 2..=8u64).prop_map(|(base_length, level, points)| SketchParams::new(base_length, level, points))
+    }
+
+    /// Up to `max_len` random items.
+    pub fn items(max_len: usize) -> impl Strategy<Value = Vec<TestItem>> {
+        (any::<u64>(), 0..=max_len).prop_map(|(seed, n)| random_items(seed, n))
+    }
+
+    /// Scenarios whose difference stays within a tenth of a point per bit, where decoding
+    /// is expected to be exact.
+    pub fn light_scenarios() -> impl Strategy<Value = Scenario> {
+        params().prop_flat_map(|params| {
+            let capacity = (params.base_length << params.level) as usize * 64 / params.points as usize / 10;
+            (Just(params), 0..=1000usize, 0..=capacity).prop_map(|(params, common, difference)| Scenario::new(params, common, difference))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_random_items() {
+        assert_eq!(random_items(7, 10), random_items(7, 10));
+        assert_ne!(random_items(7, 10), random_items(8, 10));
+        assert_eq!(random_items(7, 10)[..5], random_items(7, 5)[..]);
+    }
+
+    #[test]
+    fn test_scenario_peers() {
+        let peers = Scenario::new(SketchParams::new(10, 1, 3), 50, 0).with_sides(2, 3).peers(7);
+        assert_eq!((peers.common.len(), peers.local_only.len(), peers.remote_only.len()), (50, 2, 3));
+        assert_eq!(peers, Scenario::new(SketchParams::new(10, 1, 3), 50, 5).peers(7));
+
+        let (diff, candidates, difference) = peers.decode_inputs();
+        assert_eq!(candidates.len(), 55);
+        assert_eq!(difference, candidates[50..]);
+        let mut expected = BinaryCountSketch::new(10, 1, 3);
+        expected.toggle_all(&difference);
+        assert_eq!(diff, expected);
+    }
+
+    #[test]
+    fn test_scenario_rates() {
+        let scenario = Scenario::new(SketchParams::new(100, 1, 5), 2000, 100);
+        let rates = scenario.rates(10, 0);
+        assert_eq!((rates.trials, rates.positives, rates.negatives), (10, 1000, 20000));
+        rates.assert_fp_rate_at_most(1e-3);
+        rates.assert_fn_rate_at_most(1e-2);
+
+        // A sketch far too small for the difference fails the assertion.
+        let overloaded = Scenario::new(SketchParams::new(2, 0, 5), 2000, 100).rates(3, 0);
+        assert!(overloaded.fn_rate() > 0.5);
+        assert!(std::panic::catch_unwind(|| overloaded.assert_fn_rate_at_most(0.1)).is_err());
+    }
+
+    #[cfg(feature = "proptest")]
+    mod properties {
+        use proptest::prelude::*;
+
+        use super::super::strategies;
+
+        proptest! {
+            #![proptest_config(ProptestConfig::with_cases(32))]
+
+            #[test]
+            fn light_scenarios_mostly_decode(scenario in strategies::light_scenarios(), seed in any::<u64>()) {
+                let outcome = scenario.run(seed);
+                prop_assert!(outcome.false_negatives * 10 <= outcome.positives.max(10));
+            }
+        }
+    }
+}
@@ -0,0 +1,164 @@
+use std::collections::HashSet;
+use std::hash::Hash;
+
+use crate::{BinaryCountSketch, BinaryCountSketchError, DecodeReport, Item, PeelingDecoder, SketchParams};
+
+/// Result of `TrackedSet::diff`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SymmetricDifference<T> {
+    pub only_in_self: Vec<T>,
+    pub only_in_other: Vec<T>,
+}
+
+/// A `HashSet` paired with a sketch that is kept in sync on every insert and remove,
+/// so the sketch can never drift from the set it describes.
+pub struct TrackedSet<T> {
+    items: HashSet<T>,
+    sketch: BinaryCountSketch,
+}
+
+impl<T: Item + Hash + Eq + Clone> TrackedSet<T> {
+    pub fn new(params: SketchParams) -> Self {
+        TrackedSet {
+            items: HashSet::new(),
+            sketch: BinaryCountSketch::from_params(params),
+        }
+    }
+
+    /// Adds `item`, returning whether it was newly inserted. The sketch is only toggled
+    /// for new items.
+    pub fn insert(&mut self, item: T) -> bool {
+        if self.items.contains(&item) {
+            return false;
+        }
+        self.sketch.toggle(&item);
+        self.items.insert(item)
+    }
+
+    /// Removes `item`, returning whether it was present.
+    pub fn remove(&mut self, item: &T) -> bool {
+        if !self.items.remove(item) {
+            return false;
+        }
+        self.sketch.toggle(item);
+        true
+    }
+
+    pub fn contains(&self, item: &T) -> bool {
+        self.items.contains(item)
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.items.iter()
+    }
+
+    pub fn sketch(&self) -> &BinaryCountSketch {
+        &self.sketch
+    }
+
+    /// The sketch folded down to `level`, ready to send to a peer.
+    pub fn sketch_at_level(&self, level: u64) -> Result<BinaryCountSketch, BinaryCountSketchError> {
+        self.sketch.at_level(level)
+    }
+
+    /// Decodes which of our items are missing from the peer described by `peer_sketch`
+    /// (which may be at a lower level than ours). Items only the peer has cannot be
+    /// recovered here, since our own items are the only candidates.
+    pub fn reconcile_with(&self, peer_sketch: &BinaryCountSketch, min_threshold: usize) -> Result<DecodeReport<T>, BinaryCountSketchError> {
+        let mut diff = self.sketch_at_level(peer_sketch.level)?;
+        diff.diff_with(peer_sketch)?;
+
+        let candidates: Vec<T> = self.items.iter().cloned().collect();
+        PeelingDecoder::new(min_threshold).decode(&mut diff, &candidates)
+    }
+
+    /// Exact symmetric difference with another tracked set in the same process, from one
+    /// hash lookup per item of either set. Both sets being at hand, a sketch decode
+    /// could only lose differences; a peer known only by its sketch is reconciled with
+    /// `reconcile_with` instead.
+    pub fn diff(&self, other: &TrackedSet<T>) -> SymmetricDifference<T> {
+        SymmetricDifference {
+            only_in_self: self.items.difference(&other.items).cloned().collect(),
+            only_in_other: other.items.difference(&self.items).cloned().collect(),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "rand"))]
+mod tests {
+    use super::*;
+    use crate::testing::Scenario;
+    use crate::TestItem;
+
+    #[test]
+    fn test_tracked_insert_remove() {
+        let params = SketchParams::new(10, 2, 3);
+        let mut set = TrackedSet::new(params);
+        let item = TestItem { points: vec![1, 2, 3] };
+
+        assert!(set.insert(item.clone()));
+        assert!(!set.insert(item.clone()));
+        assert_eq!(set.len(), 1);
+        assert_eq!(set.sketch().check(&item), 3);
+
+        assert!(set.remove(&item));
+        assert!(!set.remove(&item));
+        assert!(set.is_empty());
+        assert!(set.sketch().words.iter().all(|w| *w == 0));
+    }
+
+    #[test]
+    fn test_tracked_diff() {
+        let params = SketchParams::new(100, 2, 5);
+        let peers = Scenario::new(params, 2000, 0).with_sides(5, 7).peers(0);
+        let mut a = TrackedSet::new(params);
+        let mut b = TrackedSet::new(params);
+        for item in peers.common.iter().chain(&peers.local_only) {
+            a.insert(item.clone());
+        }
+        for item in peers.common.iter().chain(&peers.remote_only) {
+            b.insert(item.clone());
+        }
+        let (only_a, only_b) = (peers.local_only, peers.remote_only);
+
+        let diff = a.diff(&b);
+        assert_eq!(diff.only_in_self.len(), only_a.len());
+        assert!(diff.only_in_self.iter().all(|item| only_a.contains(item)));
+        assert_eq!(diff.only_in_other.len(), only_b.len());
+        assert!(diff.only_in_other.iter().all(|item| only_b.contains(item)));
+
+        let empty = TrackedSet::new(SketchParams::new(100, 2, 4));
+        assert_eq!(a.diff(&empty).only_in_self.len(), a.len());
+    }
+
+    #[test]
+    fn test_tracked_reconcile() {
+        let params = SketchParams::new(100, 2, 5);
+        let peers = Scenario::new(params, 2000, 0).with_sides(10, 0).peers(0);
+        let mut local = TrackedSet::new(params);
+        let mut peer = TrackedSet::new(params);
+        for item in &peers.common {
+            local.insert(item.clone());
+            peer.insert(item.clone());
+        }
+        for item in &peers.local_only {
+            local.insert(item.clone());
+        }
+        let missing = peers.local_only;
+
+        let peer_sketch = peer.sketch_at_level(1).expect("No errors");
+        assert!(peer.sketch_at_level(3).is_err());
+
+        let report = local.reconcile_with(&peer_sketch, 4).expect("No errors");
+        assert!(report.decoded.iter().all(|item| missing.contains(item)));
+        assert!(report.decoded.len() >= missing.len() - 1);
+    }
+}
@@ -0,0 +1,139 @@
+use alloc::vec::Vec;
+
+use crate::wire::parse_header;
+use crate::{code_index, BinaryCountSketch, BinaryCountSketchError, Item, SketchParams};
+
+#[derive(Clone, Copy, Debug)]
+enum Words<'a> {
+    Native(&'a [u64]),
+    /// Little-endian words, read one at a time so the bytes need not be aligned.
+    Bytes(&'a [u8]),
+}
+
+/// Read-only sketch over borrowed words, e.g. a `to_bytes` encoding received off the wire
+/// or mapped from disk, so a large sketch can be queried without copying its words.
+/// `diff` and `to_sketch` allocate the owned sketch they return.
+#[derive(Clone, Copy, Debug)]
+pub struct BinaryCountSketchView<'a> {
+    params: SketchParams,
+    words: Words<'a>,
+}
+
+impl<'a> BinaryCountSketchView<'a> {
+    pub fn new(params: SketchParams, words: &'a [u64]) -> Result<Self, BinaryCountSketchError> {
+        let len = params.validate()?;
+        if words.len() != len { return Err(BinaryCountSketchError::with_mismatch("words length", len, words.len())); }
+        Ok(BinaryCountSketchView { params, words: Words::Native(words) })
+    }
+
+    /// View over words stored as little-endian bytes, at any alignment.
+    pub fn from_le_bytes(params: SketchParams, bytes: &'a [u8]) -> Result<Self, BinaryCountSketchError> {
+        let len = params.validate()?;
+        if bytes.len() != len * 8 { return Err(BinaryCountSketchError::with_mismatch("words length", len, bytes.len() / 8)); }
+        Ok(BinaryCountSketchView { params, words: Words::Bytes(bytes) })
+    }
+
+    /// View over the words of a `to_bytes` encoding. Compressed encodings must be
+    /// decoded with `BinaryCountSketch::from_bytes`.
+    pub fn from_bytes(bytes: &'a [u8]) -> Result<Self, BinaryCountSketchError> {
+        let (params, header_len) = parse_header(bytes)?;
+        BinaryCountSketchView::from_le_bytes(params, &bytes[header_len..])
+    }
+
+    pub fn params(&self) -> SketchParams {
+        self.params
+    }
+
+    pub fn len(&self) -> usize {
+        match self.words {
+            Words::Native(words) => words.len(),
+            Words::Bytes(bytes) => bytes.len() / 8,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn bits(&self) -> usize {
+        self.len() * 64
+    }
+
+    pub fn word(&self, i: usize) -> u64 {
+        match self.words {
+            Words::Native(words) => words[i],
+            Words::Bytes(bytes) => u64::from_le_bytes(bytes[i * 8..i * 8 + 8].try_into().unwrap()),
+        }
+    }
+
+    pub fn words(&self) -> impl Iterator<Item = u64> + 'a {
+        let view = *self;
+        (0..view.len()).map(move |i| view.word(i))
+    }
+
+    pub fn check<V: Item>(&self, v: &V) -> usize {
+        let l = self.bits();
+        (0..v.points(self.params.points).min(self.params.points))
+            .filter(|i| {
+                let b = code_index(v, *i, l);
+                self.word(b / 64) & (1 << (b % 64)) != 0
+            })
+            .count()
+    }
+
+    pub fn decode<V: Item>(&self, items: &[V]) -> Vec<usize> {
+        items.iter().map(|item| self.check(item)).collect()
+    }
+
+    /// Owned diff of both views, as `BinaryCountSketch::diff`.
+    pub fn diff(&self, other: &BinaryCountSketchView<'_>) -> Result<BinaryCountSketch, BinaryCountSketchError> {
+        let (a, b) = (self.params, other.params);
+        if a.base_length != b.base_length { return Err(BinaryCountSketchError::with_mismatch("base length", a.base_length, b.base_length)); }
+        if a.level != b.level { return Err(BinaryCountSketchError::with_mismatch("level", a.level, b.level)); }
+        if a.points != b.points { return Err(BinaryCountSketchError::with_mismatch("points", a.points, b.points)); }
+        if a.seed != b.seed { return Err(BinaryCountSketchError::with_mismatch("seed", a.seed, b.seed)); }
+
+        let words = self.words().zip(other.words()).map(|(x, y)| x ^ y).collect();
+        BinaryCountSketch::from_parts(self.params, words)
+    }
+
+    pub fn to_sketch(&self) -> BinaryCountSketch {
+        BinaryCountSketch::from_parts(self.params, self.words().collect()).expect("Words match the parameters")
+    }
+}
+
+impl BinaryCountSketch {
+    pub fn view(&self) -> BinaryCountSketchView<'_> {
+        BinaryCountSketchView { params: self.params(), words: Words::Native(&self.words) }
+    }
+}
+
+#[cfg(all(test, feature = "rand"))]
+mod tests {
+    use super::*;
+    use crate::TestItem;
+
+    #[test]
+    fn test_view_queries() {
+        let items: Vec<TestItem> = (0..100).map(TestItem::from_seed).collect();
+        let mut local = BinaryCountSketch::new(10, 2, 5);
+        let mut remote = BinaryCountSketch::new(10, 2, 5);
+        local.toggle_all(&items[..60]);
+        remote.toggle_all(&items[40..]);
+
+        // Misaligned by one byte, as a buffer with a frame header in front may be.
+        let mut buffer = vec![0u8];
+        buffer.extend_from_slice(&remote.to_bytes());
+        let received = BinaryCountSketchView::from_bytes(&buffer[1..]).expect("No errors");
+        assert_eq!(received.params(), remote.params());
+        assert_eq!(received.decode(&items), remote.decode(&items));
+        assert_eq!(received.to_sketch(), remote);
+
+        let diff = local.view().diff(&received).expect("No errors");
+        assert_eq!(diff, local.diff(&remote).expect("No errors"));
+        assert!(local.view().diff(&BinaryCountSketch::new(10, 1, 5).view()).is_err());
+
+        assert!(BinaryCountSketchView::new(local.params(), &local.words()[1..]).is_err());
+        assert!(BinaryCountSketchView::from_bytes(&buffer[1..buffer.len() - 8]).is_err());
+    }
+}
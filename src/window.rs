@@ -0,0 +1,265 @@
+use alloc::collections::VecDeque;
+
+use crate::{BinaryCountSketch, BinaryCountSketchError, ErrorKind, Item, SketchParams};
+#[cfg(feature = "std")]
+use crate::{DecodeReport, PeelStrategy, PeelingDecoder};
+
+/// Sketch of the event ids seen during the last `epochs` epochs, e.g. by a log receiver.
+///
+/// Each epoch has its own sub-sketch and events are toggled into the epoch of their own
+/// timestamp, so a sender and a receiver with the same window agree on its contents even
+/// if their clocks rotate at slightly different times. Diffing the receiver's window
+/// against the sender's and decoding with the ids the sender shipped yields the events
+/// whose received count has the wrong parity: gaps, or ids received twice. Ids present
+/// in the receiver's own log are duplicates, the others are gaps.
+///
+/// Event ids are unique and uniformly hashed, so the decode usually succeeds at a strict
+/// threshold: `PeelingDecoder::new(points - 1)` is a good default for this workload.
+pub struct WindowedSketch {
+    params: SketchParams,
+    newest: u64,
+    epochs: VecDeque<BinaryCountSketch>,
+}
+
+impl WindowedSketch {
+    pub fn new(params: SketchParams, epochs: usize) -> Result<Self, BinaryCountSketchError> {
+        if epochs == 0 { return Err(BinaryCountSketchError::with_kind(ErrorKind::InvalidArgument, "Incorrect epochs")); }
+
+        Ok(WindowedSketch {
+            params,
+            newest: epochs as u64 - 1,
+            epochs: (0..epochs).map(|_| BinaryCountSketch::from_params(params)).collect(),
+        })
+    }
+
+    pub fn newest_epoch(&self) -> u64 {
+        self.newest
+    }
+
+    pub fn oldest_epoch(&self) -> u64 {
+        self.newest + 1 - self.epochs.len() as u64
+    }
+
+    /// Rotates the window forward so that `epoch` is the newest, dropping expired epochs.
+    pub fn advance(&mut self, epoch: u64) {
+        while self.newest < epoch {
+            self.epochs.pop_front();
+            self.epochs.push_back(BinaryCountSketch::from_params(self.params));
+            self.newest += 1;
+        }
+    }
+
+    /// Toggles `v` into the sub-sketch of `epoch`, advancing the window if `epoch` is
+    /// newer. Returns `false`, leaving the sketch unchanged, if `epoch` already expired.
+    pub fn toggle<V: Item>(&mut self, v: &V, epoch: u64) -> bool {
+        self.advance(epoch);
+        if epoch < self.oldest_epoch() {
+            return false;
+        }
+        let i = (epoch - self.oldest_epoch()) as usize;
+        self.epochs[i].toggle(v);
+        true
+    }
+
+    pub fn epoch(&self, epoch: u64) -> Option<&BinaryCountSketch> {
+        if epoch < self.oldest_epoch() || epoch > self.newest {
+            return None;
+        }
+        Some(&self.epochs[(epoch - self.oldest_epoch()) as usize])
+    }
+
+    /// Sketch of every event in the window.
+    pub fn window(&self) -> BinaryCountSketch {
+        let mut sketch = BinaryCountSketch::from_params(self.params);
+        for epoch in &self.epochs {
+            sketch.diff_with(epoch).expect("Same parameters");
+        }
+        sketch
+    }
+
+    /// Decodes the events of the sender's window whose parity differs from ours, among
+    /// the ids the sender shipped.
+    #[cfg(feature = "std")]
+    pub fn find_gaps<V: Item + Clone, S: PeelStrategy>(&self, sender: &BinaryCountSketch, sent: &[V], decoder: &PeelingDecoder<S>) -> Result<DecodeReport<V>, BinaryCountSketchError> {
+        let mut sketch = self.window();
+        sketch.diff_with(sender)?;
+        decoder.decode(&mut sketch, sent)
+    }
+}
+
+/// Sketch of the items toggled during the last `buckets` periods, e.g. one bucket per
+/// minute for a window of an hour. Items go into the newest bucket and `advance`, called
+/// by the owner at each period boundary, expires the oldest bucket with its items.
+///
+/// Unlike `WindowedSketch` items carry no timestamp, so both peers must advance at about
+/// the same time; items toggled near a boundary may then show up as differences. The
+/// sketch of the whole window is kept up to date on every toggle, so diffing it against a
+/// peer costs no more than diffing a plain sketch.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RotatingSketch {
+    buckets: VecDeque<BinaryCountSketch>,
+    window: BinaryCountSketch,
+}
+
+impl RotatingSketch {
+    pub fn new(params: SketchParams, buckets: usize) -> Result<Self, BinaryCountSketchError> {
+        if buckets == 0 { return Err(BinaryCountSketchError::with_kind(ErrorKind::InvalidArgument, "Incorrect buckets")); }
+
+        Ok(RotatingSketch {
+            buckets: (0..buckets).map(|_| BinaryCountSketch::from_params(params)).collect(),
+            window: BinaryCountSketch::from_params(params),
+        })
+    }
+
+    pub fn params(&self) -> SketchParams {
+        self.window.params()
+    }
+
+    pub fn len(&self) -> usize {
+        self.buckets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buckets.is_empty()
+    }
+
+    /// Bucket `i` of the window, from the oldest at 0 to the newest at `len() - 1`.
+    pub fn bucket(&self, i: usize) -> Option<&BinaryCountSketch> {
+        self.buckets.get(i)
+    }
+
+    pub fn toggle<V: Item>(&mut self, v: &V) {
+        self.buckets.back_mut().expect("At least one bucket").toggle(v);
+        self.window.toggle(v);
+    }
+
+    pub fn check<V: Item>(&self, v: &V) -> usize {
+        self.window.check(v)
+    }
+
+    /// Starts a new bucket and expires the oldest one, whose sketch is returned.
+    pub fn advance(&mut self) -> BinaryCountSketch {
+        let expired = self.buckets.pop_front().expect("At least one bucket");
+        self.window.diff_with(&expired).expect("Same parameters");
+        self.buckets.push_back(BinaryCountSketch::from_params(expired.params()));
+        expired
+    }
+
+    /// Sketch of every item in the window, to send to a peer.
+    pub fn window(&self) -> &BinaryCountSketch {
+        &self.window
+    }
+
+    /// Diff of our window with a peer's window.
+    pub fn diff(&self, other: &BinaryCountSketch) -> Result<BinaryCountSketch, BinaryCountSketchError> {
+        self.window.diff(other)
+    }
+
+    /// Decodes the diff of our window with a peer's window against `candidates`.
+    #[cfg(feature = "std")]
+    pub fn decode<V: Item + Clone, S: PeelStrategy>(&self, other: &BinaryCountSketch, decoder: &PeelingDecoder<S>, candidates: &[V]) -> Result<DecodeReport<V>, BinaryCountSketchError> {
+        let mut sketch = self.diff(other)?;
+        decoder.decode(&mut sketch, candidates)
+    }
+}
+
+#[cfg(all(test, feature = "rand"))]
+mod tests {
+    use super::*;
+    use crate::TestItem;
+
+    #[test]
+    fn test_window_rotation() {
+        let params = SketchParams::new(10, 1, 3);
+        assert!(WindowedSketch::new(params, 0).is_err());
+
+        let mut window = WindowedSketch::new(params, 3).expect("No errors");
+        let item = TestItem { points: vec![1, 2, 3] };
+        assert!(window.toggle(&item, 1));
+        assert_eq!(window.window().check(&item), 3);
+
+        assert!(window.toggle(&TestItem::new(), 3));
+        assert_eq!((window.oldest_epoch(), window.newest_epoch()), (1, 3));
+        assert!(!window.toggle(&TestItem::new(), 0));
+        assert!(window.epoch(0).is_none());
+        assert_eq!(window.epoch(1).expect("In window").check(&item), 3);
+
+        // Epoch 1 expires and takes the item with it.
+        window.advance(4);
+        assert_eq!(window.oldest_epoch(), 2);
+        assert_eq!(window.window().check(&item), 0);
+    }
+
+    #[test]
+    fn test_window_gaps() {
+        let params = SketchParams::new(100, 2, 5);
+        let mut sender = WindowedSketch::new(params, 4).expect("No errors");
+        let mut receiver = WindowedSketch::new(params, 4).expect("No errors");
+
+        let mut sent = vec![];
+        let mut lost = vec![];
+        for i in 0..2000u64 {
+            let item = TestItem::new();
+            let epoch = i / 100;
+            sender.toggle(&item, epoch);
+            if i % 400 == 399 {
+                lost.push(item.clone());
+            } else {
+                receiver.toggle(&item, epoch);
+            }
+            sent.push(item);
+        }
+
+        let report = receiver.find_gaps(&sender.window(), &sent[1600..], &PeelingDecoder::new(4)).expect("No errors");
+        assert_eq!(report.decoded.len(), 1);
+        assert_eq!(report.decoded[0], lost[4]);
+    }
+
+    #[test]
+    fn test_rotating_expiry() {
+        let params = SketchParams::new(10, 1, 3);
+        assert!(RotatingSketch::new(params, 0).is_err());
+
+        let mut sketch = RotatingSketch::new(params, 2).expect("No errors");
+        let (first, second) = (TestItem::from_seed(1), TestItem::from_seed(2));
+        sketch.toggle(&first);
+        sketch.advance();
+        sketch.toggle(&second);
+        assert_eq!((sketch.check(&first), sketch.check(&second)), (3, 3));
+        assert_eq!(sketch.bucket(0).expect("In window").check(&first), 3);
+        assert!(sketch.bucket(2).is_none());
+
+        let expired = sketch.advance();
+        assert_eq!(expired.check(&first), 3);
+        assert_eq!(sketch.check(&first), 0);
+        assert_eq!(sketch.advance().check(&second), 3);
+        assert_eq!(*sketch.window(), BinaryCountSketch::from_params(params));
+    }
+
+    #[test]
+    fn test_rotating_decode() {
+        let params = SketchParams::new(100, 1, 5);
+        let mut local = RotatingSketch::new(params, 3).expect("No errors");
+        let mut remote = RotatingSketch::new(params, 3).expect("No errors");
+
+        let items: Vec<TestItem> = (0..1000).map(TestItem::from_seed).collect();
+        for (i, chunk) in items.chunks(200).enumerate() {
+            for item in chunk {
+                remote.toggle(item);
+                if item.get_code(0) % 50 != 0 {
+                    local.toggle(item);
+                }
+            }
+            if i < 4 {
+                local.advance();
+                remote.advance();
+            }
+        }
+
+        // Only the items missing from the last three buckets are still in the diff.
+        let expected: Vec<TestItem> = items[400..].iter().filter(|item| item.get_code(0) % 50 == 0).cloned().collect();
+        let report = local.decode(remote.window(), &PeelingDecoder::new(4), &items).expect("No errors");
+        assert_eq!(report.decoded.len(), expected.len());
+        assert!(expected.iter().all(|item| report.decoded.contains(item)));
+    }
+}
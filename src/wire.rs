@@ -0,0 +1,238 @@
+use alloc::vec::Vec;
+
+use crate::{BinaryCountSketch, BinaryCountSketchError, ErrorKind, SketchParams};
+
+const MAGIC: &[u8; 4] = b"BCSK";
+const VERSION: u8 = 2;
+
+/// Version of `to_bytes_compressed` encodings: the magic, this version, a codec byte, the
+/// length of the `to_bytes` encoding as a little-endian `u64` and the compressed encoding.
+const VERSION_COMPRESSED: u8 = 3;
+
+/// Codec byte of deflate-compressed encodings.
+#[cfg(feature = "deflate")]
+const CODEC_DEFLATE: u8 = 1;
+
+/// Length of the version 1 header: magic, version and the three parameters.
+const HEADER_LEN_V1: usize = 4 + 1 + 3 * 8;
+
+/// Length of the `to_bytes` header, which adds the seed.
+pub(crate) const HEADER_LEN: usize = HEADER_LEN_V1 + 16;
+
+fn parse_error(details: &str) -> BinaryCountSketchError {
+    BinaryCountSketchError::with_kind(ErrorKind::Parse, details)
+}
+
+/// Parameters and header length of an uncompressed `to_bytes` encoding, whose words
+/// follow the header.
+pub(crate) fn parse_header(bytes: &[u8]) -> Result<(SketchParams, usize), BinaryCountSketchError> {
+    if bytes.len() < HEADER_LEN_V1 || &bytes[..4] != MAGIC { return Err(parse_error("Incorrect magic")); }
+    let header_len = match bytes[4] {
+        1 => HEADER_LEN_V1,
+        VERSION => HEADER_LEN,
+        _ => return Err(parse_error("Incorrect version")),
+    };
+    if bytes.len() < header_len || !(bytes.len() - header_len).is_multiple_of(8) { return Err(parse_error("Incorrect words length")); }
+
+    let mut values = bytes[5..HEADER_LEN_V1].chunks(8).map(|c| u64::from_le_bytes(c.try_into().unwrap()));
+    let mut params = SketchParams::new(values.next().unwrap(), values.next().unwrap(), values.next().unwrap());
+    if header_len == HEADER_LEN {
+        params = params.with_seed(bytes[HEADER_LEN_V1..HEADER_LEN].try_into().unwrap());
+    }
+    params.validate()?;
+    Ok((params, header_len))
+}
+
+impl BinaryCountSketch {
+    /// Encodes the sketch as the magic bytes `BCSK`, a version byte, the `base_length`,
+    /// `level` and `points` parameters as little-endian `u64`, the 16 byte seed and the
+    /// words as little-endian `u64`. Version 1 encodings, without a seed, are still read.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(HEADER_LEN + self.words.len() * 8);
+        self.write_header(&mut out);
+        for word in &self.words {
+            out.extend_from_slice(&word.to_le_bytes());
+        }
+        out
+    }
+
+    /// Writes the `to_bytes` header, without the words, e.g. to stream them after it.
+    pub(crate) fn write_header(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(MAGIC);
+        out.push(VERSION);
+        for v in [self.base_length, self.level, self.points] {
+            out.extend_from_slice(&v.to_le_bytes());
+        }
+        out.extend_from_slice(&self.seed);
+    }
+
+    /// Same as `to_bytes`, with the encoding deflate-compressed behind the magic bytes,
+    /// version 3, a codec byte and the uncompressed length. `from_bytes` reads it back
+    /// when the `deflate` feature is enabled. It inflates the header first and refuses a
+    /// length other than that of the header and the words its parameters call for, so
+    /// nothing past the announced sketch is ever inflated. Worth it for sketches that
+    /// are mostly zeros, e.g. diffs.
+    #[cfg(feature = "deflate")]
+    pub fn to_bytes_compressed(&self) -> Vec<u8> {
+        use std::io::Write;
+
+        let bytes = self.to_bytes();
+        let mut out = MAGIC.to_vec();
+        out.extend_from_slice(&[VERSION_COMPRESSED, CODEC_DEFLATE]);
+        out.extend_from_slice(&(bytes.len() as u64).to_le_bytes());
+        let mut encoder = flate2::write::DeflateEncoder::new(out, flate2::Compression::default());
+        encoder.write_all(&bytes).expect("Writing to a Vec");
+        encoder.finish().expect("Writing to a Vec")
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, BinaryCountSketchError> {
+        if bytes.len() >= 6 && &bytes[..4] == MAGIC && bytes[4] == VERSION_COMPRESSED {
+            return BinaryCountSketch::from_compressed_bytes(bytes[5], &bytes[6..]);
+        }
+        let (params, header_len) = parse_header(bytes)?;
+        let words = bytes[header_len..].chunks(8).map(|c| u64::from_le_bytes(c.try_into().unwrap())).collect();
+        BinaryCountSketch::from_parts(params, words)
+    }
+
+    #[cfg(feature = "deflate")]
+    fn from_compressed_bytes(codec: u8, data: &[u8]) -> Result<Self, BinaryCountSketchError> {
+        use std::io::Read;
+
+        if codec != CODEC_DEFLATE { return Err(parse_error("Incorrect codec")); }
+        if data.len() < 8 { return Err(parse_error("Incorrect compressed data")); }
+        let (len, data) = data.split_at(8);
+        let len = u64::from_le_bytes(len.try_into().unwrap());
+
+        // Inflate the header alone first, so the announced length can be checked against
+        // the words its parameters call for before anything else is inflated.
+        let inflate_error = |_| parse_error("Incorrect compressed data");
+        let mut decoder = flate2::read::DeflateDecoder::new(data);
+        let mut inner = vec![0; HEADER_LEN_V1];
+        decoder.read_exact(&mut inner).map_err(inflate_error)?;
+        if inner[4] == VERSION {
+            inner.resize(HEADER_LEN, 0);
+            decoder.read_exact(&mut inner[HEADER_LEN_V1..]).map_err(inflate_error)?;
+        }
+        let (params, header_len) = parse_header(&inner)?;
+        let expected = (header_len + params.validate()? * 8) as u64;
+        if !(len == expected) { return Err(parse_error("Incorrect compressed length")); }
+
+        // Inflate at most one byte past the words, to tell trailing data apart.
+        decoder.take(len - header_len as u64 + 1).read_to_end(&mut inner).map_err(inflate_error)?;
+        if inner.len() as u64 != len { return Err(parse_error("Incorrect compressed length")); }
+        BinaryCountSketch::from_bytes(&inner)
+    }
+
+    #[cfg(not(feature = "deflate"))]
+    fn from_compressed_bytes(_codec: u8, _data: &[u8]) -> Result<Self, BinaryCountSketchError> {
+        Err(parse_error("Incorrect codec"))
+    }
+}
+
+#[cfg(all(test, feature = "rand"))]
+mod tests {
+    use super::*;
+    use crate::TestItem;
+
+    #[test]
+    fn test_bytes_roundtrip() {
+        let item = TestItem::new();
+        let mut sketch = BinaryCountSketch::new(10, 2, 3);
+        sketch.toggle(&item);
+
+        let bytes = sketch.to_bytes();
+        assert_eq!(bytes.len(), HEADER_LEN + 40 * 8);
+        assert_eq!(&bytes[..5], b"BCSK\x02");
+        assert_eq!(bytes[5..13], 10u64.to_le_bytes());
+
+        let received = BinaryCountSketch::from_bytes(&bytes).expect("No errors");
+        assert_eq!(received.params(), sketch.params());
+        assert_eq!(received.check(&item), 3);
+
+        let seeded = BinaryCountSketch::with_seed(10, 2, 3, [7; 16]);
+        assert_eq!(BinaryCountSketch::from_bytes(&seeded.to_bytes()).expect("No errors").params(), seeded.params());
+
+        // Version 1 had no seed.
+        let mut v1 = bytes[..HEADER_LEN_V1].to_vec();
+        v1[4] = 1;
+        v1.extend_from_slice(&bytes[HEADER_LEN..]);
+        let received = BinaryCountSketch::from_bytes(&v1).expect("No errors");
+        assert_eq!(received.params(), sketch.params());
+        assert_eq!(received.check(&item), 3);
+    }
+
+    #[test]
+    fn test_bytes_rejects_bad_input() {
+        let bytes = BinaryCountSketch::new(10, 2, 3).to_bytes();
+        let parse = |b: &[u8]| BinaryCountSketch::from_bytes(b).expect_err("Error").kind();
+
+        assert_eq!(parse(&bytes[..20]), ErrorKind::Parse);
+        assert_eq!(parse(&bytes[..bytes.len() - 3]), ErrorKind::Parse);
+        assert_eq!(parse(&bytes[..bytes.len() - 8]), ErrorKind::Parse);
+
+        let mut version = bytes.clone();
+        version[4] = 3;
+        assert_eq!(parse(&version), ErrorKind::Parse);
+
+        // A level that would overflow the words length.
+        let mut level = bytes.clone();
+        level[13] = 70;
+        assert_eq!(parse(&level), ErrorKind::Parse);
+
+        // No words, so every check would divide by zero.
+        let mut empty = bytes[..HEADER_LEN].to_vec();
+        empty[5..13].copy_from_slice(&0u64.to_le_bytes());
+        assert_eq!(parse(&empty), ErrorKind::Parse);
+
+        // So many points that every check would loop for ages.
+        let mut points = bytes.clone();
+        points[21..29].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert_eq!(parse(&points), ErrorKind::Parse);
+    }
+
+    #[cfg(feature = "deflate")]
+    #[test]
+    fn test_bytes_compressed() {
+        let item = TestItem::new();
+        let mut sketch = BinaryCountSketch::with_seed(1000, 2, 3, [5; 16]);
+        sketch.toggle(&item);
+
+        let bytes = sketch.to_bytes_compressed();
+        assert_eq!(&bytes[..6], b"BCSK\x03\x01");
+        assert!(bytes.len() < sketch.to_bytes().len() / 10);
+        assert_eq!(BinaryCountSketch::from_bytes(&bytes).expect("No errors"), sketch);
+
+        let parse = |b: &[u8]| BinaryCountSketch::from_bytes(b).expect_err("Error").kind();
+        let mut codec = bytes.clone();
+        codec[5] = 9;
+        assert_eq!(parse(&codec), ErrorKind::Parse);
+        assert_eq!(parse(&bytes[..bytes.len() / 2]), ErrorKind::Parse);
+        assert_eq!(parse(&bytes[..10]), ErrorKind::Parse);
+
+        // Inflating past the announced length is refused, as is inflating short of it.
+        for len in [100u64, u64::MAX] {
+            let mut bomb = MAGIC.to_vec();
+            bomb.extend_from_slice(&[VERSION_COMPRESSED, CODEC_DEFLATE]);
+            bomb.extend_from_slice(&len.to_le_bytes());
+            let mut encoder = flate2::write::DeflateEncoder::new(bomb, flate2::Compression::default());
+            std::io::Write::write_all(&mut encoder, &[0; 1 << 20]).expect("Writing to a Vec");
+            assert_eq!(parse(&encoder.finish().expect("Writing to a Vec")), ErrorKind::Parse);
+        }
+
+        // A valid sketch announced with any other length is refused before its words
+        // are inflated.
+        for len in [0, sketch.to_bytes().len() as u64 + 8, u64::MAX] {
+            let mut announced = bytes.clone();
+            announced[6..14].copy_from_slice(&len.to_le_bytes());
+            assert_eq!(BinaryCountSketch::from_bytes(&announced).expect_err("Error").to_string(), "Sketch Error: Incorrect compressed length");
+        }
+
+        // A compressed encoding cannot nest another one.
+        let mut nested = MAGIC.to_vec();
+        nested.extend_from_slice(&[VERSION_COMPRESSED, CODEC_DEFLATE]);
+        nested.extend_from_slice(&(bytes.len() as u64).to_le_bytes());
+        let mut encoder = flate2::write::DeflateEncoder::new(nested, flate2::Compression::default());
+        std::io::Write::write_all(&mut encoder, &bytes).expect("Writing to a Vec");
+        assert_eq!(parse(&encoder.finish().expect("Writing to a Vec")), ErrorKind::Parse);
+    }
+}